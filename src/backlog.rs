@@ -0,0 +1,235 @@
+//! The release schedule of backlog mode.
+//!
+//! The first episode is released on `backlog_start`, and another one every
+//! `backlog_interval` days after that. The schedule is kept next to the download tracker,
+//! so that a changed interval only affects the episodes that haven't been released yet.
+
+use crate::config::DownloadMode;
+use crate::config::GlobalConfig;
+use crate::config::PodcastConfigs;
+use crate::download_tracker::DownloadedEpisodes;
+use crate::index;
+use crate::index::PodcastIndex;
+use crate::paths;
+use crate::read_only;
+use crate::utils;
+use crate::utils::Unix;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Where the schedule was picked up again after the interval changed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Rebase {
+    /// Unix time of when the new interval was first seen.
+    at: u64,
+    /// Episodes released by then, which stay released.
+    released: u64,
+    previous_interval: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Schedule {
+    /// Unix time of `backlog_start`, when the first episode is released.
+    anchor: u64,
+    /// Seconds between two releases.
+    interval: u64,
+    #[serde(default)]
+    rebase: Option<Rebase>,
+    /// Episodes released as of the last time the schedule was evaluated.
+    #[serde(default)]
+    released: u64,
+    /// Unix time of the last time the schedule was evaluated.
+    #[serde(default)]
+    computed: u64,
+}
+
+impl Schedule {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".backlog");
+        tracker_path.with_file_name(name)
+    }
+
+    /// Loads the schedule of a backlog and evaluates it for the current time.
+    ///
+    /// A different `start` than the stored one begins a new backlog. A different `interval`
+    /// keeps the episodes released so far, and releases the rest at the new interval
+    /// counting from now.
+    pub fn load(tracker_path: &Path, start: Unix, interval: Unix) -> Self {
+        let stored: Option<Self> = fs::read_to_string(Self::path(tracker_path))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok());
+
+        let mut schedule = match stored {
+            Some(schedule) if schedule.anchor == start.as_secs() => schedule,
+            _ => Self {
+                anchor: start.as_secs(),
+                interval: interval.as_secs(),
+                rebase: None,
+                released: 0,
+                computed: 0,
+            },
+        };
+
+        let now = utils::current_unix().as_secs();
+
+        if schedule.interval != interval.as_secs() {
+            schedule.rebase = Some(Rebase {
+                at: now,
+                released: schedule.released_at(now),
+                previous_interval: schedule.interval,
+            });
+            schedule.interval = interval.as_secs();
+        }
+
+        schedule.released = schedule.released_at(now);
+        schedule.computed = now;
+
+        // A read-only run works out the schedule without keeping it.
+        if read_only::is_enabled() {
+            return schedule;
+        }
+
+        if let Err(e) = schedule.save(tracker_path) {
+            log::warn!("{}", e);
+        }
+
+        schedule
+    }
+
+    fn save(&self, tracker_path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|_| "failed to serialize backlog schedule".to_string())?;
+        paths::write(Self::path(tracker_path), json)
+            .map_err(|_| "failed to save backlog schedule".to_string())
+    }
+
+    /// How many episodes are released by the given unix time, from now on.
+    fn released_at(&self, time: u64) -> u64 {
+        let interval = self.interval.max(1);
+        match &self.rebase {
+            Some(rebase) => rebase.released + time.saturating_sub(rebase.at) / interval,
+            None if time < self.anchor => 0,
+            None => (time - self.anchor) / interval + 1,
+        }
+    }
+
+    /// Unix time of when the episode at `index` gets released.
+    fn release_time(&self, index: u64) -> u64 {
+        match &self.rebase {
+            Some(rebase) if index < rebase.released => rebase.at,
+            Some(rebase) => rebase.at + (index + 1 - rebase.released) * self.interval,
+            None => self.anchor + index * self.interval,
+        }
+    }
+
+    /// How many episodes, oldest first, are released as of the last evaluation.
+    pub fn released(&self) -> u64 {
+        self.released
+    }
+
+    fn describe(&self, downloaded: usize, total: usize) -> Vec<String> {
+        let total = total as u64;
+        let downloaded = downloaded as u64;
+        let released = self.released.min(total);
+        let days = |secs: u64| secs / 86400;
+
+        let mut lines = vec![format!(
+            "{} of {} episodes downloaded, {} released so far",
+            downloaded, total, released
+        )];
+
+        if let Some(rebase) = &self.rebase {
+            lines.push(format!(
+                "interval changed from {} to {} days on {}; the {} episodes released by then stay released, the rest follow every {} days from then",
+                days(rebase.previous_interval),
+                days(self.interval),
+                format_date(rebase.at),
+                rebase.released,
+                days(self.interval),
+            ));
+        }
+
+        if released < total {
+            lines.push(format!(
+                "next episode on {}, done on {}",
+                format_date(self.release_time(released)),
+                format_date(self.release_time(total - 1)),
+            ));
+        } else {
+            lines.push("every episode has been released".to_string());
+        }
+
+        match downloaded.cmp(&released) {
+            std::cmp::Ordering::Less => lines.push(format!(
+                "{} episodes behind schedule, they'll be downloaded on the next sync",
+                released - downloaded
+            )),
+            std::cmp::Ordering::Greater => lines.push(format!(
+                "{} episodes ahead of schedule",
+                downloaded - released
+            )),
+            std::cmp::Ordering::Equal => lines.push("on schedule".to_string()),
+        }
+
+        lines
+    }
+}
+
+fn format_date(unix: u64) -> String {
+    chrono::DateTime::from_timestamp(unix as i64, 0)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Prints how far along the podcasts in backlog mode are.
+pub async fn status(filter: Option<Regex>, global_config: &GlobalConfig) {
+    let podcasts: Vec<_> = PodcastConfigs::load()
+        .assert_not_empty()
+        .filter(filter)
+        .into_iter()
+        .filter(|(_, config)| config.is_backlog())
+        .collect();
+
+    if podcasts.is_empty() {
+        eprintln!("no podcasts in backlog mode");
+        return;
+    }
+
+    for (name, config) in podcasts {
+        let tracker_path = config.tracker_path(&name, global_config);
+        let DownloadMode::Backlog { schedule } =
+            DownloadMode::new(global_config, &config, &tracker_path)
+        else {
+            continue;
+        };
+
+        let podcast_index = match PodcastIndex::load(&name) {
+            Some(podcast_index) => podcast_index,
+            None => {
+                index::fetch_index(&name, config, global_config).await;
+                match PodcastIndex::load(&name) {
+                    Some(podcast_index) => podcast_index,
+                    None => {
+                        eprintln!("{}: failed to fetch the feed", name);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let tracker = DownloadedEpisodes::load(&tracker_path);
+        let total = podcast_index.episode_ids().count();
+        let downloaded = podcast_index
+            .episode_ids()
+            .filter(|id| tracker.contains_episode(id))
+            .count();
+
+        for line in schedule.describe(downloaded, total) {
+            println!("{}: {}", name, line);
+        }
+    }
+}
@@ -1,9 +1,242 @@
+use crate::chaos;
 use crate::display::DownloadBar;
+use crate::format;
+use crate::net;
+use crate::net::Credentials;
+use crate::paths;
+use crate::read_only;
 use crate::utils;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::io::{self, Read};
-use std::path::Path;
+use std::io;
 use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// What a cached file is used for.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum CacheKind {
+    /// Searchable text of the podcast feeds.
+    Feeds,
+    /// Images embedded into episodes.
+    Artwork,
+    /// Image urls the server said don't exist.
+    Negative,
+    /// Feeds as they were last downloaded, reused while they haven't changed.
+    Xml,
+}
+
+impl CacheKind {
+    const ALL: [Self; 4] = [Self::Feeds, Self::Artwork, Self::Negative, Self::Xml];
+
+    fn dir_name(&self) -> &'static str {
+        match self {
+            Self::Feeds => "feeds",
+            Self::Artwork => "artwork",
+            Self::Negative => "negative",
+            Self::Xml => "xml",
+        }
+    }
+}
+
+/// Files cached under `$XDG_CACHE_HOME/talecast`, one directory per [`CacheKind`].
+///
+/// Runs hold a shared lock on the cache while they're using it. Removing files requires an
+/// exclusive lock, so that a run never has its cached files removed from under it.
+///
+/// A read-only run keeps the files it caches in memory instead, and reads them from there
+/// before looking on disk.
+pub struct Cache;
+
+/// The files cached by a read-only run.
+fn in_memory() -> &'static Mutex<HashMap<PathBuf, Vec<u8>>> {
+    static FILES: OnceLock<Mutex<HashMap<PathBuf, Vec<u8>>>> = OnceLock::new();
+    FILES.get_or_init(Default::default)
+}
+
+/// Shared lock on the cache, held for the duration of a run.
+pub struct CacheLock {
+    _file: fs::File,
+}
+
+impl Cache {
+    fn lock_file() -> io::Result<fs::File> {
+        let path = paths::cache_dir().join(".lock");
+        read_only::check(&path);
+        fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+    }
+
+    /// Waits for any exclusive lock to be released, and then locks the cache for this run.
+    pub fn lock_shared() -> Option<CacheLock> {
+        let file = Self::lock_file().ok()?;
+        file.lock_shared().ok()?;
+        Some(CacheLock { _file: file })
+    }
+
+    /// Locks the cache for removing files, unless another run is using it.
+    fn try_lock_exclusive() -> Option<fs::File> {
+        let file = Self::lock_file().ok()?;
+        file.try_lock().ok()?;
+        Some(file)
+    }
+
+    pub fn dir(kind: CacheKind) -> PathBuf {
+        let dir = paths::cache_dir().join(kind.dir_name());
+        if !read_only::is_enabled() {
+            paths::create_dir(&dir);
+        }
+        dir
+    }
+
+    pub fn path(kind: CacheKind, key: &str) -> PathBuf {
+        Self::dir(kind).join(key)
+    }
+
+    /// Reads a cached file, marking it as recently used.
+    pub fn read(kind: CacheKind, key: &str) -> Option<Vec<u8>> {
+        let path = Self::path(kind, key);
+        if read_only::is_enabled() {
+            let cached = in_memory().lock().unwrap().get(&path).cloned();
+            return cached.or_else(|| fs::read(&path).ok());
+        }
+
+        let data = fs::read(&path).ok()?;
+
+        if let Ok(file) = fs::File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Some(data)
+    }
+
+    /// Writes a cached file, so that it's never seen half written.
+    pub fn write(kind: CacheKind, key: &str, data: &[u8]) -> io::Result<()> {
+        let path = Self::path(kind, key);
+        if read_only::is_enabled() {
+            in_memory().lock().unwrap().insert(path, data.to_vec());
+            return Ok(());
+        }
+
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, data)?;
+        fs::rename(&temp_path, &path)
+    }
+
+    /// The cached files of a kind, with their size and when they were last used.
+    fn entries(kind: CacheKind) -> Vec<(PathBuf, u64, SystemTime)> {
+        let Ok(entries) = fs::read_dir(Self::dir(kind)) else {
+            return vec![];
+        };
+
+        entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let meta = entry.metadata().ok()?;
+                let used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                meta.is_file().then(|| (entry.path(), meta.len(), used))
+            })
+            .collect()
+    }
+
+    /// Prints the number of files and size of each kind of cache.
+    pub fn print_stats() {
+        let mut total = 0;
+        for kind in CacheKind::ALL {
+            let entries = Self::entries(kind);
+            let size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+            total += size;
+            println!(
+                "{:<8} {:>5} files {:>10}",
+                kind.dir_name(),
+                entries.len(),
+                format::human_bytes(size)
+            );
+        }
+        println!("{:<8} {:>22}", "total", format::human_bytes(total));
+    }
+
+    /// Removes every cached file of the given kinds, or the entire cache if none are given.
+    pub fn clear(kind: Option<CacheKind>) {
+        let Some(_lock) = Self::try_lock_exclusive() else {
+            eprintln!("the cache is in use by another run, try again later");
+            process::exit(1);
+        };
+
+        let dirs = match kind {
+            Some(kind) => vec![Self::dir(kind)],
+            None => {
+                // Also removes files left over from older versions.
+                let Ok(entries) = fs::read_dir(paths::cache_dir()) else {
+                    return;
+                };
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path())
+                    .filter(|path| path.file_name().is_some_and(|name| name != ".lock"))
+                    .collect()
+            }
+        };
+
+        for path in dirs {
+            let res = match path.is_dir() {
+                true => fs::remove_dir_all(&path),
+                false => fs::remove_file(&path),
+            };
+
+            if let Err(e) = res {
+                eprintln!("failed to remove {:?}: {:?}", path, e);
+                process::exit(1);
+            }
+        }
+
+        eprintln!("cache cleared");
+    }
+
+    /// Removes the least recently used files until the cache fits within `max_size` bytes.
+    ///
+    /// Releases the lock of this run first, and skips eviction if another run is still
+    /// using the cache.
+    pub fn evict(max_size: u64, lock: Option<CacheLock>) {
+        drop(lock);
+
+        let Some(_lock) = Self::try_lock_exclusive() else {
+            log::info!("cache in use by another run, skipping eviction");
+            return;
+        };
+
+        let mut entries: Vec<(PathBuf, u64, SystemTime)> = CacheKind::ALL
+            .into_iter()
+            .flat_map(Self::entries)
+            .filter(|(path, _, _)| !path.ends_with(MimeMap::FILE_NAME))
+            .collect();
+
+        let mut size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        if size <= max_size {
+            return;
+        }
+
+        entries.sort_by_key(|(_, _, used)| *used);
+        for (path, file_size, _) in entries {
+            if size <= max_size {
+                break;
+            }
+
+            log::debug!("evicting cached file: {:?}", path);
+            if fs::remove_file(&path).is_ok() {
+                size -= file_size;
+            }
+        }
+    }
+}
 
 struct MimeMap;
 
@@ -21,18 +254,13 @@ impl MimeMap {
         Some(())
     }
 
+    const FILE_NAME: &'static str = "mime_types";
+
     fn path() -> PathBuf {
-        utils::cache_dir().join("mime_types")
+        Cache::path(CacheKind::Artwork, Self::FILE_NAME)
     }
 }
 
-fn read_file_to_vec(path: &Path) -> io::Result<Vec<u8>> {
-    let mut file = fs::File::open(path)?;
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
-    Ok(data)
-}
-
 fn hashed_url(url: &str) -> String {
     use std::hash::Hasher;
     let mut hasher = fnv::FnvHasher::default();
@@ -41,10 +269,176 @@ fn hashed_url(url: &str) -> String {
     format!("{:x}", hash)
 }
 
+/// Image urls that the server answered with 404 or 410, so they aren't requested again on
+/// every sync until `asset_negative_cache_days` have passed.
+///
+/// Other failures, like server errors and timeouts, are usually temporary and never
+/// remembered.
+struct NegativeCache;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Failure {
+    status: u16,
+    /// Unix time of when the url failed.
+    failed_at: u64,
+}
+
+impl NegativeCache {
+    fn is_permanent(status: reqwest::StatusCode) -> bool {
+        status == reqwest::StatusCode::NOT_FOUND || status == reqwest::StatusCode::GONE
+    }
+
+    fn record(url: &str, status: reqwest::StatusCode) {
+        let failure = Failure {
+            status: status.as_u16(),
+            failed_at: utils::current_unix().as_secs(),
+        };
+
+        let res = serde_json::to_vec(&failure)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                Cache::write(CacheKind::Negative, &hashed_url(url), &json)
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = res {
+            log::warn!("failed to remember missing image: {}", e);
+        }
+    }
+
+    /// The failure of the url, if it happened less than `ttl` ago.
+    fn get(url: &str, ttl: Duration) -> Option<Failure> {
+        let data = Cache::read(CacheKind::Negative, &hashed_url(url))?;
+        let failure: Failure = serde_json::from_slice(&data).ok()?;
+        let age = utils::current_unix()
+            .as_secs()
+            .saturating_sub(failure.failed_at);
+        (age < ttl.as_secs()).then_some(failure)
+    }
+}
+
+/// A feed as it was last downloaded, with the `ETag` and `Last-Modified` headers it came with.
+///
+/// Sending those back lets the server answer 304 Not Modified instead of sending the whole
+/// feed again. The file is a line of json with the headers, followed by the xml.
+///
+/// Once a sync of the feed leaves nothing for the next one, the state it left is recorded
+/// too, so a sync that finds the feed and that state unchanged can skip it altogether.
+pub struct CachedFeed {
+    validators: Validators,
+    pub xml: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// A digest of the settings and download tracker a sync of this feed left nothing to do
+    /// with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    settled: Option<u64>,
+}
+
+impl Validators {
+    fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+impl CachedFeed {
+    /// The cached copy of the feed at the url, unless there's none or it can't be read.
+    pub fn load(url: &str) -> Option<Self> {
+        let data = Cache::read(CacheKind::Xml, &hashed_url(url))?;
+        let feed = Self::parse(&data);
+        if feed.is_none() {
+            log::warn!(
+                "ignoring unreadable cached copy of {}",
+                net::redact_url(url)
+            );
+        }
+        feed
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        let newline = data.iter().position(|&b| b == b'\n')?;
+        let validators: Validators = serde_json::from_slice(&data[..newline]).ok()?;
+        let xml = String::from_utf8(data[newline + 1..].to_vec()).ok()?;
+        (!validators.is_empty()).then_some(Self { validators, xml })
+    }
+
+    /// Caches a downloaded feed, if the server sent anything to check it against next time.
+    pub fn save(url: &str, headers: &reqwest::header::HeaderMap, xml: &str) {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let feed = Self {
+            validators: Validators {
+                etag: header(reqwest::header::ETAG),
+                last_modified: header(reqwest::header::LAST_MODIFIED),
+                settled: None,
+            },
+            xml: xml.to_string(),
+        };
+        if feed.validators.is_empty() {
+            return;
+        }
+
+        feed.write(url);
+    }
+
+    /// Records that a sync of the cached feed at the url left nothing to do, given `state`.
+    pub fn settle(url: &str, state: u64) {
+        let Some(mut feed) = Self::load(url) else {
+            return;
+        };
+        if feed.validators.settled != Some(state) {
+            feed.validators.settled = Some(state);
+            feed.write(url);
+        }
+    }
+
+    /// Whether a sync of this copy of the feed left nothing to do, given `state`.
+    pub fn is_settled(&self, state: u64) -> bool {
+        self.validators.settled == Some(state)
+    }
+
+    fn write(&self, url: &str) {
+        let res = serde_json::to_vec(&self.validators)
+            .map_err(|e| e.to_string())
+            .and_then(|mut data| {
+                data.push(b'\n');
+                data.extend_from_slice(self.xml.as_bytes());
+                Cache::write(CacheKind::Xml, &hashed_url(url), &data).map_err(|e| e.to_string())
+            });
+        if let Err(e) = res {
+            log::warn!("failed to cache feed: {}", e);
+        }
+    }
+
+    /// Asks the server to only send the feed if it changed since it was cached.
+    pub fn revalidate(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let mut request = request;
+        if let Some(etag) = &self.validators.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.validators.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    }
+}
+
+/// Where the image at the url is cached, if it has been downloaded.
+pub fn cached_image_path(url: &str) -> Option<PathBuf> {
+    let path = Cache::path(CacheKind::Artwork, &hashed_url(url));
+    path.is_file().then_some(path)
+}
+
 fn cached_image(url: &str, ui: &DownloadBar) -> Option<Vec<u8>> {
     let hash = hashed_url(url);
-    let path = utils::cache_dir().join(hash);
-    let image = read_file_to_vec(&path).ok();
+    let image = Cache::read(CacheKind::Artwork, &hash);
 
     if image.is_some() {
         ui.log_debug("loaded cached image");
@@ -55,11 +449,28 @@ fn cached_image(url: &str, ui: &DownloadBar) -> Option<Vec<u8>> {
     image
 }
 
-async fn write_image(url: &str, ui: &DownloadBar) -> Option<()> {
-    use std::io::Write;
+/// Guards against huge or slow image urls when embedding images.
+#[derive(Debug, Clone, Default)]
+pub struct ImageLimits {
+    /// Max size in bytes of an image.
+    pub max_size: u64,
+    pub timeout: Duration,
+    /// How long to remember that an image was missing, unless it's fetched regardless.
+    pub negative_ttl: Option<Duration>,
+}
 
+async fn write_image(
+    client: &reqwest::Client,
+    credentials: &Credentials,
+    url: &str,
+    limits: &ImageLimits,
+    ui: &DownloadBar,
+) -> Option<()> {
     let hashed = hashed_url(url);
-    let response = match reqwest::get(url).await {
+    let request = credentials.apply(client.get(url), url);
+    let response = chaos::send(request.timeout(limits.timeout), url).await;
+
+    let response = match response {
         Ok(res) => {
             ui.log_info("connected to image url");
             res
@@ -78,26 +489,77 @@ async fn write_image(url: &str, ui: &DownloadBar) -> Option<()> {
             .and_then(|value| value.to_str().ok())
             .unwrap_or("")
             .to_string();
-        let data = response.bytes().await.ok()?.to_vec();
-        let path = utils::cache_dir().join(&hashed);
-        let mut file = fs::File::create(&path).ok()?;
-        file.write_all(&data).ok()?;
+        if response
+            .content_length()
+            .is_some_and(|len| len > limits.max_size)
+        {
+            ui.log_warn(format!(
+                "skipping image larger than {} bytes: {}",
+                limits.max_size, url
+            ));
+            return None;
+        }
+
+        let mut data = vec![];
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    ui.log_error(format!("failed to download image: {:?}", e));
+                    return None;
+                }
+            };
+
+            if data.len() as u64 + chunk.len() as u64 > limits.max_size {
+                ui.log_warn(format!(
+                    "skipping image larger than {} bytes: {}",
+                    limits.max_size, url
+                ));
+                return None;
+            }
+
+            data.extend_from_slice(&chunk);
+        }
+
+        Cache::write(CacheKind::Artwork, &hashed, &data).ok()?;
         MimeMap::append(url, &mime_type)?;
     } else {
-        ui.log_error("response status to image url connection not successful");
+        let status = response.status();
+        if NegativeCache::is_permanent(status) {
+            NegativeCache::record(url, status);
+        }
+        ui.log_error(format!(
+            "response status to image url connection not successful: {}",
+            status
+        ));
     };
     Some(())
 }
 
 pub async fn get_image(
+    client: &reqwest::Client,
+    credentials: &Credentials,
     url: &str,
     picture_type: id3::frame::PictureType,
+    limits: &ImageLimits,
     ui: &DownloadBar,
 ) -> Option<id3::frame::Frame> {
     let data = match cached_image(url, ui) {
         Some(data) => data,
         None => {
-            write_image(url, ui).await?;
+            if let Some(failure) = limits
+                .negative_ttl
+                .and_then(|ttl| NegativeCache::get(url, ttl))
+            {
+                ui.log_debug(format!(
+                    "skipping image that was missing with status {}: {}",
+                    failure.status, url
+                ));
+                return None;
+            }
+
+            write_image(client, credentials, url, limits, ui).await?;
             cached_image(url, ui)?
         }
     };
@@ -122,3 +584,129 @@ pub async fn get_image(
         id3::frame::Content::Picture(pic),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GlobalConfig;
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+    use reqwest::StatusCode;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn ui() -> DownloadBar {
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        DownloadBar::new("test".to_string(), GlobalConfig::default().style(), &mp, 4)
+    }
+
+    fn limits(toml: &str) -> ImageLimits {
+        toml::from_str::<GlobalConfig>(toml).unwrap().image_limits()
+    }
+
+    /// Fetches the image at `path` of a local server once with each of `limits`, returning
+    /// how often the server was asked for it and whether the last fetch got an image.
+    async fn fetch_image(path: &'static str, limits: &[ImageLimits]) -> (usize, bool) {
+        let (url, requests) =
+            net::test_server(|_| vec![("/art.png", "image/png", b"png".to_vec())]).await;
+        let url = format!("{}{}", url, path);
+
+        let client = reqwest::Client::new();
+        let mut found = false;
+        for limits in limits {
+            let image = get_image(
+                &client,
+                &Credentials::default(),
+                &url,
+                id3::frame::PictureType::CoverFront,
+                limits,
+                &ui(),
+            )
+            .await;
+            found = image.is_some();
+        }
+
+        let count = requests.lock().unwrap().len();
+        (count, found)
+    }
+
+    #[test]
+    fn permanent_failures() {
+        assert!(NegativeCache::is_permanent(StatusCode::NOT_FOUND));
+        assert!(NegativeCache::is_permanent(StatusCode::GONE));
+        for status in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::FORBIDDEN,
+            StatusCode::TOO_MANY_REQUESTS,
+        ] {
+            assert!(!NegativeCache::is_permanent(status), "{}", status);
+        }
+    }
+
+    #[test]
+    fn failures_expire() {
+        let url = "https://example.com/negative-cache-ttl.png";
+        NegativeCache::record(url, StatusCode::GONE);
+
+        let failure = NegativeCache::get(url, Duration::from_secs(7 * DAY)).unwrap();
+        assert_eq!(failure.status, 410);
+        assert!(NegativeCache::get(url, Duration::ZERO).is_none());
+
+        let old = Failure {
+            status: 404,
+            failed_at: utils::current_unix().as_secs() - 8 * DAY,
+        };
+        let json = serde_json::to_vec(&old).unwrap();
+        Cache::write(CacheKind::Negative, &hashed_url(url), &json).unwrap();
+        assert!(NegativeCache::get(url, Duration::from_secs(7 * DAY)).is_none());
+        assert!(NegativeCache::get(url, Duration::from_secs(9 * DAY)).is_some());
+
+        assert!(
+            NegativeCache::get("https://example.com/never-failed.png", Duration::MAX).is_none()
+        );
+    }
+
+    #[test]
+    fn negative_cache_settings() {
+        let week = Some(Duration::from_secs(7 * DAY));
+        assert_eq!(limits("").negative_ttl, week);
+        assert_eq!(
+            limits("asset_negative_cache_days = 2").negative_ttl,
+            Some(Duration::from_secs(2 * DAY))
+        );
+        assert_eq!(limits("asset_negative_cache_days = 0").negative_ttl, None);
+
+        let bypassed = GlobalConfig::default()
+            .bypass_negative_cache()
+            .image_limits();
+        assert_eq!(bypassed.negative_ttl, None);
+    }
+
+    #[tokio::test]
+    async fn missing_images_are_not_requested_again() {
+        let (requests, found) = fetch_image("/missing.png", &[limits(""), limits("")]).await;
+        assert_eq!((requests, found), (1, false));
+    }
+
+    #[tokio::test]
+    async fn bypassing_requests_missing_images_again() {
+        let bypassed = GlobalConfig::default()
+            .bypass_negative_cache()
+            .image_limits();
+        let (requests, found) = fetch_image("/missing.png", &[limits(""), bypassed]).await;
+        assert_eq!((requests, found), (2, false));
+    }
+
+    #[tokio::test]
+    async fn turned_off_negative_cache() {
+        let off = limits("asset_negative_cache_days = 0");
+        let (requests, _) = fetch_image("/missing.png", &[off.clone(), off]).await;
+        assert_eq!(requests, 2);
+    }
+
+    #[tokio::test]
+    async fn found_images_are_cached() {
+        let (requests, found) = fetch_image("/art.png", &[limits(""), limits("")]).await;
+        assert_eq!((requests, found), (1, true));
+    }
+}
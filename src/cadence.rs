@@ -0,0 +1,216 @@
+//! How often a podcast publishes, for noticing feeds that have stopped updating.
+//!
+//! The cadence is derived from the publication dates of the episodes every time the feed is
+//! parsed, and kept next to the download tracker so it can be checked without fetching the
+//! feed.
+
+use crate::format;
+use crate::paths;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Episodes published this close together count as a single release.
+const SAME_RELEASE: u64 = 60 * 60;
+
+/// Below this many intervals between releases the cadence isn't known well enough.
+const MIN_INTERVALS: usize = 5;
+
+/// How many typical intervals may pass without a new episode before a feed looks stalled.
+const STALLED_FACTOR: u64 = 3;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Cadence {
+    /// Median seconds between two releases, if there were enough releases.
+    median_interval: Option<u64>,
+    /// Unix time of the newest episode.
+    newest: u64,
+}
+
+impl Cadence {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".cadence");
+        tracker_path.with_file_name(name)
+    }
+
+    pub fn load(tracker_path: &Path) -> Option<Self> {
+        let s = fs::read_to_string(Self::path(tracker_path)).ok()?;
+        serde_json::from_str(&s).ok()
+    }
+
+    fn save(&self, tracker_path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|_| "failed to serialize cadence".to_string())?;
+        paths::write(Self::path(tracker_path), json)
+            .map_err(|_| "failed to save cadence".to_string())
+    }
+
+    /// Stores the cadence of a podcast from the unix times its episodes were published.
+    pub fn record(tracker_path: &Path, published: impl IntoIterator<Item = u64>) {
+        let mut published: Vec<u64> = published.into_iter().collect();
+        published.sort_unstable();
+
+        let Some(&newest) = published.last() else {
+            return;
+        };
+
+        let cadence = Self {
+            median_interval: median_interval(&published),
+            newest,
+        };
+
+        if let Err(e) = cadence.save(tracker_path) {
+            log::warn!("{}", e);
+        }
+    }
+
+    /// Describes how overdue the next episode is, if the feed looks like it stopped updating.
+    pub fn describe_stalled(&self) -> Option<String> {
+        self.describe_stalled_at(utils::current_unix().as_secs())
+    }
+
+    fn describe_stalled_at(&self, now: u64) -> Option<String> {
+        let interval = self.median_interval?;
+        let age = now.saturating_sub(self.newest);
+
+        if age <= interval * STALLED_FACTOR {
+            return None;
+        }
+
+        Some(format!(
+            "possibly stalled, the newest episode is from {} but episodes are usually {} apart",
+            format::relative_date(self.newest, now),
+            format::human_duration(Duration::from_secs(interval))
+        ))
+    }
+}
+
+/// The median time between releases, from sorted publication times.
+///
+/// Episodes published within [`SAME_RELEASE`] of each other are one release, so that a
+/// batch of episodes or an old episode republished next to a new one doesn't make the
+/// podcast look like it publishes every few minutes.
+fn median_interval(published: &[u64]) -> Option<u64> {
+    let mut releases: Vec<u64> = vec![];
+    for &time in published {
+        match releases.last() {
+            Some(&last) if time - last < SAME_RELEASE => {}
+            _ => releases.push(time),
+        }
+    }
+
+    let mut intervals: Vec<u64> = releases.windows(2).map(|w| w[1] - w[0]).collect();
+    if intervals.len() < MIN_INTERVALS {
+        return None;
+    }
+
+    intervals.sort_unstable();
+    let mid = intervals.len() / 2;
+    Some(match intervals.len() % 2 {
+        0 => (intervals[mid - 1] + intervals[mid]) / 2,
+        _ => intervals[mid],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+    const START: u64 = 1_700_000_000;
+
+    /// Publication times that are `gaps` apart, starting from [`START`].
+    fn releases(gaps: &[u64]) -> Vec<u64> {
+        let mut time = START;
+        let mut published = vec![time];
+        for gap in gaps {
+            time += gap;
+            published.push(time);
+        }
+        published
+    }
+
+    #[test]
+    fn median_of_intervals() {
+        let weekly = releases(&[7 * DAY; 5]);
+        assert_eq!(median_interval(&weekly), Some(7 * DAY));
+
+        let odd = releases(&[DAY, 9 * DAY, 3 * DAY, 2 * DAY, 100 * DAY]);
+        assert_eq!(median_interval(&odd), Some(3 * DAY));
+
+        let even = releases(&[DAY, 2 * DAY, 3 * DAY, 4 * DAY, 5 * DAY, 6 * DAY]);
+        assert_eq!(median_interval(&even), Some(3 * DAY + DAY / 2));
+    }
+
+    #[test]
+    fn too_few_releases() {
+        assert_eq!(median_interval(&[]), None);
+        assert_eq!(median_interval(&[START]), None);
+        assert_eq!(median_interval(&releases(&[7 * DAY; 4])), None);
+    }
+
+    #[test]
+    fn batches_are_one_release() {
+        let mut published = releases(&[7 * DAY; 5]);
+        // A batch of bonus episodes next to every release.
+        let batches: Vec<u64> = published
+            .iter()
+            .flat_map(|&time| [time + 60, time + SAME_RELEASE - 1])
+            .collect();
+        published.extend(batches);
+        published.sort_unstable();
+
+        assert_eq!(median_interval(&published), Some(7 * DAY));
+
+        // Without grouping, four releases would be too few to know the cadence.
+        let mut published = releases(&[7 * DAY; 4]);
+        published.push(START + 30);
+        published.sort_unstable();
+        assert_eq!(median_interval(&published), None);
+    }
+
+    #[test]
+    fn stalled_after_three_intervals() {
+        let cadence = Cadence {
+            median_interval: Some(7 * DAY),
+            newest: START,
+        };
+
+        assert_eq!(cadence.describe_stalled_at(START + 21 * DAY), None);
+        assert_eq!(
+            cadence.describe_stalled_at(START + 22 * DAY).as_deref(),
+            Some("possibly stalled, the newest episode is from 3 weeks ago but episodes are usually 7d apart")
+        );
+    }
+
+    #[test]
+    fn unknown_cadence_never_stalls() {
+        let cadence = Cadence {
+            median_interval: None,
+            newest: START,
+        };
+        assert_eq!(cadence.describe_stalled_at(START + 1000 * DAY), None);
+    }
+
+    #[test]
+    fn recorded_next_to_tracker() {
+        let dir = paths::test_dir("cadence");
+        let tracker = dir.join(".downloaded");
+
+        Cadence::record(&tracker, vec![]);
+        assert!(Cadence::load(&tracker).is_none());
+
+        let mut published = releases(&[DAY; 5]);
+        published.reverse();
+        Cadence::record(&tracker, published);
+
+        let cadence = Cadence::load(&tracker).unwrap();
+        assert_eq!(cadence.median_interval, Some(DAY));
+        assert_eq!(cadence.newest, START + 5 * DAY);
+        assert!(Cadence::path(&tracker).ends_with(".downloaded.cadence"));
+    }
+}
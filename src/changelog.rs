@@ -0,0 +1,285 @@
+//! A Markdown log of the episodes each sync downloaded, for `changelog_path`.
+//!
+//! Every sync that downloads anything appends a section headed with the date, with a bullet
+//! per episode: the podcast, the title linking to the episode's page, its duration and the
+//! start of its description.
+
+use crate::episode::Attributes;
+use crate::format;
+use crate::paths;
+use crate::summary::SyncResult;
+use crate::text;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How wide the description excerpt of an episode may be.
+const EXCERPT_WIDTH: usize = 160;
+
+/// A downloaded episode, as it's listed in the changelog.
+#[derive(Debug, Clone)]
+pub struct ChangelogEntry {
+    title: String,
+    link: Option<String>,
+    duration: Option<String>,
+    excerpt: Option<String>,
+}
+
+impl ChangelogEntry {
+    pub fn new(attrs: &Attributes) -> Self {
+        let link = attrs
+            .get_str("link")
+            .ok()
+            .map(str::trim)
+            .filter(|link| !link.is_empty())
+            .map(String::from);
+
+        let duration = attrs
+            .itunes_duration()
+            .filter(|duration| !duration.is_zero())
+            .map(format::human_duration);
+
+        let excerpt = attrs
+            .description()
+            .ok()
+            .map(excerpt)
+            .filter(|excerpt| !excerpt.is_empty());
+
+        Self {
+            title: attrs.title().to_string(),
+            link,
+            duration,
+            excerpt,
+        }
+    }
+
+    fn to_markdown(&self, podcast: &str) -> String {
+        let title = escape(&self.title);
+        let mut line = match &self.link {
+            Some(link) => format!("- **{}**: [{}](<{}>)", escape(podcast), title, link),
+            None => format!("- **{}**: {}", escape(podcast), title),
+        };
+
+        if let Some(duration) = &self.duration {
+            line.push_str(&format!(" ({})", duration));
+        }
+
+        if let Some(excerpt) = &self.excerpt {
+            line.push_str(&format!(" — {}", escape(excerpt)));
+        }
+
+        line
+    }
+
+    /// The episode as plain text, for the email digest: the title with its duration, then
+    /// the link and the excerpt indented below it.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("- {}", self.title);
+        if let Some(duration) = &self.duration {
+            text.push_str(&format!(" ({})", duration));
+        }
+
+        for detail in [&self.link, &self.excerpt].into_iter().flatten() {
+            text.push_str(&format!("\n  {}", detail));
+        }
+
+        text
+    }
+}
+
+/// Appends a section for the episodes of this sync, unless nothing was downloaded.
+pub fn append(path: &Path, results: &[SyncResult]) {
+    let Some(section) = section(results, chrono::Local::now()) else {
+        return;
+    };
+
+    if let Err(e) = append_section(path, &section) {
+        log::error!("failed to write changelog to {:?}: {}", path, e);
+        eprintln!("failed to write changelog to {:?}: {}", path, e);
+    }
+}
+
+fn section(results: &[SyncResult], date: chrono::DateTime<chrono::Local>) -> Option<String> {
+    let mut results: Vec<&SyncResult> = results
+        .iter()
+        .filter(|res| !res.changelog.is_empty())
+        .collect();
+    if results.is_empty() {
+        return None;
+    }
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut section = format!("## {}\n\n", date.format("%Y-%m-%d %H:%M"));
+    for res in results {
+        for entry in &res.changelog {
+            section.push_str(&entry.to_markdown(&res.name));
+            section.push('\n');
+        }
+    }
+
+    Some(section)
+}
+
+/// Writes the file with the section added to a temporary file first, so the changelog is
+/// never left half written.
+fn append_section(path: &Path, section: &str) -> io::Result<()> {
+    let mut text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    if !text.is_empty() {
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push('\n');
+    }
+    text.push_str(section);
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    paths::write(&temp_path, text)?;
+    fs::rename(&temp_path, path)
+}
+
+/// The start of a description as a single line of text, without any html.
+fn excerpt(description: &str) -> String {
+    let plain = text::html_to_text(description);
+    let plain = plain.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    text::truncate_string(&plain, EXCERPT_WIDTH, true)
+}
+
+/// Escapes the characters that Markdown would read as formatting.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(
+            c,
+            '\\' | '`'
+                | '*'
+                | '_'
+                | '{'
+                | '}'
+                | '['
+                | ']'
+                | '<'
+                | '>'
+                | '('
+                | ')'
+                | '#'
+                | '|'
+                | '!'
+                | '~'
+        ) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::episode::RawEpisode;
+    use crate::media::MediaPreference;
+    use chrono::TimeZone;
+
+    fn entry(item: serde_json::Value) -> ChangelogEntry {
+        let mut item = item.as_object().unwrap().clone();
+        item.insert("pubDate".into(), "Mon, 01 Jan 2024 06:00:00 +0000".into());
+        let raw = RawEpisode::new(item);
+        ChangelogEntry::new(&Attributes::new(raw, &MediaPreference::default()).unwrap())
+    }
+
+    fn result(name: &str, entries: Vec<ChangelogEntry>) -> SyncResult {
+        SyncResult {
+            changelog: entries,
+            ..SyncResult::new(name.to_string())
+        }
+    }
+
+    fn date() -> chrono::DateTime<chrono::Local> {
+        chrono::Local
+            .with_ymd_and_hms(2024, 1, 2, 6, 30, 0)
+            .unwrap()
+    }
+
+    fn results() -> Vec<SyncResult> {
+        let full = entry(serde_json::json!({
+            "title": "The *best* [episode]",
+            "link": " https://example.com/episodes/1 ",
+            "itunes:duration": "1:02:03",
+            "description": "<p>Guests &amp; <b>news</b></p><p>More   text</p>",
+            "enclosure": { "@url": "https://example.com/1.mp3", "@type": "audio/mpeg" },
+        }));
+        let bare = entry(serde_json::json!({
+            "title": "Episode 2",
+            "itunes:duration": "0",
+            "enclosure": { "@url": "https://example.com/2.mp3", "@type": "audio/mpeg" },
+        }));
+        let long = entry(serde_json::json!({
+            "title": "Episode 3",
+            "description": "word ".repeat(50),
+            "enclosure": { "@url": "https://example.com/3.mp3", "@type": "audio/mpeg" },
+        }));
+
+        vec![
+            result("zebra_pod", vec![bare]),
+            result("empty", vec![]),
+            result("a_pod", vec![full, long]),
+        ]
+    }
+
+    #[test]
+    fn markdown_section() {
+        let expected = format!(
+            "## 2024-01-02 06:30\n\n\
+            - **a\\_pod**: [The \\*best\\* \\[episode\\]](<https://example.com/episodes/1>) (1h 2m) — Guests & news More text\n\
+            - **a\\_pod**: Episode 3 — {}...\n\
+            - **zebra\\_pod**: Episode 2\n",
+            "word ".repeat(31) + "wo"
+        );
+        assert_eq!(section(&results(), date()).unwrap(), expected);
+        assert_eq!(section(&results()[1..2], date()), None);
+    }
+
+    #[test]
+    fn plain_text() {
+        let results = results();
+        let texts: Vec<String> = results[2].changelog.iter().map(|e| e.to_text()).collect();
+        assert_eq!(
+            texts[0],
+            "- The *best* [episode] (1h 2m)\n  https://example.com/episodes/1\n  Guests & news More text"
+        );
+    }
+
+    #[test]
+    fn appending_sections() {
+        let path = paths::test_dir("changelog")
+            .join("notes")
+            .join("changelog.md");
+        append_section(&path, "## one\n\n- a\n").unwrap();
+        append_section(&path, "## two\n\n- b\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "## one\n\n- a\n\n## two\n\n- b\n"
+        );
+
+        // A file edited by hand without a final line break.
+        fs::write(&path, "# Changelog").unwrap();
+        append_section(&path, "## three\n").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Changelog\n\n## three\n"
+        );
+        assert!(!path.with_file_name("changelog.md.tmp").exists());
+    }
+}
@@ -0,0 +1,279 @@
+//! Randomly injected network failures, for checking that syncing recovers from them.
+//!
+//! Enabled with `--simulate-failures p=0.2`. Every injected failure is logged, so it can be
+//! correlated with what happened afterwards.
+//!
+//! Requests that can fail this way are sent with [`send`], and their bodies are read
+//! through the [`Response`] it returns. Whether a request fails is decided from a hash of
+//! the seed, its url and how many times that url was requested before, so a seed
+//! reproduces the same failures whatever order concurrent requests are sent in.
+
+use crate::net;
+use futures::Stream;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hasher;
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static CHAOS: OnceLock<Chaos> = OnceLock::new();
+
+#[cfg(test)]
+tokio::task_local! {
+    /// Failures injected by a single test, leaving the requests of other tests alone.
+    static SCOPED: std::sync::Arc<Chaos>;
+}
+
+/// How long a simulated slow response is delayed.
+const SLOW_RESPONSE: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fault {
+    Timeout,
+    ServerError,
+    Disconnect,
+    Slow,
+}
+
+impl Fault {
+    const ALL: [Self; 4] = [
+        Self::Timeout,
+        Self::ServerError,
+        Self::Disconnect,
+        Self::Slow,
+    ];
+}
+
+/// A xorshift generator.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn roll(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+struct Chaos {
+    probability: f64,
+    seed: u64,
+    slow: Duration,
+    /// How many times each url was requested so far.
+    requests: Mutex<HashMap<String, u64>>,
+}
+
+impl Chaos {
+    fn new(probability: f64, seed: u64, slow: Duration) -> Self {
+        Self {
+            probability,
+            seed,
+            slow,
+            requests: Mutex::default(),
+        }
+    }
+
+    /// The generator for the next request to `url`.
+    fn rng(&self, url: &str) -> Rng {
+        let attempt = {
+            let mut requests = self.requests.lock().unwrap();
+            let count = requests.entry(url.to_string()).or_default();
+            *count += 1;
+            *count
+        };
+
+        let mut hasher = fnv::FnvHasher::default();
+        hasher.write_u64(self.seed);
+        hasher.write(url.as_bytes());
+        hasher.write_u64(attempt);
+        // Xorshift gets stuck on zero.
+        Rng(hasher.finish().max(1))
+    }
+
+    /// The failure to inject into the next request to `url`, if any, along with the
+    /// generator it was drawn from.
+    fn fault(&self, url: &str) -> Option<(Fault, Rng)> {
+        let mut rng = self.rng(url);
+        if rng.roll() >= self.probability {
+            return None;
+        }
+
+        let fault = Fault::ALL[rng.next() as usize % Fault::ALL.len()];
+        Some((fault, rng))
+    }
+}
+
+/// Runs `f` with the failures to inject, if they're enabled.
+fn with<R>(f: impl FnOnce(&Chaos) -> R) -> Option<R> {
+    #[cfg(test)]
+    if let Ok(chaos) = SCOPED.try_with(std::sync::Arc::clone) {
+        return Some(f(&chaos));
+    }
+
+    CHAOS.get().map(f)
+}
+
+/// Parses a spec like `p=0.2` and enables failure injection.
+pub fn init(spec: &str, seed: Option<u64>) -> Result<(), String> {
+    let probability = spec
+        .strip_prefix("p=")
+        .and_then(|p| p.parse::<f64>().ok())
+        .filter(|p| (0.0..=1.0).contains(p))
+        .ok_or_else(|| format!("invalid failure spec: \"{}\", expected e.g. p=0.2", spec))?;
+
+    let seed = seed.unwrap_or_else(|| crate::utils::current_unix().as_nanos() as u64);
+    log::warn!(
+        "simulating failures with probability {} and seed {}",
+        probability,
+        seed
+    );
+
+    CHAOS
+        .set(Chaos::new(probability, seed, SLOW_RESPONSE))
+        .map_err(|_| "failure simulation already enabled".to_string())
+}
+
+/// Runs `future` with failures injected into its requests, for tests. Returns its output
+/// along with how many requests were sent, failed ones included.
+#[cfg(test)]
+pub async fn simulate<F: std::future::Future>(
+    probability: f64,
+    seed: u64,
+    future: F,
+) -> (F::Output, u64) {
+    let chaos = std::sync::Arc::new(Chaos::new(probability, seed, Duration::from_millis(10)));
+    let output = SCOPED.scope(std::sync::Arc::clone(&chaos), future).await;
+    let sent = chaos.requests.lock().unwrap().values().sum();
+    (output, sent)
+}
+
+/// A request that failed, for real or simulated.
+#[derive(Debug)]
+pub enum Error {
+    Simulated(&'static str),
+    Reqwest(reqwest::Error),
+}
+
+impl Error {
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Self::Simulated(msg) => *msg == TIMED_OUT,
+            Self::Reqwest(e) => e.is_timeout(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Simulated(msg) => write!(f, "{}", msg),
+            Self::Reqwest(e) => write!(f, "{}", net::short_error_message(e)),
+        }
+    }
+}
+
+const TIMED_OUT: &str = "request timed out";
+
+/// A response whose body can be cut short by a simulated dropped connection.
+pub struct Response {
+    inner: reqwest::Response,
+    /// How many bytes of the body are received before the connection drops, if it does.
+    cutoff: Option<u64>,
+}
+
+impl Deref for Response {
+    type Target = reqwest::Response;
+
+    fn deref(&self) -> &reqwest::Response {
+        &self.inner
+    }
+}
+
+impl Response {
+    /// The body in chunks, failing where a dropped connection is simulated.
+    pub fn bytes_stream(self) -> impl Stream<Item = Result<impl Deref<Target = [u8]>, Error>> {
+        let cutoff = self.cutoff;
+        let mut received = 0;
+        self.inner.bytes_stream().map(move |chunk| {
+            let chunk = chunk.map_err(Error::Reqwest)?;
+            if cutoff.is_some_and(|cutoff| received >= cutoff) {
+                return Err(Error::Simulated("connection dropped"));
+            }
+            received += chunk.len() as u64;
+            Ok(chunk)
+        })
+    }
+}
+
+/// Sends a request to `url`, possibly injecting a failure into it.
+pub async fn send(request: reqwest::RequestBuilder, url: &str) -> Result<Response, Error> {
+    let Some((fault, mut rng, slow)) = with(|chaos| {
+        chaos
+            .fault(url)
+            .map(|(fault, rng)| (fault, rng, chaos.slow))
+    })
+    .flatten() else {
+        let inner = request.send().await.map_err(Error::Reqwest)?;
+        return Ok(Response {
+            inner,
+            cutoff: None,
+        });
+    };
+
+    log::warn!(
+        "simulated failure: {:?} for {}",
+        fault,
+        net::redact_url(url)
+    );
+
+    let cutoff = match fault {
+        Fault::Timeout => return Err(Error::Simulated(TIMED_OUT)),
+        Fault::ServerError => return Err(Error::Simulated("server error")),
+        Fault::Disconnect => Some(rng.next() % (256 * 1024)),
+        Fault::Slow => {
+            tokio::time::sleep(slow).await;
+            None
+        }
+    };
+
+    let inner = request.send().await.map_err(Error::Reqwest)?;
+    Ok(Response { inner, cutoff })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The failures of the next five requests to `url`.
+    fn faults(chaos: &Chaos, url: &str) -> Vec<Option<Fault>> {
+        (0..5)
+            .map(|_| chaos.fault(url).map(|(fault, _)| fault))
+            .collect()
+    }
+
+    #[test]
+    fn seed_reproduces_failures_in_any_order() {
+        let (one, two) = ("https://example.com/1.mp3", "https://example.com/2.mp3");
+
+        let chaos = Chaos::new(0.5, 42, Duration::ZERO);
+        let expected = (faults(&chaos, one), faults(&chaos, two));
+        assert!(expected.0.iter().any(Option::is_some));
+        assert!(expected.0.iter().any(Option::is_none));
+
+        // Concurrent podcasts can send their requests in another order.
+        let chaos = Chaos::new(0.5, 42, Duration::ZERO);
+        let two_first = faults(&chaos, two);
+        assert_eq!((faults(&chaos, one), two_first), expected);
+
+        let chaos = Chaos::new(0.5, 43, Duration::ZERO);
+        assert_ne!(faults(&chaos, one), expected.0);
+    }
+}
@@ -0,0 +1,332 @@
+//! Chapters linked from episodes with `podcast:chapters`, written to mp3 files as ID3 `CHAP`
+//! frames along with a `CTOC` frame that lists them.
+//!
+//! Only the JSON format of the Podcasting 2.0 namespace is understood. A chapter without an
+//! `endTime` ends where the next one starts, and the last one where the episode ends, as far
+//! as `itunes:duration` tells.
+
+use crate::display::DownloadBar;
+use crate::net;
+use crate::net::Credentials;
+use id3::frame::Chapter;
+use id3::frame::Content;
+use id3::frame::ExtendedLink;
+use id3::frame::Frame;
+use id3::frame::TableOfContents;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// The MIME types of chapters in the JSON format.
+const JSON_TYPES: [&str; 2] = ["application/json+chapters", "application/json"];
+
+/// The element id of the `CTOC` frame.
+const TOC_ID: &str = "toc";
+
+/// Tells ID3 readers that a chapter is given by its times rather than by byte offsets.
+const NO_OFFSET: u32 = u32::MAX;
+
+/// Whether chapters with the MIME type from the `type` attribute are in the JSON format.
+pub fn is_json(mime: &str) -> bool {
+    let mime = mime.split(';').next().unwrap_or_default().trim();
+    JSON_TYPES
+        .iter()
+        .any(|json| json.eq_ignore_ascii_case(mime))
+}
+
+#[derive(Deserialize, Debug)]
+struct ChapterFile {
+    chapters: Vec<JsonChapter>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JsonChapter {
+    /// Seconds from the start of the episode.
+    start_time: f64,
+    end_time: Option<f64>,
+    title: Option<String>,
+    img: Option<String>,
+    url: Option<String>,
+    /// Chapters that are `false` here, like ads, are left out of the table of contents.
+    toc: Option<bool>,
+}
+
+/// The chapters of an episode, sorted by when they start.
+#[derive(Debug)]
+pub struct Chapters(Vec<JsonChapter>);
+
+impl Chapters {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let file: ChapterFile =
+            serde_json::from_str(json).map_err(|e| format!("invalid chapters: {}", e))?;
+
+        let is_time = |secs: f64| secs.is_finite() && secs >= 0.0;
+        let mut chapters = file.chapters;
+        if let Some(chapter) = chapters
+            .iter()
+            .find(|chapter| !is_time(chapter.start_time) || !chapter.end_time.is_none_or(is_time))
+        {
+            return Err(format!(
+                "invalid chapters: invalid time in chapter starting at {}",
+                chapter.start_time
+            ));
+        }
+
+        chapters.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+        Ok(Self(chapters))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The image url of each chapter, if it has one.
+    pub fn image_urls(&self) -> Vec<Option<&str>> {
+        self.0
+            .iter()
+            .map(|chapter| chapter.img.as_deref())
+            .collect()
+    }
+
+    /// A `CHAP` frame for every chapter, followed by the `CTOC` frame.
+    ///
+    /// `images` are the pictures of the chapters, in the same order as [`Self::image_urls`].
+    pub fn into_frames(self, duration: Option<Duration>, images: Vec<Option<Frame>>) -> Vec<Frame> {
+        let millis = |secs: f64| (secs * 1000.0).round().min(f64::from(u32::MAX - 1)) as u32;
+        let starts: Vec<u32> = self
+            .0
+            .iter()
+            .map(|chapter| millis(chapter.start_time))
+            .collect();
+        let episode_end = duration.map(|duration| millis(duration.as_secs_f64()));
+
+        let mut frames = vec![];
+        let mut toc = vec![];
+        let mut images = images.into_iter();
+        for (index, chapter) in self.0.into_iter().enumerate() {
+            let element_id = format!("chp{}", index);
+            let start_time = starts[index];
+            let end_time = chapter
+                .end_time
+                .map(millis)
+                .or(starts.get(index + 1).copied())
+                .or(episode_end)
+                .unwrap_or(start_time)
+                .max(start_time);
+
+            let mut sub_frames = vec![];
+            if let Some(title) = chapter.title {
+                sub_frames.push(Frame::text("TIT2", title));
+            }
+            if let Some(url) = chapter.url {
+                let link = ExtendedLink {
+                    description: String::new(),
+                    link: url,
+                };
+                sub_frames.push(Frame::with_content("WXXX", Content::ExtendedLink(link)));
+            }
+            if let Some(image) = images.next().flatten() {
+                sub_frames.push(image);
+            }
+
+            if chapter.toc != Some(false) {
+                toc.push(element_id.clone());
+            }
+
+            let chapter = Chapter {
+                element_id,
+                start_time,
+                end_time,
+                start_offset: NO_OFFSET,
+                end_offset: NO_OFFSET,
+                frames: sub_frames,
+            };
+            frames.push(Frame::with_content("CHAP", Content::Chapter(chapter)));
+        }
+
+        let toc = TableOfContents {
+            element_id: TOC_ID.to_string(),
+            top_level: true,
+            ordered: true,
+            elements: toc,
+            frames: vec![],
+        };
+        frames.push(Frame::with_content("CTOC", Content::TableOfContents(toc)));
+
+        frames
+    }
+}
+
+/// Downloads the chapters at the url.
+pub async fn fetch(
+    client: &reqwest::Client,
+    credentials: &Credentials,
+    url: &str,
+    ui: &DownloadBar,
+) -> Result<String, String> {
+    ui.log_debug(format!("downloading chapters: {}", net::redact_url(url)));
+    let response = credentials
+        .apply(client.get(url), url)
+        .send()
+        .await
+        .map_err(|e| format!("failed to download chapters: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!(
+            "failed to download chapters: server responded with {}",
+            status
+        ));
+    }
+
+    response
+        .text()
+        .await
+        .map_err(|e| format!("failed to download chapters: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::TagLike;
+
+    const CHAPTERS: &str = r#"{
+        "version": "1.2.0",
+        "chapters": [
+            { "startTime": 90.5, "title": "News", "url": "https://example.com/news" },
+            { "startTime": 0, "title": "Intro", "img": "https://example.com/intro.jpg" },
+            { "startTime": 300, "endTime": 320, "title": "Ad", "toc": false },
+            { "startTime": 600 }
+        ]
+    }"#;
+
+    fn chapter(frame: &Frame) -> &Chapter {
+        match frame.content() {
+            Content::Chapter(chapter) => chapter,
+            content => panic!("not a chapter: {:?}", content),
+        }
+    }
+
+    fn title(chapter: &Chapter) -> Option<&str> {
+        chapter
+            .frames
+            .iter()
+            .find(|frame| frame.id() == "TIT2")
+            .and_then(|frame| frame.content().text())
+    }
+
+    #[test]
+    fn json_types() {
+        assert!(is_json("application/json+chapters"));
+        assert!(is_json("application/JSON; charset=utf-8"));
+        assert!(!is_json("text/vtt"));
+        assert!(!is_json(""));
+    }
+
+    #[test]
+    fn sorted_by_start() {
+        let chapters = Chapters::parse(CHAPTERS).unwrap();
+        assert_eq!(chapters.len(), 4);
+        assert_eq!(
+            chapters.image_urls(),
+            vec![Some("https://example.com/intro.jpg"), None, None, None]
+        );
+    }
+
+    #[test]
+    fn invalid_chapters() {
+        let negative = r#"{"chapters": [{"startTime": -1}]}"#;
+        assert_eq!(
+            Chapters::parse(negative).unwrap_err(),
+            "invalid chapters: invalid time in chapter starting at -1"
+        );
+
+        let bad_end = r#"{"chapters": [{"startTime": 5, "endTime": -2}]}"#;
+        assert!(Chapters::parse(bad_end).is_err());
+
+        let err = Chapters::parse(r#"{"title": "no chapters"}"#).unwrap_err();
+        assert!(err.starts_with("invalid chapters: "), "{}", err);
+
+        assert!(Chapters::parse(r#"{"chapters": []}"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn chapter_frames() {
+        let chapters = Chapters::parse(CHAPTERS).unwrap();
+        let image = Frame::text("TIT3", "stands in for a picture");
+        let frames = chapters.into_frames(
+            Some(Duration::from_secs(1800)),
+            vec![Some(image.clone()), None, None, None],
+        );
+        assert_eq!(frames.len(), 5);
+
+        let intro = chapter(&frames[0]);
+        assert_eq!(intro.element_id, "chp0");
+        assert_eq!(title(intro), Some("Intro"));
+        assert_eq!((intro.start_time, intro.end_time), (0, 90_500));
+        assert_eq!(
+            (intro.start_offset, intro.end_offset),
+            (NO_OFFSET, NO_OFFSET)
+        );
+        assert!(intro.frames.contains(&image));
+
+        let news = chapter(&frames[1]);
+        assert_eq!((news.start_time, news.end_time), (90_500, 300_000));
+        let link = news
+            .frames
+            .iter()
+            .find(|frame| frame.id() == "WXXX")
+            .unwrap();
+        assert_eq!(
+            link.content()
+                .extended_link()
+                .map(|link| link.link.as_str()),
+            Some("https://example.com/news")
+        );
+
+        let ad = chapter(&frames[2]);
+        assert_eq!((ad.start_time, ad.end_time), (300_000, 320_000));
+
+        // The last chapter ends with the episode.
+        let last = chapter(&frames[3]);
+        assert_eq!((last.start_time, last.end_time), (600_000, 1_800_000));
+        assert!(last.frames.is_empty());
+
+        let Content::TableOfContents(toc) = frames[4].content() else {
+            panic!("no table of contents");
+        };
+        assert_eq!(toc.element_id, TOC_ID);
+        assert!(toc.top_level && toc.ordered);
+        assert_eq!(toc.elements, vec!["chp0", "chp1", "chp3"]);
+    }
+
+    #[test]
+    fn last_chapter_without_duration() {
+        let chapters = Chapters::parse(r#"{"chapters": [{"startTime": 60}]}"#).unwrap();
+        let frames = chapters.into_frames(None, vec![]);
+        let last = chapter(&frames[0]);
+        assert_eq!((last.start_time, last.end_time), (60_000, 60_000));
+    }
+
+    #[test]
+    fn written_to_mp3_tags() {
+        let chapters = Chapters::parse(CHAPTERS).unwrap();
+        let mut tag = id3::Tag::new();
+        for frame in chapters.into_frames(Some(Duration::from_secs(1800)), vec![]) {
+            tag.add_frame(frame);
+        }
+
+        let mut bytes = vec![];
+        tag.write_to(&mut bytes, id3::Version::Id3v24).unwrap();
+        let tag = id3::Tag::read_from2(std::io::Cursor::new(bytes)).unwrap();
+
+        let titles: Vec<Option<&str>> = tag.chapters().map(title).collect();
+        assert_eq!(titles, vec![Some("Intro"), Some("News"), Some("Ad"), None]);
+        let toc = tag.tables_of_contents().next().unwrap();
+        assert_eq!(toc.elements, vec!["chp0", "chp1", "chp3"]);
+    }
+}
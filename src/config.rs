@@ -1,14 +1,55 @@
+use crate::backlog::Schedule;
+use crate::cache::ImageLimits;
+use crate::cadence::Cadence;
+use crate::deadline;
 use crate::display::DownloadBar;
+use crate::display::TotalBar;
+use crate::download_tracker::DownloadedEpisodes;
+use crate::email::EmailConfig;
 use crate::episode;
+use crate::estimate::RunProgress;
+use crate::feed_delta::SeenItems;
+use crate::feed_hints::FeedHints;
+use crate::fingerprint::Fingerprints;
+use crate::format::SizeUnits;
+use crate::hook;
+use crate::hook::HookCommand;
+use crate::hook::HookRecords;
+use crate::hook::HookSandbox;
+use crate::hook::IoPriority;
+use crate::media;
+use crate::media::MediaPreference;
+use crate::mp3::DeferredTags;
+use crate::naming;
+use crate::net;
+use crate::net::Credentials;
+use crate::orphans;
+use crate::orphans::FeedRecord;
+use crate::paths;
 use crate::patterns::Evaluate;
 use crate::patterns::FullPattern;
 use crate::podcast::Podcast;
 use crate::podcast::RawPodcast;
+use crate::quota::DailyCap;
+use crate::read_only;
+use crate::reliability::LengthReliability;
+use crate::schedule::LastSync;
+use crate::schedule::SyncSchedule;
+use crate::snapshot;
+use crate::summary::SyncResult;
+use crate::throttle;
+use crate::transcript;
+use crate::transcript::TranscriptFormat;
 use crate::utils;
 use crate::utils::Unix;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use futures::future;
+use id3::frame::PictureType;
 use indicatif::MultiProgress;
 use regex::Regex;
+use regex::RegexBuilder;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION};
 use serde::de::IntoDeserializer;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -21,6 +62,7 @@ use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time;
+use tokio::sync::Semaphore;
 
 /// Represents a [`PodcastConfig`] value that is either enabled, disabled,
 /// or deferring to the global config. Only valid for optional values.
@@ -86,10 +128,90 @@ where
     }
 }
 
+/// Settings that are patterns, which are checked before `--set` changes them.
+const PATTERN_SETTINGS: [&str; 6] = [
+    "name_pattern",
+    "id_pattern",
+    "download_path",
+    "partial_path",
+    "tracker_path",
+    "symlink",
+];
+
+/// Splits `key=value` arguments, with `path` read as its newer name `download_path`.
+fn parse_settings(assignments: &[String]) -> Result<Vec<(String, String)>, String> {
+    assignments
+        .iter()
+        .map(|assignment| match assignment.split_once('=') {
+            Some(("path", value)) => Ok(("download_path".to_string(), value.to_string())),
+            Some((key, value)) if !key.trim().is_empty() => {
+                Ok((key.trim().to_string(), value.to_string()))
+            }
+            _ => Err(format!("expected key=value, found \"{}\"", assignment)),
+        })
+        .collect()
+}
+
+/// A date in the format `--un-acknowledge` writes `earliest_date` in.
+///
+/// It's in local time, since that's how dates without a timezone are read.
+fn earliest_date_str(unix: Unix) -> String {
+    chrono::DateTime::from_timestamp(unix.as_secs() as i64, 0)
+        .expect("failed to convert unix to datetime")
+        .with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// Checks that `earliest_date` and `latest_date` are dates, with `latest_date` after
+/// `earliest_date` if both are set.
+fn check_date_range(earliest: Option<String>, latest: Option<String>) -> Result<(), String> {
+    let earliest = earliest
+        .map(|date| check_date("earliest_date", &date))
+        .transpose()?;
+    let latest = latest
+        .map(|date| check_date("latest_date", &date))
+        .transpose()?;
+
+    match (earliest, latest) {
+        (Some(earliest), Some(latest)) if latest <= earliest => {
+            Err("'latest_date' must be after 'earliest_date'".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parses a date setting, the way [`DownloadMode::new`] reads it.
+///
+/// A date without a time means the start of that day, so that `earliest_date = "2023-01-01"`
+/// takes in every episode from that day.
+fn check_date(key: &str, date: &str) -> Result<Unix, String> {
+    utils::day_str_to_unix(date)
+        .map_err(|_| format!("invalid '{}': \"{}\" isn't a date", key, date))
+}
+
+/// Checks the pattern settings, so that a mistake in one of them is reported when the config
+/// is loaded rather than when the first episode is named.
+fn check_patterns<'a>(
+    patterns: impl IntoIterator<Item = (&'a str, Option<&'a str>)>,
+) -> Result<(), String> {
+    for (key, pattern) in patterns {
+        let Some(pattern) = pattern.filter(|pattern| pattern.trim() != ORIGINAL_FILENAME) else {
+            continue;
+        };
+        FullPattern::check(pattern).map_err(|e| format!("invalid '{}': {}", key, e))?;
+    }
+
+    Ok(())
+}
+
 fn default_name_pattern() -> String {
     "{pubdate::%Y-%m-%d} {rss::episode::title}".to_string()
 }
 
+/// Name pattern for keeping the file name the server serves the episode under.
+const ORIGINAL_FILENAME: &str = "{original_filename}";
+
 fn default_download_path() -> String {
     "{home}/talecast/{podname}".to_string()
 }
@@ -120,6 +242,178 @@ impl<'a> EvalData<'a> {
     }
 }
 
+/// Which image of the feed to embed for a given picture type.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageSource {
+    /// The image of the episode itself.
+    Episode,
+    /// The image of the podcast channel.
+    Podcast,
+}
+
+/// A post-processing step that runs on a freshly downloaded episode.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessStep {
+    /// Rename the file according to the name pattern.
+    Rename,
+    /// Symlink the file to the configured symlink directory.
+    Symlink,
+    /// Write id3 tags and pictures.
+    Tag,
+    /// Run the configured download hook.
+    Hook,
+}
+
+impl ProcessStep {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Rename => "rename",
+            Self::Symlink => "symlink",
+            Self::Tag => "tag",
+            Self::Hook => "hook",
+        }
+    }
+
+    fn default_pipeline() -> Vec<Self> {
+        vec![Self::Rename, Self::Symlink, Self::Tag, Self::Hook]
+    }
+}
+
+/// How the downloaded episodes of different podcasts are laid out on disk.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// Every podcast has its own directory.
+    #[default]
+    #[value(name = "per_podcast")]
+    PerPodcast,
+    /// All podcasts share a single directory, with the podcast name in the file names.
+    Flat,
+}
+
+impl Layout {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::PerPodcast => "per_podcast",
+            Self::Flat => "flat",
+        }
+    }
+
+    fn other(&self) -> Self {
+        match self {
+            Self::PerPodcast => Self::Flat,
+            Self::Flat => Self::PerPodcast,
+        }
+    }
+}
+
+/// What to do with episodes whose enclosure is a video.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoHandling {
+    /// Download them like any other episode.
+    #[default]
+    Download,
+    /// Don't download them.
+    Skip,
+    /// Download them into a `video` directory inside the download path.
+    Subdir,
+}
+
+/// Which enclosure to download when an item has several of the preferred type.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaSize {
+    /// The one the feed lists first.
+    #[default]
+    First,
+    Smallest,
+    Largest,
+}
+
+/// What file names start with in the flat layout.
+fn flat_prefix(name: &str) -> String {
+    format!("{} - ", name)
+}
+
+/// Checks that an explicitly configured pipeline only references steps that can run.
+fn validate_pipeline(
+    pipeline: &[ProcessStep],
+    symlink: bool,
+    download_hook: bool,
+) -> Result<(), String> {
+    for (idx, step) in pipeline.iter().enumerate() {
+        if pipeline[..idx].contains(step) {
+            return Err(format!(
+                "pipeline step '{}' is listed more than once",
+                step.name()
+            ));
+        }
+    }
+
+    let position = |step| pipeline.iter().position(|s| s == &step);
+
+    let Some(rename) = position(ProcessStep::Rename) else {
+        return Err("pipeline must include the 'rename' step".to_string());
+    };
+
+    if let Some(idx) = position(ProcessStep::Symlink) {
+        if !symlink {
+            return Err(
+                "pipeline includes 'symlink' but no symlink path is configured".to_string(),
+            );
+        }
+
+        if idx < rename {
+            return Err("pipeline step 'symlink' must come after 'rename'".to_string());
+        }
+    }
+
+    if position(ProcessStep::Hook).is_some() && !download_hook {
+        return Err("pipeline includes 'hook' but no download_hook is configured".to_string());
+    }
+
+    Ok(())
+}
+
+/// Adds the headers of a table from the config, where later ones override earlier ones.
+fn insert_headers<'a>(
+    headers: &mut HeaderMap,
+    table: impl IntoIterator<Item = (&'a String, &'a String)>,
+) {
+    for (name, value) in table {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => log::warn!("ignoring invalid header: {}", name),
+        }
+    }
+}
+
+fn parse_picture_type(s: &str) -> Option<PictureType> {
+    let ty = match s {
+        "cover_front" => PictureType::CoverFront,
+        "cover_back" => PictureType::CoverBack,
+        "icon" => PictureType::Icon,
+        "other_icon" => PictureType::OtherIcon,
+        "other" => PictureType::Other,
+        "leaflet" => PictureType::Leaflet,
+        "media" => PictureType::Media,
+        "illustration" => PictureType::Illustration,
+        "band_logo" => PictureType::BandLogo,
+        "publisher_logo" => PictureType::PublisherLogo,
+        _ => return None,
+    };
+
+    Some(ty)
+}
+
 /// Full configuration for a specific podcast-episode.
 ///
 /// Combines settings from [`GlobalConfig`] and [`PodcastConfig`].
@@ -128,13 +422,34 @@ impl<'a> EvalData<'a> {
 pub struct Config {
     pub url: String,
     pub name_pattern: String,
+    /// Name files after what the server calls them, see [`ORIGINAL_FILENAME`].
+    pub original_filename: bool,
     pub id_pattern: String,
     pub download_path: PathBuf,
+    /// Whether other podcasts may download into the same directory.
+    pub flat: bool,
     pub partial_path: Option<PathBuf>,
+    /// How many bytes of an episode are held in memory before waiting on the disk.
+    pub write_buffer: usize,
     pub tracker_path: PathBuf,
     pub symlink: Option<PathBuf>,
     pub id3_tags: HashMap<String, String>,
-    pub download_hook: Option<PathBuf>,
+    pub pictures: Vec<(PictureType, ImageSource)>,
+    pub image_limits: ImageLimits,
+    pub download_hook: Option<HookCommand>,
+    pub keep_hook_context: bool,
+    pub hook_timeout: Option<time::Duration>,
+    pub hook_sandbox: Option<HookSandbox>,
+    pub credentials: Credentials,
+    /// The formats to download transcripts in, most preferred first.
+    pub transcript_formats: Vec<TranscriptFormat>,
+    pub embed_chapters: bool,
+    /// Whether to embed the images of the chapters along with them.
+    pub chapter_images: bool,
+    /// Whether downloads must also match the enclosure length of the feed.
+    pub strict_size_check: bool,
+    pub pipeline: Vec<ProcessStep>,
+    pub video_handling: VideoHandling,
 }
 
 impl Config {
@@ -143,6 +458,35 @@ impl Config {
         podcast_config: &PodcastConfig,
         data: EvalData<'_>,
     ) -> Self {
+        let download_path = podcast_config.download_pattern(global_config);
+        let tracker_path = podcast_config.tracker_pattern(global_config);
+        let flat = podcast_config.layout(global_config) == Layout::Flat;
+        let video_handling = podcast_config.video_handling(global_config);
+        let hook_optional = podcast_config.hook_optional();
+        // A password that can't be read already failed fetching the feed.
+        let credentials = podcast_config.credentials().unwrap_or_default();
+        let transcript_formats = podcast_config.transcript_formats(global_config);
+        let embed_chapters = podcast_config.embed_chapters(global_config);
+        let chapter_images = podcast_config.chapter_images(global_config);
+        let strict_size_check = podcast_config.strict_size_check(global_config);
+        // A dry run shows where episodes would go without creating the directories, and
+        // so does a read-only run.
+        let dry_run = podcast_config.dry_run || read_only::is_enabled();
+        let eval_dir = |s: &str| match dry_run {
+            true => PathBuf::from(FullPattern::direct_eval(s, data)),
+            false => FullPattern::direct_eval_dir(s, data),
+        };
+        let eval_file = |s: &str| match dry_run {
+            true => PathBuf::from(FullPattern::direct_eval(s, data)),
+            false => FullPattern::direct_eval_file(s, data),
+        };
+
+        let download_path = match video_handling {
+            VideoHandling::Subdir if data.episode.is_video() => {
+                format!("{}/video", download_path.trim_end_matches('/'))
+            }
+            _ => download_path,
+        };
         let podcast_config = podcast_config.to_owned();
         let id3_tags = {
             let mut map = HashMap::with_capacity(
@@ -156,41 +500,66 @@ impl Config {
             for (key, val) in podcast_config.id3_tags.iter() {
                 map.insert(key.clone(), val.clone());
             }
+
+            // Values can be patterns, like `{podcast_title}`.
+            for val in map.values_mut() {
+                *val = FullPattern::direct_eval(val, data);
+            }
             map
         };
 
+        let pictures = {
+            let mut map = global_config.pictures.clone();
+            map.extend(podcast_config.pictures.clone());
+
+            let mut pictures = vec![];
+            for (key, source) in map {
+                let Some(picture_type) = parse_picture_type(&key) else {
+                    eprintln!("invalid picture type: \"{}\"", key);
+                    process::exit(1);
+                };
+                pictures.push((picture_type, source));
+            }
+            pictures
+        };
+
         let download_hook = podcast_config
             .download_hook
             .into_val(global_config.download_hook.as_ref());
+        let hook_configured = download_hook.is_some();
 
-        let download_path_str = podcast_config
-            .download_path
-            .unwrap_or_else(|| global_config.download_path.clone());
+        // A missing optional hook was reported once before syncing.
+        let download_hook = download_hook
+            .map(|hook| HookCommand::parse(&hook).resolved())
+            .filter(|hook| !hook_optional || hook.resolve().is_ok());
 
-        let download_path = FullPattern::direct_eval_dir(&download_path_str, data);
+        let download_path = eval_dir(&download_path);
 
-        let tracker_path = match podcast_config
-            .tracker_path
-            .into_val(global_config.tracker_path.as_ref())
-        {
-            Some(tracker_path) => tracker_path,
-            None => {
-                if download_path_str.ends_with('/') {
-                    download_path_str + ".downloaded"
-                } else {
-                    download_path_str + "/.downloaded"
-                }
-            }
+        let tracker_path = eval_file(&tracker_path);
+
+        let name_pattern = podcast_config
+            .name_pattern
+            .unwrap_or_else(|| global_config.name_pattern.clone());
+
+        // The original filename is only known after downloading, so the templated
+        // name is kept around as a fallback.
+        let original_filename = name_pattern.trim() == ORIGINAL_FILENAME;
+        let name_pattern = if !original_filename {
+            name_pattern
+        } else if global_config.name_pattern.trim() != ORIGINAL_FILENAME {
+            global_config.name_pattern.clone()
+        } else {
+            default_name_pattern()
         };
 
-        let tracker_path = FullPattern::direct_eval_file(&tracker_path, data);
+        // Keeps the episodes of different podcasts apart in a shared directory.
+        let name_pattern = if flat && !name_pattern.contains("{podname}") {
+            format!("{{podname}} - {}", name_pattern)
+        } else {
+            name_pattern
+        };
 
-        let name_pattern = FullPattern::from_str(
-            &podcast_config
-                .name_pattern
-                .unwrap_or_else(|| global_config.name_pattern.clone()),
-        )
-        .evaluate(data);
+        let name_pattern = FullPattern::from_str(&name_pattern).evaluate(data);
 
         let id_pattern = podcast_config
             .id_pattern
@@ -201,23 +570,56 @@ impl Config {
         let symlink = podcast_config
             .symlink
             .or(global_config.symlink.clone())
-            .map(|str| FullPattern::direct_eval_dir(str.as_ref(), data));
+            .map(|str| eval_dir(&str));
 
         let partial_path = podcast_config
             .partial_path
             .or(global_config.partial_path.clone())
-            .map(|str| FullPattern::direct_eval_dir(str.as_ref(), data));
+            .map(|str| eval_dir(&str));
+
+        let write_buffer = podcast_config
+            .write_buffer_kb
+            .or(global_config.write_buffer_kb)
+            .unwrap_or(256)
+            .max(1) as usize
+            * 1024;
+
+        let pipeline = match podcast_config.pipeline.or(global_config.pipeline.clone()) {
+            Some(pipeline) => {
+                if let Err(e) = validate_pipeline(&pipeline, symlink.is_some(), hook_configured) {
+                    eprintln!("invalid pipeline for '{}': {}", data.pod_name, e);
+                    process::exit(1);
+                }
+                pipeline
+            }
+            None => ProcessStep::default_pipeline(),
+        };
 
         Config {
             url: podcast_config.url.clone(),
             name_pattern,
+            original_filename,
             id_pattern,
             download_path,
+            flat,
             partial_path,
+            write_buffer,
             tracker_path,
             symlink,
             id3_tags: id3_tags.clone(),
+            pictures,
+            image_limits: global_config.image_limits(),
             download_hook: download_hook.clone(),
+            keep_hook_context: global_config.keep_hook_context(),
+            hook_timeout: global_config.hook_timeout(),
+            hook_sandbox: global_config.hook_sandbox(),
+            credentials,
+            transcript_formats,
+            embed_chapters,
+            chapter_images,
+            strict_size_check,
+            pipeline,
+            video_handling,
         }
     }
 }
@@ -288,6 +690,11 @@ impl IndicatifSettings {
         Self::default_podcast_fetch_template()
     }
 
+    /// For podcasts waiting for others to finish, with `max_concurrent_podcasts`.
+    pub fn waiting_template() -> String {
+        "   {msg}waiting...".to_string()
+    }
+
     pub fn download_template(&self) -> String {
         self.download_bar
             .clone()
@@ -336,19 +743,85 @@ pub struct GlobalConfig {
     id_pattern: String,
     max_days: Option<i64>,
     max_episodes: Option<i64>,
+    min_episodes_retained: Option<usize>,
+    max_per_day: Option<usize>,
+    keep_latest: Option<usize>,
     earliest_date: Option<String>,
+    latest_date: Option<String>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     id3_tags: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pictures: HashMap<String, ImageSource>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    feed_headers: HashMap<String, String>,
+    max_image_mb: Option<u64>,
+    image_timeout: Option<u64>,
+    connect_timeout: Option<u64>,
+    timeout: Option<u64>,
+    asset_negative_cache_days: Option<u64>,
+    cache_max_mb: Option<u64>,
+    save_funding_links: Option<bool>,
+    infer_episode_number: Option<bool>,
+    keep_description_html: Option<bool>,
+    layout: Option<Layout>,
+    video_handling: Option<VideoHandling>,
+    preferred_media: Option<Vec<String>>,
+    media_size: Option<MediaSize>,
     download_hook: Option<PathBuf>,
+    keep_hook_context: Option<bool>,
+    hook_timeout: Option<u64>,
+    hook_sandbox: Option<bool>,
+    hook_sandbox_env: Option<Vec<String>>,
+    hook_nice: Option<i32>,
+    hook_ionice: Option<String>,
+    hook_memory_limit_mb: Option<u64>,
+    rerun_failed_hooks: Option<bool>,
+    pipeline: Option<Vec<ProcessStep>>,
     tracker_path: Option<String>,
     #[serde(default, skip_serializing_if = "IndicatifSettings::is_default")]
     style: Arc<IndicatifSettings>,
     user_agent: Option<String>,
+    proxy: Option<String>,
     #[serde(default, skip_serializing_if = "SearchSettings::is_default")]
     search: SearchSettings,
     symlink: Option<String>,
+    default_root: Option<String>,
+    recheck_last_n: Option<usize>,
+    redownload_changed: Option<bool>,
+    change_tolerance: Option<f64>,
+    old_backups: Option<usize>,
+    download_retries: Option<usize>,
+    retry_backoff_secs: Option<u64>,
+    continue_on_error: Option<bool>,
+    write_buffer_kb: Option<u64>,
+    episode_concurrency: Option<usize>,
+    max_concurrent_podcasts: Option<usize>,
+    max_concurrent_downloads: Option<usize>,
+    adaptive_concurrency: Option<bool>,
+    download_transcripts: Option<bool>,
+    transcript_formats: Option<Vec<TranscriptFormat>>,
+    embed_chapters: Option<bool>,
+    chapter_images: Option<bool>,
+    strict_size_check: Option<bool>,
+    archive_feed: Option<bool>,
+    max_feed_snapshots: Option<usize>,
+    trash_dir: Option<String>,
+    trash_retention_days: Option<u64>,
+    auto_disambiguate: Option<bool>,
+    size_units: Option<SizeUnits>,
+    changelog_path: Option<String>,
+    #[serde(default, skip_serializing_if = "EmailConfig::is_default")]
+    email: EmailConfig,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    roots: HashMap<String, String>,
     #[serde(default, skip_serializing_if = "LogConfig::is_default")]
     log: Arc<LogConfig>,
+    /// Set for commands that should fetch images even if they were missing last time.
+    #[serde(skip)]
+    bypass_negative_cache: bool,
+    /// Set by `--force-refresh` to download every feed in full.
+    #[serde(skip)]
+    bypass_feed_cache: bool,
 }
 
 impl GlobalConfig {
@@ -364,7 +837,9 @@ impl GlobalConfig {
         let path = Self::default_path();
         if !path.exists() {
             let config = Self::default();
-            config.save();
+            if !read_only::is_enabled() {
+                config.save();
+            }
             return config;
         }
 
@@ -383,8 +858,11 @@ impl GlobalConfig {
                 process::exit(1);
             }
         };
+        config.check();
 
-        config.save();
+        if !read_only::is_enabled() {
+            config.save();
+        }
         config
     }
 
@@ -404,12 +882,39 @@ impl GlobalConfig {
             }
         };
 
-        match toml::from_str(&str) {
+        let config: Self = match toml::from_str(&str) {
             Ok(config) => config,
             Err(e) => {
                 eprintln!("unable to parse given config file: {:?}\n{:?}", path, e);
                 process::exit(1);
             }
+        };
+        config.check();
+        config
+    }
+
+    fn check(&self) {
+        let patterns = [
+            ("name_pattern", Some(self.name_pattern.as_str())),
+            ("id_pattern", Some(self.id_pattern.as_str())),
+            ("download_path", Some(self.download_path.as_str())),
+            ("partial_path", self.partial_path.as_deref()),
+            ("tracker_path", self.tracker_path.as_deref()),
+            ("symlink", self.symlink.as_deref()),
+        ];
+        let id3_tags = self
+            .id3_tags
+            .values()
+            .map(|val| ("id3_tags", Some(val.as_str())));
+
+        let checked = check_date_range(self.earliest_date.clone(), self.latest_date.clone())
+            .and_then(|_| check_patterns(patterns.into_iter().chain(id3_tags)))
+            .and_then(|_| {
+                media::check_preferred_media(self.preferred_media.as_deref().unwrap_or_default())
+            });
+        if let Err(e) = checked {
+            eprintln!("invalid config file: {}", e);
+            process::exit(1);
         }
     }
 
@@ -425,6 +930,7 @@ impl GlobalConfig {
     pub fn save(&self) {
         let path = Self::default_path();
         let str = toml::to_string(self).unwrap();
+        read_only::check(&path);
         let mut f = std::fs::File::create(&path).expect("unable to create config file");
         f.write_all(str.as_bytes()).unwrap();
     }
@@ -438,16 +944,188 @@ impl GlobalConfig {
     }
 
     pub fn default_path() -> PathBuf {
-        utils::config_dir().join("config.toml")
+        paths::config_dir().join("config.toml")
     }
 
     pub fn max_search_results(&self) -> usize {
         self.search.max_results.unwrap_or(9)
     }
 
+    /// The directory of the named download root.
+    ///
+    /// Referencing a root that isn't defined in the config is an error, which is reported
+    /// when the config is loaded.
+    pub fn root_dir(&self, root: &str) -> Result<&str, String> {
+        self.roots.get(root).map(String::as_str).ok_or_else(|| {
+            let names: Vec<&str> = self.roots().into_iter().map(|(name, _)| name).collect();
+            format!(
+                "undefined download root: '{}', defined roots: {:?}",
+                root, names
+            )
+        })
+    }
+
+    /// The download roots with their directories, sorted by name.
+    pub fn roots(&self) -> Vec<(&str, &str)> {
+        let mut roots: Vec<(&str, &str)> = self
+            .roots
+            .iter()
+            .map(|(name, dir)| (name.as_str(), dir.as_str()))
+            .collect();
+        roots.sort();
+        roots
+    }
+
+    pub fn image_limits(&self) -> ImageLimits {
+        let negative_days = self.asset_negative_cache_days.unwrap_or(7);
+        ImageLimits {
+            max_size: self.max_image_mb.unwrap_or(20) * 1024 * 1024,
+            timeout: time::Duration::from_secs(self.image_timeout.unwrap_or(60)),
+            negative_ttl: (!self.bypass_negative_cache && negative_days > 0)
+                .then(|| time::Duration::from_secs(negative_days * 24 * 60 * 60)),
+        }
+    }
+
+    /// Fetches images again even if the server said they were missing not long ago.
+    pub fn bypass_negative_cache(self) -> Self {
+        Self {
+            bypass_negative_cache: true,
+            ..self
+        }
+    }
+
+    /// Downloads feeds in full even if the server says they haven't changed.
+    pub fn bypass_feed_cache(self) -> Self {
+        Self {
+            bypass_feed_cache: true,
+            ..self
+        }
+    }
+
+    pub fn use_feed_cache(&self) -> bool {
+        !self.bypass_feed_cache
+    }
+
+    /// Max size in bytes of the cache, if it's limited.
+    pub fn cache_max_size(&self) -> Option<u64> {
+        self.cache_max_mb.map(|mb| mb * 1024 * 1024)
+    }
+
+    /// Where deleted files of podcasts on the download root `root` are moved to, if they
+    /// should be kept around.
+    ///
+    /// With `{root}` in `trash_dir`, each root has a trash of its own, so that deleting a
+    /// file moves it within its drive. For podcasts that aren't on a root, `{root}` is the
+    /// download path without its trailing `{podname}`.
+    pub fn trash_dir(&self, root: Option<&str>) -> Option<PathBuf> {
+        let pattern = self.trash_dir.as_ref()?;
+        let root_dir = match root.and_then(|root| self.root_dir(root).ok()) {
+            Some(dir) => dir.to_string(),
+            None => PodcastConfig::shared_download_pattern(&self.download_path)
+                .unwrap_or_else(|| self.download_path.clone()),
+        };
+        let pattern = pattern.replace("{root}", &root_dir);
+        Some(PodcastConfig::podcast_level_eval(&pattern, ""))
+    }
+
+    /// Every trash directory, one per download root if `trash_dir` uses `{root}`.
+    pub fn trash_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = self
+            .roots()
+            .into_iter()
+            .map(|(root, _)| Some(root))
+            .chain([None])
+            .filter_map(|root| self.trash_dir(root))
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    }
+
+    /// The Markdown file new episodes are logged to after each sync, if enabled.
+    pub fn changelog_path(&self) -> Option<PathBuf> {
+        let pattern = self.changelog_path.as_ref()?;
+        Some(PodcastConfig::podcast_level_eval(pattern, ""))
+    }
+
+    pub fn email(&self) -> &EmailConfig {
+        &self.email
+    }
+
+    /// How many podcasts are synced at once, unless there's no limit.
+    pub fn max_concurrent_podcasts(&self) -> Option<usize> {
+        self.max_concurrent_podcasts.filter(|max| *max > 0)
+    }
+
+    /// How many episodes are downloaded at once across all podcasts, unless there's no limit.
+    pub fn max_concurrent_downloads(&self) -> Option<usize> {
+        self.max_concurrent_downloads.filter(|max| *max > 0)
+    }
+
+    /// Whether fewer episodes are downloaded at once while downloads keep failing.
+    pub fn adaptive_concurrency(&self) -> bool {
+        self.adaptive_concurrency.unwrap_or(false)
+    }
+
+    /// How long files are kept in the trash, if they aren't kept forever.
+    pub fn trash_retention(&self) -> Option<time::Duration> {
+        self.trash_retention_days
+            .map(|days| time::Duration::from_secs(days * 24 * 60 * 60))
+    }
+
     pub fn max_line_width(&self) -> usize {
         self.search.line_width.unwrap_or(79)
     }
+
+    /// Whether to keep the context files of download hooks after they finish, for debugging.
+    pub fn keep_hook_context(&self) -> bool {
+        self.keep_hook_context.unwrap_or(false)
+    }
+
+    /// How long a download hook may run before it's stopped, if it's limited.
+    pub fn hook_timeout(&self) -> Option<time::Duration> {
+        self.hook_timeout.map(time::Duration::from_secs)
+    }
+
+    /// How download hooks are limited, if they run in a sandbox.
+    pub fn hook_sandbox(&self) -> Option<HookSandbox> {
+        if !self.hook_sandbox.unwrap_or(false) {
+            return None;
+        }
+
+        let ionice = self.hook_ionice.as_deref().map(|ionice| {
+            IoPriority::parse(ionice).unwrap_or_else(|e| {
+                eprintln!("invalid hook_ionice: {}", e);
+                process::exit(1);
+            })
+        });
+
+        Some(HookSandbox {
+            env: self.hook_sandbox_env.clone().unwrap_or_default(),
+            nice: self.hook_nice,
+            ionice,
+            memory_limit: self.hook_memory_limit_mb.map(|mb| mb * 1024 * 1024),
+        })
+    }
+
+    /// How long to wait for a connection to a server, unless set to 0.
+    pub fn connect_timeout(&self) -> Option<time::Duration> {
+        Some(self.connect_timeout.unwrap_or(10))
+            .filter(|secs| *secs > 0)
+            .map(time::Duration::from_secs)
+    }
+
+    /// How long a request may take from start to finish, unless set to 0.
+    pub fn timeout(&self) -> Option<time::Duration> {
+        Some(self.timeout.unwrap_or(300))
+            .filter(|secs| *secs > 0)
+            .map(time::Duration::from_secs)
+    }
+
+    /// Whether sizes are shown in binary or decimal units.
+    pub fn size_units(&self) -> SizeUnits {
+        self.size_units.unwrap_or_default()
+    }
 }
 
 impl Default for GlobalConfig {
@@ -458,18 +1136,206 @@ impl Default for GlobalConfig {
             id_pattern: default_id_pattern(),
             max_days: None,
             max_episodes: Some(10),
+            min_episodes_retained: None,
+            max_per_day: None,
+            keep_latest: None,
             earliest_date: None,
+            latest_date: None,
             id3_tags: Default::default(),
+            pictures: Default::default(),
+            feed_headers: Default::default(),
+            max_image_mb: None,
+            image_timeout: None,
+            connect_timeout: None,
+            timeout: None,
+            asset_negative_cache_days: None,
+            cache_max_mb: None,
+            save_funding_links: None,
+            infer_episode_number: None,
+            keep_description_html: None,
+            layout: None,
+            video_handling: None,
+            preferred_media: None,
+            media_size: None,
             download_hook: None,
+            keep_hook_context: None,
+            hook_timeout: None,
+            hook_sandbox: None,
+            hook_sandbox_env: None,
+            hook_nice: None,
+            hook_ionice: None,
+            hook_memory_limit_mb: None,
+            rerun_failed_hooks: None,
+            pipeline: None,
             tracker_path: None,
             style: Default::default(),
             search: Default::default(),
             log: Default::default(),
             symlink: None,
             user_agent: None,
+            proxy: None,
             partial_path: None,
+            default_root: None,
+            recheck_last_n: None,
+            redownload_changed: None,
+            change_tolerance: None,
+            old_backups: None,
+            download_retries: None,
+            retry_backoff_secs: None,
+            continue_on_error: None,
+            write_buffer_kb: None,
+            episode_concurrency: None,
+            max_concurrent_podcasts: None,
+            max_concurrent_downloads: None,
+            adaptive_concurrency: None,
+            download_transcripts: None,
+            transcript_formats: None,
+            embed_chapters: None,
+            chapter_images: None,
+            strict_size_check: None,
+            archive_feed: None,
+            max_feed_snapshots: None,
+            changelog_path: None,
+            email: Default::default(),
+            trash_dir: None,
+            trash_retention_days: None,
+            auto_disambiguate: None,
+            size_units: None,
+            roots: Default::default(),
+            bypass_negative_cache: false,
+            bypass_feed_cache: false,
+        }
+    }
+}
+
+/// Settings for detecting downloaded episodes that were replaced on the server.
+#[derive(Debug, Clone, Default)]
+pub struct RecheckSettings {
+    /// How many of the most recent downloaded episodes to recheck. 0 means off.
+    pub last_n: usize,
+    /// Re-download episodes that changed.
+    pub redownload: bool,
+    /// Fraction of the original length that the remote length may differ by.
+    pub tolerance: f64,
+    /// How many `.old` backups of a re-downloaded episode to keep.
+    pub backups: usize,
+}
+
+impl RecheckSettings {
+    pub fn new(global_config: &GlobalConfig, podcast_config: &PodcastConfig) -> Self {
+        Self {
+            last_n: podcast_config
+                .recheck_last_n
+                .or(global_config.recheck_last_n)
+                .unwrap_or(0),
+            redownload: podcast_config
+                .redownload_changed
+                .or(global_config.redownload_changed)
+                .unwrap_or(false),
+            tolerance: podcast_config
+                .change_tolerance
+                .or(global_config.change_tolerance)
+                .unwrap_or(0.01),
+            backups: podcast_config
+                .old_backups
+                .or(global_config.old_backups)
+                .unwrap_or(1),
+        }
+    }
+}
+
+/// Settings for what happens when an episode fails to download.
+#[derive(Debug, Clone, Default)]
+pub struct RetrySettings {
+    /// How many more times a failed download is attempted.
+    pub retries: usize,
+    /// How long to wait before the first retry, doubled for every one after it.
+    pub backoff: time::Duration,
+    /// Move on to the next episode instead of giving up on the podcast.
+    pub continue_on_error: bool,
+}
+
+impl RetrySettings {
+    pub fn new(global_config: &GlobalConfig, podcast_config: &PodcastConfig) -> Self {
+        Self {
+            retries: podcast_config
+                .download_retries
+                .or(global_config.download_retries)
+                .unwrap_or(3),
+            backoff: time::Duration::from_secs(
+                podcast_config
+                    .retry_backoff_secs
+                    .or(global_config.retry_backoff_secs)
+                    .unwrap_or(5),
+            ),
+            continue_on_error: podcast_config
+                .continue_on_error
+                .or(global_config.continue_on_error)
+                .unwrap_or(false),
         }
     }
+
+    /// How long to wait before the given retry, counting from 1.
+    ///
+    /// Up to a quarter is added at random, so that downloads from the same server that failed
+    /// together don't all retry at the same moment.
+    pub fn delay(&self, retry: usize) -> time::Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1) as u32);
+        let delay = self.backoff.saturating_mul(factor);
+        let jitter = f64::from(utils::current_unix().subsec_nanos() % 1000) / 4000.0;
+        delay.mul_f64(1.0 + jitter)
+    }
+}
+
+/// Which episodes of a podcast are considered at all, by `title_pattern` and
+/// `title_exclude_pattern`.
+#[derive(Debug, Clone, Default)]
+pub struct TitleFilter {
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+}
+
+impl TitleFilter {
+    /// Compiles the patterns of the podcast, failing with which one is invalid.
+    pub fn new(podcast_config: &PodcastConfig) -> Result<Self, String> {
+        let case_insensitive = !podcast_config.title_pattern_case_sensitive.unwrap_or(false);
+        let compile = |key: &str, pattern: Option<&str>| {
+            pattern
+                .map(|pattern| {
+                    RegexBuilder::new(pattern)
+                        .case_insensitive(case_insensitive)
+                        .build()
+                        .map_err(|e| Self::error(key, pattern, &e))
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            include: compile("title_pattern", podcast_config.title_pattern.as_deref())?,
+            exclude: compile(
+                "title_exclude_pattern",
+                podcast_config.title_exclude_pattern.as_deref(),
+            )?,
+        })
+    }
+
+    /// The error for an invalid pattern, on one line.
+    fn error(key: &str, pattern: &str, error: &regex::Error) -> String {
+        // A syntax error points at where the problem is on the lines before the last.
+        let error = error.to_string();
+        let reason = error.lines().last().unwrap_or_default();
+        format!(
+            "invalid {} {:?}: {}",
+            key,
+            pattern,
+            reason.trim_start_matches("error: ")
+        )
+    }
+
+    pub fn matches(&self, title: &str) -> bool {
+        self.include.as_ref().is_none_or(|re| re.is_match(title))
+            && !self.exclude.as_ref().is_some_and(|re| re.is_match(title))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -477,21 +1343,32 @@ pub enum DownloadMode {
     Standard {
         max_time: Option<Unix>,
         earliest_date: Option<Unix>,
+        /// Episodes published from this date on are never wanted.
+        latest_date: Option<Unix>,
         max_episodes: Option<i64>,
+        /// The newest episodes that are wanted regardless of `max_time` and `earliest_date`.
+        min_episodes: Option<usize>,
+        /// Only the newest this many episodes are kept, so older ones are never wanted.
+        keep_latest: Option<usize>,
     },
     Backlog {
-        start: Unix,
-        interval: Unix,
+        schedule: Schedule,
     },
 }
 
 impl DownloadMode {
-    pub fn new(global_config: &GlobalConfig, podcast_config: &PodcastConfig) -> Self {
+    pub fn new(
+        global_config: &GlobalConfig,
+        podcast_config: &PodcastConfig,
+        tracker_path: &Path,
+    ) -> Self {
+        // A backlog setting without the other one, or along with settings of the standard
+        // mode, is rejected when the config is loaded.
         match (
             podcast_config.backlog_start.clone(),
-            podcast_config.backlog_interval.clone(),
+            podcast_config.backlog_interval,
         ) {
-            (None, None) => DownloadMode::Standard {
+            (None, _) | (_, None) => DownloadMode::Standard {
                 max_time: podcast_config
                     .max_days
                     .into_val(global_config.max_days.as_ref())
@@ -499,50 +1376,39 @@ impl DownloadMode {
                 max_episodes: podcast_config
                     .max_episodes
                     .into_val(global_config.max_episodes.as_ref()),
+                min_episodes: podcast_config
+                    .min_episodes_retained
+                    .or(global_config.min_episodes_retained),
+                keep_latest: podcast_config.keep_latest(global_config),
                 earliest_date: {
                     podcast_config
                         .earliest_date
                         .clone()
                         .into_val(global_config.earliest_date.as_ref())
                         .map(|date| {
-                            utils::date_str_to_unix(&date)
+                            utils::day_str_to_unix(&date)
                                 .expect("failed to parse earliest_date string")
                         })
                 },
+                latest_date: {
+                    podcast_config
+                        .latest_date
+                        .clone()
+                        .into_val(global_config.latest_date.as_ref())
+                        .map(|date| {
+                            utils::day_str_to_unix(&date)
+                                .expect("failed to parse latest_date string")
+                        })
+                },
             },
-            (Some(_), None) => {
-                eprintln!("missing backlog_interval");
-                std::process::exit(1);
-            }
-            (None, Some(_)) => {
-                eprintln!("missing backlog_start");
-                std::process::exit(1);
-            }
             (Some(start), Some(interval)) => {
-                if podcast_config.max_days.is_enabled() {
-                    eprintln!("'max_days' not compatible with backlog mode.");
-                    std::process::exit(1);
-                }
-
-                if podcast_config.max_episodes.is_enabled() {
-                    eprintln!("'max_episodes' not compatible with backlog mode.");
-                    eprintln!("If you want to limit the amount of episodes to download, consider changing the 'backlog_start' setting.");
-                    std::process::exit(1);
-                }
-
-                if podcast_config.earliest_date.is_enabled() {
-                    eprintln!("'earliest_date' not compatible with backlog mode.");
-                    std::process::exit(1);
-                }
-
-                let Ok(start) = dateparser::parse(&start) else {
-                    eprintln!("invalid backlog_start format.");
-                    std::process::exit(1);
-                };
+                let start =
+                    dateparser::parse(&start).expect("failed to parse backlog_start string");
+                let start = Unix::from_secs(start.timestamp() as u64);
+                let interval = Unix::from_secs(interval as u64 * 86400);
 
                 DownloadMode::Backlog {
-                    start: std::time::Duration::from_secs(start.timestamp() as u64),
-                    interval: Unix::from_secs(interval as u64 * 86400),
+                    schedule: Schedule::load(tracker_path, start, interval),
                 }
             }
         }
@@ -554,77 +1420,213 @@ impl Default for DownloadMode {
         Self::Standard {
             max_time: None,
             earliest_date: None,
+            latest_date: None,
             max_episodes: None,
+            min_episodes: None,
+            keep_latest: None,
         }
     }
 }
 
-fn init_reqwest_client(config: &GlobalConfig) -> Arc<reqwest::Client> {
-    reqwest::Client::builder()
-        .user_agent(&config.user_agent())
+pub fn init_reqwest_client(config: &GlobalConfig) -> Arc<reqwest::Client> {
+    client_builder(config)
         .build()
         .map(Arc::new)
         .expect("error: failed to instantiate reqwest client")
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct PodcastConfigs(HashMap<String, PodcastConfig>);
+/// A client builder with the user agent, timeouts and proxy from the config.
+///
+/// Without a configured proxy, reqwest picks one up from `HTTP_PROXY`, `HTTPS_PROXY` and
+/// `ALL_PROXY`.
+pub fn client_builder(config: &GlobalConfig) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder().user_agent(config.user_agent());
+    if let Some(timeout) = config.connect_timeout() {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = config.timeout() {
+        builder = builder.timeout(timeout);
+    }
 
-impl PodcastConfigs {
-    pub async fn sync(self, global_config: GlobalConfig, log_file: &Path) -> Vec<PathBuf> {
-        eprintln!("syncing {} podcasts", self.len());
-        log::info!("syncing podcasts..");
+    let Some(url) = &config.proxy else {
+        return builder;
+    };
 
-        let mp = MultiProgress::new();
-        let global_config = Arc::new(global_config);
-        let client = init_reqwest_client(&global_config);
+    match reqwest::Proxy::all(url) {
+        Ok(proxy) => builder.proxy(proxy),
+        Err(e) if url.starts_with("socks") => {
+            eprintln!(
+                "invalid proxy: {}: socks proxies aren't supported by this build, use an http proxy",
+                e
+            );
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("invalid proxy: {}", e);
+            process::exit(1);
+        }
+    }
+}
 
-        let Some(longest_name) = self.longest_name() else {
-            return vec![];
-        };
+/// The state of `podcasts.toml` at some point, to tell if it has been changed since.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigStamp {
+    modified: Option<time::SystemTime>,
+    hash: Option<u64>,
+}
 
-        let error_occured = Arc::new(AtomicBool::new(false));
+impl ConfigStamp {
+    pub fn take() -> Self {
+        Self::of(&PodcastConfigs::path())
+    }
 
-        let futures = self
+    fn of(path: &Path) -> Self {
+        use std::hash::Hasher;
+
+        let modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        let hash = fs::read(path).ok().map(|content| {
+            let mut hasher = fnv::FnvHasher::default();
+            hasher.write(&content);
+            hasher.finish()
+        });
+
+        Self { modified, hash }
+    }
+
+    /// Whether the file is unchanged since the stamp was taken.
+    ///
+    /// The content is compared as well, since the modification time may be too coarse
+    /// to notice a quick edit.
+    fn is_current(&self, path: &Path) -> bool {
+        *self == Self::of(path)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PodcastConfigs(HashMap<String, PodcastConfig>);
+
+impl PodcastConfigs {
+    pub async fn sync(self, global_config: GlobalConfig, log_file: &Path) -> Vec<SyncResult> {
+        eprintln!("syncing {} podcasts", self.len());
+        log::info!("syncing podcasts..");
+
+        let mp = MultiProgress::new();
+        let global_config = Arc::new(global_config);
+        let client = init_reqwest_client(&global_config);
+
+        let Some(longest_name) = self.longest_name() else {
+            return vec![];
+        };
+
+        let error_occured = Arc::new(AtomicBool::new(false));
+        let run = Arc::new(RunProgress::new());
+        throttle::init(
+            global_config.max_concurrent_downloads(),
+            global_config.adaptive_concurrency(),
+        );
+        let max_podcasts = global_config.max_concurrent_podcasts();
+        let permits = Arc::new(Semaphore::new(
+            max_podcasts.unwrap_or(Semaphore::MAX_PERMITS),
+        ));
+        let total_bar = TotalBar::new(&global_config.style(), &mp, Arc::clone(&run));
+
+        let (names, futures): (Vec<String>, Vec<_>) = self
             .into_inner()
             .into_iter()
             .map(|(name, config)| {
                 let client = Arc::clone(&client);
                 let settings = global_config.style();
-                let mut ui = DownloadBar::new(name.clone(), settings, &mp, longest_name);
+                let mut ui = DownloadBar::new(name.clone(), settings, &mp, longest_name)
+                    .with_run_progress(Arc::clone(&run));
                 let global_config = Arc::clone(&global_config);
                 let val = error_occured.clone();
+                let permits = Arc::clone(&permits);
+
+                let task_name = name.clone();
+                let task = tokio::task::spawn(async move {
+                    // Held until the podcast is done, downloads and hooks included.
+                    let _permit = match Arc::clone(&permits).try_acquire_owned() {
+                        Ok(permit) => permit,
+                        Err(_) => {
+                            ui.waiting();
+                            permits
+                                .acquire_owned()
+                                .await
+                                .expect("semaphore is never closed")
+                        }
+                    };
+
+                    let url = config.url.clone();
+                    let started = chrono::Local::now();
+                    let tracker_path = config.tracker_path(&name, &global_config);
+                    if deadline::passed() {
+                        ui.log_info("max runtime reached, not fetching the feed");
+                        let mut result = SyncResult::not_fetched(name);
+                        result.feed_url = Some(net::redact_url(&url));
+                        return result;
+                    }
 
-                tokio::task::spawn(async move {
-                    match Podcast::new(name, config, &global_config, client, &ui).await {
-                        Ok(podcast) => podcast.sync(&mut ui).await,
+                    let podcast =
+                        Podcast::unless_settled(name.clone(), config, &global_config, client, &ui)
+                            .await;
+                    let mut result = match podcast {
+                        Ok(Some(podcast)) => {
+                            let result = podcast.sync(&mut ui).await;
+                            LastSync::save(&tracker_path, started);
+                            result
+                        }
+                        Ok(None) => {
+                            ui.up_to_date();
+                            LastSync::save(&tracker_path, started);
+                            SyncResult::unchanged(name)
+                        }
                         Err(e) => {
-                            ui.error(&e);
+                            ui.log_debug(format!(
+                                "reproduce with: {}",
+                                net::curl_command(&url, &global_config.user_agent(), "feed.xml")
+                            ));
+                            ui.error_with_url(&e, &url);
                             val.store(true, Ordering::SeqCst);
-                            vec![]
+                            SyncResult::failed(name, e)
                         }
-                    }
-                })
+                    };
+
+                    result.feed_url = Some(net::redact_url(&url));
+                    result.timings = ui.timings();
+                    result
+                });
+                (task_name, task)
             })
-            .collect::<Vec<_>>();
+            .unzip();
 
-        let paths: Vec<PathBuf> = future::join_all(futures)
+        // A podcast whose sync panicked fails on its own, without the rest of the sync.
+        let results: Vec<SyncResult> = future::join_all(futures)
             .await
             .into_iter()
-            .filter_map(Result::ok)
-            .flatten()
+            .zip(names)
+            .map(|(res, name)| {
+                res.unwrap_or_else(|e| {
+                    log::error!("{}: sync crashed: {}", name, e);
+                    error_occured.store(true, Ordering::SeqCst);
+                    SyncResult::failed(name, format!("sync crashed: {}", e))
+                })
+            })
             .collect();
 
+        total_bar.finish();
+        run.save_history();
+        hook::clean_up_contexts();
+
         if let Some(p) = global_config.log().path() {
             if true || error_occured.load(Ordering::SeqCst) {
-                utils::create_dir(p);
+                paths::create_dir(p);
                 let log_name = log_file.file_name().unwrap();
                 let new_path = p.join(log_name);
                 fs::rename(log_file, new_path).unwrap();
             }
         }
 
-        paths
+        results
     }
 
     pub fn load() -> Self {
@@ -633,12 +1635,63 @@ impl PodcastConfigs {
             process::exit(1);
         };
 
-        match toml::from_str(&config_str) {
+        let podcasts = match toml::from_str(&config_str) {
             Ok(s) => Self(s),
             Err(e) => {
                 eprintln!("failed to deserialize podcasts.toml file\n{:?}", e);
                 process::exit(1);
             }
+        };
+
+        for (name, config) in &podcasts.0 {
+            let earliest = config.earliest_date.clone().into_val(None);
+            let latest = config.latest_date.clone().into_val(None);
+            let tracker_path = match &config.tracker_path {
+                ConfigOption::Enabled(path) => Some(path.as_str()),
+                _ => None,
+            };
+            let patterns = [
+                ("name_pattern", config.name_pattern.as_deref()),
+                ("id_pattern", config.id_pattern.as_deref()),
+                ("download_path", config.download_path.as_deref()),
+                ("partial_path", config.partial_path.as_deref()),
+                ("tracker_path", tracker_path),
+                ("symlink", config.symlink.as_deref()),
+            ];
+            let id3_tags = config
+                .id3_tags
+                .values()
+                .map(|val| ("id3_tags", Some(val.as_str())));
+
+            let checked = check_date_range(earliest, latest)
+                .and_then(|_| check_patterns(patterns.into_iter().chain(id3_tags)))
+                .and_then(|_| {
+                    media::check_preferred_media(
+                        config.preferred_media.as_deref().unwrap_or_default(),
+                    )
+                })
+                .and_then(|_| config.check_download_mode());
+            if let Err(e) = checked {
+                eprintln!("invalid settings for '{}': {}", name, e);
+                process::exit(1);
+            }
+        }
+
+        podcasts
+    }
+
+    /// Checks the paths of every podcast that depend on the global config, exiting if one
+    /// of them is on an undefined download root or can't be found without fetching the
+    /// feed.
+    pub fn check_paths(global_config: &GlobalConfig) {
+        for (name, config) in &Self::load().0 {
+            let checked = config
+                .check_root(global_config)
+                .and_then(|_| config.check_tracker_pattern(global_config));
+            if let Err(e) = checked {
+                eprintln!("invalid settings for '{}': {}", name, e);
+                process::exit(1);
+            }
         }
     }
 
@@ -655,226 +1708,1694 @@ impl PodcastConfigs {
         self
     }
 
-    pub fn assert_not_empty(self) -> Self {
-        if self.is_empty() {
-            eprintln!("No podcasts configured!");
-            eprintln!("You can add podcasts with the following methods:\n");
-            eprintln!("* \"{} --search <name of podcast>\"", crate::APPNAME);
-            eprintln!(
-                "* \"{} --add <feed url>  <name of podcast>\"",
-                crate::APPNAME
-            );
-            eprintln!(
-                "*  Manually configuring the {:?} file.",
-                &PodcastConfigs::path()
-            );
-            process::exit(1);
-        }
+    /// Removes the podcasts that are paused.
+    pub fn skip_paused(mut self) -> Self {
+        self.0.retain(|name, config| {
+            if config.paused_until(name).is_none() {
+                return true;
+            }
+
+            let date = config.paused_until.as_deref().unwrap_or_default();
+            eprintln!("skipping '{}': paused until {}", name, date);
+            false
+        });
 
         self
     }
 
-    pub fn longest_name(&self) -> Option<usize> {
-        self.0.iter().map(|(name, _)| name.chars().count()).max()
+    /// Removes the podcasts in backlog mode.
+    pub fn skip_backlog(mut self) -> Self {
+        self.0.retain(|name, config| {
+            if !config.is_backlog() {
+                return true;
+            }
+
+            eprintln!("skipping '{}': in backlog mode", name);
+            false
+        });
+
+        self
     }
 
-    /// All podcasts matching the regex will only download upcoming episodes.
-    /// time. Podcasts with backlog mode ignored.
-    pub fn catch_up(filter: Option<Regex>) {
-        let mut podcasts = Self::load().filter(filter);
+    /// Reports the podcasts whose download hook can't be run, before anything is downloaded.
+    ///
+    /// With `strict` this is an error. Optional hooks that can't be found are skipped instead.
+    pub fn check_hooks(self, global_config: &GlobalConfig, strict: bool) -> Self {
+        // Invalid sandbox settings exit here rather than halfway through the sync.
+        global_config.hook_sandbox();
+
+        let mut unrunnable = vec![];
 
-        for (name, config) in &mut podcasts.0 {
-            if config.catch_up() {
-                eprintln!("caught up with {}", &name);
+        for (name, config) in &self.0 {
+            let pipeline = config.pipeline.as_ref().or(global_config.pipeline.as_ref());
+            if pipeline.is_some_and(|pipeline| !pipeline.contains(&ProcessStep::Hook)) {
+                continue;
             }
-        }
 
-        podcasts.save_modified();
-    }
+            let Some(hook) = config
+                .download_hook
+                .clone()
+                .into_val(global_config.download_hook.as_ref())
+            else {
+                continue;
+            };
+
+            let Err(e) = HookCommand::parse(&hook).resolve() else {
+                continue;
+            };
+
+            match config.hook_optional() {
+                true => log::info!("{}: skipping optional download hook: {}", name, e),
+                false => unrunnable.push(format!("{}: {}", name, e)),
+            }
+        }
 
-    pub fn len(&self) -> usize {
-        self.0.len()
-    }
+        if unrunnable.is_empty() {
+            return self;
+        }
 
-    pub fn is_empty(&self) -> bool {
-        self.len() == 0
-    }
+        unrunnable.sort();
+        let level = if strict { "error" } else { "warning" };
+        eprintln!(
+            "{}: the download hook of these podcasts can't be run:",
+            level
+        );
+        for podcast in &unrunnable {
+            eprintln!("  {}", podcast);
+        }
 
-    pub fn save_modified(self) {
-        let mut all_podcasts = Self::load();
-        for (name, config) in self.0 {
-            all_podcasts.0.insert(name, config);
+        if strict {
+            process::exit(1);
         }
 
-        all_podcasts.save_to_file();
+        self
     }
 
-    pub fn save_to_file(self) {
-        use std::fs::File;
+    /// Warns about the limits of the standard mode in the global config that don't apply
+    /// to podcasts in backlog mode, which a podcast's own settings would otherwise leave
+    /// unnoticed.
+    ///
+    /// Limits left at their built-in default, or turned off for the podcast with `false`,
+    /// aren't warned about.
+    pub fn check_download_modes(self, global_config: &GlobalConfig) -> Self {
+        let ignored = self.ignored_global_limits(global_config);
+        if ignored.is_empty() {
+            return self;
+        }
 
-        let str = toml::to_string(&self).expect("failed to serialize podcastconfigs");
-        let path = Self::path();
+        eprintln!("warning: these podcasts are in backlog mode, so these global settings don't apply to them:");
+        for podcast in &ignored {
+            eprintln!("  {}", podcast);
+        }
+        eprintln!("set them to false in podcasts.toml to silence this");
 
-        if let Err(e) = File::create(&path).map(|mut file| file.write_all(str.as_bytes())) {
-            eprintln!("failed to save podcast configs to file: {:?}", e);
-            process::exit(1);
-        };
+        self
     }
 
-    pub fn extend(new_podcasts: HashMap<String, PodcastConfig>) {
-        let mut podcasts = Self::load();
-        for (name, podcast) in new_podcasts {
-            if !podcasts.0.contains_key(&name) {
-                podcasts.0.insert(name, podcast);
-            }
+    /// The global limits that each podcast in backlog mode ignores, like
+    /// `name: max_days, keep_latest`, sorted by name.
+    fn ignored_global_limits(&self, global_config: &GlobalConfig) -> Vec<String> {
+        /// Whether the podcast takes a global value that was changed from the default.
+        fn inherits<T: PartialEq>(
+            option: &ConfigOption<T>,
+            global: &Option<T>,
+            default: &Option<T>,
+        ) -> bool {
+            matches!(option, ConfigOption::UseGlobal) && global.is_some() && global != default
         }
 
-        podcasts.save_to_file();
-    }
+        let defaults = GlobalConfig::default();
+        let mut ignored = vec![];
 
-    /// Appends the `podcast.toml` file with the given podcast.
-    ///
-    /// If a podcast with the same name already exist,
-    /// it does nothing and will return false. Otherwise true.
-    pub fn push(name: String, podcast: PodcastConfig) -> bool {
-        let mut podcasts = Self::load();
-        if podcasts.0.contains_key(&name) {
-            false
-        } else {
-            podcasts.0.insert(name, podcast);
-            podcasts.save_to_file();
+        for (name, config) in &self.0 {
+            if !config.is_backlog() {
+                continue;
+            }
 
-            true
+            let g = global_config;
+            let keys: Vec<&str> = [
+                (
+                    "max_days",
+                    inherits(&config.max_days, &g.max_days, &defaults.max_days),
+                ),
+                (
+                    "max_episodes",
+                    inherits(
+                        &config.max_episodes,
+                        &g.max_episodes,
+                        &defaults.max_episodes,
+                    ),
+                ),
+                (
+                    "max_per_day",
+                    inherits(&config.max_per_day, &g.max_per_day, &defaults.max_per_day),
+                ),
+                (
+                    "keep_latest",
+                    inherits(&config.keep_latest, &g.keep_latest, &defaults.keep_latest),
+                ),
+                (
+                    "earliest_date",
+                    inherits(
+                        &config.earliest_date,
+                        &g.earliest_date,
+                        &defaults.earliest_date,
+                    ),
+                ),
+                (
+                    "latest_date",
+                    inherits(&config.latest_date, &g.latest_date, &defaults.latest_date),
+                ),
+            ]
+            .into_iter()
+            .filter(|(_, ignored)| *ignored)
+            .map(|(key, _)| key)
+            .collect();
+
+            if !keys.is_empty() {
+                ignored.push(format!("{}: {}", name, keys.join(", ")));
+            }
         }
+
+        ignored.sort();
+        ignored
     }
 
-    pub fn path() -> PathBuf {
-        let path = utils::config_dir().join("podcasts.toml");
+    /// Skips the podcasts that have nothing downloaded while a tracker left behind by an
+    /// earlier name of theirs does, since syncing them would download everything again.
+    pub fn check_orphans(mut self, global_config: &GlobalConfig) -> Self {
+        let orphans = orphans::find(&Self::load(), global_config);
+
+        for orphan in orphans {
+            let name = &orphan.podcast;
+            let Some(config) = self.0.get(name) else {
+                continue;
+            };
+            if !DownloadedEpisodes::load(&config.tracker_path(name, global_config)).is_empty() {
+                continue;
+            }
 
-        if !path.exists() {
-            std::fs::File::create(&path).expect("failed to create podcasts.toml file");
+            eprintln!(
+                "skipping '{}': its downloads seem to be tracked in {:?}, from before it was renamed",
+                name, orphan.tracker
+            );
+            eprintln!("run 'talecast --fix-orphans' to adopt them, or remove that directory to download the podcast again");
+            self.0.remove(name);
         }
 
-        path
-    }
-
-    pub fn into_outlines(self) -> Vec<opml::Outline> {
-        self.0
-            .into_iter()
-            .map(|(name, pod)| opml::Outline {
-                text: name.clone(),
-                r#type: Some("rss".to_string()),
-                xml_url: Some(pod.url.clone()),
-                title: Some(name),
-                ..opml::Outline::default()
-            })
-            .collect()
+        self
     }
-}
 
-impl From<PodcastConfigs> for opml::OPML {
-    fn from(podcasts: PodcastConfigs) -> opml::OPML {
-        use opml::{Body, Head, OPML};
+    /// Exits if two podcasts would share a download tracker or directory, which would mix
+    /// up their state.
+    ///
+    /// Paths are compared the way case-insensitive filesystems compare them. With
+    /// `auto_disambiguate`, podcasts using the default download path are given one with a
+    /// short hash of their name instead, except for the one that already owns the path.
+    pub fn check_collisions(mut self, global_config: &GlobalConfig) -> Self {
+        let mut collisions = self.collisions(global_config);
+
+        if !collisions.is_empty() && global_config.auto_disambiguate.unwrap_or(false) {
+            let mut renamed = vec![];
+            for (name, other, _) in &collisions {
+                let owns = |name: &str| self.owns_download_dir(name, global_config);
+                let loser = match owns(other) && !owns(name) {
+                    true => name,
+                    false => other,
+                };
+                if let Some(download_path) = self.disambiguate(loser, global_config) {
+                    renamed.push((loser.clone(), download_path));
+                }
+            }
 
-        let mut opml = OPML {
-            head: Some(Head {
-                title: Some("TaleCast Podcast Feeds".to_string()),
-                date_created: Some(chrono::Utc::now().to_rfc2822()),
-                ..Head::default()
-            }),
-            ..Default::default()
-        };
+            if !renamed.is_empty() {
+                let mut podcasts = Self::load();
+                for (name, download_path) in renamed {
+                    eprintln!(
+                        "'{}' collided with another podcast, it now downloads to \"{}\"",
+                        name, download_path
+                    );
+                    if let Some(config) = podcasts.0.get_mut(&name) {
+                        config.download_path = Some(download_path.clone());
+                    }
+                    if let Some(config) = self.0.get_mut(&name) {
+                        config.download_path = Some(download_path);
+                    }
+                }
+                podcasts.save_to_file();
+            }
 
-        let outlines = podcasts.into_outlines();
+            collisions = self.collisions(global_config);
+        }
 
-        opml.body = Body { outlines };
+        if collisions.is_empty() {
+            return self;
+        }
 
-        opml
+        for (name, other, path) in &collisions {
+            eprintln!("'{}' and '{}' would both use {:?}", name, other, path);
+        }
+        eprintln!("give them distinct names, set a 'download_path' for one of them, or set 'auto_disambiguate = true'");
+        process::exit(1);
     }
-}
 
-impl IntoIterator for PodcastConfigs {
-    type Item = (String, PodcastConfig);
-    type IntoIter = std::collections::hash_map::IntoIter<String, PodcastConfig>;
+    /// Pairs of podcasts that share a download tracker or directory, along with the path.
+    fn collisions(&self, global_config: &GlobalConfig) -> Vec<(String, String, PathBuf)> {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+
+        let mut seen: HashMap<String, &String> = HashMap::new();
+        let mut collisions = vec![];
+
+        for name in names {
+            let config = &self.0[name];
+            let mut paths = vec![config.tracker_path(name, global_config)];
+            if config.layout(global_config) == Layout::PerPodcast
+                && !config.download_path_uses_feed(global_config)
+            {
+                paths.push(config.download_dir(name, global_config));
+            }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter()
+            for path in paths {
+                let key = naming::fold(&path.to_string_lossy());
+                match seen.get(&key) {
+                    Some(other) if *other != name => {
+                        collisions.push(((*other).clone(), name.clone(), path));
+                    }
+                    _ => {
+                        seen.insert(key, name);
+                    }
+                }
+            }
+        }
+
+        collisions.dedup_by(|a, b| a.0 == b.0 && a.1 == b.1);
+        collisions
     }
-}
 
-impl<'a> IntoIterator for &'a PodcastConfigs {
-    type Item = (&'a String, &'a PodcastConfig);
-    type IntoIter = std::collections::hash_map::Iter<'a, String, PodcastConfig>;
+    /// Whether the download directory exists under exactly the name the podcast gives it,
+    /// rather than one that only folds to the same.
+    fn owns_download_dir(&self, name: &str, global_config: &GlobalConfig) -> bool {
+        let Some(config) = self.0.get(name) else {
+            return false;
+        };
+        let dir = config.download_dir(name, global_config);
+        let (Some(parent), Some(dir_name)) = (dir.parent(), dir.file_name()) else {
+            return false;
+        };
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.iter()
+        fs::read_dir(parent).is_ok_and(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .any(|entry| entry.file_name() == dir_name)
+        })
     }
-}
 
-/// The configuration of a specific podcast.
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(deny_unknown_fields)]
-pub struct PodcastConfig {
-    pub url: String,
-    name_pattern: Option<String>,
-    id_pattern: Option<String>,
-    #[serde(alias = "path")]
-    download_path: Option<String>,
-    partial_path: Option<String>,
-    backlog_start: Option<String>,
-    backlog_interval: Option<i64>,
-    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
-    id3_tags: HashMap<String, String>,
-    max_days: ConfigOption<i64>,
-    max_episodes: ConfigOption<i64>,
-    earliest_date: ConfigOption<String>,
-    download_hook: ConfigOption<PathBuf>,
-    tracker_path: ConfigOption<String>,
-    symlink: Option<String>,
-}
+    /// A download path for the podcast that includes a hash of its name, if its download
+    /// path is the default one.
+    fn disambiguate(&self, name: &str, global_config: &GlobalConfig) -> Option<String> {
+        use std::hash::Hasher;
 
-impl PodcastConfig {
-    pub fn new(url: String) -> Self {
-        Self {
-            url,
-            name_pattern: Default::default(),
-            id_pattern: Default::default(),
-            download_path: Default::default(),
-            backlog_start: Default::default(),
-            backlog_interval: Default::default(),
-            id3_tags: Default::default(),
-            max_days: Default::default(),
-            max_episodes: Default::default(),
-            earliest_date: Default::default(),
-            download_hook: Default::default(),
-            tracker_path: Default::default(),
-            symlink: Default::default(),
-            partial_path: Default::default(),
+        let config = self.0.get(name)?;
+        if config.download_path.is_some() || config.layout(global_config) == Layout::Flat {
+            return None;
+        }
+
+        let pattern = config.podcast_download_pattern(global_config);
+        if !pattern.contains("{podname}") {
+            return None;
         }
+
+        let mut hasher = fnv::FnvHasher::default();
+        hasher.write(name.as_bytes());
+        let hash = format!("{:016x}", hasher.finish());
+
+        Some(pattern.replace("{podname}", &format!("{{podname}}-{}", &hash[..6])))
     }
 
-    /// Changes the `earliest_date` setting to the current time.
+    /// Removes the podcasts whose `schedule` says they aren't due yet.
     ///
-    /// This means only episodes published after this function was called will be downloaded.
-    /// Remember to save after calling this function.
-    pub fn catch_up(&mut self) -> bool {
-        use chrono::DateTime;
+    /// Unless `ignore_feed_hints` is set, the update period and skipped hours declared by
+    /// the feed can postpone a podcast further.
+    pub fn skip_unscheduled(mut self, global_config: &GlobalConfig) -> Self {
+        let now = chrono::Local::now();
+        self.0.retain(|name, config| {
+            let tracker_path = config.tracker_path(name, global_config);
+            let last_sync = LastSync::load(&tracker_path);
+
+            let due = match (&config.schedule, last_sync) {
+                (Some(schedule), Some(last_sync)) => schedule.next_after(last_sync),
+                _ => Some(now),
+            };
+
+            let hints = match config.ignore_feed_hints() {
+                true => None,
+                false => FeedHints::load(&tracker_path),
+            };
+
+            let next = match (due, hints) {
+                (Some(due), Some(hints)) => Some(hints.next_poll(last_sync, due, now)),
+                (due, None) => due,
+                (None, Some(_)) => None,
+            };
+
+            if next.is_some_and(|next| next <= now) {
+                return true;
+            }
 
-        let unix = utils::current_unix();
-        let current_date = DateTime::from_timestamp(unix.as_secs() as i64, 0)
-            .expect("failed to convert unix to datetime")
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
+            let next = next
+                .map(|next| next.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "never".to_string());
+            eprintln!("skipping '{}': next scheduled sync at {}", name, next);
+            false
+        });
 
-        if self.backlog_start.is_some() || self.backlog_interval.is_some() {
-            return false;
+        self
+    }
+
+    /// Changes the url of a podcast, returning the old one.
+    pub fn set_url(&mut self, name: &str, url: String) -> Option<String> {
+        let config = self.0.get_mut(name)?;
+        Some(std::mem::replace(&mut config.url, url))
+    }
+
+    /// Pauses syncing a podcast until the given date.
+    pub fn pause(name: &str, until: String) {
+        if let Err(e) = utils::date_str_to_unix(&until) {
+            eprintln!("{}", e);
+            process::exit(1);
         }
 
-        self.earliest_date = ConfigOption::Enabled(current_date.clone());
+        let mut podcasts = Self::load();
+        let Some(config) = podcasts.0.get_mut(name) else {
+            eprintln!("no podcast named '{}'", name);
+            process::exit(1);
+        };
 
-        true
+        config.paused_until = Some(until.clone());
+        podcasts.save_to_file();
+        eprintln!("paused '{}' until {}", name, until);
     }
-}
+
+    /// Sets the given `key=value` settings on every podcast matching the filter.
+    ///
+    /// Nothing is written unless every setting is valid for every podcast. With `dry_run`,
+    /// the changes are only printed.
+    pub fn set(assignments: &[String], filter: Option<Regex>, dry_run: bool) {
+        let settings = parse_settings(assignments).and_then(|settings| {
+            settings
+                .into_iter()
+                .map(
+                    |(key, value)| match PodcastConfig::coerce_setting(&key, &value) {
+                        Ok(value) => Ok((key, value)),
+                        Err(e) => Err(format!("{}={}: {}", key, value, e)),
+                    },
+                )
+                .collect::<Result<Vec<_>, _>>()
+        });
+
+        let settings = match settings {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("{}", e);
+                eprintln!("podcasts.toml was not changed");
+                process::exit(1);
+            }
+        };
+
+        let mut podcasts = Self::load();
+        let matching = podcasts.clone().filter(filter);
+        let mut names: Vec<&String> = matching.0.keys().collect();
+        names.sort();
+
+        let mut errors = vec![];
+        let mut changes = vec![];
+        for name in names {
+            let old = &podcasts.0[name];
+            match old.with_settings(&settings) {
+                Ok(new) => {
+                    let diff = PodcastConfig::diff(old, &new, &settings);
+                    if !diff.is_empty() {
+                        changes.push((name.clone(), new, diff));
+                    }
+                }
+                Err(e) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+
+        if !errors.is_empty() {
+            for error in &errors {
+                eprintln!("{}", error);
+            }
+            eprintln!("podcasts.toml was not changed");
+            process::exit(1);
+        }
+
+        for (name, _, diff) in &changes {
+            println!("[{}]", name);
+            for line in diff {
+                println!("{}", line);
+            }
+        }
+
+        let verb = if dry_run { "would change" } else { "changed" };
+        eprintln!("{} {} of {} podcasts", verb, changes.len(), matching.len());
+
+        if dry_run || changes.is_empty() {
+            return;
+        }
+
+        for (name, new, _) in changes {
+            podcasts.0.insert(name, new);
+        }
+        podcasts.save_to_file();
+    }
+
+    pub fn assert_not_empty(self) -> Self {
+        if self.is_empty() {
+            eprintln!("No podcasts configured!");
+            eprintln!("You can add podcasts with the following methods:\n");
+            eprintln!("* \"{} --search <name of podcast>\"", crate::APPNAME);
+            eprintln!(
+                "* \"{} --add <feed url>  <name of podcast>\"",
+                crate::APPNAME
+            );
+            eprintln!(
+                "*  Manually configuring the {:?} file.",
+                &PodcastConfigs::path()
+            );
+            process::exit(1);
+        }
+
+        self
+    }
+
+    pub fn longest_name(&self) -> Option<usize> {
+        self.0.iter().map(|(name, _)| name.chars().count()).max()
+    }
+
+    /// A filter that matches exactly the podcasts with these names.
+    pub fn name_filter(names: &[String]) -> Regex {
+        let names: Vec<String> = names.iter().map(|name| regex::escape(name)).collect();
+        Regex::new(&format!("^(?:{})$", names.join("|"))).unwrap()
+    }
+
+    /// Lets the next sync download the episodes of a podcast published since `since` that
+    /// were acknowledged by catching up, or skipped because of `earliest_date`.
+    ///
+    /// Other limits, like `max_episodes`, still apply.
+    pub fn un_acknowledge(name: &str, since: Unix, global_config: &GlobalConfig) {
+        let mut podcasts = Self::load();
+        let Some(config) = podcasts.0.get_mut(name) else {
+            eprintln!("no podcast named '{}'", name);
+            process::exit(1);
+        };
+
+        let tracker_path = config.tracker_path(name, global_config);
+        let removed = match DownloadedEpisodes::remove_acknowledged(&tracker_path, since.as_secs())
+        {
+            Ok(removed) => removed,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        };
+
+        let moved = config.un_acknowledge(since, global_config);
+        if removed == 0 && !moved {
+            eprintln!("no episodes of '{}' since then are acknowledged", name);
+            return;
+        }
+
+        eprintln!(
+            "episodes of '{}' published since {} will be downloaded",
+            name,
+            earliest_date_str(since)
+        );
+        if moved {
+            podcasts.save_to_file();
+        }
+    }
+
+    /// Moves a podcast and its episodes to another download root, layout, or both.
+    pub fn move_podcast(
+        name: &str,
+        root: Option<String>,
+        layout: Option<Layout>,
+        global_config: &GlobalConfig,
+    ) {
+        let mut podcasts = Self::load();
+        let Some(config) = podcasts.0.get_mut(name) else {
+            eprintln!("no podcast named '{}'", name);
+            process::exit(1);
+        };
+
+        if let Some(Err(e)) = root.as_deref().map(|root| global_config.root_dir(root)) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+
+        let old = config.clone();
+        if let Some(root) = &root {
+            config.root = Some(root.clone());
+        }
+        if let Some(layout) = layout {
+            config.layout = Some(layout);
+        }
+
+        let old_dir = old.download_dir(name, global_config);
+        let new_dir = config.download_dir(name, global_config);
+        let old_layout = old.layout(global_config);
+        let new_layout = config.layout(global_config);
+
+        if old_dir == new_dir && old_layout == new_layout {
+            eprintln!(
+                "'{}' would still be downloaded to {:?}, nothing to move",
+                name, new_dir
+            );
+            eprintln!("if 'download_path' is set for the podcast, make sure it uses '{{root}}'");
+            process::exit(1);
+        }
+
+        let moves = {
+            let old_tracker = old.tracker_path(name, global_config);
+            let new_tracker = config.tracker_path(name, global_config);
+            Self::planned_moves(
+                name,
+                (&old_dir, old_layout, &old_tracker),
+                (&new_dir, new_layout, &new_tracker),
+            )
+        };
+
+        if let Some((_, to)) = moves.iter().find(|(_, to)| to.exists()) {
+            eprintln!("can't move '{}', file already exists: {:?}", name, to);
+            process::exit(1);
+        }
+
+        paths::create_dir(&new_dir);
+        for (from, to) in moves {
+            if let Err(e) = paths::move_path(&from, &to) {
+                eprintln!("failed to move {:?} to {:?}: {:?}", from, to, e);
+                process::exit(1);
+            }
+        }
+
+        // Only succeeds if nothing else is left in it.
+        if old_layout == Layout::PerPodcast && old_dir != new_dir {
+            let _ = fs::remove_dir(&old_dir);
+        }
+
+        podcasts.save_to_file();
+        eprintln!(
+            "moved '{}' to {:?} with the {} layout",
+            name,
+            new_dir,
+            new_layout.name()
+        );
+    }
+
+    /// Which files to move where when moving a podcast.
+    ///
+    /// In the flat layout, only the files named after the podcast are its own.
+    pub fn planned_moves(
+        name: &str,
+        (old_dir, old_layout, old_tracker): (&Path, Layout, &Path),
+        (new_dir, new_layout, new_tracker): (&Path, Layout, &Path),
+    ) -> Vec<(PathBuf, PathBuf)> {
+        let prefix = flat_prefix(name);
+        let mut moves = vec![];
+
+        // The tracker and the state kept next to it.
+        let state_files = [
+            (old_tracker.to_path_buf(), new_tracker.to_path_buf()),
+            (
+                Fingerprints::path(old_tracker),
+                Fingerprints::path(new_tracker),
+            ),
+            (
+                LengthReliability::path(old_tracker),
+                LengthReliability::path(new_tracker),
+            ),
+            (Schedule::path(old_tracker), Schedule::path(new_tracker)),
+            (LastSync::path(old_tracker), LastSync::path(new_tracker)),
+            (Cadence::path(old_tracker), Cadence::path(new_tracker)),
+            (DailyCap::path(old_tracker), DailyCap::path(new_tracker)),
+            (FeedHints::path(old_tracker), FeedHints::path(new_tracker)),
+            (
+                HookRecords::path(old_tracker),
+                HookRecords::path(new_tracker),
+            ),
+            (
+                DeferredTags::path(old_tracker),
+                DeferredTags::path(new_tracker),
+            ),
+            (FeedRecord::path(old_tracker), FeedRecord::path(new_tracker)),
+            (SeenItems::path(old_tracker), SeenItems::path(new_tracker)),
+            (snapshot::dir(old_tracker), snapshot::dir(new_tracker)),
+        ];
+
+        if old_tracker != new_tracker {
+            for (from, to) in &state_files {
+                if from.exists() {
+                    moves.push((from.clone(), to.clone()));
+                }
+            }
+        }
+
+        let Ok(entries) = fs::read_dir(old_dir) else {
+            return moves;
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if state_files.iter().any(|(from, _)| *from == path) {
+                continue;
+            }
+
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let base = match old_layout {
+                Layout::PerPodcast => file_name.as_str(),
+                Layout::Flat => match file_name.strip_prefix(&prefix) {
+                    Some(base) => base,
+                    None => continue,
+                },
+            };
+
+            let new_name = match new_layout {
+                Layout::PerPodcast => base.to_string(),
+                Layout::Flat => format!("{}{}", prefix, base),
+            };
+
+            moves.push((path, new_dir.join(new_name)));
+        }
+
+        moves
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn save_modified(self) {
+        let mut all_podcasts = Self::load();
+        for (name, config) in self.0 {
+            all_podcasts.0.insert(name, config);
+        }
+
+        all_podcasts.save_to_file();
+    }
+
+    pub fn save_to_file(self) {
+        if let Err(e) = self.write_to(&Self::path()) {
+            eprintln!("failed to save podcast configs to file: {:?}", e);
+            process::exit(1);
+        };
+    }
+
+    fn write_to(&self, path: &Path) -> std::io::Result<()> {
+        use std::fs::File;
+
+        let str = toml::to_string(self).expect("failed to serialize podcastconfigs");
+
+        // Written next to the file first, so it's never left half written.
+        let temp_path = path.with_extension("toml.tmp");
+        read_only::check(path);
+        File::create(&temp_path)
+            .and_then(|mut file| file.write_all(str.as_bytes()))
+            .and_then(|_| fs::rename(&temp_path, path))
+    }
+
+    /// Applies `edit` to `podcasts.toml` without undoing changes made to it since `stamp`.
+    ///
+    /// Meant for changes made automatically at the end of a long run, while the user may
+    /// have added or edited podcasts. The file is always read again and only `edit` is
+    /// applied to it, so a stale copy of the whole file is never written back.
+    pub fn edit_file(stamp: &ConfigStamp, edit: impl FnMut(&mut Self)) {
+        if let Err(e) = Self::edit_file_at(&Self::path(), stamp, edit) {
+            eprintln!("failed to save podcast configs to file: {}", e);
+            process::exit(1);
+        }
+    }
+
+    fn edit_file_at(
+        path: &Path,
+        stamp: &ConfigStamp,
+        mut edit: impl FnMut(&mut Self),
+    ) -> Result<(), String> {
+        if !stamp.is_current(path) {
+            log::info!("podcasts.toml changed during the run, re-applying changes to it");
+        }
+
+        // The file may also change between reading and writing it, in which case
+        // the edit is applied again on top of the newer content.
+        for _ in 0..5 {
+            let before = ConfigStamp::of(path);
+            let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let mut podcasts = toml::from_str(&content)
+                .map(Self)
+                .map_err(|e| format!("failed to deserialize podcasts.toml file: {}", e))?;
+
+            edit(&mut podcasts);
+
+            if before.is_current(path) {
+                return podcasts.write_to(path).map_err(|e| e.to_string());
+            }
+        }
+
+        Err("podcasts.toml kept changing while saving it".to_string())
+    }
+
+    /// Adds the podcasts whose url isn't subscribed to yet, in order. Returns the names of the
+    /// skipped podcasts, and the name each added podcast was given along with the name it
+    /// came with.
+    ///
+    /// A podcast whose name is taken gets the first free name with a counter appended, like
+    /// "Name (2)", so importing the same podcasts always gives the same names.
+    pub fn extend(
+        new_podcasts: Vec<(String, PodcastConfig)>,
+    ) -> (Vec<(String, String)>, Vec<String>) {
+        let mut podcasts = Self::load();
+        let mut added = vec![];
+        let mut skipped = vec![];
+
+        for (name, podcast) in new_podcasts {
+            let url = podcast.url.trim();
+            let subscribed = podcasts.0.values().any(|config| config.url.trim() == url);
+            if subscribed {
+                skipped.push(name);
+            } else {
+                let free_name = podcasts.free_name(&name);
+                podcasts.0.insert(free_name.clone(), podcast);
+                added.push((name, free_name));
+            }
+        }
+
+        if !added.is_empty() {
+            podcasts.save_to_file();
+        }
+
+        (added, skipped)
+    }
+
+    /// The name if no podcast has it yet, otherwise the name with the lowest counter that
+    /// makes it free.
+    ///
+    /// Names are compared like [`naming::fold`] does, so that the podcasts don't end up
+    /// sharing a download directory on filesystems that ignore case.
+    fn free_name(&self, name: &str) -> String {
+        let taken = |candidate: &str| {
+            let candidate = naming::fold(candidate);
+            self.0.keys().any(|key| naming::fold(key) == candidate)
+        };
+
+        let mut free_name = name.to_string();
+        let mut counter = 2;
+        while taken(&free_name) {
+            free_name = format!("{} ({})", name, counter);
+            counter += 1;
+        }
+
+        free_name
+    }
+
+    /// Appends the `podcast.toml` file with the given podcast.
+    ///
+    /// If a podcast with the same name already exist,
+    /// it does nothing and will return false. Otherwise true.
+    pub fn push(name: String, podcast: PodcastConfig) -> bool {
+        let mut podcasts = Self::load();
+        if podcasts.0.contains_key(&name) {
+            false
+        } else {
+            podcasts.0.insert(name, podcast);
+            podcasts.save_to_file();
+
+            true
+        }
+    }
+
+    pub fn path() -> PathBuf {
+        let path = paths::config_dir().join("podcasts.toml");
+
+        if !path.exists() && !read_only::is_enabled() {
+            std::fs::File::create(&path).expect("failed to create podcasts.toml file");
+        }
+
+        path
+    }
+
+    /// An outline of each podcast, sorted by name.
+    pub fn into_outlines(self) -> Vec<opml::Outline> {
+        let mut podcasts: Vec<_> = self.0.into_iter().collect();
+        podcasts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        podcasts
+            .into_iter()
+            .map(|(name, pod)| opml::Outline {
+                text: name.clone(),
+                r#type: Some("rss".to_string()),
+                xml_url: Some(pod.url.clone()),
+                title: Some(name),
+                ..opml::Outline::default()
+            })
+            .collect()
+    }
+}
+
+impl From<PodcastConfigs> for opml::OPML {
+    fn from(podcasts: PodcastConfigs) -> opml::OPML {
+        use opml::{Body, Head, OPML};
+
+        let mut opml = OPML {
+            head: Some(Head {
+                title: Some("TaleCast Podcast Feeds".to_string()),
+                date_created: Some(chrono::Utc::now().to_rfc2822()),
+                ..Head::default()
+            }),
+            ..Default::default()
+        };
+
+        let outlines = podcasts.into_outlines();
+
+        opml.body = Body { outlines };
+
+        opml
+    }
+}
+
+impl FromIterator<(String, PodcastConfig)> for PodcastConfigs {
+    fn from_iter<I: IntoIterator<Item = (String, PodcastConfig)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for PodcastConfigs {
+    type Item = (String, PodcastConfig);
+    type IntoIter = std::collections::hash_map::IntoIter<String, PodcastConfig>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a PodcastConfigs {
+    type Item = (&'a String, &'a PodcastConfig);
+    type IntoIter = std::collections::hash_map::Iter<'a, String, PodcastConfig>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// The configuration of a specific podcast.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PodcastConfig {
+    pub url: String,
+    name_pattern: Option<String>,
+    id_pattern: Option<String>,
+    #[serde(alias = "path")]
+    download_path: Option<String>,
+    partial_path: Option<String>,
+    backlog_start: Option<String>,
+    backlog_interval: Option<i64>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    id3_tags: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pictures: HashMap<String, ImageSource>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    feed_headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    headers: HashMap<String, String>,
+    username: Option<String>,
+    password: Option<String>,
+    password_env: Option<String>,
+    max_days: ConfigOption<i64>,
+    max_episodes: ConfigOption<i64>,
+    min_episodes_retained: Option<usize>,
+    max_per_day: ConfigOption<usize>,
+    keep_latest: ConfigOption<usize>,
+    earliest_date: ConfigOption<String>,
+    latest_date: ConfigOption<String>,
+    download_hook: ConfigOption<PathBuf>,
+    pipeline: Option<Vec<ProcessStep>>,
+    tracker_path: ConfigOption<String>,
+    symlink: Option<String>,
+    stop_at_known: Option<bool>,
+    paused_until: Option<String>,
+    schedule: Option<SyncSchedule>,
+    save_funding_links: Option<bool>,
+    infer_episode_number: Option<bool>,
+    keep_description_html: Option<bool>,
+    layout: Option<Layout>,
+    video_handling: Option<VideoHandling>,
+    preferred_media: Option<Vec<String>>,
+    media_size: Option<MediaSize>,
+    root: Option<String>,
+    recheck_last_n: Option<usize>,
+    redownload_changed: Option<bool>,
+    change_tolerance: Option<f64>,
+    old_backups: Option<usize>,
+    download_retries: Option<usize>,
+    retry_backoff_secs: Option<u64>,
+    continue_on_error: Option<bool>,
+    write_buffer_kb: Option<u64>,
+    episode_concurrency: Option<usize>,
+    download_transcripts: Option<bool>,
+    transcript_formats: Option<Vec<TranscriptFormat>>,
+    embed_chapters: Option<bool>,
+    chapter_images: Option<bool>,
+    strict_size_check: Option<bool>,
+    archive_feed: Option<bool>,
+    max_feed_snapshots: Option<usize>,
+    hook_optional: Option<bool>,
+    rerun_failed_hooks: ConfigOption<bool>,
+    ignore_feed_hints: Option<bool>,
+    title_pattern: Option<String>,
+    title_exclude_pattern: Option<String>,
+    title_pattern_case_sensitive: Option<bool>,
+    /// Set on the copy of the config used for `--mirror`.
+    #[serde(skip)]
+    mirror: bool,
+    /// Set on the copy of the config used for `--dry-run`.
+    #[serde(skip)]
+    dry_run: bool,
+}
+
+impl PodcastConfig {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            name_pattern: Default::default(),
+            id_pattern: Default::default(),
+            download_path: Default::default(),
+            backlog_start: Default::default(),
+            backlog_interval: Default::default(),
+            id3_tags: Default::default(),
+            pictures: Default::default(),
+            feed_headers: Default::default(),
+            headers: Default::default(),
+            username: Default::default(),
+            password: Default::default(),
+            password_env: Default::default(),
+            max_days: Default::default(),
+            max_episodes: Default::default(),
+            min_episodes_retained: Default::default(),
+            max_per_day: Default::default(),
+            keep_latest: Default::default(),
+            earliest_date: Default::default(),
+            latest_date: Default::default(),
+            download_hook: Default::default(),
+            pipeline: Default::default(),
+            tracker_path: Default::default(),
+            symlink: Default::default(),
+            partial_path: Default::default(),
+            stop_at_known: Default::default(),
+            paused_until: Default::default(),
+            schedule: Default::default(),
+            save_funding_links: Default::default(),
+            infer_episode_number: Default::default(),
+            keep_description_html: Default::default(),
+            layout: Default::default(),
+            video_handling: Default::default(),
+            preferred_media: Default::default(),
+            media_size: Default::default(),
+            root: Default::default(),
+            recheck_last_n: Default::default(),
+            redownload_changed: Default::default(),
+            change_tolerance: Default::default(),
+            old_backups: Default::default(),
+            download_retries: Default::default(),
+            retry_backoff_secs: Default::default(),
+            continue_on_error: Default::default(),
+            write_buffer_kb: Default::default(),
+            episode_concurrency: Default::default(),
+            download_transcripts: Default::default(),
+            transcript_formats: Default::default(),
+            embed_chapters: Default::default(),
+            chapter_images: Default::default(),
+            strict_size_check: Default::default(),
+            archive_feed: Default::default(),
+            max_feed_snapshots: Default::default(),
+            hook_optional: Default::default(),
+            rerun_failed_hooks: Default::default(),
+            ignore_feed_hints: Default::default(),
+            title_pattern: Default::default(),
+            title_exclude_pattern: Default::default(),
+            title_pattern_case_sensitive: Default::default(),
+            mirror: false,
+            dry_run: false,
+        }
+    }
+
+    /// The config for mirroring the podcast into `dest`.
+    ///
+    /// Episodes are named and tagged as usual, but nothing that would be written next to
+    /// the tracker or the downloads of the podcast is enabled.
+    pub fn mirror_to(&self, dest: &Path) -> Self {
+        let dest = dest.to_string_lossy().into_owned();
+        Self {
+            tracker_path: ConfigOption::Enabled(format!("{}/.downloaded", &dest)),
+            download_path: Some(dest),
+            partial_path: None,
+            root: None,
+            layout: Some(Layout::PerPodcast),
+            backlog_start: None,
+            backlog_interval: None,
+            save_funding_links: Some(false),
+            symlink: None,
+            download_hook: ConfigOption::Disabled,
+            mirror: true,
+            ..self.clone()
+        }
+    }
+
+    pub fn is_mirror(&self) -> bool {
+        self.mirror
+    }
+
+    /// The config for showing what a sync would download, which loads the podcast without
+    /// writing anything next to its tracker.
+    pub fn dry_run(&self) -> Self {
+        Self {
+            dry_run: true,
+            ..self.clone()
+        }
+    }
+
+    /// Whether loading the podcast may record state next to its tracker.
+    ///
+    /// A feed read from a snapshot says nothing about the current state of the podcast.
+    pub fn records_state(&self) -> bool {
+        !self.mirror && !self.dry_run && !read_only::is_enabled() && snapshot::from().is_none()
+    }
+
+    /// A copy of the config with the given settings changed, checked like the settings
+    /// in `podcasts.toml` are.
+    fn with_settings(&self, settings: &[(String, toml::Value)]) -> Result<Self, String> {
+        let mut table = match toml::Value::try_from(self) {
+            Ok(toml::Value::Table(table)) => table,
+            _ => return Err("failed to serialize podcast config".to_string()),
+        };
+
+        for (key, value) in settings {
+            table.insert(key.clone(), value.clone());
+        }
+
+        let config: Self = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("{}", e))?;
+        config.check_download_mode()?;
+        Ok(config)
+    }
+
+    /// Reads a setting the way it would be written in `podcasts.toml`, or as a string if
+    /// that doesn't fit the setting.
+    fn coerce_setting(key: &str, value: &str) -> Result<toml::Value, String> {
+        if PATTERN_SETTINGS.contains(&key) && value.trim() != ORIGINAL_FILENAME {
+            FullPattern::check(value)?;
+        }
+
+        if matches!(key, "title_pattern" | "title_exclude_pattern") {
+            Regex::new(value).map_err(|e| TitleFilter::error(key, value, &e))?;
+        }
+
+        if matches!(key, "earliest_date" | "latest_date") && value != "false" {
+            check_date(key, value)?;
+        }
+
+        let fits = |value: &toml::Value| {
+            let mut table = toml::value::Table::new();
+            table.insert("url".to_string(), toml::Value::String(String::new()));
+            table.insert(key.to_string(), value.clone());
+            toml::Value::Table(table).try_into::<Self>()
+        };
+
+        let typed = format!("{} = {}", key, value)
+            .parse::<toml::Value>()
+            .ok()
+            .and_then(|table| table.get(key).cloned());
+        let string = toml::Value::String(value.to_string());
+
+        match typed {
+            Some(typed) => match fits(&typed) {
+                Ok(_) => Ok(typed),
+                Err(e) => fits(&string).map(|_| string).map_err(|_| e.to_string()),
+            },
+            None => fits(&string).map(|_| string).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// The changed settings, as lines of a diff.
+    fn diff(old: &Self, new: &Self, settings: &[(String, toml::Value)]) -> Vec<String> {
+        let table = |config: &Self| match toml::Value::try_from(config) {
+            Ok(toml::Value::Table(table)) => table,
+            _ => toml::value::Table::new(),
+        };
+        let (old, new) = (table(old), table(new));
+
+        let mut lines = vec![];
+        for (key, _) in settings {
+            let (before, after) = (old.get(key), new.get(key));
+            if before == after {
+                continue;
+            }
+            if let Some(before) = before {
+                lines.push(format!("- {} = {}", key, before));
+            }
+            if let Some(after) = after {
+                lines.push(format!("+ {} = {}", key, after));
+            }
+        }
+        lines
+    }
+
+    /// Name of the download root this podcast lives on, if any.
+    pub fn root<'a>(&'a self, global_config: &'a GlobalConfig) -> Option<&'a str> {
+        self.root
+            .as_deref()
+            .or(global_config.default_root.as_deref())
+    }
+
+    /// The unevaluated pattern of where episodes are downloaded.
+    ///
+    /// When the podcast lives on a download root, `{root}` resolves to the root's directory.
+    /// If only the global download path is used and it doesn't reference `{root}`, episodes
+    /// are downloaded to `{root}/{podname}`.
+    ///
+    /// In the flat layout, a trailing `{podname}` is left out so that all podcasts share the
+    /// directory.
+    fn download_pattern(&self, global_config: &GlobalConfig) -> String {
+        let pattern = self.podcast_download_pattern(global_config);
+        if self.layout(global_config) == Layout::PerPodcast {
+            return pattern;
+        }
+
+        match Self::shared_download_pattern(&pattern) {
+            Some(shared) => shared,
+            None => {
+                eprintln!("the flat layout needs a download path that's shared by all podcasts");
+                eprintln!("download path: \"{}\"", pattern);
+                process::exit(1);
+            }
+        }
+    }
+
+    /// The download path without its trailing `{podname}`, if no other part of it depends
+    /// on the podcast.
+    fn shared_download_pattern(pattern: &str) -> Option<String> {
+        let shared = pattern
+            .trim_end_matches('/')
+            .trim_end_matches("{podname}")
+            .trim_end_matches('/')
+            .to_string();
+
+        (!shared.contains("{podname}")).then_some(shared)
+    }
+
+    fn podcast_download_pattern(&self, global_config: &GlobalConfig) -> String {
+        let Some(root) = self.root(global_config) else {
+            return self
+                .download_path
+                .clone()
+                .unwrap_or_else(|| global_config.download_path.clone());
+        };
+
+        let pattern = match &self.download_path {
+            Some(path) => path.clone(),
+            None if global_config.download_path.contains("{root}") => {
+                global_config.download_path.clone()
+            }
+            None => "{root}/{podname}".to_string(),
+        };
+
+        // An undefined root was already reported by `check_root`.
+        match global_config.root_dir(root) {
+            Ok(dir) => pattern.replace("{root}", dir),
+            Err(_) => pattern,
+        }
+    }
+
+    /// Checks that the download root of the podcast is defined.
+    fn check_root(&self, global_config: &GlobalConfig) -> Result<(), String> {
+        match self.root(global_config) {
+            Some(root) => global_config.root_dir(root).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Path to the download directory, evaluated without the feed.
+    ///
+    /// A download path that depends on the feed, like one with `{podcast_title}` or
+    /// `{pubdate::%Y}`, gives the directory above the first part that does, which holds all
+    /// of the podcast's episodes.
+    pub fn download_dir(&self, name: &str, global_config: &GlobalConfig) -> PathBuf {
+        let pattern = self.download_pattern(global_config);
+        let dir = match FullPattern::feed_placeholders(&pattern).first() {
+            Some((at, _)) => pattern[..*at].rsplit_once('/').map_or("", |(dir, _)| dir),
+            None => &pattern,
+        };
+        Self::podcast_level_eval(dir, name)
+    }
+
+    /// Whether the download path depends on the feed, so that the podcast may not have a
+    /// directory of its own.
+    fn download_path_uses_feed(&self, global_config: &GlobalConfig) -> bool {
+        !FullPattern::feed_placeholders(&self.download_pattern(global_config)).is_empty()
+    }
+
+    /// The unevaluated pattern of where the download tracker is located.
+    fn tracker_pattern(&self, global_config: &GlobalConfig) -> String {
+        if let Some(tracker_path) = self
+            .tracker_path
+            .clone()
+            .into_val(global_config.tracker_path.as_ref())
+        {
+            return tracker_path;
+        }
+
+        let download_path = self.download_pattern(global_config);
+        let file_name = match self.layout(global_config) {
+            Layout::PerPodcast => ".downloaded",
+            Layout::Flat => ".{podname}.downloaded",
+        };
+
+        if download_path.ends_with('/') {
+            download_path + file_name
+        } else {
+            download_path + "/" + file_name
+        }
+    }
+
+    /// Path to the download tracker.
+    ///
+    /// The tracker is found without fetching the feed, since the state next to it is read
+    /// before the feed is, so its pattern can't depend on the feed. That's checked when
+    /// the config is loaded, by [`Self::check_tracker_pattern`].
+    pub fn tracker_path(&self, name: &str, global_config: &GlobalConfig) -> PathBuf {
+        Self::podcast_level_eval(&self.tracker_pattern(global_config), name)
+    }
+
+    /// Checks that the tracker pattern doesn't use anything from the feed, which
+    /// [`Self::tracker_path`] would leave empty.
+    fn check_tracker_pattern(&self, global_config: &GlobalConfig) -> Result<(), String> {
+        let pattern = self.tracker_pattern(global_config);
+        let Some((_, placeholder)) = FullPattern::feed_placeholders(&pattern).into_iter().next()
+        else {
+            return Ok(());
+        };
+
+        let key = match self
+            .tracker_path
+            .clone()
+            .into_val(global_config.tracker_path.as_ref())
+        {
+            Some(_) => "tracker_path",
+            None => "download_path",
+        };
+        Err(format!(
+            "'{}' uses {} from the feed, but the download tracker is found without fetching the feed; set a 'tracker_path' that doesn't",
+            key, placeholder
+        ))
+    }
+
+    fn podcast_level_eval(pattern: &str, name: &str) -> PathBuf {
+        let podcast = RawPodcast::default();
+        let episode = episode::Attributes::default();
+        let data = EvalData::new(name, &podcast, &episode);
+        PathBuf::from(FullPattern::direct_eval(pattern, data))
+    }
+
+    /// How many new episodes may be downloaded per day, outside of backlog mode.
+    pub fn max_per_day(&self, global_config: &GlobalConfig) -> Option<usize> {
+        self.max_per_day
+            .into_val(global_config.max_per_day.as_ref())
+    }
+
+    /// How many of the downloaded episodes are kept on disk, if older ones are removed.
+    ///
+    /// Keeping none would remove every episode right after downloading it, so `0` means
+    /// there's no limit.
+    pub fn keep_latest(&self, global_config: &GlobalConfig) -> Option<usize> {
+        self.keep_latest
+            .into_val(global_config.keep_latest.as_ref())
+            .filter(|keep| *keep > 0)
+    }
+
+    /// Whether syncing should stop scanning the feed at the first already downloaded episode.
+    pub fn stop_at_known(&self) -> bool {
+        self.stop_at_known.unwrap_or(false)
+    }
+
+    /// Whether a download hook that can't be found is skipped instead of failing every episode.
+    pub fn hook_optional(&self) -> bool {
+        self.hook_optional.unwrap_or(false)
+    }
+
+    /// Whether download hooks that failed or didn't finish in an earlier sync are run again.
+    pub fn rerun_failed_hooks(&self, global_config: &GlobalConfig) -> bool {
+        self.rerun_failed_hooks
+            .into_val(global_config.rerun_failed_hooks.as_ref())
+            .unwrap_or(false)
+    }
+
+    /// Whether to ignore the update period and skipped hours the feed declares.
+    pub fn ignore_feed_hints(&self) -> bool {
+        self.ignore_feed_hints.unwrap_or(false)
+    }
+
+    /// Whether to save the funding links of the podcast to `FUNDING.txt` in its download directory.
+    pub fn save_funding_links(&self, global_config: &GlobalConfig) -> bool {
+        self.save_funding_links
+            .or(global_config.save_funding_links)
+            .unwrap_or(false)
+    }
+
+    /// How many episodes of the podcast are downloaded at once.
+    pub fn episode_concurrency(&self, global_config: &GlobalConfig) -> usize {
+        self.episode_concurrency
+            .or(global_config.episode_concurrency)
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// The formats to download transcripts in, most preferred first, or none if they aren't
+    /// downloaded.
+    pub fn transcript_formats(&self, global_config: &GlobalConfig) -> Vec<TranscriptFormat> {
+        let enabled = self
+            .download_transcripts
+            .or(global_config.download_transcripts)
+            .unwrap_or(false);
+        if !enabled {
+            return vec![];
+        }
+
+        self.transcript_formats
+            .clone()
+            .or(global_config.transcript_formats.clone())
+            .unwrap_or_else(|| transcript::DEFAULT_FORMATS.to_vec())
+    }
+
+    /// How many snapshots of the feed to keep, `0` for all of them, or `None` if the feed
+    /// isn't archived.
+    pub fn feed_snapshots(&self, global_config: &GlobalConfig) -> Option<usize> {
+        let enabled = self
+            .archive_feed
+            .or(global_config.archive_feed)
+            .unwrap_or(false);
+
+        enabled.then(|| {
+            self.max_feed_snapshots
+                .or(global_config.max_feed_snapshots)
+                .unwrap_or(snapshot::DEFAULT_MAX_SNAPSHOTS)
+        })
+    }
+
+    /// Whether to write the chapters linked from the feed into mp3 files.
+    pub fn embed_chapters(&self, global_config: &GlobalConfig) -> bool {
+        self.embed_chapters
+            .or(global_config.embed_chapters)
+            .unwrap_or(true)
+    }
+
+    /// Whether to embed the images of the chapters, which can add up to a lot for long
+    /// episodes.
+    pub fn chapter_images(&self, global_config: &GlobalConfig) -> bool {
+        self.chapter_images
+            .or(global_config.chapter_images)
+            .unwrap_or(true)
+    }
+
+    /// Whether to fail downloads whose size differs from the enclosure length in the feed,
+    /// which many feeds only get roughly right.
+    pub fn strict_size_check(&self, global_config: &GlobalConfig) -> bool {
+        self.strict_size_check
+            .or(global_config.strict_size_check)
+            .unwrap_or(false)
+    }
+
+    pub fn layout(&self, global_config: &GlobalConfig) -> Layout {
+        self.layout.or(global_config.layout).unwrap_or_default()
+    }
+
+    pub fn video_handling(&self, global_config: &GlobalConfig) -> VideoHandling {
+        self.video_handling
+            .or(global_config.video_handling)
+            .unwrap_or_default()
+    }
+
+    /// How to choose between the enclosures of items that offer several.
+    pub fn media_preference(&self, global_config: &GlobalConfig) -> MediaPreference {
+        let types = self
+            .preferred_media
+            .clone()
+            .or_else(|| global_config.preferred_media.clone())
+            .unwrap_or_else(|| media::DEFAULT_PREFERRED_MEDIA.map(String::from).to_vec());
+        let size = self
+            .media_size
+            .or(global_config.media_size)
+            .unwrap_or_default();
+        MediaPreference::new(types, size)
+    }
+
+    /// Refuses to sync a podcast that was downloaded with another layout, since its
+    /// episodes would be downloaded again.
+    pub fn check_layout(&self, name: &str, global_config: &GlobalConfig) -> Result<(), String> {
+        if self.tracker_path(name, global_config).exists() {
+            return Ok(());
+        }
+
+        let layout = self.layout(global_config);
+        let mut other = self.clone();
+        other.layout = Some(layout.other());
+
+        // A download path that can't be flat was never used with the flat layout.
+        let pattern = self.podcast_download_pattern(global_config);
+        if layout == Layout::PerPodcast && Self::shared_download_pattern(&pattern).is_none() {
+            return Ok(());
+        }
+
+        if other.tracker_path(name, global_config).exists() {
+            return Err(format!(
+                "downloaded with the {} layout, migrate with: talecast --move \"{}\" --layout {}",
+                layout.other().name(),
+                name,
+                layout.name()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Where to save the funding links of the podcast.
+    pub fn funding_path(&self, name: &str, global_config: &GlobalConfig) -> PathBuf {
+        let file_name = match self.layout(global_config) {
+            Layout::PerPodcast => "FUNDING.txt".to_string(),
+            Layout::Flat => format!("{}FUNDING.txt", flat_prefix(name)),
+        };
+        self.download_dir(name, global_config).join(file_name)
+    }
+
+    /// The headers sent along when fetching the feed.
+    ///
+    /// The podcast's `feed_headers` override the global ones, and both override its
+    /// credentials and the default `Accept` header. Names are case-insensitive.
+    pub fn feed_headers(&self, global_config: &GlobalConfig) -> Result<HeaderMap, String> {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(net::FEED_ACCEPT));
+        headers.extend(self.credential_headers()?);
+        insert_headers(
+            &mut headers,
+            global_config.feed_headers.iter().chain(&self.feed_headers),
+        );
+
+        Ok(headers)
+    }
+
+    /// What the podcast sends along with the requests for its episodes and images, on top
+    /// of the feed.
+    pub fn credentials(&self) -> Result<Credentials, String> {
+        Ok(Credentials::new(&self.url, self.credential_headers()?))
+    }
+
+    /// The `headers` table, and the `Authorization` header if `username` is set.
+    ///
+    /// Fails if `password_env` names a variable that isn't set, rather than trying without
+    /// the password.
+    fn credential_headers(&self) -> Result<HeaderMap, String> {
+        let mut headers = HeaderMap::new();
+        insert_headers(&mut headers, &self.headers);
+
+        let Some(username) = &self.username else {
+            return Ok(headers);
+        };
+
+        let password = match (&self.password_env, &self.password) {
+            (Some(var), _) => Some(std::env::var(var).map_err(|_| {
+                format!("password_env: the environment variable {} isn't set", var)
+            })?),
+            (None, password) => password.clone(),
+        };
+
+        let token = format!("{}:{}", username, password.unwrap_or_default());
+        let mut value = HeaderValue::from_str(&format!("Basic {}", BASE64.encode(token)))
+            .map_err(|_| "username: not a valid header value".to_string())?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+
+        Ok(headers)
+    }
+
+    /// Whether to guess episode numbers from titles when the feed doesn't set `itunes:episode`.
+    pub fn infer_episode_number(&self, global_config: &GlobalConfig) -> bool {
+        self.infer_episode_number
+            .or(global_config.infer_episode_number)
+            .unwrap_or(false)
+    }
+
+    /// Whether to write episode descriptions into the tags as the feed has them, html included.
+    pub fn keep_description_html(&self, global_config: &GlobalConfig) -> bool {
+        self.keep_description_html
+            .or(global_config.keep_description_html)
+            .unwrap_or(false)
+    }
+
+    /// The date the podcast is paused until, if that date hasn't passed yet.
+    pub fn paused_until(&self, name: &str) -> Option<Unix> {
+        let date = self.paused_until.as_ref()?;
+        let unix = match utils::date_str_to_unix(date) {
+            Ok(unix) => unix,
+            Err(e) => {
+                eprintln!("invalid 'paused_until' for '{}': {}", name, e);
+                process::exit(1);
+            }
+        };
+
+        if unix <= utils::current_unix() {
+            eprintln!(
+                "warning: '{}' was paused until {}, which has passed",
+                name, date
+            );
+            return None;
+        }
+
+        Some(unix)
+    }
+
+    pub fn is_backlog(&self) -> bool {
+        self.backlog_start.is_some() || self.backlog_interval.is_some()
+    }
+
+    /// A setting of the standard download mode that the podcast sets itself, which can't be
+    /// combined with backlog mode.
+    fn standard_mode_setting(&self) -> Option<&'static str> {
+        [
+            ("max_days", self.max_days.is_enabled()),
+            ("max_episodes", self.max_episodes.is_enabled()),
+            (
+                "min_episodes_retained",
+                self.min_episodes_retained.is_some(),
+            ),
+            ("max_per_day", self.max_per_day.is_enabled()),
+            ("keep_latest", self.keep_latest.is_enabled()),
+        ]
+        .into_iter()
+        .find(|(_, set)| *set)
+        .map(|(key, _)| key)
+    }
+
+    /// Checks that the settings deciding the download mode agree with each other.
+    ///
+    /// Backlog mode needs both `backlog_start` and `backlog_interval`, and the limits of
+    /// the standard mode set for the podcast would be ignored in it. A `false` leaves them
+    /// unset, for turning off the global ones.
+    fn check_download_mode(&self) -> Result<(), String> {
+        let start = match (&self.backlog_start, self.backlog_interval) {
+            (None, None) => return Ok(()),
+            (Some(_), None) => {
+                return Err("'backlog_start' is set without 'backlog_interval'".to_string())
+            }
+            (None, Some(_)) => {
+                return Err("'backlog_interval' is set without 'backlog_start'".to_string())
+            }
+            (Some(start), Some(_)) => start,
+        };
+
+        if dateparser::parse(start).is_err() {
+            return Err(format!(
+                "invalid 'backlog_start': \"{}\" isn't a date",
+                start
+            ));
+        }
+
+        match self.standard_mode_setting() {
+            Some("max_episodes") => Err(
+                "'max_episodes' can't be combined with 'backlog_start' and 'backlog_interval', \
+                 change 'backlog_start' to limit how many episodes are downloaded"
+                    .to_string(),
+            ),
+            Some(key) => Err(format!(
+                "'{}' can't be combined with 'backlog_start' and 'backlog_interval'",
+                key
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// The `earliest_date` and `latest_date` that limit a backlog to part of the feed.
+    ///
+    /// Only the podcast's own dates count, since the global ones are meant for following new
+    /// episodes rather than for going through old ones.
+    pub fn backlog_dates(&self) -> (Option<Unix>, Option<Unix>) {
+        if !self.is_backlog() {
+            return (None, None);
+        }
+
+        let earliest_date = self
+            .earliest_date
+            .clone()
+            .into_val(None)
+            .and_then(|date| utils::day_str_to_unix(&date).ok());
+        let latest_date = self
+            .latest_date
+            .clone()
+            .into_val(None)
+            .and_then(|date| utils::day_str_to_unix(&date).ok());
+
+        (earliest_date, latest_date)
+    }
+
+    /// Episodes published before this date are acknowledged: they're skipped as if they had
+    /// been downloaded, because of `earliest_date`.
+    pub fn acknowledged_before(&self, global_config: &GlobalConfig) -> Option<Unix> {
+        if self.is_backlog() {
+            return None;
+        }
+
+        self.earliest_date
+            .clone()
+            .into_val(global_config.earliest_date.as_ref())
+            .and_then(|date| utils::day_str_to_unix(&date).ok())
+    }
+
+    /// Moves `earliest_date` back to `since`, so the acknowledged episodes published since
+    /// then are downloaded. Returns whether any could be.
+    ///
+    /// Remember to save after calling this function.
+    pub fn un_acknowledge(&mut self, since: Unix, global_config: &GlobalConfig) -> bool {
+        match self.acknowledged_before(global_config) {
+            Some(before) if since < before => {
+                self.earliest_date = ConfigOption::Enabled(earliest_date_str(since));
+                true
+            }
+            _ => false,
+        }
+    }
+}
 
 #[derive(Serialize, Default, Deserialize, Debug, PartialEq, Clone)]
 #[serde(deny_unknown_fields)]
@@ -900,3 +3421,388 @@ impl LogConfig {
         self == &Self::default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn podcast_urls(path: &Path) -> Vec<(String, String)> {
+        let content = fs::read_to_string(path).unwrap();
+        let podcasts: HashMap<String, PodcastConfig> = toml::from_str(&content).unwrap();
+        let mut urls: Vec<(String, String)> = podcasts
+            .into_iter()
+            .map(|(name, config)| (name, config.url))
+            .collect();
+        urls.sort();
+        urls
+    }
+
+    fn move_feed(podcasts: &mut PodcastConfigs) {
+        if let Some(config) = podcasts.0.get_mut("a") {
+            config.url = "https://example.com/moved.xml".to_string();
+        }
+    }
+
+    fn podcast(toml: &str) -> PodcastConfig {
+        toml::from_str(&format!("url = \"https://example.com/feed.xml\"\n{}", toml)).unwrap()
+    }
+
+    const BACKLOG: &str = "backlog_start = \"2024-01-01\"\nbacklog_interval = 7\n";
+
+    #[test]
+    fn partial_backlog() {
+        assert_eq!(
+            podcast("backlog_start = \"2024-01-01\"").check_download_mode(),
+            Err("'backlog_start' is set without 'backlog_interval'".to_string())
+        );
+        assert_eq!(
+            podcast("backlog_interval = 7").check_download_mode(),
+            Err("'backlog_interval' is set without 'backlog_start'".to_string())
+        );
+        assert_eq!(
+            podcast("backlog_start = \"someday\"\nbacklog_interval = 7").check_download_mode(),
+            Err("invalid 'backlog_start': \"someday\" isn't a date".to_string())
+        );
+        assert_eq!(podcast(BACKLOG).check_download_mode(), Ok(()));
+        assert_eq!(podcast("").check_download_mode(), Ok(()));
+    }
+
+    #[test]
+    fn backlog_with_standard_limits() {
+        for key in [
+            "max_days",
+            "min_episodes_retained",
+            "max_per_day",
+            "keep_latest",
+        ] {
+            let config = podcast(&format!("{}{} = 3", BACKLOG, key));
+            assert_eq!(
+                config.check_download_mode(),
+                Err(format!(
+                    "'{}' can't be combined with 'backlog_start' and 'backlog_interval'",
+                    key
+                ))
+            );
+        }
+
+        let err = podcast(&format!("{}max_episodes = 3", BACKLOG))
+            .check_download_mode()
+            .unwrap_err();
+        assert!(err.starts_with("'max_episodes' can't be combined"));
+        assert!(err.contains("change 'backlog_start'"));
+    }
+
+    #[test]
+    fn backlog_with_limits_turned_off() {
+        for key in ["max_days", "max_episodes", "max_per_day", "keep_latest"] {
+            let config = podcast(&format!("{}{} = false", BACKLOG, key));
+            assert_eq!(config.check_download_mode(), Ok(()), "{}", key);
+        }
+    }
+
+    #[test]
+    fn ignored_global_limits() {
+        let global: GlobalConfig =
+            toml::from_str("max_days = 30\nkeep_latest = 5\nearliest_date = \"2024-01-01\"")
+                .unwrap();
+        let podcasts = PodcastConfigs(HashMap::from([
+            ("standard".to_string(), podcast("")),
+            ("backlog".to_string(), podcast(BACKLOG)),
+            (
+                "turned off".to_string(),
+                podcast(&format!("{}max_days = false\nkeep_latest = false", BACKLOG)),
+            ),
+        ]));
+
+        assert_eq!(
+            podcasts.ignored_global_limits(&global),
+            vec![
+                "backlog: max_days, keep_latest, earliest_date",
+                "turned off: earliest_date",
+            ]
+        );
+    }
+
+    #[test]
+    fn default_global_limits_are_not_ignored() {
+        let global: GlobalConfig = toml::from_str("max_episodes = 10").unwrap();
+        let podcasts = PodcastConfigs(HashMap::from([("backlog".to_string(), podcast(BACKLOG))]));
+
+        assert!(podcasts.ignored_global_limits(&global).is_empty());
+        assert!(podcasts
+            .ignored_global_limits(&GlobalConfig::default())
+            .is_empty());
+    }
+
+    #[test]
+    fn edits_keep_changes_made_during_the_run() {
+        let path = paths::test_dir("edit-during-run").join("podcasts.toml");
+        fs::write(&path, "[a]\nurl = \"https://example.com/a.xml\"\n").unwrap();
+        let stamp = ConfigStamp::of(&path);
+
+        // The user adds a podcast while the run is going on.
+        fs::write(
+            &path,
+            "[a]\nurl = \"https://example.com/a.xml\"\n\n[b]\nurl = \"https://example.com/b.xml\"\n",
+        )
+        .unwrap();
+        assert!(!stamp.is_current(&path));
+
+        PodcastConfigs::edit_file_at(&path, &stamp, move_feed).unwrap();
+
+        assert_eq!(
+            podcast_urls(&path),
+            [
+                ("a".to_string(), "https://example.com/moved.xml".to_string()),
+                ("b".to_string(), "https://example.com/b.xml".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn edits_are_applied_again_when_the_file_changes_while_saving() {
+        let path = paths::test_dir("edit-while-saving").join("podcasts.toml");
+        fs::write(&path, "[a]\nurl = \"https://example.com/a.xml\"\n").unwrap();
+        let stamp = ConfigStamp::of(&path);
+
+        let mut edits = 0;
+        PodcastConfigs::edit_file_at(&path, &stamp, |podcasts| {
+            edits += 1;
+            if edits == 1 {
+                // The user saves their edit after the file was read for this one.
+                fs::write(
+                    &path,
+                    "[a]\nurl = \"https://example.com/a.xml\"\n\n[c]\nurl = \"https://example.com/c.xml\"\n",
+                )
+                .unwrap();
+            }
+            move_feed(podcasts);
+        })
+        .unwrap();
+
+        assert_eq!(edits, 2);
+        assert_eq!(
+            podcast_urls(&path),
+            [
+                ("a".to_string(), "https://example.com/moved.xml".to_string()),
+                ("c".to_string(), "https://example.com/c.xml".to_string()),
+            ]
+        );
+    }
+
+    fn collisions(global: &str, names: &[&str]) -> Vec<(String, String)> {
+        let global: GlobalConfig = toml::from_str(&format!(
+            "download_path = \"/podcasts/{{podname}}\"\n{}",
+            global
+        ))
+        .unwrap();
+        let podcasts = PodcastConfigs(
+            names
+                .iter()
+                .map(|name| (name.to_string(), podcast("")))
+                .collect(),
+        );
+
+        podcasts
+            .collisions(&global)
+            .into_iter()
+            .map(|(name, other, _)| (name, other))
+            .collect()
+    }
+
+    #[test]
+    fn names_that_fold_to_the_same_path_collide() {
+        assert_eq!(
+            collisions("", &["Café", "Cafe\u{301}", "CAFÉ"]),
+            [
+                ("CAFÉ".to_string(), "Cafe\u{301}".to_string()),
+                ("CAFÉ".to_string(), "Café".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn names_that_differ_in_case_collide() {
+        assert_eq!(
+            collisions("", &["Ελληνικά Νέα", "ελληνικά νέα", "Ελληνικά"]),
+            [("Ελληνικά Νέα".to_string(), "ελληνικά νέα".to_string())]
+        );
+    }
+
+    #[test]
+    fn flat_layout_trackers_collide() {
+        assert_eq!(
+            collisions("layout = \"flat\"", &["Ünïcode", "ÜNÏCODE", "Unicode"]),
+            [("ÜNÏCODE".to_string(), "Ünïcode".to_string())]
+        );
+    }
+
+    #[test]
+    fn distinct_names_dont_collide() {
+        assert!(collisions("", &["Café", "Cafe", "日本", "日本語"]).is_empty());
+    }
+
+    #[test]
+    fn tracker_pattern_without_feed() {
+        let global = GlobalConfig::default();
+        assert_eq!(podcast("").check_tracker_pattern(&global), Ok(()));
+        assert_eq!(
+            podcast("download_path = \"/pods/{podname}/{currdate::%Y}\"")
+                .check_tracker_pattern(&global),
+            Ok(())
+        );
+
+        let err = podcast("download_path = \"/pods/{rss::channel::title|sanitize}\"")
+            .check_tracker_pattern(&global)
+            .unwrap_err();
+        assert!(err.starts_with("'download_path' uses {rss::channel::title|sanitize}"));
+
+        let config = podcast(
+            "download_path = \"/pods/{podcast_title}\"\ntracker_path = \"/pods/.{podname}\"",
+        );
+        assert_eq!(config.check_tracker_pattern(&global), Ok(()));
+
+        let err = podcast("tracker_path = \"/pods/{title}.downloaded\"")
+            .check_tracker_pattern(&global)
+            .unwrap_err();
+        assert!(err.starts_with("'tracker_path' uses {title}"));
+
+        let global: GlobalConfig = toml::from_str("tracker_path = \"/t/{guid}\"").unwrap();
+        assert!(podcast("").check_tracker_pattern(&global).is_err());
+    }
+
+    #[test]
+    fn download_dir_stops_at_feed_data() {
+        let global = GlobalConfig::default();
+        let dir = |toml: &str| podcast(toml).download_dir("name", &global);
+        assert_eq!(
+            dir("download_path = \"/pods/{podname}\""),
+            PathBuf::from("/pods/name")
+        );
+        assert_eq!(
+            dir("download_path = \"/pods/{podname}/{pubdate::%Y}\""),
+            PathBuf::from("/pods/name")
+        );
+        assert_eq!(
+            dir("download_path = \"/pods/{podname}/{podcast_title} ({pubdate::%Y})\""),
+            PathBuf::from("/pods/name")
+        );
+
+        let shared = |toml: &str| {
+            let configs: PodcastConfigs = [("a", podcast(toml)), ("b", podcast(toml))]
+                .into_iter()
+                .map(|(name, config)| (name.to_string(), config))
+                .collect();
+            configs.collisions(&global)
+        };
+        let per_title =
+            "download_path = \"/pods/{podcast_title}\"\ntracker_path = \"/t/{podname}\"";
+        assert!(shared(per_title).is_empty());
+        assert_eq!(shared("download_path = \"/pods\"").len(), 1);
+    }
+
+    #[test]
+    fn undefined_roots_are_reported() {
+        let global: GlobalConfig =
+            toml::from_str("[roots]\nfast = \"/ssd\"\narchive = \"/hdd\"").unwrap();
+        assert_eq!(podcast("").check_root(&global), Ok(()));
+        assert_eq!(podcast("root = \"archive\"").check_root(&global), Ok(()));
+        assert_eq!(
+            podcast("root = \"slow\"").check_root(&global),
+            Err(
+                "undefined download root: 'slow', defined roots: [\"archive\", \"fast\"]"
+                    .to_string()
+            )
+        );
+
+        let global: GlobalConfig =
+            toml::from_str("default_root = \"slow\"\n[roots]\nfast = \"/ssd\"").unwrap();
+        assert!(podcast("").check_root(&global).is_err());
+        assert_eq!(podcast("root = \"fast\"").check_root(&global), Ok(()));
+        assert_eq!(
+            podcast("root = \"fast\"").download_dir("name", &global),
+            PathBuf::from("/ssd/name")
+        );
+    }
+
+    #[test]
+    fn trash_per_root() {
+        let global: GlobalConfig = toml::from_str(
+            "download_path = \"/pods/{podname}\"\ntrash_dir = \"{root}/.trash\"\n[roots]\nfast = \"/ssd\"\narchive = \"/hdd\"",
+        )
+        .unwrap();
+        assert_eq!(
+            global.trash_dir(Some("fast")),
+            Some(PathBuf::from("/ssd/.trash"))
+        );
+        assert_eq!(global.trash_dir(None), Some(PathBuf::from("/pods/.trash")));
+        assert_eq!(
+            global.trash_dirs(),
+            vec![
+                PathBuf::from("/hdd/.trash"),
+                PathBuf::from("/pods/.trash"),
+                PathBuf::from("/ssd/.trash"),
+            ]
+        );
+
+        let global: GlobalConfig =
+            toml::from_str("trash_dir = \"/trash\"\n[roots]\nfast = \"/ssd\"").unwrap();
+        assert_eq!(
+            global.trash_dir(Some("fast")),
+            Some(PathBuf::from("/trash"))
+        );
+        assert_eq!(global.trash_dirs(), vec![PathBuf::from("/trash")]);
+        assert!(GlobalConfig::default().trash_dirs().is_empty());
+    }
+
+    #[test]
+    fn chapter_images_precedence() {
+        let global = GlobalConfig::default();
+        assert!(podcast("").chapter_images(&global));
+        assert!(!podcast("chapter_images = false").chapter_images(&global));
+
+        let global: GlobalConfig = toml::from_str("chapter_images = false").unwrap();
+        assert!(!podcast("").chapter_images(&global));
+        assert!(podcast("chapter_images = true").chapter_images(&global));
+    }
+
+    #[test]
+    fn network_timeouts() {
+        let global = GlobalConfig::default();
+        assert_eq!(
+            global.connect_timeout(),
+            Some(time::Duration::from_secs(10))
+        );
+        assert_eq!(global.timeout(), Some(time::Duration::from_secs(300)));
+
+        let global: GlobalConfig = toml::from_str("connect_timeout = 0\ntimeout = 0").unwrap();
+        assert_eq!((global.connect_timeout(), global.timeout()), (None, None));
+    }
+
+    #[tokio::test]
+    async fn connect_timeout_fires_before_the_handshake() {
+        use std::os::fd::AsRawFd;
+
+        // With a full accept queue the kernel drops new handshakes, so connecting stalls.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        assert_eq!(unsafe { libc::listen(listener.as_raw_fd(), 0) }, 0);
+        let addr = listener.local_addr().unwrap();
+        let _queued: Vec<_> = (0..2)
+            .filter_map(|_| {
+                std::net::TcpStream::connect_timeout(&addr, time::Duration::from_millis(200)).ok()
+            })
+            .collect();
+
+        let global: GlobalConfig = toml::from_str("connect_timeout = 1\ntimeout = 0").unwrap();
+        let client = client_builder(&global).build().unwrap();
+        let started = time::Instant::now();
+        let e = client
+            .get(format!("http://{}/feed.xml", addr))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(e.is_connect() && e.is_timeout(), "{:?}", e);
+        assert!(started.elapsed() < time::Duration::from_secs(5));
+    }
+}
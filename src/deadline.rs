@@ -0,0 +1,155 @@
+//! A time budget for a sync, after which no new work is started.
+//!
+//! Enabled with `--max-runtime 12m`. Once it has passed, no more feeds are fetched and no
+//! more episodes downloaded. Downloads that are already running get `--grace` to finish,
+//! after which they're suspended and resumed from their partial file on the next sync.
+
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::time::Instant;
+
+static DEADLINE: OnceLock<Deadline> = OnceLock::new();
+
+#[cfg(test)]
+tokio::task_local! {
+    /// The budget of a single test, leaving other tests without one.
+    static SCOPED: Deadline;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Deadline {
+    /// When no new work is started anymore.
+    soft: Instant,
+    /// When running downloads are suspended.
+    hard: Instant,
+}
+
+impl Deadline {
+    fn new(max_runtime: Duration, grace: Duration) -> Self {
+        let soft = Instant::now() + max_runtime;
+        Self {
+            soft,
+            hard: soft + grace,
+        }
+    }
+}
+
+/// The budget of the sync, if there's one.
+fn current() -> Option<Deadline> {
+    #[cfg(test)]
+    if let Ok(deadline) = SCOPED.try_with(|deadline| *deadline) {
+        return Some(deadline);
+    }
+
+    DEADLINE.get().copied()
+}
+
+/// Parses a duration like `90s`, `12m` or `1h`. A plain number is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => s.split_at(index),
+        None => (s, "s"),
+    };
+
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => 0,
+    };
+
+    match number.parse::<u64>() {
+        Ok(number) if secs > 0 => Ok(Duration::from_secs(number * secs)),
+        _ => Err(format!(
+            "invalid duration: \"{}\", expected e.g. 90s, 12m or 1h",
+            s
+        )),
+    }
+}
+
+/// Starts the budget, counting from now.
+pub fn init(max_runtime: Duration, grace: Duration) {
+    let deadline = Deadline::new(max_runtime, grace);
+
+    log::info!(
+        "stopping new work after {:?}, and running downloads {:?} after that",
+        max_runtime,
+        grace
+    );
+
+    if DEADLINE.set(deadline).is_err() {
+        log::warn!("max runtime already set");
+    }
+}
+
+/// Runs `future` with a budget starting now, for tests.
+#[cfg(test)]
+pub async fn limit<F: Future>(max_runtime: Duration, grace: Duration, future: F) -> F::Output {
+    SCOPED
+        .scope(Deadline::new(max_runtime, grace), future)
+        .await
+}
+
+/// Whether new work should no longer be started.
+pub fn passed() -> bool {
+    current().is_some_and(|deadline| Instant::now() >= deadline.soft)
+}
+
+/// Whether running downloads should be suspended.
+pub fn grace_expired() -> bool {
+    current().is_some_and(|deadline| Instant::now() >= deadline.hard)
+}
+
+/// Runs `fut` until it completes, or returns `None` if the grace period runs out first.
+pub async fn within_grace<F: Future>(fut: F) -> Option<F::Output> {
+    match current() {
+        Some(deadline) => tokio::time::timeout_at(deadline.hard, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration(" 12m "), Ok(Duration::from_secs(12 * 60)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(60 * 60)));
+        assert_eq!(
+            parse_duration("1d"),
+            Err("invalid duration: \"1d\", expected e.g. 90s, 12m or 1h".to_string())
+        );
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("1.5h").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn budget_and_grace() {
+        assert!(!passed());
+        assert_eq!(within_grace(async { 1 }).await, Some(1));
+
+        let minute = Duration::from_secs(60);
+        limit(minute, minute, async {
+            tokio::time::advance(minute - Duration::from_secs(1)).await;
+            assert!(!passed());
+
+            tokio::time::advance(Duration::from_secs(1)).await;
+            assert!(passed());
+            assert!(!grace_expired());
+            let finished = tokio::time::sleep(Duration::from_secs(30));
+            assert_eq!(within_grace(finished).await, Some(()));
+
+            // Downloads that don't finish in time are stopped when the grace period ends.
+            let started = Instant::now();
+            assert_eq!(within_grace(std::future::pending::<()>()).await, None);
+            assert_eq!(started.elapsed(), Duration::from_secs(30));
+            assert!(grace_expired());
+        })
+        .await;
+    }
+}
@@ -1,10 +1,21 @@
 use crate::config::IndicatifSettings;
 use crate::episode::Episode;
-use crate::utils;
+use crate::estimate::RunProgress;
+use crate::format;
+use crate::net;
+use crate::summary::Phase;
+use crate::summary::PhaseTimings;
+use crate::text;
+use crate::throttle;
 use indicatif::MultiProgress;
 use indicatif::ProgressBar;
+use indicatif::ProgressState;
 use indicatif::ProgressStyle;
+use std::fmt::Write;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
 
 #[derive(Debug)]
 pub struct DownloadBar {
@@ -13,6 +24,11 @@ pub struct DownloadBar {
     longest_podcast_name: usize,
     settings: Arc<IndicatifSettings>,
     completed: bool,
+    /// Whether several episodes are downloading at once, so the bar shows their combined
+    /// progress instead of that of one episode.
+    concurrent: bool,
+    timings: Mutex<PhaseTimings>,
+    run: Option<Arc<RunProgress>>,
 }
 
 impl DownloadBar {
@@ -34,9 +50,18 @@ impl DownloadBar {
             podcast_name,
             longest_podcast_name,
             completed: false,
+            concurrent: false,
+            timings: Default::default(),
+            run: None,
         }
     }
 
+    /// Reports the downloads of this podcast to the progress of the whole run.
+    pub fn with_run_progress(mut self, run: Arc<RunProgress>) -> Self {
+        self.run = Some(run);
+        self
+    }
+
     pub fn log_debug(&self, msg: impl Into<String>) {
         log::debug!("{}: {}", &self.podcast_name, msg.into());
     }
@@ -57,6 +82,49 @@ impl DownloadBar {
         log::error!("{}: {}", &self.podcast_name, msg.into());
     }
 
+    /// Adds to the time spent on a phase of syncing this podcast.
+    pub fn record(&self, phase: Phase, elapsed: Duration) {
+        self.log_trace(format!("{} took {:?}", phase.name(), elapsed));
+        self.timings.lock().unwrap().add(phase, elapsed);
+    }
+
+    pub fn timings(&self) -> PhaseTimings {
+        *self.timings.lock().unwrap()
+    }
+
+    pub fn add_pending(&self, url: &str, length: Option<u64>) {
+        if let Some(run) = &self.run {
+            run.add(&self.podcast_name, url, length);
+        }
+    }
+
+    pub fn receive(&self, url: &str, bytes: u64) {
+        if let Some(run) = &self.run {
+            run.receive(&self.podcast_name, url, bytes);
+        }
+
+        if let Some(pb) = self.bar.as_ref().filter(|_| self.concurrent) {
+            pb.inc(bytes);
+            // Episodes without a length in the feed aren't part of the total.
+            if pb.length().is_some_and(|length| pb.position() > length) {
+                pb.set_length(pb.position());
+            }
+        }
+    }
+
+    pub fn finish_download(&self, url: &str, elapsed: Duration, success: bool) {
+        if let Some(run) = &self.run {
+            run.finish(&self.podcast_name, url, elapsed, success);
+        }
+    }
+
+    /// Forgets the downloads of this podcast that didn't happen.
+    pub fn finish_pending(&self) {
+        if let Some(run) = &self.run {
+            run.finish_podcast(&self.podcast_name);
+        }
+    }
+
     fn prefix(&self) -> String {
         let pad_len = self.longest_podcast_name + 2 - self.podcast_name.chars().count();
         let padding: String = std::iter::repeat(' ').take(pad_len).collect();
@@ -70,7 +138,7 @@ impl DownloadBar {
     pub fn fetching(&self) {
         if let Some(pb) = &self.bar {
             let template = IndicatifSettings::podcast_fetch_template();
-            pb.set_style(ProgressStyle::default_bar().template(&template).unwrap());
+            pb.set_style(bar_style(&template));
 
             let msg = self.prefix();
             pb.set_message(msg);
@@ -78,10 +146,18 @@ impl DownloadBar {
         }
     }
 
+    /// Shows that the podcast waits for a turn to sync.
+    pub fn waiting(&self) {
+        if let Some(pb) = &self.bar {
+            pb.set_style(bar_style(&IndicatifSettings::waiting_template()));
+            pb.set_message(self.prefix());
+        }
+    }
+
     pub fn init(&self) {
         if let Some(pb) = &self.bar {
             let template = self.settings.download_template();
-            pb.set_style(ProgressStyle::default_bar().template(&template).unwrap());
+            pb.set_style(bar_style(&template));
             pb.enable_steady_tick(self.settings.spinner_speed());
         }
     }
@@ -91,7 +167,7 @@ impl DownloadBar {
             let fitted_episode_title = {
                 let title_length = self.settings.title_length();
                 let padded = &format!("{:<width$}", episode.attrs.title(), width = title_length);
-                utils::truncate_string(padded, title_length, true)
+                text::truncate_string(padded, title_length, true)
             };
 
             let msg = format!(
@@ -104,13 +180,43 @@ impl DownloadBar {
             );
 
             pb.set_message(msg);
+            if !self.concurrent {
+                pb.set_position(0);
+            }
+        }
+    }
+
+    /// Shows the combined progress of the episodes from here on, which download at once.
+    ///
+    /// `total_size` is the sum of their lengths.
+    pub fn begin_concurrent(&mut self, total_size: u64) {
+        self.concurrent = true;
+        if let Some(pb) = &self.bar {
+            pb.set_length(total_size);
             pb.set_position(0);
         }
     }
 
+    /// Shows that a failed download is tried again after a delay.
+    pub fn retrying(&self, retry: usize, retries: usize, delay: Duration) {
+        if let Some(pb) = &self.bar {
+            let msg = format!(
+                "{}retrying {}/{} in {}",
+                pb.message(),
+                retry,
+                retries,
+                format::human_duration(delay)
+            );
+            pb.set_message(msg);
+            if !self.concurrent {
+                pb.set_position(0);
+            }
+        }
+    }
+
     pub fn set_template(&self, style: &str) {
         if let Some(pb) = &self.bar {
-            pb.set_style(ProgressStyle::default_bar().template(style).unwrap());
+            pb.set_style(bar_style(style));
         }
     }
 
@@ -120,14 +226,14 @@ impl DownloadBar {
     }
 
     pub fn init_download_bar(&self, start_point: u64, total_size: u64) {
-        if let Some(pb) = &self.bar {
+        if let Some(pb) = self.bar.as_ref().filter(|_| !self.concurrent) {
             pb.set_length(total_size);
             pb.set_position(start_point);
         }
     }
 
     pub fn set_progress(&self, progress: u64) {
-        if let Some(pb) = &self.bar {
+        if let Some(pb) = self.bar.as_ref().filter(|_| !self.concurrent) {
             pb.set_position(progress);
         }
     }
@@ -147,7 +253,23 @@ impl DownloadBar {
         }
     }
 
+    /// Like [`Self::error`], but renders the url involved on a second line.
+    pub fn error_with_url(&mut self, msg: &str, url: &str) {
+        let indent = " ".repeat(self.prefix().chars().count() + 3);
+        let msg = format!("{}\n{}{}", msg, indent, net::redact_url(url));
+        self.error(&msg);
+    }
+
     pub fn complete(&mut self) {
+        self.finish(self.podcast_name.clone());
+    }
+
+    /// Completes the bar of a podcast whose feed was skipped because it hadn't changed.
+    pub fn up_to_date(&mut self) {
+        self.finish(format!("{}up to date", self.prefix()));
+    }
+
+    fn finish(&mut self, msg: String) {
         if self.completed {
             return;
         }
@@ -155,8 +277,103 @@ impl DownloadBar {
         if let Some(pb) = &self.bar {
             let template = self.settings.completion_template();
             self.set_template(&template);
-            pb.finish_with_message(self.podcast_name.clone());
+            pb.finish_with_message(msg);
             self.completed = true;
         }
     }
 }
+
+/// A progress bar style whose sizes and durations are formatted like everywhere else.
+///
+/// Templates from the config can use the usual `{bytes}`, `{total_bytes}`, `{bytes_per_sec}`,
+/// `{elapsed}` and `{eta}` keys.
+fn bar_style(template: &str) -> ProgressStyle {
+    ProgressStyle::default_bar()
+        .template(template)
+        .unwrap()
+        .with_key("bytes", |state: &ProgressState, w: &mut dyn Write| {
+            let _ = write!(w, "{}", format::human_bytes(state.pos()));
+        })
+        .with_key("total_bytes", |state: &ProgressState, w: &mut dyn Write| {
+            let _ = write!(w, "{}", format::human_bytes(state.len().unwrap_or(0)));
+        })
+        .with_key(
+            "bytes_per_sec",
+            |state: &ProgressState, w: &mut dyn Write| {
+                let _ = write!(w, "{}/s", format::human_bytes(state.per_sec() as u64));
+            },
+        )
+        .with_key("elapsed", |state: &ProgressState, w: &mut dyn Write| {
+            let _ = write!(w, "{}", format::human_duration(state.elapsed()));
+        })
+        .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
+            let _ = write!(w, "{}", format::human_duration(state.eta()));
+        })
+}
+
+/// A line below the podcasts showing how much is left to download in the whole run.
+pub struct TotalBar {
+    bar: Option<ProgressBar>,
+    ticker: Option<JoinHandle<()>>,
+}
+
+impl TotalBar {
+    const TEMPLATE: &'static str = "{spinner:.green}  {msg}";
+    const REFRESH: Duration = Duration::from_millis(500);
+
+    pub fn new(settings: &IndicatifSettings, mp: &MultiProgress, run: Arc<RunProgress>) -> Self {
+        if !settings.enabled() {
+            return Self {
+                bar: None,
+                ticker: None,
+            };
+        }
+
+        let bar = mp.add(ProgressBar::new_spinner());
+        bar.set_style(bar_style(Self::TEMPLATE));
+        bar.enable_steady_tick(settings.spinner_speed());
+
+        let ticker = {
+            let bar = bar.clone();
+            tokio::task::spawn(async move {
+                loop {
+                    bar.set_message(Self::message(&run));
+                    tokio::time::sleep(Self::REFRESH).await;
+                }
+            })
+        };
+
+        Self {
+            bar: Some(bar),
+            ticker: Some(ticker),
+        }
+    }
+
+    fn message(run: &RunProgress) -> String {
+        let eta = match run.estimate() {
+            Some(eta) => format!("~ {}", format::human_duration(eta)),
+            None => "~ unknown".to_string(),
+        };
+
+        let mut message = format!(
+            "total: {} left, {}",
+            format::human_bytes(run.remaining()),
+            eta
+        );
+        if let Some(limit) = throttle::adaptive_limit() {
+            message.push_str(&format!(", {} downloads at once", limit));
+        }
+
+        message
+    }
+
+    pub fn finish(self) {
+        if let Some(ticker) = self.ticker {
+            ticker.abort();
+        }
+
+        if let Some(bar) = self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
@@ -1,16 +1,89 @@
-use crate::episode::DownloadedEpisode;
+use crate::episode::Episode;
+use crate::paths;
+use crate::read_only;
+use crate::text;
 use crate::utils;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::fs;
 use std::path::Path;
 
+/// Marks the tracker entries that catching up wrote, followed by the publication time.
+const ACKNOWLEDGED: &str = "acknowledged=";
+
 /// Keeps track of which episodes have already been downloaded.
 #[derive(Debug, Default)]
-pub struct DownloadedEpisodes(HashSet<String>);
+pub struct DownloadedEpisodes {
+    ids: HashSet<String>,
+    /// The ones that were acknowledged by catching up, rather than downloaded.
+    acknowledged: HashSet<String>,
+}
+
+/// A single line of the download tracker.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrackerEntry {
+    pub id: String,
+    /// Unix time of when the episode was downloaded, or acknowledged.
+    pub downloaded: u64,
+    pub title: String,
+    /// Unix time of when an episode that catching up acknowledged was published.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged: Option<u64>,
+}
+
+impl TrackerEntry {
+    fn from_line(line: &str) -> Option<Self> {
+        let (id, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let (downloaded, title) = rest.split_once(' ').unwrap_or((rest, ""));
+
+        if id.is_empty() {
+            return None;
+        }
+
+        // Titles are quoted, so one that ends in the marker isn't mistaken for it.
+        let acknowledged = title.rsplit_once(' ').and_then(|(title, status)| {
+            let published = status.strip_prefix(ACKNOWLEDGED)?.parse().ok()?;
+            Some((title, published))
+        });
+        let (title, acknowledged) = match acknowledged {
+            Some((title, published)) => (title, Some(published)),
+            None => (title, None),
+        };
+
+        Some(Self {
+            id: id.to_string(),
+            downloaded: downloaded.parse().unwrap_or_default(),
+            title: text::trim_quotes(title),
+            acknowledged,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        let status = match self.acknowledged {
+            Some(published) => format!(" {}{}", ACKNOWLEDGED, published),
+            None => String::new(),
+        };
+
+        format!(
+            "{} {} \"{}\"{}",
+            self.id, self.downloaded, self.title, status
+        )
+    }
+}
 
 impl DownloadedEpisodes {
     pub fn contains_episode(&self, episode_id: &str) -> bool {
-        self.0.contains(episode_id)
+        self.ids.contains(episode_id)
+    }
+
+    /// Whether the episode is in the tracker because catching up acknowledged it, so it
+    /// has no file.
+    pub fn is_acknowledged(&self, episode_id: &str) -> bool {
+        self.acknowledged.contains(episode_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
     }
 
     pub fn load(path: &Path) -> Self {
@@ -22,19 +95,63 @@ impl DownloadedEpisodes {
             e @ Err(_) => e.unwrap(),
         };
 
-        let mut hashmap: HashSet<String> = HashSet::new();
+        let mut episodes = Self::default();
 
         for line in s.trim().lines() {
             let mut parts = line.split_whitespace();
             if let Some(id) = parts.next() {
-                hashmap.insert(id.to_string());
+                episodes.ids.insert(id.to_string());
+            }
+
+            if let Some(entry) = TrackerEntry::from_line(line) {
+                if entry.acknowledged.is_some() {
+                    episodes.acknowledged.insert(entry.id);
+                }
             }
         }
 
-        Self(hashmap)
+        episodes
+    }
+
+    /// All the entries of the tracker, in the order they were downloaded.
+    pub fn entries(path: &Path) -> Vec<TrackerEntry> {
+        match fs::read_to_string(path) {
+            Ok(s) => s
+                .trim()
+                .lines()
+                .filter_map(TrackerEntry::from_line)
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn append(path: &Path, id: &str, episode: &Episode) -> Result<(), String> {
+        let entry = TrackerEntry {
+            id: id.to_string(),
+            downloaded: utils::current_unix().as_secs(),
+            title: episode.attrs.title().to_string(),
+            acknowledged: None,
+        };
+
+        Self::append_entry(path, &entry)
+    }
+
+    /// Records an episode as acknowledged: it's never downloaded, but unlike a downloaded
+    /// one it can be brought back with `remove_acknowledged`.
+    pub fn append_acknowledged(path: &Path, id: &str, episode: &Episode) -> Result<(), String> {
+        let entry = TrackerEntry {
+            id: id.to_string(),
+            downloaded: utils::current_unix().as_secs(),
+            title: episode.attrs.title().to_string(),
+            acknowledged: Some(episode.attrs.published().as_secs()),
+        };
+
+        Self::append_entry(path, &entry)
     }
 
-    pub fn append(path: &Path, id: &str, episode: &DownloadedEpisode) -> Result<(), String> {
+    /// Adds an entry to the tracker. An episode that's already in it, like one that was
+    /// downloaded again, has its entry replaced rather than getting a second one.
+    pub fn append_entry(path: &Path, entry: &TrackerEntry) -> Result<(), String> {
         use std::io::Write;
 
         if path.is_dir() {
@@ -44,7 +161,13 @@ impl DownloadedEpisodes {
         }
 
         if let Some(parent) = path.parent() {
-            utils::create_dir(&parent)
+            paths::create_dir(&parent)
+        }
+
+        read_only::check(path);
+        if Self::load(path).contains_episode(&entry.id) {
+            let ids = HashSet::from([entry.id.clone()]);
+            return Self::rewrite(path, &ids, Some(entry));
         }
 
         let mut file = fs::OpenOptions::new()
@@ -53,15 +176,129 @@ impl DownloadedEpisodes {
             .open(path)
             .map_err(|_| "failed to open tracker file".to_string())?;
 
-        writeln!(
-            file,
-            "{} {} \"{}\"",
-            id,
-            utils::current_unix().as_secs(),
-            episode.inner().attrs.title()
-        )
-        .unwrap();
+        writeln!(file, "{}", entry.to_line())
+            .map_err(|_| "failed to write to tracker file".to_string())?;
 
         Ok(())
     }
+
+    /// Removes the entries of the given episodes, so they count as never downloaded.
+    ///
+    /// The tracker is written to a temporary file first, so it's never left half written.
+    pub fn remove(path: &Path, ids: &HashSet<String>) -> Result<(), String> {
+        Self::rewrite(path, ids, None)
+    }
+
+    /// Writes the tracker again without the entries of `ids`, and with `added` at the end.
+    fn rewrite(
+        path: &Path,
+        ids: &HashSet<String>,
+        added: Option<&TrackerEntry>,
+    ) -> Result<(), String> {
+        let s = fs::read_to_string(path).map_err(|_| "failed to read tracker file".to_string())?;
+
+        let mut kept = String::new();
+        for line in s.lines() {
+            let id = line.split_whitespace().next().unwrap_or_default();
+            if !ids.contains(id) {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+
+        if let Some(entry) = added {
+            kept.push_str(&entry.to_line());
+            kept.push('\n');
+        }
+
+        let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+        paths::write(&temp_path, kept)
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|_| "failed to write to tracker file".to_string())
+    }
+
+    /// Removes the entries of the acknowledged episodes published since `since`, so the
+    /// next sync downloads them. Returns how many were removed.
+    pub fn remove_acknowledged(path: &Path, since: u64) -> Result<usize, String> {
+        let ids: HashSet<String> = Self::entries(path)
+            .into_iter()
+            .filter(|entry| {
+                entry
+                    .acknowledged
+                    .is_some_and(|published| published >= since)
+            })
+            .map(|entry| entry.id)
+            .collect();
+
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        Self::remove(path, &ids)?;
+        Ok(ids.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, downloaded: u64) -> TrackerEntry {
+        TrackerEntry {
+            id: id.to_string(),
+            downloaded,
+            title: format!("Episode {}", id),
+            acknowledged: None,
+        }
+    }
+
+    #[test]
+    fn downloading_again_replaces_the_entry() {
+        let path = paths::test_dir("tracker-replace").join(".downloaded");
+        DownloadedEpisodes::append_entry(&path, &entry("a", 1)).unwrap();
+        DownloadedEpisodes::append_entry(&path, &entry("b", 2)).unwrap();
+        DownloadedEpisodes::append_entry(&path, &entry("a", 3)).unwrap();
+
+        let entries: Vec<(String, u64)> = DownloadedEpisodes::entries(&path)
+            .into_iter()
+            .map(|entry| (entry.id, entry.downloaded))
+            .collect();
+        assert_eq!(entries, [("b".to_string(), 2), ("a".to_string(), 3)]);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "b 2 \"Episode b\"\na 3 \"Episode a\"\n"
+        );
+    }
+
+    #[test]
+    fn removing_entries() {
+        let path = paths::test_dir("tracker-remove").join(".downloaded");
+        for (id, downloaded) in [("a", 1), ("b", 2), ("c", 3)] {
+            DownloadedEpisodes::append_entry(&path, &entry(id, downloaded)).unwrap();
+        }
+
+        let ids = HashSet::from(["b".to_string(), "unknown".to_string()]);
+        DownloadedEpisodes::remove(&path, &ids).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "a 1 \"Episode a\"\nc 3 \"Episode c\"\n"
+        );
+        assert!(!path.with_file_name(".downloaded.tmp").exists());
+    }
+
+    #[test]
+    fn failed_rewrite_keeps_the_tracker() {
+        let path = paths::test_dir("tracker-atomic").join(".downloaded");
+        DownloadedEpisodes::append_entry(&path, &entry("a", 1)).unwrap();
+        DownloadedEpisodes::append_entry(&path, &entry("b", 2)).unwrap();
+        let before = fs::read_to_string(&path).unwrap();
+
+        // The new tracker can't be written next to the old one.
+        fs::create_dir(path.with_file_name(".downloaded.tmp")).unwrap();
+        let ids = HashSet::from(["a".to_string()]);
+        assert!(DownloadedEpisodes::remove(&path, &ids).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+    }
 }
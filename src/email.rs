@@ -0,0 +1,745 @@
+//! An email at the end of a sync with the episodes it downloaded and what failed, for the
+//! `[email]` table of `config.toml`.
+//!
+//! The message is plain text, with the episodes listed under their podcast the way the
+//! changelog lists them, followed by the items removed from feeds and the failures. It's
+//! piped to `sendmail_command`, or sent to `smtp_host` directly. An SMTP delivery that fails
+//! is tried once more, after which it's logged. An email that can't be sent never fails the
+//! sync.
+
+use crate::hook::HookCommand;
+use crate::summary::SyncResult;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+const DEFAULT_SUBJECT: &str = "TaleCast: {new} new episodes, {failed} failed";
+
+/// How long sending an email over SMTP may take, from connecting to the server on.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait before trying a failed SMTP delivery again.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The longest line of a base64 encoded body.
+const LINE_WIDTH: usize = 76;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Connects without encryption and upgrades the connection with `STARTTLS`.
+    #[default]
+    Starttls,
+    /// Connects with TLS from the start.
+    Tls,
+    /// Never encrypts the connection, for a relay on the same machine.
+    None,
+}
+
+impl SmtpSecurity {
+    fn default_port(self) -> u16 {
+        match self {
+            Self::Starttls => 587,
+            Self::Tls => 465,
+            Self::None => 25,
+        }
+    }
+}
+
+#[derive(Serialize, Default, Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    to: Option<String>,
+    from: Option<String>,
+    subject: Option<String>,
+    /// Send the email even when nothing was downloaded and nothing failed.
+    always: Option<bool>,
+    /// Send the email when items were removed from feeds, even if that's all that happened.
+    notify_removed: Option<bool>,
+    sendmail_command: Option<String>,
+    smtp_host: Option<String>,
+    smtp_port: Option<u16>,
+    smtp_security: Option<SmtpSecurity>,
+    smtp_username: Option<String>,
+    smtp_password: Option<String>,
+    smtp_password_env: Option<String>,
+}
+
+impl EmailConfig {
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Fails if `smtp_password_env` names a variable that isn't set.
+    fn smtp_password(&self) -> Result<String, String> {
+        match (&self.smtp_password_env, &self.smtp_password) {
+            (Some(var), _) => std::env::var(var).map_err(|_| {
+                format!(
+                    "smtp_password_env: the environment variable {} isn't set",
+                    var
+                )
+            }),
+            (None, password) => Ok(password.clone().unwrap_or_default()),
+        }
+    }
+}
+
+/// Sends the email for the sync, if one is configured and there's anything to tell.
+pub async fn notify(config: &EmailConfig, results: &[SyncResult]) {
+    if config.is_default() {
+        return;
+    }
+
+    let digest = Digest::new(results);
+    let removals = config.notify_removed.unwrap_or(false) && !digest.removed.is_empty();
+    if digest.is_empty() && !removals && !config.always.unwrap_or(false) {
+        return;
+    }
+
+    match send(config, &digest).await {
+        Ok(()) => log::info!("email sent"),
+        Err(e) => {
+            log::error!("failed to send email: {}", e);
+            eprintln!("failed to send email: {}", e);
+        }
+    }
+}
+
+async fn send(config: &EmailConfig, digest: &Digest<'_>) -> Result<(), String> {
+    let to = config.to.as_deref().ok_or("'to' isn't set")?;
+    let from = config.from.as_deref().unwrap_or(to);
+    let subject = digest.subject(config.subject.as_deref().unwrap_or(DEFAULT_SUBJECT));
+    let date = chrono::Local::now().fixed_offset();
+    let message = message(from, to, &subject, date, &digest.body());
+
+    match (&config.sendmail_command, &config.smtp_host) {
+        (Some(_), Some(_)) => Err("only one of sendmail_command and smtp_host can be set".into()),
+        (Some(command), None) => sendmail(command, &message).await,
+        (None, Some(host)) => {
+            let security = config.smtp_security.unwrap_or_default();
+            let server = SmtpServer {
+                host,
+                port: config.smtp_port.unwrap_or(security.default_port()),
+                security,
+                login: match &config.smtp_username {
+                    Some(username) => Some((username.as_str(), config.smtp_password()?)),
+                    None => None,
+                },
+            };
+
+            let recipients: Vec<&str> = to.split(',').map(address).collect();
+            let deliver = || async {
+                tokio::time::timeout(SMTP_TIMEOUT, server.deliver(from, &recipients, &message))
+                    .await
+                    .map_err(|_| format!("{} didn't respond in time", host))?
+            };
+
+            match deliver().await {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    log::warn!("failed to send email, trying again: {}", e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    deliver().await
+                }
+            }
+        }
+        (None, None) => Err("neither sendmail_command nor smtp_host is set".into()),
+    }
+}
+
+/// What the email says about a sync.
+struct Digest<'a> {
+    /// Podcasts that had episodes downloaded, by name.
+    downloaded: Vec<&'a SyncResult>,
+    /// Podcasts that failed to sync, or had episodes that failed to download, by name.
+    failed: Vec<&'a SyncResult>,
+    /// Podcasts whose feeds had items removed since the last sync, by name.
+    removed: Vec<&'a SyncResult>,
+}
+
+impl<'a> Digest<'a> {
+    fn new(results: &'a [SyncResult]) -> Self {
+        let mut results: Vec<&SyncResult> = results.iter().collect();
+        results.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            downloaded: results
+                .iter()
+                .filter(|res| !res.changelog.is_empty())
+                .copied()
+                .collect(),
+            failed: results
+                .iter()
+                .filter(|res| res.error.is_some() || res.failed > 0)
+                .copied()
+                .collect(),
+            removed: results
+                .iter()
+                .filter(|res| !res.upstream.removed.is_empty())
+                .copied()
+                .collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.downloaded.is_empty() && self.failed.is_empty()
+    }
+
+    fn new_episodes(&self) -> usize {
+        self.downloaded.iter().map(|res| res.changelog.len()).sum()
+    }
+
+    fn failures(&self) -> usize {
+        self.failed
+            .iter()
+            .map(|res| res.failed + usize::from(res.error.is_some()))
+            .sum()
+    }
+
+    fn removed_items(&self) -> usize {
+        self.removed
+            .iter()
+            .map(|res| res.upstream.removed.len())
+            .sum()
+    }
+
+    /// The subject with `{new}`, `{failed}`, `{podcasts}` and `{removed}` filled in.
+    fn subject(&self, template: &str) -> String {
+        template
+            .replace("{new}", &self.new_episodes().to_string())
+            .replace("{failed}", &self.failures().to_string())
+            .replace("{podcasts}", &self.downloaded.len().to_string())
+            .replace("{removed}", &self.removed_items().to_string())
+    }
+
+    fn body(&self) -> String {
+        let mut body = String::new();
+        if self.downloaded.is_empty() {
+            body.push_str("No new episodes.\n\n");
+        }
+
+        for res in &self.downloaded {
+            body.push_str(&format!("{}\n", res.name));
+            for entry in &res.changelog {
+                body.push_str(&format!("{}\n", entry.to_text()));
+            }
+            body.push('\n');
+        }
+
+        if !self.removed.is_empty() {
+            body.push_str("Removed from feeds\n");
+            for res in &self.removed {
+                for title in &res.upstream.removed {
+                    body.push_str(&format!("- {}: {}\n", res.name, title));
+                }
+            }
+            body.push('\n');
+        }
+
+        if !self.failed.is_empty() {
+            body.push_str("Failures\n");
+            for res in &self.failed {
+                let failure = match &res.error {
+                    Some(error) => error.clone(),
+                    None => format!(
+                        "{} episodes failed to download, {} skipped after a failure",
+                        res.failed, res.skipped
+                    ),
+                };
+                body.push_str(&format!("- {}: {}\n", res.name, failure));
+            }
+        }
+
+        body.trim_end().to_string()
+    }
+}
+
+/// The whole message, headers included, with lines ending in `\n`.
+fn message(
+    from: &str,
+    to: &str,
+    subject: &str,
+    date: chrono::DateTime<chrono::FixedOffset>,
+    body: &str,
+) -> String {
+    let (encoding, body) = match body.is_ascii() {
+        true => ("7bit", body.to_string()),
+        false => ("base64", base64_lines(body)),
+    };
+
+    format!(
+        "From: {}\nTo: {}\nSubject: {}\nDate: {}\nMIME-Version: 1.0\n\
+         Content-Type: text/plain; charset=utf-8\nContent-Transfer-Encoding: {}\n\n{}\n",
+        from,
+        to,
+        encode_header(subject),
+        date.to_rfc2822(),
+        encoding,
+        body
+    )
+}
+
+/// Encodes a header with characters that headers can't hold as is.
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+
+    format!("=?utf-8?B?{}?=", BASE64.encode(value))
+}
+
+fn base64_lines(body: &str) -> String {
+    let body = body.replace('\n', "\r\n");
+    let encoded = BASE64.encode(body);
+    encoded
+        .as_bytes()
+        .chunks(LINE_WIDTH)
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The bare address of a mailbox like `Name <name@example.com>`.
+fn address(mailbox: &str) -> &str {
+    let mailbox = mailbox.trim();
+    match (mailbox.rfind('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox,
+    }
+}
+
+async fn sendmail(command: &str, message: &str) -> Result<(), String> {
+    let command = HookCommand::parse(Path::new(command)).resolved();
+    let mut child = tokio::process::Command::new(&command.program)
+        .args(&command.args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run sendmail_command: {}", e))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin
+        .write_all(message.as_bytes())
+        .await
+        .map_err(|e| format!("failed to write to sendmail_command: {}", e))?;
+    drop(stdin);
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| format!("failed to run sendmail_command: {}", e))?;
+    if !status.success() {
+        return Err(format!("sendmail_command exited with {}", status));
+    }
+
+    Ok(())
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+struct SmtpServer<'a> {
+    host: &'a str,
+    port: u16,
+    security: SmtpSecurity,
+    login: Option<(&'a str, String)>,
+}
+
+impl SmtpServer<'_> {
+    async fn deliver(&self, from: &str, recipients: &[&str], message: &str) -> Result<(), String> {
+        let tcp = TcpStream::connect((self.host, self.port))
+            .await
+            .map_err(|e| format!("failed to connect to {}:{}: {}", self.host, self.port, e))?;
+        let stream: Box<dyn Stream> = match self.security {
+            SmtpSecurity::Tls => tls(self.host, tcp).await?,
+            _ => Box::new(tcp),
+        };
+
+        self.converse(stream, from, recipients, message).await
+    }
+
+    /// Sends the message over a connection to the server, from its greeting on.
+    async fn converse(
+        &self,
+        stream: Box<dyn Stream>,
+        from: &str,
+        recipients: &[&str],
+        message: &str,
+    ) -> Result<(), String> {
+        let mut conn = SmtpConnection(BufReader::new(stream));
+        conn.reply("greeting", 220).await?;
+
+        // Servers only accept the domain of the client, which the sender's is a stand-in for.
+        let domain = address(from)
+            .rsplit_once('@')
+            .map_or("localhost", |(_, domain)| domain);
+        let ehlo = format!("EHLO {}", domain);
+        conn.command(&ehlo, 250).await?;
+
+        if self.security == SmtpSecurity::Starttls {
+            conn.command("STARTTLS", 220).await?;
+            conn = SmtpConnection(BufReader::new(tls(self.host, conn.0.into_inner()).await?));
+            conn.command(&ehlo, 250).await?;
+        }
+
+        if let Some((username, password)) = &self.login {
+            let token = BASE64.encode(format!("\0{}\0{}", username, password));
+            conn.command(&format!("AUTH PLAIN {}", token), 235).await?;
+        }
+
+        conn.command(&format!("MAIL FROM:<{}>", address(from)), 250)
+            .await?;
+        for recipient in recipients {
+            conn.command(&format!("RCPT TO:<{}>", recipient), 250)
+                .await?;
+        }
+
+        conn.command("DATA", 354).await?;
+        conn.write(&dot_stuffed(message)).await?;
+        conn.write(".\r\n").await?;
+        conn.reply("DATA", 250).await?;
+
+        // The message is accepted, so a server that hangs up now doesn't matter.
+        let _ = conn.command("QUIT", 221).await;
+        Ok(())
+    }
+}
+
+async fn tls<S>(host: &str, stream: S) -> Result<Box<dyn Stream>, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let connector = tokio_native_tls::native_tls::TlsConnector::new()
+        .map_err(|e| format!("failed to set up TLS: {}", e))?;
+    let stream = tokio_native_tls::TlsConnector::from(connector)
+        .connect(host, stream)
+        .await
+        .map_err(|e| format!("TLS with {} failed: {}", host, e))?;
+
+    Ok(Box::new(stream))
+}
+
+/// The message with `\r\n` line endings, and a `.` added to lines that start with one so
+/// the server doesn't read them as the end.
+fn dot_stuffed(message: &str) -> String {
+    let mut stuffed = String::with_capacity(message.len());
+    for line in message.lines() {
+        if line.starts_with('.') {
+            stuffed.push('.');
+        }
+        stuffed.push_str(line);
+        stuffed.push_str("\r\n");
+    }
+    stuffed
+}
+
+struct SmtpConnection(BufReader<Box<dyn Stream>>);
+
+impl SmtpConnection {
+    /// Sends a command and checks that the reply is of the same kind as `expected`, like any
+    /// 2xx reply for 250.
+    async fn command(&mut self, command: &str, expected: u16) -> Result<(), String> {
+        self.write(&format!("{}\r\n", command)).await?;
+
+        // Leaves out the arguments, which can be credentials.
+        let verb = command.split([' ', ':']).next().unwrap_or(command);
+        self.reply(verb, expected).await
+    }
+
+    async fn write(&mut self, s: &str) -> Result<(), String> {
+        self.0
+            .write_all(s.as_bytes())
+            .await
+            .map_err(|e| format!("failed to send to the server: {}", e))?;
+        self.0
+            .flush()
+            .await
+            .map_err(|e| format!("failed to send to the server: {}", e))
+    }
+
+    async fn reply(&mut self, what: &str, expected: u16) -> Result<(), String> {
+        let mut text = vec![];
+        let code = loop {
+            let mut line = String::new();
+            let read = self
+                .0
+                .read_line(&mut line)
+                .await
+                .map_err(|e| format!("failed to read from the server: {}", e))?;
+            if read == 0 {
+                return Err("the server closed the connection".to_string());
+            }
+
+            let line = line.trim_end();
+            let Some(code) = line.get(..3).and_then(|code| code.parse::<u16>().ok()) else {
+                return Err(format!("invalid reply from the server: {}", line));
+            };
+            text.push(line.get(4..).unwrap_or_default().to_string());
+
+            // The last line of a reply has a space after the code instead of a dash.
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break code;
+            }
+        };
+
+        if code / 100 != expected / 100 {
+            return Err(format!(
+                "{} failed: the server replied {} {}",
+                what,
+                code,
+                text.join(" ")
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changelog::ChangelogEntry;
+    use crate::episode::{Attributes, RawEpisode};
+    use crate::media::MediaPreference;
+    use tokio::io::DuplexStream;
+
+    fn entry(title: &str) -> ChangelogEntry {
+        let item = serde_json::json!({
+            "title": title,
+            "link": "https://example.com/episode",
+            "pubDate": "Mon, 01 Jan 2024 00:00:00 +0000",
+            "enclosure": { "@url": "https://example.com/episode.mp3", "@type": "audio/mpeg" },
+            "description": "<p>What it's about.</p>",
+        });
+        let raw = RawEpisode::new(item.as_object().unwrap().clone());
+        ChangelogEntry::new(&Attributes::new(raw, &MediaPreference::default()).unwrap())
+    }
+
+    fn results() -> Vec<SyncResult> {
+        let mut downloaded = SyncResult::new("Podcast B".to_string());
+        downloaded.changelog = vec![entry("First"), entry("Second")];
+
+        let mut failed = SyncResult::new("Podcast A".to_string());
+        failed.failed = 1;
+        failed.skipped = 2;
+        failed.upstream.removed = vec!["Gone".to_string()];
+
+        let mut broken = SyncResult::new("Podcast C".to_string());
+        broken.error = Some("failed to download the feed".to_string());
+
+        vec![downloaded, failed, broken]
+    }
+
+    fn date() -> chrono::DateTime<chrono::FixedOffset> {
+        chrono::DateTime::parse_from_rfc3339("2024-01-02T03:04:05+09:00").unwrap()
+    }
+
+    #[test]
+    fn digest_message() {
+        let results = results();
+        let digest = Digest::new(&results);
+        let subject = digest.subject(DEFAULT_SUBJECT);
+        let message = message(
+            "TaleCast <talecast@example.com>",
+            "me@example.com",
+            &subject,
+            date(),
+            &digest.body(),
+        );
+
+        assert_eq!(
+            message,
+            "From: TaleCast <talecast@example.com>\n\
+             To: me@example.com\n\
+             Subject: TaleCast: 2 new episodes, 2 failed\n\
+             Date: Tue, 2 Jan 2024 03:04:05 +0900\n\
+             MIME-Version: 1.0\n\
+             Content-Type: text/plain; charset=utf-8\n\
+             Content-Transfer-Encoding: 7bit\n\
+             \n\
+             Podcast B\n\
+             - First\n  https://example.com/episode\n  What it's about.\n\
+             - Second\n  https://example.com/episode\n  What it's about.\n\
+             \n\
+             Removed from feeds\n\
+             - Podcast A: Gone\n\
+             \n\
+             Failures\n\
+             - Podcast A: 1 episodes failed to download, 2 skipped after a failure\n\
+             - Podcast C: failed to download the feed\n"
+        );
+    }
+
+    #[test]
+    fn non_ascii_message() {
+        let message = message(
+            "a@example.com",
+            "b@example.com",
+            "Ép",
+            date(),
+            "Épisode\nfin",
+        );
+
+        assert_eq!(
+            message,
+            "From: a@example.com\n\
+             To: b@example.com\n\
+             Subject: =?utf-8?B?w4lw?=\n\
+             Date: Tue, 2 Jan 2024 03:04:05 +0900\n\
+             MIME-Version: 1.0\n\
+             Content-Type: text/plain; charset=utf-8\n\
+             Content-Transfer-Encoding: base64\n\
+             \n\
+             w4lwaXNvZGUNCmZpbg==\n"
+        );
+    }
+
+    #[test]
+    fn long_base64_bodies_are_wrapped() {
+        let body = "é".repeat(100);
+        let lines = base64_lines(&body);
+
+        assert!(lines.lines().all(|line| line.len() <= LINE_WIDTH));
+        assert_eq!(
+            BASE64.decode(lines.replace('\n', "")).unwrap(),
+            body.as_bytes()
+        );
+    }
+
+    #[test]
+    fn dot_stuffing() {
+        assert_eq!(
+            dot_stuffed("Subject: x\n\n.\n..two\nplain. line\n"),
+            "Subject: x\r\n\r\n..\r\n...two\r\nplain. line\r\n"
+        );
+    }
+
+    #[test]
+    fn mailbox_addresses() {
+        assert_eq!(address("Name <name@example.com>"), "name@example.com");
+        assert_eq!(address(" name@example.com "), "name@example.com");
+    }
+
+    /// Plays an SMTP server that rejects `reject`, and returns what the client sent.
+    async fn serve(stream: DuplexStream, reject: Option<&str>) -> Vec<String> {
+        let mut stream = BufReader::new(stream);
+        let mut received = vec![];
+        stream.write_all(b"220 ready\r\n").await.unwrap();
+
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            received.push(line.clone());
+
+            let verb = line.split([' ', ':', '\r']).next().unwrap().to_string();
+            let reply = match verb.as_str() {
+                verb if Some(verb) == reject => "550 no such user\r\n",
+                "EHLO" => "250-example.com\r\n250 AUTH PLAIN\r\n",
+                "AUTH" => "235 ok\r\n",
+                "DATA" => "354 go ahead\r\n",
+                "QUIT" => "221 bye\r\n",
+                _ => "250 ok\r\n",
+            };
+            stream.write_all(reply.as_bytes()).await.unwrap();
+
+            if verb == "DATA" {
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    received.push(line.clone());
+                    if line == ".\r\n" {
+                        break;
+                    }
+                }
+                stream.write_all(b"250 queued\r\n").await.unwrap();
+            }
+
+            if verb == "QUIT" || Some(verb.as_str()) == reject {
+                break;
+            }
+        }
+
+        received
+    }
+
+    fn server(login: Option<(&'static str, String)>) -> SmtpServer<'static> {
+        SmtpServer {
+            host: "localhost",
+            port: 25,
+            security: SmtpSecurity::None,
+            login,
+        }
+    }
+
+    #[tokio::test]
+    async fn smtp_dialogue() {
+        let (client, server_side) = tokio::io::duplex(4096);
+        let served = tokio::spawn(serve(server_side, None));
+
+        let login = Some(("user", "secret".to_string()));
+        let message = "Subject: hi\n\n.hidden\nbody\n";
+        server(login)
+            .converse(
+                Box::new(client),
+                "TaleCast <talecast@example.com>",
+                &["a@example.com", "b@example.com"],
+                message,
+            )
+            .await
+            .unwrap();
+
+        let auth = format!("AUTH PLAIN {}\r\n", BASE64.encode("\0user\0secret"));
+        assert_eq!(
+            served.await.unwrap(),
+            [
+                "EHLO example.com\r\n",
+                &auth,
+                "MAIL FROM:<talecast@example.com>\r\n",
+                "RCPT TO:<a@example.com>\r\n",
+                "RCPT TO:<b@example.com>\r\n",
+                "DATA\r\n",
+                "Subject: hi\r\n",
+                "\r\n",
+                "..hidden\r\n",
+                "body\r\n",
+                ".\r\n",
+                "QUIT\r\n",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn smtp_rejection() {
+        let (client, server_side) = tokio::io::duplex(4096);
+        let served = tokio::spawn(serve(server_side, Some("RCPT")));
+
+        let res = server(None)
+            .converse(
+                Box::new(client),
+                "a@example.com",
+                &["nobody@example.com"],
+                "body\n",
+            )
+            .await;
+
+        assert_eq!(
+            res,
+            Err("RCPT failed: the server replied 550 no such user".to_string())
+        );
+        assert_eq!(
+            served.await.unwrap(),
+            [
+                "EHLO example.com\r\n",
+                "MAIL FROM:<a@example.com>\r\n",
+                "RCPT TO:<nobody@example.com>\r\n",
+            ]
+        );
+    }
+}
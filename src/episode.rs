@@ -1,17 +1,57 @@
 use crate::cache;
+use crate::chaos;
+use crate::chapters;
+use crate::chapters::Chapters;
 use crate::config::Config;
 use crate::config::DownloadMode;
+use crate::config::ImageSource;
+use crate::config::ProcessStep;
+use crate::config::VideoHandling;
+use crate::deadline;
 use crate::display::DownloadBar;
 use crate::download_tracker::DownloadedEpisodes;
+use crate::fingerprint::Fingerprint;
+use crate::fingerprint::Fingerprints;
+use crate::format;
+use crate::hook::HookCommand;
+use crate::hook::HookContext;
+use crate::hook::HookRun;
+use crate::media;
+use crate::media::MediaPreference;
+use crate::mp3;
+use crate::mp3::DeferredTags;
+use crate::mp3::FileTags;
+use crate::mp4;
+use crate::mp4::Mp4Tags;
+use crate::naming;
+use crate::naming::NameOptions;
+use crate::net;
+use crate::net::DownloadError;
+use crate::ogg;
+use crate::ogg::OggTags;
+use crate::paths;
+use crate::paths::PathLimits;
+use crate::podcast::Channel;
+use crate::reliability::LengthReliability;
+use crate::summary::Phase;
+use crate::tags;
+use crate::text;
+use crate::transcript;
+use crate::trash::Trash;
 use crate::utils;
+use crate::xml;
+use futures_util::Stream;
 use futures_util::StreamExt;
+use id3::frame::PictureType;
 use std::cmp;
 use std::fs;
 use std::io::Seek;
-use std::io::Write as IOWrite;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time;
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
 use tokio::task::JoinHandle;
 
 pub trait XmlWrapper {
@@ -20,12 +60,12 @@ pub trait XmlWrapper {
     fn get_str(&self, key: &str) -> Result<&str, String> {
         let val = self.get_val(key)?;
 
-        utils::val_to_str(val).ok_or_else(|| "value could not be parsed as string".into())
+        xml::val_to_str(val).ok_or_else(|| "value could not be parsed as string".into())
     }
 
     fn get_url(&self, key: &str) -> Result<&str, String> {
         let val = self.get_val(key)?;
-        match utils::val_to_url(val) {
+        match xml::val_to_url(val) {
             Some(val) => Ok(val),
             None => return Err("failed to parse val as url".to_string()),
         }
@@ -48,7 +88,7 @@ impl XmlWrapper for RawEpisode {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct RawEpisode(serde_json::Map<String, serde_json::Value>);
 
 impl RawEpisode {
@@ -57,42 +97,52 @@ impl RawEpisode {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Attributes {
     pub title: String,
     pub url: String,
     pub mime: Option<String>,
+    /// The size of the enclosure in bytes, as advertised by the feed.
+    pub length: Option<u64>,
     pub guid: String,
     pub published: time::Duration,
     pub raw: RawEpisode,
+    /// Whether the episode number was guessed from the title.
+    pub episode_inferred: bool,
 }
 
-impl Attributes {
-    pub fn new(raw: RawEpisode) -> Result<Self, String> {
-        let title = raw.get_string("title")?;
-        let enclosure = raw.get_val("enclosure")?;
+/// Why an item without anything to download isn't an episode.
+pub const NO_ENCLOSURE: &str = "no enclosure to download";
 
-        let url = enclosure
-            .get("@url")
-            .ok_or_else(|| "url not found".to_string())?
-            .to_string();
-        let url = utils::trim_quotes(&url);
+/// Enclosure extensions that are videos when the feed doesn't give a MIME type.
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "m4v", "mov", "mkv", "webm", "avi"];
 
-        let mime = enclosure
-            .get("@type")
-            .and_then(|x| Some(x.as_str()?.to_string()));
+impl Attributes {
+    pub fn new(raw: RawEpisode, media: &MediaPreference) -> Result<Self, String> {
+        let title = raw.get_string("title")?;
+        let enclosures = media::enclosures(&raw);
+        // Feeds without guids are still downloaded, going by the first enclosure instead, so
+        // that changing `preferred_media` doesn't make them new episodes.
+        let first_url = enclosures.first().map(|enclosure| enclosure.url.clone());
+        let enclosure = media
+            .choose(enclosures)
+            .ok_or_else(|| NO_ENCLOSURE.to_string())?;
 
         let published = raw.get_str("pubDate")?;
         let published = utils::date_str_to_unix(published)?;
-        let guid = raw.get_string("guid")?;
+        let guid = raw
+            .get_string("guid")
+            .unwrap_or_else(|_| first_url.unwrap_or_default());
 
         Ok(Self {
             title,
-            url,
-            mime,
+            url: enclosure.url,
+            mime: enclosure.mime,
+            length: enclosure.length,
             guid,
             published,
             raw,
+            episode_inferred: false,
         })
     }
 
@@ -100,10 +150,28 @@ impl Attributes {
         self.published
     }
 
-    pub fn _mime(&self) -> Option<&str> {
+    pub fn mime(&self) -> Option<&str> {
         self.mime.as_deref()
     }
 
+    /// Whether the enclosure is a video, going by its MIME type or else its extension.
+    pub fn is_video(&self) -> bool {
+        if let Some(mime) = self.mime() {
+            return mime.starts_with("video/");
+        }
+
+        let extension = reqwest::Url::parse(self.url())
+            .ok()
+            .and_then(|url| {
+                let segment = url.path_segments()?.next_back()?.to_string();
+                let (_, extension) = segment.rsplit_once('.')?;
+                Some(extension.to_lowercase())
+            })
+            .unwrap_or_default();
+
+        VIDEO_EXTENSIONS.contains(&extension.as_str())
+    }
+
     pub fn title(&self) -> &str {
         &self.title
     }
@@ -120,6 +188,16 @@ impl Attributes {
         self.raw.get_str(key)
     }
 
+    /// The text of a tag, with numbers written out too.
+    pub fn get_text(&self, key: &str) -> Option<String> {
+        xml::val_to_string(self.raw.get_val(key).ok()?)
+    }
+
+    /// The length of the enclosure in bytes, as advertised by the feed.
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
     pub fn image(&self) -> Result<&str, String> {
         let key = "itunes:image";
         self.raw.get_url(key)
@@ -132,14 +210,57 @@ impl Attributes {
         self.get_str("description")
     }
 
+    pub fn keywords(&self) -> Result<&str, String> {
+        self.get_str("itunes:keywords")
+    }
+
+    pub fn shownotes(&self) -> Result<&str, String> {
+        self.get_str("content:encoded")
+            .or_else(|_| self.get_str("encoded"))
+    }
+
     pub fn itunes_episode(&self) -> Result<&str, String> {
         let key = "itunes:episode";
         self.get_str(&key)
     }
 
-    pub fn itunes_duration(&self) -> Result<&str, String> {
-        let key = "itunes:duration";
-        self.get_str(&key)
+    /// Uses an episode number guessed from the title as if the feed had set `itunes:episode`.
+    pub fn set_inferred_episode(&mut self, number: u32) {
+        self.raw.0.insert(
+            "itunes:episode".to_string(),
+            serde_json::Value::String(number.to_string()),
+        );
+        self.episode_inferred = true;
+    }
+
+    /// The length of the episode according to `itunes:duration`, which is either seconds,
+    /// `MM:SS` or `HH:MM:SS`.
+    pub fn itunes_duration(&self) -> Option<time::Duration> {
+        let duration = self.get_text("itunes:duration")?;
+        format::parse_clock_duration(&duration)
+    }
+
+    /// The url of the `podcast:chapters` of the episode, if they're in the JSON format.
+    pub fn chapters_url(&self) -> Option<&str> {
+        xml::val_to_vec(self.raw.inner().get("podcast:chapters"))
+            .into_iter()
+            .find(|chapters| {
+                let mime = chapters.get("@type").and_then(|mime| mime.as_str());
+                chapters::is_json(mime.unwrap_or_default())
+            })
+            .and_then(|chapters| chapters.get("@url")?.as_str())
+    }
+
+    /// The url and MIME type of every `podcast:transcript` of the episode.
+    pub fn transcripts(&self) -> Vec<(&str, &str)> {
+        xml::val_to_vec(self.raw.inner().get("podcast:transcript"))
+            .into_iter()
+            .filter_map(|transcript| {
+                let url = transcript.get("@url")?.as_str()?;
+                let mime = transcript.get("@type").and_then(|mime| mime.as_str());
+                Some((url, mime.unwrap_or_default()))
+            })
+            .collect()
     }
 }
 
@@ -149,7 +270,10 @@ pub struct Episode {
     pub tags: Option<id3::Tag>,
     pub index: usize,
     pub attrs: Attributes,
+    /// Image of the episode, falling back to the podcast image.
     pub image_url: Option<String>,
+    pub podcast_image_url: Option<String>,
+    pub channel: Arc<Channel>,
 }
 
 impl Episode {
@@ -159,6 +283,8 @@ impl Episode {
         config: Config,
         tags: Option<id3::Tag>,
         image_url: Option<String>,
+        podcast_image_url: Option<String>,
+        channel: Arc<Channel>,
     ) -> Self {
         Self {
             attrs,
@@ -166,36 +292,223 @@ impl Episode {
             tags,
             index,
             image_url,
+            podcast_image_url,
+            channel,
+        }
+    }
+
+    /// The image urls to embed, along with which picture type they should be embedded as.
+    ///
+    /// Unless configured otherwise, the front cover is the episode image with the
+    /// podcast image as a fallback.
+    pub fn picture_urls(&self) -> Vec<(PictureType, &str)> {
+        let mut urls = vec![];
+
+        let front_configured = self
+            .config
+            .pictures
+            .iter()
+            .any(|(ty, _)| *ty == PictureType::CoverFront);
+
+        if !front_configured {
+            if let Some(url) = self.image_url.as_deref() {
+                urls.push((PictureType::CoverFront, url));
+            }
+        }
+
+        for (picture_type, source) in &self.config.pictures {
+            let url = match source {
+                ImageSource::Episode => self.attrs.image().ok(),
+                ImageSource::Podcast => self.podcast_image_url.as_deref(),
+            };
+
+            if let Some(url) = url {
+                urls.push((*picture_type, url));
+            }
+        }
+
+        urls
+    }
+
+    /// Adds the tags from the feed that are missing from `file_tags`, then sets the
+    /// configured tags and embeds the configured pictures.
+    ///
+    /// Returns how long fetching the pictures took.
+    pub async fn apply_tags(
+        &self,
+        client: &reqwest::Client,
+        file_tags: &mut id3::Tag,
+        ui: &DownloadBar,
+    ) -> time::Duration {
+        use id3::TagLike;
+
+        if let Some(xml_tags) = &self.tags {
+            for frame in xml_tags.frames() {
+                if file_tags.get(frame.id()).is_none() {
+                    file_tags.add_frame(frame.to_owned());
+                    self.log_trace(ui, format!("adding frame: {:?}", &frame));
+                }
+            }
+        }
+
+        // Frames that already have the configured value are left alone, so they aren't
+        // reordered on every run.
+        for (id, value) in &self.config.id3_tags {
+            let value = tags::canonical_text(value);
+            let current = file_tags.get(id).and_then(|frame| frame.content().text());
+            if current != Some(value.as_str()) {
+                file_tags.set_text(id, value);
+            }
+        }
+
+        tags::canonicalize(file_tags);
+
+        let start = Instant::now();
+        for (picture_type, img_url) in self.picture_urls() {
+            self.add_picture(client, file_tags, picture_type, img_url, ui)
+                .await;
+        }
+        let artwork = start.elapsed();
+        ui.record(Phase::Artwork, artwork);
+
+        artwork
+    }
+
+    /// Adds the chapters the feed links to, unless the file already has chapters.
+    ///
+    /// Chapters that can't be downloaded or read are skipped with a warning.
+    pub async fn add_chapters(
+        &self,
+        client: &reqwest::Client,
+        file_tags: &mut id3::Tag,
+        ui: &DownloadBar,
+    ) {
+        use id3::TagLike;
+
+        if !self.config.embed_chapters {
+            return;
+        }
+
+        let Some(url) = self.attrs.chapters_url() else {
+            return;
+        };
+
+        if file_tags.chapters().next().is_some() || file_tags.tables_of_contents().next().is_some()
+        {
+            self.log_trace(ui, "keeping the chapters the file already has");
+            return;
+        }
+
+        let chapters = chapters::fetch(client, &self.config.credentials, url, ui)
+            .await
+            .and_then(|json| Chapters::parse(&json));
+        let chapters = match chapters {
+            Ok(chapters) if chapters.is_empty() => return,
+            Ok(chapters) => chapters,
+            Err(e) => {
+                self.log_warn(ui, format!("skipping chapters: {}", e));
+                return;
+            }
+        };
+
+        let mut images = vec![];
+        for img_url in chapters.image_urls() {
+            let image = match img_url {
+                Some(img_url) if self.config.chapter_images => {
+                    cache::get_image(
+                        client,
+                        &self.config.credentials,
+                        img_url,
+                        PictureType::Other,
+                        &self.config.image_limits,
+                        ui,
+                    )
+                    .await
+                }
+                _ => None,
+            };
+            images.push(image);
+        }
+
+        let count = chapters.len();
+        let duration = self.attrs.itunes_duration();
+        for frame in chapters.into_frames(duration, images) {
+            file_tags.add_frame(frame);
+        }
+        self.log_debug(ui, format!("added {} chapters", count));
+    }
+
+    fn has_picture_type(tags: &id3::Tag, picture_type: PictureType) -> bool {
+        tags.pictures().any(|pic| pic.picture_type == picture_type)
+    }
+
+    /// Embeds the image unless the file already has a picture of the same type.
+    async fn add_picture(
+        &self,
+        client: &reqwest::Client,
+        tags: &mut id3::Tag,
+        picture_type: PictureType,
+        img_url: &str,
+        ui: &DownloadBar,
+    ) {
+        use id3::TagLike;
+
+        if Self::has_picture_type(tags, picture_type) {
+            return;
         }
+
+        if let Some(frame) = cache::get_image(
+            client,
+            &self.config.credentials,
+            img_url,
+            picture_type,
+            &self.config.image_limits,
+            ui,
+        )
+        .await
+        {
+            tags.add_frame(frame);
+            self.log_debug(
+                ui,
+                format!("added {:?} image to podcast episode", picture_type),
+            );
+        } else {
+            self.log_warn(ui, format!("failed to fetch image from url: {:?}", img_url));
+        };
     }
 
     const TITLELEN: usize = 30;
 
-    pub fn _log_error(&self, ui: &DownloadBar, msg: impl Into<String>) {
-        let ep_name = utils::truncate_string(self.attrs.title(), Self::TITLELEN, true);
+    pub fn log_error(&self, ui: &DownloadBar, msg: impl Into<String>) {
+        let ep_name = text::truncate_string(self.attrs.title(), Self::TITLELEN, true);
         let msg = format!("{}: {}", ep_name, msg.into());
         ui.log_error(msg);
     }
 
     pub fn log_warn(&self, ui: &DownloadBar, msg: impl Into<String>) {
-        let ep_name = utils::truncate_string(self.attrs.title(), Self::TITLELEN, true);
+        let ep_name = text::truncate_string(self.attrs.title(), Self::TITLELEN, true);
         let msg = format!("{}: {}", ep_name, msg.into());
         ui.log_warn(msg);
     }
 
     pub fn log_trace(&self, ui: &DownloadBar, msg: impl Into<String>) {
-        let ep_name = utils::truncate_string(self.attrs.title(), Self::TITLELEN, true);
+        let ep_name = text::truncate_string(self.attrs.title(), Self::TITLELEN, true);
         let msg = format!("{}: {}", ep_name, msg.into());
         ui.log_trace(msg);
     }
 
     pub fn log_debug(&self, ui: &DownloadBar, msg: impl Into<String>) {
-        let ep_name = utils::truncate_string(self.attrs.title(), Self::TITLELEN, true);
+        let ep_name = text::truncate_string(self.attrs.title(), Self::TITLELEN, true);
         let msg = format!("{}: {}", ep_name, msg.into());
         ui.log_debug(msg);
     }
 
-    fn is_downloaded(&self) -> bool {
+    /// Whether the episode is a video that `video_handling` says to skip.
+    pub fn is_skipped_video(&self) -> bool {
+        self.config.video_handling == VideoHandling::Skip && self.attrs.is_video()
+    }
+
+    pub fn is_downloaded(&self) -> bool {
         let id = self.get_id();
         let path = self.tracker_path();
         DownloadedEpisodes::load(&path).contains_episode(&id)
@@ -203,126 +516,582 @@ impl Episode {
 
     pub fn should_download(&self, mode: &DownloadMode, episode_qty: usize) -> bool {
         let passed_filter = match mode {
-            DownloadMode::Backlog { start, interval } => {
-                let time_passed = utils::current_unix() - *start;
-                let intervals_passed = time_passed.as_secs() / interval.as_secs();
-                intervals_passed >= self.index as u64
-            }
+            DownloadMode::Backlog { schedule } => (self.index as u64) < schedule.released(),
 
+            // `max_episodes`, `keep_latest` and `latest_date` are hard limits. The age limits
+            // drop older episodes, except for the newest `min_episodes` which are always kept.
             DownloadMode::Standard {
                 max_time,
                 max_episodes,
                 earliest_date,
+                latest_date,
+                min_episodes,
+                keep_latest,
             } => {
+                let is_among_newest = |n: usize| episode_qty.saturating_sub(n) <= self.index;
+
                 let max_time_exceeded = max_time.map_or(false, |max_time| {
                     (utils::current_unix() - self.attrs.published) > max_time
                 });
 
                 let max_episodes_exceeded = max_episodes.map_or(false, |max_episodes| {
-                    (episode_qty - max_episodes as usize) > self.index
+                    !is_among_newest(max_episodes as usize)
                 });
 
+                // Older episodes would be removed again right after downloading them.
+                let keep_latest_exceeded = keep_latest.is_some_and(|keep| !is_among_newest(keep));
+
                 let episode_too_old =
                     earliest_date.map_or(false, |date| date > self.attrs.published);
 
-                !max_time_exceeded && !max_episodes_exceeded && !episode_too_old
+                let episode_too_new = latest_date.is_some_and(|date| self.attrs.published >= date);
+
+                let retained = min_episodes.map_or(false, is_among_newest);
+
+                !max_episodes_exceeded
+                    && !keep_latest_exceeded
+                    && !episode_too_new
+                    && (retained || (!max_time_exceeded && !episode_too_old))
             }
         };
 
         passed_filter && !self.is_downloaded()
     }
 
-    /// Filename of episode when it's being downloaded.
-    fn partial_name(&self) -> String {
-        let file_name = sanitize_filename::sanitize(&self.attrs.guid);
+    /// Filename of episode when it's being downloaded into `dir`.
+    fn partial_name(&self, dir: &Path) -> String {
+        let options = NameOptions {
+            max_len: Some(PathLimits::of(dir).file_name_len(dir)),
+            extension: Some("partial"),
+        };
+        let file_name = naming::sanitize_filename(options, &self.attrs.guid);
         format!("{}.partial", file_name)
     }
 
-    fn get_id(&self) -> String {
+    pub fn get_id(&self) -> String {
         self.config.id_pattern.replace(" ", "_")
     }
 
+    /// Records the episode in the download tracker, so it's never downloaded.
+    pub fn mark_downloaded(&self) -> Result<(), String> {
+        DownloadedEpisodes::append(self.tracker_path(), &self.get_id(), self)
+    }
+
+    /// Records the episode in the download tracker as acknowledged, for catching up with
+    /// episodes that won't have a file.
+    pub fn mark_acknowledged(&self) -> Result<(), String> {
+        DownloadedEpisodes::append_acknowledged(self.tracker_path(), &self.get_id(), self)
+    }
+
+    fn hook_context<'b>(&'b self, path: &'b Path, first_download: bool) -> HookContext<'b> {
+        HookContext {
+            artwork_path: self.image_url.as_deref().and_then(cache::cached_image_path),
+            transcript_url: self.attrs.transcripts().first().map(|(url, _)| *url),
+            ..HookContext::new(
+                &self.channel.name,
+                path,
+                first_download,
+                self.attrs.raw.inner(),
+                &self.channel.tags,
+            )
+        }
+    }
+
+    /// Prepares a run of the download hook on the episode at `path`.
+    pub fn hook_run(
+        &self,
+        hook: HookCommand,
+        path: &Path,
+        first_download: bool,
+        ui: &DownloadBar,
+    ) -> HookRun {
+        let context = match self.hook_context(path, first_download).write() {
+            Ok(context) => Some(context),
+            Err(e) => {
+                self.log_warn(ui, e);
+                None
+            }
+        };
+
+        HookRun {
+            hook,
+            tracker_path: self.tracker_path().to_path_buf(),
+            id: self.get_id(),
+            path: path.to_path_buf(),
+            first_download,
+            context,
+            keep_context: self.config.keep_hook_context,
+            timeout: self.config.hook_timeout,
+            sandbox: self.config.hook_sandbox.clone(),
+            dir: self.config.download_path.clone(),
+        }
+    }
+
+    /// Where the downloaded episode is on disk, if it's still there.
+    ///
+    /// Episodes whose recorded path is missing, like ones downloaded before paths were
+    /// recorded or moved since, are looked up by their name pattern.
+    pub fn downloaded_path(&self) -> Option<PathBuf> {
+        let recorded = Fingerprints::load(self.tracker_path())
+            .get(&self.get_id())
+            .map(|fingerprint| fingerprint.path.clone())
+            .filter(|path| !path.as_os_str().is_empty());
+
+        if let Some(path) = recorded.filter(|path| path.is_file()) {
+            return Some(path);
+        }
+
+        self.find_by_name()
+    }
+
+    /// Where a sync would save the episode, worked out without downloading it.
+    ///
+    /// A real download can end up elsewhere when the server decides part of the name: an
+    /// enclosure without an extension in its url or a known MIME type in the feed gets the
+    /// extension of the type the server sends, and `{original_filename}` is the last part of
+    /// the url rather than what the server calls the file.
+    pub fn planned_path(&self) -> PathBuf {
+        let config = &self.config;
+        let url = self.attrs.url();
+        let mime = self
+            .attrs
+            .mime()
+            .filter(|mime| mime_guess::get_mime_extensions_str(mime).is_some());
+
+        let mut path = config
+            .download_path
+            .join(self.partial_name(&config.download_path));
+        path.set_extension(net::extension(url, self.attrs.mime(), mime));
+
+        if !config.pipeline.contains(&ProcessStep::Rename) {
+            return path;
+        }
+
+        let original_name = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| naming::url_filename(&url));
+
+        match original_name.filter(|_| config.original_filename) {
+            Some(original_name) => {
+                let mut new_path = path.with_file_name(original_name);
+                if new_path.extension().is_none() {
+                    if let Some(extension) = path.extension() {
+                        new_path.set_extension(extension);
+                    }
+                }
+                free_path(&path, new_path)
+            }
+            None => {
+                let new_path = pattern_path(&path, &config.name_pattern);
+                match config.flat {
+                    true => free_path(&path, new_path),
+                    false => new_path,
+                }
+            }
+        }
+    }
+
+    /// Finds a file in the download path that's named after the episode.
+    pub fn find_by_name(&self) -> Option<PathBuf> {
+        fs::read_dir(&self.config.download_path)
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_none_or(|ext| ext != "partial"))
+            .find(|path| path.is_file() && pattern_path(path, &self.config.name_pattern) == *path)
+    }
+
     fn tracker_path(&self) -> &Path {
         self.config.tracker_path.as_path()
     }
 
-    fn into_downloaded(&self, path: PathBuf) -> DownloadedEpisode<'_> {
-        DownloadedEpisode::new(self, path)
+    fn to_downloaded(
+        &self,
+        path: PathBuf,
+        fingerprint: Fingerprint,
+        original_name: Option<String>,
+    ) -> DownloadedEpisode<'_> {
+        DownloadedEpisode::new(self, path, fingerprint, original_name)
     }
 
+    /// Downloads and processes the episode.
+    ///
+    /// `first_download` tells whether nothing had been downloaded from the podcast before
+    /// this run.
     pub async fn download<'a>(
         &'a self,
         client: &reqwest::Client,
+        first_download: bool,
         ui: &DownloadBar,
-    ) -> Result<DownloadedEpisode<'a>, String> {
+    ) -> Result<DownloadedEpisode<'a>, DownloadError> {
         self.log_debug(ui, "downloading episode");
-        let audio_file = self.download_enclosure(client, ui).await?;
-        let mut episode = self.into_downloaded(audio_file);
-        episode.process(ui).await?;
-        episode.run_download_hook(ui);
+        let start = Instant::now();
+        let res = self.download_enclosure(client, ui).await;
+        ui.finish_download(self.attrs.url(), start.elapsed(), res.is_ok());
+        let (audio_file, fingerprint, original_name) = res?;
+        ui.record(Phase::Downloading, start.elapsed());
+        self.check_length(&audio_file, ui);
+
+        let mut episode = self.to_downloaded(audio_file, fingerprint, original_name);
+        episode.first_download = first_download;
+        episode.process(client, ui).await?;
+
+        let start = Instant::now();
         episode.mark_downloaded()?;
+        ui.record(Phase::StateWrites, start.elapsed());
         Ok(episode)
     }
 
+    /// Downloads the episode without recording it, for mirroring.
+    ///
+    /// Only the rename and tag steps of the pipeline are run.
+    pub async fn mirror(
+        &self,
+        client: &reqwest::Client,
+        ui: &DownloadBar,
+    ) -> Result<PathBuf, String> {
+        self.log_debug(ui, "mirroring episode");
+        let (audio_file, fingerprint, original_name) = self.download_enclosure(client, ui).await?;
+
+        let mut episode = self.to_downloaded(audio_file, fingerprint, original_name);
+        for step in self.config.pipeline.clone() {
+            match step {
+                ProcessStep::Rename => {
+                    episode.rename()?;
+                    episode.save_transcript(client, ui).await;
+                }
+                ProcessStep::Tag => episode.normalize_tags(client, ui).await,
+                ProcessStep::Symlink | ProcessStep::Hook => {}
+            }
+        }
+
+        Ok(episode.into_path())
+    }
+
+    /// Warns if the downloaded file is far off from the length advertised in the feed.
+    fn check_length(&self, path: &Path, ui: &DownloadBar) {
+        let (Some(advertised), Ok(metadata)) = (self.attrs.length(), fs::metadata(path)) else {
+            return;
+        };
+
+        if let Err(e) = LengthReliability::record(self.tracker_path(), advertised, metadata.len()) {
+            self.log_warn(ui, e);
+        }
+    }
+
+    /// Fails the download if the size of the file differs from the expected one.
+    ///
+    /// A file shorter than the server announced is worth trying again, resuming where it
+    /// stopped.
+    fn check_size(
+        &self,
+        written: u64,
+        expected: Option<u64>,
+        source: &str,
+    ) -> Result<(), DownloadError> {
+        let Some(expected) = expected.filter(|expected| written != *expected) else {
+            return Ok(());
+        };
+
+        match written < expected {
+            true => Err(DownloadError::Transient(format!(
+                "truncated download, received {} of the {} bytes {}",
+                written, expected, source
+            ))),
+            false => Err(DownloadError::Permanent(format!(
+                "received {} bytes but {} {}",
+                written, source, expected
+            ))),
+        }
+    }
+
+    /// Asks the server whether the enclosure changed since it was downloaded.
+    ///
+    /// Returns the fingerprint of the downloaded episode if it changed.
+    pub async fn remote_changed(
+        &self,
+        client: &reqwest::Client,
+        tolerance: f64,
+        ui: &DownloadBar,
+    ) -> Option<Fingerprint> {
+        let fingerprints = Fingerprints::load(self.tracker_path());
+        let local = fingerprints.get(&self.get_id())?;
+
+        self.log_trace(ui, "checking if enclosure changed");
+        let url = self.attrs.url();
+        let request = self.config.credentials.apply(client.head(url), url);
+        let response = match chaos::send(request, url).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.log_warn(ui, format!("failed to recheck enclosure: {}", e));
+                return None;
+            }
+        };
+
+        let remote = Fingerprint::from_response(&response);
+        if !local.differs(&remote, tolerance) {
+            return None;
+        }
+
+        self.log_warn(
+            ui,
+            format!(
+                "enclosure changed since download. before: {}. now: {}",
+                local.describe(),
+                remote.describe()
+            ),
+        );
+
+        Some(local.clone())
+    }
+
+    /// Downloads the episode again, keeping the old file as a `.old` backup.
+    pub async fn redownload<'a>(
+        &'a self,
+        client: &reqwest::Client,
+        local: &Fingerprint,
+        backups: usize,
+        trash: Option<&Trash>,
+        ui: &DownloadBar,
+    ) -> Result<DownloadedEpisode<'a>, String> {
+        let path = &local.path;
+        let backup = {
+            let mut name = path.file_name().unwrap_or_default().to_owned();
+            name.push(format!(".{}.old", utils::current_unix().as_secs()));
+            path.with_file_name(name)
+        };
+
+        if path.exists() {
+            fs::rename(path, &backup).map_err(|_| "failed to back up episode".to_string())?;
+        }
+
+        match self.download(client, false, ui).await {
+            Ok(episode) => {
+                paths::prune_backups(path, backups, trash);
+                Ok(episode)
+            }
+            Err(e) => {
+                let _ = fs::rename(&backup, path);
+                Err(e.into())
+            }
+        }
+    }
+
     async fn download_enclosure<'a>(
         &'a self,
         client: &reqwest::Client,
         ui: &DownloadBar,
-    ) -> Result<PathBuf, String> {
+    ) -> Result<(PathBuf, Fingerprint, Option<String>), DownloadError> {
         let config = &self.config;
 
-        let partial_path = config
+        let partial_dir = config
             .partial_path
             .clone()
-            .unwrap_or_else(|| config.download_path.clone())
-            .join(self.partial_name());
+            .unwrap_or_else(|| config.download_path.clone());
+        let partial_path = partial_dir.join(self.partial_name(&partial_dir));
 
         let mut file = fs::OpenOptions::new()
             .write(true)
             .create(true)
             .open(&partial_path)
-            .map_err(|_| "failed to write file".to_string())?;
+            .map_err(|e| paths::io_error_message(&partial_path, &e, "failed to write file"))?;
 
         let mut downloaded = file
             .seek(std::io::SeekFrom::End(0))
             .map_err(|_| "file error".to_string())?;
 
-        self.log_trace(ui, format!("connecting to url: {:?}", self.as_ref().url()));
-        let response = client
-            .get(self.as_ref().url())
-            .header(reqwest::header::RANGE, format!("bytes={}-", downloaded))
-            .send()
-            .await;
+        self.log_trace(
+            ui,
+            format!(
+                "connecting to url: {:?}",
+                net::redact_url(self.as_ref().url())
+            ),
+        );
+        let url = self.as_ref().url();
+        let mut request = self.config.credentials.apply(client.get(url), url);
+        // A partial file left by an interrupted download is resumed where it stopped.
+        if downloaded > 0 {
+            self.log_debug(ui, format!("resuming download at byte {}", downloaded));
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", downloaded));
+        }
+        let mut response = chaos::send(request, url)
+            .await
+            .map_err(|e| DownloadError::from_request(&e))?;
+
+        // The server ignored the range and sent the whole file, or the partial file is
+        // longer than the enclosure is now.
+        if downloaded > 0 && response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            self.log_debug(
+                ui,
+                format!(
+                    "can't resume download ({}), starting over",
+                    response.status()
+                ),
+            );
+            file.set_len(0)
+                .and_then(|_| file.seek(std::io::SeekFrom::Start(0)))
+                .map_err(|e| {
+                    paths::io_error_message(&partial_path, &e, "failed to truncate file")
+                })?;
+            downloaded = 0;
+
+            // Asked for again the same way, without the range.
+            if response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+                let request = self.config.credentials.apply(client.get(url), url);
+                response = chaos::send(request, url)
+                    .await
+                    .map_err(|e| DownloadError::from_request(&e))?;
+            }
+        }
 
-        let response = utils::short_handle_response(response)?;
+        if !response.status().is_success() {
+            return Err(DownloadError::from_status(response.status()));
+        }
 
-        let total_size = response.content_length().unwrap_or(0);
-        let extension = utils::get_extension_from_response(&response, &self);
+        let fingerprint = Fingerprint::from_response(&response);
+        let original_name = naming::original_filename(&response);
+        // Unlike the content length, this is the size of the whole file when resuming.
+        let total_size = fingerprint.length.unwrap_or(0);
+        let extension = net::get_extension_from_response(&response, &self);
 
         ui.init_download_bar(downloaded, total_size);
 
-        let mut stream = response.bytes_stream();
-
-        while let Some(item) = stream.next().await {
-            let chunk = item.map_err(|_| "failed to load chunk".to_string())?;
-            file.write_all(&chunk)
-                .map_err(|_| "failed to write chunk to file".to_string())?;
-            downloaded = cmp::min(downloaded + (chunk.len() as u64), total_size);
-            ui.set_progress(downloaded);
+        let mut file = tokio::io::BufWriter::with_capacity(
+            config.write_buffer,
+            tokio::fs::File::from_std(file),
+        );
+        let resumed_at = downloaded;
+        let mut received = 0;
+        let mut waits = TransferWaits::default();
+        let copied = copy_chunks(
+            response.bytes_stream(),
+            &mut file,
+            &mut waits,
+            |e, copied| match e.is_timeout() {
+                true => DownloadError::Transient("timed out while downloading".into()),
+                // Like when the server closed the connection before sending everything.
+                false => self
+                    .check_size(
+                        resumed_at + copied,
+                        fingerprint.length,
+                        "the server announced",
+                    )
+                    .err()
+                    .unwrap_or_else(|| DownloadError::Transient("failed to load chunk".into())),
+            },
+            |len| {
+                received += len;
+                ui.receive(self.as_ref().url(), len);
+                downloaded = cmp::min(downloaded + len, total_size);
+                ui.set_progress(downloaded);
+            },
+        )
+        .await;
+        let written = resumed_at + received;
+
+        // What was received is written out even if the download failed, so it can resume.
+        let started = Instant::now();
+        let flushed = file.flush().await;
+        waits.disk += started.elapsed();
+        copied?;
+        flushed.map_err(|e| paths::io_error_message(&partial_path, &e, "failed to write file"))?;
+
+        // A connection closed early can look like the end of the file, so the partial file is
+        // only kept for resuming rather than passed off as the whole episode. Responses
+        // without a length, like chunked ones, can't be checked unless the feed's length is
+        // trusted.
+        self.check_size(written, fingerprint.length, "the server announced")?;
+        if config.strict_size_check {
+            // The server sent all it had, so resuming would ask for bytes past the end of the
+            // file, and downloading it again would get the same file.
+            self.check_size(written, self.attrs.length(), "the feed advertises")
+                .map_err(|e| DownloadError::Permanent(e.to_string()))?;
         }
 
+        self.log_debug(
+            ui,
+            format!(
+                "received {} after waiting {:.1?} on the network and {:.1?} on the disk, {}",
+                format::human_bytes(received),
+                waits.network,
+                waits.disk,
+                waits.bottleneck()
+            ),
+        );
+
         let path = {
             let mut path = config
                 .download_path
-                .to_path_buf()
-                .join(&self.partial_name());
+                .join(self.partial_name(&config.download_path));
             path.set_extension(extension);
             path
         };
 
-        fs::rename(partial_path, &path).map_err(|_| "failed to rename episode file".to_string())?;
+        fs::rename(partial_path, &path)
+            .map_err(|e| paths::io_error_message(&path, &e, "failed to rename episode file"))?;
+
+        Ok((path, fingerprint, original_name))
+    }
+}
+
+/// Copies the chunks of `stream` into `file`, calling `on_written` with the length of each
+/// one once it's written. A chunk that failed is turned into an error by `chunk_error`,
+/// along with how many bytes were copied before it.
+///
+/// Each write is awaited before the next chunk is asked for, so a disk slower than the
+/// network slows the download down rather than piling chunks up in memory.
+async fn copy_chunks<C, W>(
+    stream: impl Stream<Item = Result<C, chaos::Error>>,
+    file: &mut tokio::io::BufWriter<W>,
+    waits: &mut TransferWaits,
+    mut chunk_error: impl FnMut(chaos::Error, u64) -> DownloadError,
+    mut on_written: impl FnMut(u64),
+) -> Result<(), DownloadError>
+where
+    C: std::ops::Deref<Target = [u8]>,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let mut stream = std::pin::pin!(stream);
+    let mut copied = 0;
+    loop {
+        let started = Instant::now();
+        // The partial file is kept when suspended, so the download resumes next time.
+        let item = deadline::within_grace(stream.next())
+            .await
+            .ok_or_else(|| "suspended at the max runtime".to_string())?;
+        waits.network += started.elapsed();
+
+        let Some(item) = item else {
+            return Ok(());
+        };
+        let chunk = item.map_err(|e| chunk_error(e, copied))?;
+
+        let started = Instant::now();
+        file.write_all(&chunk)
+            .await
+            .map_err(|_| "failed to write chunk to file".to_string())?;
+        waits.disk += started.elapsed();
 
-        Ok(path)
+        copied += chunk.len() as u64;
+        on_written(chunk.len() as u64);
+    }
+}
+
+/// How long a download spent waiting on either side of it.
+#[derive(Default)]
+struct TransferWaits {
+    network: time::Duration,
+    disk: time::Duration,
+}
+
+impl TransferWaits {
+    /// Which side held the download up the most.
+    fn bottleneck(&self) -> &'static str {
+        if self.disk > self.network {
+            "disk-bound"
+        } else {
+            "network-bound"
+        }
     }
 }
 
@@ -331,15 +1100,28 @@ pub struct DownloadedEpisode<'a> {
     /// Where the episode is downloaded.
     path: PathBuf,
     /// The handle to the process of an optional post-download hook.
-    handle: Option<JoinHandle<()>>,
+    handle: Option<JoinHandle<Result<(), String>>>,
+    fingerprint: Fingerprint,
+    /// The file name the server served the episode under.
+    original_name: Option<String>,
+    /// Whether nothing had been downloaded from the podcast before this run.
+    first_download: bool,
 }
 
 impl<'a> DownloadedEpisode<'a> {
-    pub fn new(inner: &'a Episode, path: PathBuf) -> DownloadedEpisode<'a> {
+    pub fn new(
+        inner: &'a Episode,
+        path: PathBuf,
+        fingerprint: Fingerprint,
+        original_name: Option<String>,
+    ) -> DownloadedEpisode<'a> {
         Self {
             inner,
             path,
             handle: None,
+            fingerprint,
+            original_name,
+            first_download: false,
         }
     }
 
@@ -348,9 +1130,16 @@ impl<'a> DownloadedEpisode<'a> {
     }
 
     pub fn mark_downloaded(&self) -> Result<(), String> {
-        let id = self.inner.config.id_pattern.replace(" ", "_");
-        let path = self.inner.config.tracker_path.as_path();
-        DownloadedEpisodes::append(&path, &id, self)
+        self.inner.mark_downloaded()?;
+
+        let id = self.inner.get_id();
+        let path = self.inner.tracker_path();
+
+        let fingerprint = Fingerprint {
+            path: self.path.clone(),
+            ..self.fingerprint.clone()
+        };
+        Fingerprints::insert(path, &id, fingerprint)
     }
 
     pub fn inner(&self) -> &Episode {
@@ -361,84 +1150,90 @@ impl<'a> DownloadedEpisode<'a> {
         &self.path
     }
 
-    pub async fn normalize_id3v2(&self, ui: &DownloadBar) {
-        use id3::TagLike;
-        if self.path.extension().is_some_and(|ext| ext == "mp3") {
+    pub async fn normalize_tags(&self, client: &reqwest::Client, ui: &DownloadBar) {
+        let start = Instant::now();
+        let mut artwork = time::Duration::ZERO;
+
+        let is_mp3 = self.path.extension().is_some_and(|ext| ext == "mp3");
+        let is_mp4 = mp4::is_mp4(&self.path) && !self.inner.attrs.is_video();
+        let is_ogg = ogg::is_ogg(&self.path) && !self.inner.attrs.is_video();
+
+        if !is_mp3 && !is_mp4 && !is_ogg {
+            self.inner.log_trace(
+                ui,
+                "skipping tag normalization: enclosure not an mp3, m4a or ogg",
+            );
+        } else if let Err(e) = self
+            .inner
+            .tags
+            .as_ref()
+            .map_or(Ok(()), |_| mp3::check_room(self.path()))
+        {
+            self.inner
+                .log_warn(ui, format!("tagging deferred until --retag: {}", e));
+            DeferredTags::defer(self.inner.tracker_path(), &self.inner.get_id(), &e);
+        } else if self.inner.tags.is_some() && is_mp3 {
             self.inner.log_trace(ui, "normalizing id3 tags");
-            if let Some(xml_tags) = &self.inner.tags {
-                let mut file_tags = id3::Tag::read_from_path(&self.path()).unwrap_or_default();
-
-                for frame in xml_tags.frames() {
-                    if !file_tags.get(frame.id()).is_some() {
-                        file_tags.add_frame(frame.to_owned());
-                        self.inner
-                            .log_trace(ui, format!("adding frame: {:?}", &frame));
-                    }
-                }
+            let mut file_tags = FileTags::read(self.path());
+            if let Some(problem) = &file_tags.problem {
+                self.inner.log_warn(ui, problem.as_str());
+            }
 
-                for (id, value) in &self.inner.config.id3_tags {
-                    file_tags.set_text(id, value);
+            artwork = self.inner.apply_tags(client, &mut file_tags.tag, ui).await;
+            self.inner
+                .add_chapters(client, &mut file_tags.tag, ui)
+                .await;
+
+            if let Err(e) = file_tags.write(self.path()) {
+                self.inner.log_warn(ui, e);
+            };
+        } else if self.inner.tags.is_some() && is_mp4 {
+            self.inner.log_trace(ui, "writing mp4 metadata");
+            match Mp4Tags::read(self.path()) {
+                Ok(mut file_tags) => {
+                    artwork = self.inner.apply_tags(client, &mut file_tags.tag, ui).await;
+                    if let Err(e) = file_tags.write(self.path()) {
+                        self.inner.log_warn(ui, e);
+                    }
                 }
-
-                if !file_tags
-                    .pictures()
-                    .any(|pic| pic.picture_type == id3::frame::PictureType::CoverFront)
-                {
-                    if let Some(img_url) = self.inner.image_url.as_ref() {
-                        if let Some(frame) =
-                            cache::get_image(img_url, id3::frame::PictureType::CoverFront, ui).await
-                        {
-                            file_tags.add_frame(frame);
-                            self.inner
-                                .log_debug(ui, "added cover image to podcast episode");
-                        } else {
-                            self.inner.log_warn(
-                                ui,
-                                format!("failed to fetch image from url: {:?}", img_url),
-                            );
-                        };
+                Err(e) => self.inner.log_warn(ui, e),
+            }
+        } else if self.inner.tags.is_some() {
+            self.inner.log_trace(ui, "writing vorbis comments");
+            match OggTags::read(self.path()) {
+                Ok(mut file_tags) => {
+                    artwork = self.inner.apply_tags(client, &mut file_tags.tag, ui).await;
+                    if let Err(e) = file_tags.write(self.path()) {
+                        self.inner.log_warn(ui, e);
                     }
                 }
-
-                if let Err(e) = file_tags.write_to_path(&self.path(), id3::Version::Id3v24) {
-                    ui.log_error(format!("failed to write tags to file: {:?}", e));
-                };
+                Err(e) => self.inner.log_warn(ui, e),
             }
-        } else {
-            self.inner
-                .log_trace(ui, "skipping id3 tag normalization: enclosure not an mp3");
-        };
+        }
+
+        ui.record(Phase::Tagging, start.elapsed().saturating_sub(artwork));
     }
 
     fn file_name(&self) -> &str {
         self.path.file_name().unwrap().to_str().unwrap()
     }
 
-    pub async fn await_handle(&mut self, ui: &DownloadBar) {
-        if let Some(handle) = self.handle.take() {
-            self.inner.log_debug(ui, "awaiting download hook");
-            let _ = handle.await;
-        }
+    /// The handle to the download hook, if one was started.
+    pub fn take_hook(&mut self) -> Option<JoinHandle<Result<(), String>>> {
+        self.handle.take()
     }
 
     fn run_download_hook(&mut self, ui: &DownloadBar) {
-        let Some(script_path) = self.inner.config.download_hook.clone() else {
-            self.inner.log_trace(ui, "no download hook configured");
+        let Some(hook) = self.inner.config.download_hook.clone() else {
+            self.inner.log_trace(ui, "no download hook to run");
             return;
         };
 
         self.inner.log_debug(ui, "running download hook");
-
-        let path = self.path().to_owned();
-
-        let handle = tokio::task::spawn_blocking(move || {
-            std::process::Command::new(script_path)
-                .arg(path)
-                .output()
-                .unwrap();
-        });
-
-        self.handle = Some(handle);
+        let run = self
+            .inner
+            .hook_run(hook, self.path(), self.first_download, ui);
+        self.handle = Some(run.spawn());
     }
 
     fn make_symlink(&mut self, ui: &DownloadBar) -> Result<(), String> {
@@ -454,46 +1249,160 @@ impl<'a> DownloadedEpisode<'a> {
                 return Err("configured symlink path is not a directory".to_string());
             }
 
-            std::os::unix::fs::symlink(self.path(), new_path)
-                .map_err(|_| "failed to create symlink".to_string())?;
+            std::os::unix::fs::symlink(self.path(), &new_path)
+                .map_err(|e| paths::io_error_message(&new_path, &e, "failed to create symlink"))?;
         }
 
         Ok(())
     }
 
-    async fn process(&mut self, ui: &DownloadBar) -> Result<(), String> {
+    async fn process(&mut self, client: &reqwest::Client, ui: &DownloadBar) -> Result<(), String> {
         self.inner.log_debug(ui, "processing episode");
-        self.rename()?;
-        self.make_symlink(ui)?;
-        self.normalize_id3v2(ui).await;
+        for step in self.inner.config.pipeline.clone() {
+            self.inner
+                .log_trace(ui, format!("running step: {:?}", step));
+            match step {
+                ProcessStep::Rename => {
+                    self.rename()?;
+                    // Saved under the final name of the episode, before a hook may need it.
+                    self.save_transcript(client, ui).await;
+                }
+                ProcessStep::Symlink => self.make_symlink(ui)?,
+                ProcessStep::Tag => self.normalize_tags(client, ui).await,
+                ProcessStep::Hook => self.run_download_hook(ui),
+            }
+        }
 
         Ok(())
     }
 
-    fn rename(&mut self) -> Result<(), String> {
-        let new_name = &self.inner.config.name_pattern;
-        let mut new_name = sanitize_filename::sanitize(new_name);
-
-        let new_path = match self.path.extension() {
-            Some(extension) => {
-                let max_file_len: usize = 255;
-                let ext_len = extension.len() + 1; // + 1 for the dot.
-                let overflow = (new_name.len() + ext_len).saturating_sub(max_file_len);
-                for _ in 0..overflow {
-                    new_name.pop();
+    /// Downloads the transcript of the episode next to it, if transcripts are enabled and
+    /// the episode has one in a wanted format.
+    async fn save_transcript(&self, client: &reqwest::Client, ui: &DownloadBar) {
+        let config = &self.inner.config;
+        let transcripts = self.inner.attrs.transcripts();
+
+        let mut error = None;
+        for (url, format) in transcript::ranked(&transcripts, &config.transcript_formats) {
+            let path = self.path.with_extension(format.extension());
+            match transcript::download(client, &config.credentials, url, &path, ui).await {
+                Ok(()) => {
+                    self.inner
+                        .log_debug(ui, format!("saved transcript to {:?}", path));
+                    return;
                 }
+                Err(e) => {
+                    self.inner.log_debug(ui, &e);
+                    error = Some(e);
+                }
+            }
+        }
 
-                let mut new_path = self.path.with_file_name(new_name);
+        if let Some(e) = error {
+            self.inner.log_warn(ui, e);
+        }
+    }
+
+    fn rename(&mut self) -> Result<(), String> {
+        if self.inner.config.original_filename {
+            if let Some(original_name) = self.original_name.clone() {
+                return self.rename_to_original(&original_name);
+            }
+        }
+
+        let mut new_path = pattern_path(&self.path, &self.inner.config.name_pattern);
+        if self.inner.config.flat {
+            new_path = self.free_path(new_path);
+        }
+
+        fs::rename(&self.path, &new_path)
+            .map_err(|e| paths::io_error_message(&new_path, &e, "failed to rename episode"))?;
+        self.path = new_path;
+        Ok(())
+    }
+
+    /// Renames the file to what the server called it.
+    ///
+    /// Keeps the downloaded extension if the name lacks one.
+    fn rename_to_original(&mut self, original_name: &str) -> Result<(), String> {
+        let mut new_path = self.path.with_file_name(original_name);
+        if new_path.extension().is_none() {
+            if let Some(extension) = self.path.extension() {
                 new_path.set_extension(extension);
-                new_path
             }
-            None => self.path.with_file_name(new_name),
-        };
+        }
 
-        fs::rename(&self.path, &new_path).map_err(|_| "failed to rename episode".to_string())?;
+        let new_path = self.free_path(new_path);
+        fs::rename(&self.path, &new_path)
+            .map_err(|e| paths::io_error_message(&new_path, &e, "failed to rename episode"))?;
         self.path = new_path;
         Ok(())
     }
+
+    /// Appends a number to the name of `new_path` if another file already has it.
+    fn free_path(&self, new_path: PathBuf) -> PathBuf {
+        free_path(&self.path, new_path)
+    }
+}
+
+/// Appends a number to the name of `new_path` if a file other than `current` already has it.
+///
+/// The name is shortened to fit the filesystem, keeping the extension and the number.
+fn free_path(current: &Path, new_path: PathBuf) -> PathBuf {
+    let stem = new_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = new_path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    let dir = new_path.parent().unwrap_or(Path::new(""));
+    let max_len = PathLimits::of(dir).file_name_len(dir);
+
+    let fitted = |suffix: &str| {
+        let options = NameOptions {
+            max_len: Some(max_len.saturating_sub(suffix.len() + extension.len())),
+            extension: None,
+        };
+        let stem = naming::sanitize_filename(options, &stem);
+        let stem = match suffix.is_empty() {
+            true => stem.as_str(),
+            false => stem.trim_end(),
+        };
+        new_path.with_file_name(format!("{}{}{}", stem, suffix, extension))
+    };
+
+    let mut free_path = fitted("");
+    let mut suffix = 2;
+    while free_path.exists() && free_path != current {
+        free_path = fitted(&format!(" ({})", suffix));
+        suffix += 1;
+    }
+
+    free_path
+}
+
+/// Where a file ends up when it's renamed after the evaluated `name_pattern`.
+fn pattern_path(path: &Path, name_pattern: &str) -> PathBuf {
+    match path.extension() {
+        Some(extension) => {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let options = NameOptions {
+                max_len: Some(PathLimits::of(dir).file_name_len(dir)),
+                extension: extension.to_str(),
+            };
+            let new_name = naming::sanitize_filename(options, name_pattern);
+            let mut new_path = path.with_file_name(new_name);
+            new_path.set_extension(extension);
+            new_path
+        }
+        None => path.with_file_name(naming::sanitize_filename(
+            NameOptions::default(),
+            name_pattern,
+        )),
+    }
 }
 
 impl AsRef<Episode> for DownloadedEpisode<'_> {
@@ -507,3 +1416,621 @@ impl AsRef<Attributes> for Episode {
         &self.attrs
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EvalData;
+    use crate::config::GlobalConfig;
+    use crate::config::PodcastConfig;
+    use crate::podcast::RawPodcast;
+    use id3::TagLike;
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+
+    /// The attributes of an rss item with the given tags besides the usual ones.
+    fn attributes(tags: serde_json::Value) -> Attributes {
+        let mut item = serde_json::json!({
+            "title": "Episode",
+            "pubDate": "Mon, 02 Sep 2024 06:00:00 +0000",
+            "enclosure": { "@url": "https://example.com/episode.mp3", "@type": "audio/mpeg" },
+        });
+        let item = item.as_object_mut().unwrap();
+        item.extend(tags.as_object().unwrap().clone());
+
+        let raw = RawEpisode::new(item.clone());
+        Attributes::new(raw, &MediaPreference::default()).unwrap()
+    }
+
+    #[test]
+    fn json_chapters_url() {
+        let attrs = attributes(serde_json::json!({
+            "podcast:chapters": [
+                { "@url": "https://example.com/chapters.vtt", "@type": "text/vtt" },
+                { "@url": "https://example.com/chapters.json", "@type": "application/json+chapters" },
+            ],
+        }));
+        assert_eq!(
+            attrs.chapters_url(),
+            Some("https://example.com/chapters.json")
+        );
+
+        let attrs = attributes(serde_json::json!({
+            "podcast:chapters": { "@url": "https://example.com/chapters.vtt", "@type": "text/vtt" },
+        }));
+        assert_eq!(attrs.chapters_url(), None);
+        assert_eq!(attributes(serde_json::json!({})).chapters_url(), None);
+    }
+
+    /// An episode of a podcast with the settings in `toml`, downloaded to the test
+    /// directory `name`.
+    fn episode(name: &str, attrs: Attributes, tags: Option<id3::Tag>, toml: &str) -> Episode {
+        let podcast_config: PodcastConfig = toml::from_str(&format!(
+            "url = \"https://example.com/feed.xml\"\ndownload_path = \"{}\"\n{}",
+            paths::test_dir(name).display(),
+            toml
+        ))
+        .unwrap();
+        let raw_podcast = RawPodcast::default();
+        let data = EvalData::new("podcast", &raw_podcast, &attrs);
+        let config = Config::new(&GlobalConfig::default(), &podcast_config, data);
+        let channel = Channel {
+            name: "podcast".to_string(),
+            tags: Default::default(),
+        };
+        Episode::new(attrs, 0, config, tags, None, None, Arc::new(channel))
+    }
+
+    /// Adds the chapters of a local server to an empty tag, with the podcast settings in
+    /// `toml`. Returns the tag and the paths the server was asked for.
+    async fn add_chapters(toml: &str) -> (id3::Tag, Vec<String>) {
+        let (url, requests) = net::test_server(|url| {
+            let chapters = format!(
+                r#"{{"chapters": [
+                    {{"startTime": 0, "title": "Intro", "img": "{}/intro.png"}},
+                    {{"startTime": 60, "title": "Main"}}
+                ]}}"#,
+                url
+            );
+            vec![
+                ("/chapters.json", "application/json", chapters.into()),
+                ("/intro.png", "image/png", b"not really a png".to_vec()),
+            ]
+        })
+        .await;
+
+        let attrs = attributes(serde_json::json!({
+            "podcast:chapters": { "@url": format!("{}/chapters.json", url), "@type": "application/json" },
+            "itunes:duration": "120",
+        }));
+        let name = format!("chapters-{}", url.rsplit(':').next().unwrap());
+        let episode = episode(&name, attrs, None, toml);
+
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new(
+            "podcast".to_string(),
+            GlobalConfig::default().style(),
+            &mp,
+            7,
+        );
+        let mut tag = id3::Tag::new();
+        episode
+            .add_chapters(&reqwest::Client::new(), &mut tag, &ui)
+            .await;
+
+        let requests = requests.lock().unwrap().clone();
+        (tag, requests)
+    }
+
+    #[tokio::test]
+    async fn chapters_with_images() {
+        let (tag, requests) = add_chapters("").await;
+        assert_eq!(requests, vec!["/chapters.json", "/intro.png"]);
+
+        let chapters: Vec<&id3::frame::Chapter> = tag.chapters().collect();
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(
+            (chapters[1].start_time, chapters[1].end_time),
+            (60_000, 120_000)
+        );
+        let image = chapters[0].frames.iter().find(|frame| frame.id() == "APIC");
+        assert_eq!(
+            image
+                .and_then(|frame| frame.content().picture())
+                .map(|pic| &pic.data[..]),
+            Some(&b"not really a png"[..])
+        );
+        assert_eq!(tag.tables_of_contents().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn chapters_without_images() {
+        let (tag, requests) = add_chapters("chapter_images = false").await;
+        assert_eq!(requests, vec!["/chapters.json"]);
+
+        let chapters: Vec<&id3::frame::Chapter> = tag.chapters().collect();
+        assert_eq!(chapters.len(), 2);
+        assert!(chapters
+            .iter()
+            .all(|chapter| chapter.frames.iter().all(|frame| frame.id() != "APIC")));
+    }
+
+    #[tokio::test]
+    async fn chapters_turned_off() {
+        let (tag, requests) = add_chapters("embed_chapters = false").await;
+        assert!(requests.is_empty());
+        assert_eq!(tag.chapters().count(), 0);
+    }
+
+    /// Applies the tags of the episode to a tag, and reads it back like from a file.
+    async fn apply_tags(episode: &Episode, tag: &id3::Tag) -> id3::Tag {
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new(
+            "podcast".to_string(),
+            GlobalConfig::default().style(),
+            &mp,
+            7,
+        );
+        let mut tag = tag.clone();
+        episode
+            .apply_tags(&reqwest::Client::new(), &mut tag, &ui)
+            .await;
+
+        let mut bytes = vec![];
+        tag.write_to(&mut bytes, id3::Version::Id3v24).unwrap();
+        id3::Tag::read_from2(std::io::Cursor::new(bytes)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn retagging_reaches_a_fixed_point() {
+        let mut feed_tags = id3::Tag::new();
+        feed_tags.set_title("Episode");
+        feed_tags.set_text("TCON", "News\0Tech\0\0News");
+        feed_tags.set_text("TCOM", "Ann");
+        let toml = "[id3_tags]\nTPE1 = \"Ann\\u0000Bob\\u0000Ann\"\nTCOM = \"Bob\"";
+        let episode = episode(
+            "retag-fixed-point",
+            attributes(serde_json::json!({})),
+            Some(feed_tags),
+            toml,
+        );
+
+        // A file that already has a tag of its own.
+        let mut file_tag = id3::Tag::new();
+        file_tag.set_album("Album");
+        file_tag.set_text("TPE1", "Someone");
+
+        let first = apply_tags(&episode, &file_tag).await;
+        assert_eq!(
+            first.get("TCON").and_then(|f| f.content().text()),
+            Some("News\0Tech")
+        );
+        assert_eq!(
+            first.get("TPE1").and_then(|f| f.content().text()),
+            Some("Ann\0Bob")
+        );
+        assert_eq!(
+            first.get("TCOM").and_then(|f| f.content().text()),
+            Some("Bob")
+        );
+        assert_eq!(first.album(), Some("Album"));
+
+        let second = apply_tags(&episode, &first).await;
+        let frames = |tag: &id3::Tag| tag.frames().cloned().collect::<Vec<_>>();
+        assert_eq!(frames(&second), frames(&first));
+    }
+
+    /// Runs a hook on the episode that saves `TALECAST_FIRST_DOWNLOAD`, returning its value.
+    async fn hook_first_download(episode: &Episode, first_download: bool) -> String {
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new(
+            "podcast".to_string(),
+            GlobalConfig::default().style(),
+            &mp,
+            7,
+        );
+        let hook = HookCommand {
+            program: PathBuf::from("/bin/sh"),
+            args: vec![
+                "-c".to_string(),
+                "printf %s \"$TALECAST_FIRST_DOWNLOAD\" > \"$1.first\"".to_string(),
+                "hook".to_string(),
+            ],
+        };
+
+        let path = episode.config.download_path.join("episode.mp3");
+        let run = episode.hook_run(hook, &path, first_download, &ui);
+        run.spawn().await.unwrap().unwrap();
+        fs::read_to_string(path.with_extension("mp3.first")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn first_download_until_tracked() {
+        let episode = episode(
+            "first_download",
+            attributes(serde_json::json!({})),
+            None,
+            "",
+        );
+        let tracker = episode.tracker_path();
+
+        let first_download = DownloadedEpisodes::load(tracker).is_empty();
+        assert!(first_download);
+        assert_eq!(hook_first_download(&episode, first_download).await, "true");
+
+        DownloadedEpisodes::append(tracker, &episode.get_id(), &episode).unwrap();
+        let first_download = DownloadedEpisodes::load(tracker).is_empty();
+        assert!(!first_download);
+        assert_eq!(hook_first_download(&episode, first_download).await, "false");
+    }
+
+    #[test]
+    fn caught_up_podcast_is_not_a_first_download() {
+        let episode = episode(
+            "first_download_caught_up",
+            attributes(serde_json::json!({})),
+            None,
+            "",
+        );
+        episode.mark_acknowledged().unwrap();
+        assert!(!DownloadedEpisodes::load(episode.tracker_path()).is_empty());
+    }
+
+    /// Which of five episodes published on the first days of 2024 would be downloaded, oldest
+    /// first, with the global and podcast settings in `global` and `toml`.
+    fn retained(name: &str, global: &str, toml: &str) -> Vec<usize> {
+        let global_config: GlobalConfig = toml::from_str(global).unwrap();
+        let podcast_config: PodcastConfig =
+            toml::from_str(&format!("url = \"https://example.com/feed.xml\"\n{}", toml)).unwrap();
+        let dir = paths::test_dir(name);
+        let mode = DownloadMode::new(&global_config, &podcast_config, &dir.join(".downloaded"));
+
+        (0..5)
+            .filter(|&index| {
+                let attrs = attributes(serde_json::json!({
+                    "pubDate": format!("2024-01-0{}T06:00:00Z", index + 1),
+                }));
+                let mut episode = episode(name, attrs, None, "");
+                episode.index = index;
+                episode.should_download(&mode, 5)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_download_matrix() {
+        let name = "should_download";
+        // Five episodes published on the first days of 2024, oldest first.
+        let episodes: Vec<Episode> = (0..5)
+            .map(|index| {
+                let attrs = attributes(serde_json::json!({
+                    "guid": format!("episode-{}", index),
+                    "pubDate": format!("2024-01-0{}T06:00:00Z", index + 1),
+                }));
+                let mut episode = episode(name, attrs, None, "");
+                episode.index = index;
+                episode
+            })
+            .collect();
+        let tracker = episodes[0].tracker_path();
+
+        // The oldest and newest episodes are new, the one in between was downloaded, and the
+        // other two were acknowledged by catching up, one of which is being backfilled.
+        DownloadedEpisodes::append(tracker, &episodes[3].get_id(), &episodes[3]).unwrap();
+        episodes[1].mark_acknowledged().unwrap();
+        episodes[2].mark_acknowledged().unwrap();
+        let since = episodes[2].attrs.published().as_secs();
+        assert_eq!(
+            DownloadedEpisodes::remove_acknowledged(tracker, since),
+            Ok(1)
+        );
+
+        let wanted = |toml: &str| -> Vec<usize> {
+            let podcast_config: PodcastConfig =
+                toml::from_str(&format!("url = \"https://example.com/feed.xml\"\n{}", toml))
+                    .unwrap();
+            let mode = DownloadMode::new(&GlobalConfig::default(), &podcast_config, tracker);
+            episodes
+                .iter()
+                .filter(|episode| episode.should_download(&mode, episodes.len()))
+                .map(|episode| episode.index)
+                .collect()
+        };
+
+        assert_eq!(wanted(""), [0, 2, 4]);
+        assert_eq!(wanted("earliest_date = \"2024-01-02\""), [2, 4]);
+        assert_eq!(wanted("max_episodes = 3"), [2, 4]);
+        assert_eq!(wanted("keep_latest = 2"), [4]);
+        // Backlog mode only releases the oldest episode in its first interval.
+        assert_eq!(
+            wanted("backlog_start = \"2000-01-01\"\nbacklog_interval = 36500"),
+            [0]
+        );
+    }
+
+    #[test]
+    fn min_episodes_retained_overrides_age_limits() {
+        let name = "retention_age";
+        let earliest = "earliest_date = \"2024-01-04\"";
+        assert_eq!(retained(name, "", earliest), vec![3, 4]);
+        assert_eq!(
+            retained(
+                name,
+                "",
+                &format!("{}\nmin_episodes_retained = 3", earliest)
+            ),
+            vec![2, 3, 4]
+        );
+        assert_eq!(retained(name, "", "max_days = 1"), Vec::<usize>::new());
+        assert_eq!(
+            retained(name, "", "max_days = 1\nmin_episodes_retained = 2"),
+            vec![3, 4]
+        );
+        assert_eq!(
+            retained(name, "", "max_days = 1\nmin_episodes_retained = 9"),
+            vec![0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn max_episodes_caps_min_episodes_retained() {
+        let name = "retention_count";
+        assert_eq!(
+            retained(
+                name,
+                "",
+                "max_days = 1\nmax_episodes = 2\nmin_episodes_retained = 3"
+            ),
+            vec![3, 4]
+        );
+        assert_eq!(
+            retained(name, "", "max_episodes = 9\nmin_episodes_retained = 1"),
+            vec![0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn podcast_min_episodes_retained_over_global() {
+        let name = "retention_precedence";
+        let global = "max_days = 1\nmin_episodes_retained = 1";
+        assert_eq!(retained(name, global, ""), vec![4]);
+        assert_eq!(
+            retained(name, global, "min_episodes_retained = 2"),
+            vec![3, 4]
+        );
+        assert_eq!(
+            retained(name, global, "min_episodes_retained = 0"),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn long_names_fit_the_filesystem() {
+        let dir = paths::test_dir("name-limit");
+        let limits = PathLimits {
+            component: 143,
+            total: 4096,
+        };
+        PathLimits::assume(&dir, limits);
+        let title = "A Very Long Episode Title ".repeat(8);
+        let wanted = dir.join(format!("{}.mp3", title));
+        let file_name = |path: &Path| path.file_name().unwrap().to_str().unwrap().to_string();
+
+        let path = free_path(Path::new(""), wanted.clone());
+        let name = file_name(&path);
+        assert!(name.len() <= 143, "{} bytes: {}", name.len(), name);
+        assert!(name.starts_with("A Very Long Episode Title"));
+        assert!(name.ends_with(".mp3"));
+
+        // The number that tells apart files with the same name is kept too.
+        fs::write(&path, "").unwrap();
+        let taken = file_name(&free_path(Path::new(""), wanted.clone()));
+        assert!(taken.len() <= 143, "{} bytes: {}", taken.len(), taken);
+        assert!(taken.ends_with(" (2).mp3"), "{}", taken);
+        assert_eq!(free_path(&path, wanted), path);
+
+        let renamed = file_name(&pattern_path(&dir.join("x.m4a"), &title));
+        assert!(renamed.len() <= 143, "{} bytes: {}", renamed.len(), renamed);
+        assert!(renamed.ends_with(".m4a"));
+    }
+
+    /// A local server that answers every request with `response`, head and body, and closes
+    /// the connection. Returns the url of an enclosure on it.
+    async fn raw_server(response: Vec<u8>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/episode.mp3", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).await;
+                let _ = stream.write_all(&response).await;
+            }
+        });
+        url
+    }
+
+    /// Downloads an enclosure of 10 bytes according to the feed, from a server that answers
+    /// with `head` followed by `body`.
+    async fn download_sized(
+        name: &str,
+        head: &str,
+        body: &[u8],
+        strict: bool,
+    ) -> Result<u64, DownloadError> {
+        let response = [format!("HTTP/1.1 200 OK\r\n{}\r\n", head).as_bytes(), body].concat();
+        let url = raw_server(response).await;
+        let attrs = attributes(serde_json::json!({
+            "enclosure": { "@url": url, "@type": "audio/mpeg", "@length": "10" },
+        }));
+        let toml = format!("strict_size_check = {}", strict);
+        let episode = episode(name, attrs, None, &toml);
+
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new("x".to_string(), GlobalConfig::default().style(), &mp, 7);
+        let (path, _, _) = episode
+            .download_enclosure(&reqwest::Client::new(), &ui)
+            .await?;
+        Ok(fs::metadata(path).unwrap().len())
+    }
+
+    #[tokio::test]
+    async fn size_checks() {
+        let ten = b"0123456789";
+
+        let whole = download_sized("size-whole", "Content-Length: 10\r\n", ten, true).await;
+        assert_eq!(whole.unwrap(), 10);
+
+        // The connection closed before all the server announced arrived.
+        let cut = download_sized("size-cut", "Content-Length: 10\r\n", &ten[..5], false).await;
+        assert!(cut.unwrap_err().is_transient());
+
+        // The server sent all it announced, but the feed says otherwise.
+        let short = download_sized("size-short", "Content-Length: 5\r\n", &ten[..5], true).await;
+        assert!(!short.unwrap_err().is_transient());
+        let long = [ten.as_slice(), ten].concat();
+        let long = download_sized("size-long", "Content-Length: 20\r\n", &long, true).await;
+        assert!(!long.unwrap_err().is_transient());
+        let lenient =
+            download_sized("size-lenient", "Content-Length: 5\r\n", &ten[..5], false).await;
+        assert_eq!(lenient.unwrap(), 5);
+
+        // Without a length from the server, only the feed can tell.
+        let unknown = download_sized("size-unknown", "", &ten[..5], true).await;
+        assert!(!unknown.unwrap_err().is_transient());
+        let unknown = download_sized("size-unknown-lenient", "", &ten[..5], false).await;
+        assert_eq!(unknown.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn tagging_deferred_on_a_full_disk() {
+        let mut tag = id3::Tag::new();
+        tag.set_title("Tagged");
+        let episode = episode(
+            "tagging-deferred",
+            attributes(serde_json::json!({})),
+            Some(tag),
+            "",
+        );
+        let path = episode.config.download_path.join("episode.mp3");
+        let audio: Vec<u8> = (0..20u8)
+            .flat_map(|i| [[0xFF, 0xFB, 0x90, 0x64].as_slice(), &[i; 413]].concat())
+            .collect();
+        fs::write(&path, &audio).unwrap();
+
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new("x".to_string(), GlobalConfig::default().style(), &mp, 7);
+        let client = reqwest::Client::new();
+        let downloaded =
+            DownloadedEpisode::new(&episode, path.clone(), Fingerprint::default(), None);
+
+        paths::with_free_space(Some(1024), downloaded.normalize_tags(&client, &ui)).await;
+        assert_eq!(fs::read(&path).unwrap(), audio);
+        assert!(DeferredTags::load(episode.tracker_path()).contains(&episode.get_id()));
+
+        // With room, the file is tagged.
+        paths::with_free_space(None, downloaded.normalize_tags(&client, &ui)).await;
+        assert_eq!(
+            id3::Tag::read_from_path(&path).unwrap().title(),
+            Some("Tagged")
+        );
+        assert!(fs::read(&path).unwrap().ends_with(&audio));
+    }
+
+    /// A disk that takes `pause` for every write, and keeps track of the most bytes that had
+    /// been received but not yet written to it.
+    struct SlowDisk {
+        pause: time::Duration,
+        delay: std::pin::Pin<Box<tokio::time::Sleep>>,
+        received: Arc<std::sync::atomic::AtomicU64>,
+        written: u64,
+        max_backlog: u64,
+    }
+
+    impl tokio::io::AsyncWrite for SlowDisk {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            use std::future::Future;
+
+            std::task::ready!(self.delay.as_mut().poll(cx));
+            let next = tokio::time::Instant::now() + self.pause;
+            self.delay.as_mut().reset(next);
+
+            self.written += buf.len() as u64;
+            let received = self.received.load(std::sync::atomic::Ordering::SeqCst);
+            self.max_backlog = self.max_backlog.max(received - self.written);
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_disks_bound_the_memory() {
+        let size = 4 * 1024 * 1024;
+        let (url, _) =
+            net::test_server(|_| vec![("/episode.mp3", "audio/mpeg", vec![7; size])]).await;
+        let url = format!("{}/episode.mp3", url);
+        let response = chaos::send(reqwest::Client::new().get(&url), &url)
+            .await
+            .unwrap();
+
+        let received = Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let mut largest_chunk = 0;
+        let stream = response.bytes_stream().inspect(|chunk| {
+            if let Ok(chunk) = chunk {
+                largest_chunk = largest_chunk.max(chunk.len() as u64);
+                received.fetch_add(chunk.len() as u64, std::sync::atomic::Ordering::SeqCst);
+            }
+        });
+
+        let capacity = 16 * 1024;
+        let disk = SlowDisk {
+            pause: time::Duration::from_millis(1),
+            delay: Box::pin(tokio::time::sleep(time::Duration::ZERO)),
+            received: Arc::clone(&received),
+            written: 0,
+            max_backlog: 0,
+        };
+        let mut file = tokio::io::BufWriter::with_capacity(capacity, disk);
+        let mut waits = TransferWaits::default();
+        let mut copied = 0;
+        copy_chunks(
+            stream,
+            &mut file,
+            &mut waits,
+            |e, _| DownloadError::Transient(e.to_string()),
+            |len| copied += len,
+        )
+        .await
+        .unwrap();
+        file.flush().await.unwrap();
+
+        let disk = file.into_inner();
+        assert_eq!((copied, disk.written), (size as u64, size as u64));
+        // Only the buffer and the chunk being written were ever waiting for the disk, rather
+        // than whatever the server had sent.
+        assert!(
+            disk.max_backlog <= capacity as u64 + largest_chunk,
+            "{} bytes waited for the disk",
+            disk.max_backlog
+        );
+        assert!(largest_chunk < size as u64 / 4);
+        assert_eq!(waits.bottleneck(), "disk-bound");
+    }
+}
@@ -0,0 +1,360 @@
+//! Estimating how long the rest of a sync will take.
+//!
+//! The estimate divides the bytes left to download by a rate that blends what this run has
+//! measured so far with how fast each host was in earlier runs. The measured rate takes over
+//! as more data arrives.
+
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How much a new measurement moves the historical speed of a host.
+const HISTORY_WEIGHT: f64 = 0.3;
+
+/// Below this many bytes the measured rate is too noisy to use at all.
+const MIN_MEASURED_BYTES: u64 = 512 * 1024;
+
+/// From this many bytes on, only the measured rate is used.
+const FULL_TRUST_BYTES: u64 = 20 * 1024 * 1024;
+
+/// Average download speeds of the hosts we've downloaded from, in bytes per second.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct SpeedHistory(HashMap<String, f64>);
+
+impl SpeedHistory {
+    fn path() -> PathBuf {
+        paths::cache_dir().join("speeds.json")
+    }
+
+    fn load() -> Self {
+        fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let Ok(json) = serde_json::to_string_pretty(self) else {
+            return;
+        };
+
+        if let Err(e) = paths::write(Self::path(), json) {
+            log::warn!("failed to save download speeds: {:?}", e);
+        }
+    }
+
+    fn rate(&self, host: &str) -> Option<f64> {
+        self.0.get(host).copied()
+    }
+
+    fn add(&mut self, host: &str, rate: f64) {
+        let new = match self.0.get(host) {
+            Some(old) => old + (rate - old) * HISTORY_WEIGHT,
+            None => rate,
+        };
+        self.0.insert(host.to_string(), new);
+    }
+}
+
+fn host(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(String::from))
+        .unwrap_or_default()
+}
+
+#[derive(Debug)]
+struct PendingDownload {
+    host: String,
+    /// The length advertised in the feed.
+    length: Option<u64>,
+    received: u64,
+}
+
+impl PendingDownload {
+    fn remaining(&self) -> u64 {
+        self.length.unwrap_or(0).saturating_sub(self.received)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Downloads that haven't finished, keyed by podcast name and enclosure url.
+    pending: HashMap<(String, String), PendingDownload>,
+    /// Bytes received by every download of this run.
+    received: u64,
+    history: SpeedHistory,
+}
+
+/// Progress of all the downloads of a sync.
+#[derive(Debug)]
+pub struct RunProgress {
+    start: Instant,
+    state: Mutex<State>,
+}
+
+impl RunProgress {
+    pub fn new() -> Self {
+        let state = State {
+            history: SpeedHistory::load(),
+            ..Default::default()
+        };
+
+        Self {
+            start: Instant::now(),
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Adds a download that's about to happen.
+    pub fn add(&self, podcast: &str, url: &str, length: Option<u64>) {
+        let download = PendingDownload {
+            host: host(url),
+            length,
+            received: 0,
+        };
+
+        let key = (podcast.to_string(), url.to_string());
+        self.state.lock().unwrap().pending.insert(key, download);
+    }
+
+    pub fn receive(&self, podcast: &str, url: &str, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.received += bytes;
+
+        let key = (podcast.to_string(), url.to_string());
+        if let Some(download) = state.pending.get_mut(&key) {
+            download.received += bytes;
+        }
+    }
+
+    /// Removes a download, remembering how fast its host was if it succeeded.
+    pub fn finish(&self, podcast: &str, url: &str, elapsed: Duration, success: bool) {
+        let mut state = self.state.lock().unwrap();
+
+        let key = (podcast.to_string(), url.to_string());
+        let Some(download) = state.pending.remove(&key) else {
+            return;
+        };
+
+        let secs = elapsed.as_secs_f64();
+        if success && download.received >= MIN_MEASURED_BYTES && secs > 0.0 {
+            let rate = download.received as f64 / secs;
+            state.history.add(&download.host, rate);
+        }
+    }
+
+    /// Removes the downloads of a podcast that won't happen after all.
+    pub fn finish_podcast(&self, podcast: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .pending
+            .retain(|(name, _), _| name != podcast);
+    }
+
+    /// Bytes left to download, counting only downloads whose length is known.
+    pub fn remaining(&self) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.pending.values().map(PendingDownload::remaining).sum()
+    }
+
+    /// How long the remaining downloads are expected to take, if there's enough data to say.
+    pub fn estimate(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        state.estimate(self.start.elapsed())
+    }
+
+    /// Saves the speeds measured during this run for future estimates.
+    pub fn save_history(&self) {
+        self.state.lock().unwrap().history.save();
+    }
+}
+
+impl State {
+    /// The estimate after the run has taken `elapsed` so far.
+    fn estimate(&self, elapsed: Duration) -> Option<Duration> {
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        let remaining: u64 = self.pending.values().map(PendingDownload::remaining).sum();
+        if remaining == 0 {
+            return Some(Duration::ZERO);
+        }
+
+        let elapsed = elapsed.as_secs_f64();
+        let measured = (self.received >= MIN_MEASURED_BYTES && elapsed > 0.0)
+            .then(|| self.received as f64 / elapsed);
+
+        // What the downloads that haven't started would take going by earlier runs,
+        // expressed as a single rate.
+        let historical = {
+            let (bytes, secs) = self
+                .pending
+                .values()
+                .filter(|download| download.received == 0)
+                .filter_map(|download| {
+                    let rate = self.history.rate(&download.host)?;
+                    let bytes = download.remaining() as f64;
+                    Some((bytes, bytes / rate))
+                })
+                .fold((0.0, 0.0), |(bytes, secs), (b, s)| (bytes + b, secs + s));
+
+            (secs > 0.0).then(|| bytes / secs)
+        };
+
+        let rate = match (measured, historical) {
+            (Some(measured), Some(historical)) => {
+                let trust = (self.received as f64 / FULL_TRUST_BYTES as f64).min(1.0);
+                measured * trust + historical * (1.0 - trust)
+            }
+            (Some(rate), None) | (None, Some(rate)) => rate,
+            (None, None) => return None,
+        };
+
+        Some(Duration::from_secs_f64(remaining as f64 / rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn state(history: &[(&str, u64)], received: u64, pending: &[(&str, u64, u64)]) -> State {
+        State {
+            pending: pending
+                .iter()
+                .enumerate()
+                .map(|(i, (host, length, received))| {
+                    let download = PendingDownload {
+                        host: host.to_string(),
+                        length: Some(*length),
+                        received: *received,
+                    };
+                    (("podcast".to_string(), i.to_string()), download)
+                })
+                .collect(),
+            received,
+            history: SpeedHistory(
+                history
+                    .iter()
+                    .map(|(host, rate)| (host.to_string(), *rate as f64))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn nothing_to_go_by() {
+        assert_eq!(state(&[], 0, &[]).estimate(secs(1)), None);
+        assert_eq!(state(&[], 0, &[("a", 10 * MB, 0)]).estimate(secs(1)), None);
+        assert_eq!(
+            state(&[], 0, &[("a", 10 * MB, 10 * MB)]).estimate(secs(1)),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn history_before_anything_is_measured() {
+        let state = state(
+            &[("a", MB), ("b", 2 * MB)],
+            0,
+            &[("a", 10 * MB, 0), ("b", 10 * MB, 0)],
+        );
+        assert_eq!(state.estimate(secs(1)), Some(secs(15)));
+    }
+
+    #[test]
+    fn too_little_measured_to_use() {
+        let state = state(&[("a", MB)], MIN_MEASURED_BYTES - 1, &[("a", 10 * MB, 0)]);
+        assert_eq!(state.estimate(Duration::from_millis(1)), Some(secs(10)));
+    }
+
+    #[test]
+    fn measured_without_history() {
+        let state = state(&[], MB, &[("a", 11 * MB, MB)]);
+        assert_eq!(state.estimate(secs(1)), Some(secs(10)));
+    }
+
+    #[test]
+    fn measured_and_history_are_blended() {
+        // Halfway to full trust, 1 MB/s measured and 3 MB/s from earlier runs.
+        let received = FULL_TRUST_BYTES / 2;
+        let state = state(&[("b", 3 * MB)], received, &[("b", 8 * MB, 0)]);
+        assert_eq!(state.estimate(secs(received / MB)), Some(secs(4)));
+    }
+
+    #[test]
+    fn enough_measured_to_ignore_history() {
+        let received = 2 * FULL_TRUST_BYTES;
+        let state = state(&[("b", 100 * MB)], received, &[("b", 8 * MB, 0)]);
+        assert_eq!(state.estimate(secs(received / MB)), Some(secs(8)));
+    }
+
+    #[test]
+    fn started_downloads_are_left_out_of_the_history() {
+        // Only the download that hasn't started goes by its host's history.
+        let state = state(&[("a", MB)], 0, &[("a", 10 * MB, 0), ("a", 10 * MB, MB)]);
+        assert_eq!(state.estimate(secs(1)), Some(secs(19)));
+    }
+
+    #[test]
+    fn history_moves_towards_new_measurements() {
+        let mut history = SpeedHistory::default();
+        history.add("a", 100.0);
+        assert_eq!(history.rate("a"), Some(100.0));
+
+        history.add("a", 200.0);
+        assert_eq!(history.rate("a"), Some(100.0 + 100.0 * HISTORY_WEIGHT));
+        assert_eq!(history.rate("b"), None);
+    }
+
+    #[test]
+    fn only_successful_downloads_are_remembered() {
+        let progress = RunProgress {
+            start: Instant::now(),
+            state: Mutex::new(State::default()),
+        };
+        let url = "https://cdn.example.com/episode.mp3";
+
+        for (success, bytes) in [(false, 10 * MB), (true, MIN_MEASURED_BYTES - 1)] {
+            progress.add("podcast", url, Some(bytes));
+            progress.receive("podcast", url, bytes);
+            progress.finish("podcast", url, secs(1), success);
+        }
+        assert_eq!(
+            progress
+                .state
+                .lock()
+                .unwrap()
+                .history
+                .rate("cdn.example.com"),
+            None
+        );
+
+        progress.add("podcast", url, Some(2 * MB));
+        progress.receive("podcast", url, 2 * MB);
+        progress.finish("podcast", url, secs(2), true);
+        assert_eq!(
+            progress
+                .state
+                .lock()
+                .unwrap()
+                .history
+                .rate("cdn.example.com"),
+            Some(MB as f64)
+        );
+    }
+}
@@ -0,0 +1,572 @@
+//! Where each setting of a podcast comes from, for `--explain`.
+//!
+//! A podcast's entry in `podcasts.toml` overrides `config.toml`, which overrides the built-in
+//! defaults. Tables like `id3_tags` are merged key by key, so each of their keys is explained
+//! on its own. The layers are read the same way the config is loaded, but kept apart, so
+//! every effective value can be traced back to the file and line that set it.
+//!
+//! `download_mode` isn't a setting itself, but explains the mode a podcast is synced in
+//! along with the settings that decide it. Backlog mode is turned on by the podcast's own
+//! `backlog_start` and `backlog_interval`, and then the limits of the standard mode in
+//! `config.toml` are ignored.
+
+use crate::config::GlobalConfig;
+use crate::config::PodcastConfigs;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process;
+use toml::Value;
+
+/// Keys that are also accepted under another name.
+const ALIASES: [(&str, &str); 1] = [("path", "download_path")];
+
+/// What to explain to get the download mode of a podcast.
+const DOWNLOAD_MODE: &str = "download_mode";
+
+/// The settings that turn on backlog mode, which only podcasts.toml can set.
+const BACKLOG_SETTINGS: [&str; 2] = ["backlog_start", "backlog_interval"];
+
+/// The limits of the standard mode.
+const STANDARD_SETTINGS: [&str; 7] = [
+    "max_days",
+    "max_episodes",
+    "min_episodes_retained",
+    "max_per_day",
+    "keep_latest",
+    "earliest_date",
+    "latest_date",
+];
+
+/// An effective value, with the path of its key and the index of the layer it came from.
+type Setting = (Vec<String>, Value, usize);
+
+/// The settings of one config file, with the lines their keys are on.
+struct Layer {
+    label: String,
+    table: Value,
+    lines: HashMap<Vec<String>, usize>,
+}
+
+impl Layer {
+    fn load(path: &Path, label: String) -> Self {
+        let text = fs::read_to_string(path).unwrap_or_default();
+        let table = match text.parse::<Value>() {
+            Ok(table) => table,
+            Err(e) => {
+                eprintln!("unable to parse {}: {}", label, e);
+                process::exit(1);
+            }
+        };
+
+        Self {
+            label,
+            table,
+            lines: key_lines(&text),
+        }
+    }
+
+    /// The line a key is set on, or the line of the table it's in for inline tables.
+    ///
+    /// `depth` is where the name of the setting is in the path, in case it's set under an
+    /// alias.
+    fn line(&self, path: &[String], depth: usize) -> Option<usize> {
+        let mut paths = vec![path.to_vec()];
+        for (alias, usual) in ALIASES {
+            if path.get(depth).is_some_and(|key| key == usual) {
+                let mut aliased = path.to_vec();
+                aliased[depth] = alias.to_string();
+                paths.push(aliased);
+            }
+        }
+
+        (1..=path.len()).rev().find_map(|len| {
+            paths
+                .iter()
+                .find_map(|path| self.lines.get(&path[..len]).copied())
+        })
+    }
+}
+
+/// Prints the effective settings of a podcast and where they come from, or only the one
+/// given by `key`.
+pub fn explain(name: &str, key: Option<&str>, config_path: Option<&Path>) {
+    let podcasts = Layer::load(&PodcastConfigs::path(), "podcasts.toml".to_string());
+    let global = match config_path {
+        Some(path) => Layer::load(path, path.display().to_string()),
+        None => Layer::load(&GlobalConfig::default_path(), "config.toml".to_string()),
+    };
+    let defaults = Value::try_from(GlobalConfig::default()).unwrap_or(Value::Integer(0));
+
+    let Some(podcast) = podcasts.table.get(name) else {
+        eprintln!("no podcast named '{}' in podcasts.toml", name);
+        process::exit(1);
+    };
+
+    let mut settings = vec![];
+    merge(
+        &mut settings,
+        vec![],
+        Some(&normalize(podcast)),
+        Some(&normalize(&global.table)),
+        Some(&normalize(&defaults)),
+    );
+
+    let source = |path: &[String], layer: usize| match layer {
+        0 => with_line(&podcasts.label, podcasts.line(&podcast_path(name, path), 1)),
+        1 => with_line(&global.label, global.line(path, 0)),
+        _ => "default".to_string(),
+    };
+
+    if key == Some(DOWNLOAD_MODE) {
+        print_lines(download_mode(settings, source));
+        return;
+    }
+
+    let settings: Vec<_> = settings
+        .into_iter()
+        .filter(|(path, _, _)| key.is_none_or(|key| is_under(path, key)))
+        .collect();
+
+    if settings.is_empty() {
+        if let Some(key) = key {
+            println!(
+                "{} isn't set in podcasts.toml or {}, the built-in default applies",
+                key, global.label
+            );
+        }
+        return;
+    }
+
+    let lines = settings
+        .into_iter()
+        .map(|(path, value, layer)| {
+            let source = source(&path, layer);
+            (format!("{} = {}", path.join("."), value), source)
+        })
+        .collect();
+
+    print_lines(lines);
+}
+
+/// The mode a podcast is synced in and the settings that decide what it downloads, with
+/// the global limits that backlog mode ignores marked as such.
+fn download_mode(
+    settings: Vec<Setting>,
+    source: impl Fn(&[String], usize) -> String,
+) -> Vec<(String, String)> {
+    let backlog = BACKLOG_SETTINGS.iter().find_map(|key| {
+        settings
+            .iter()
+            .find(|(path, _, layer)| *layer == 0 && top_level(path) == *key)
+    });
+
+    let mut lines = vec![match backlog {
+        Some((path, _, layer)) => (
+            format!("{} = \"backlog\"", DOWNLOAD_MODE),
+            source(path, *layer),
+        ),
+        None => (
+            format!("{} = \"standard\"", DOWNLOAD_MODE),
+            "default".to_string(),
+        ),
+    }];
+
+    for (path, value, layer) in &settings {
+        let key = top_level(path);
+        let line = format!("{} = {}", path.join("."), value);
+        let source = source(path, *layer);
+
+        if backlog.is_none() {
+            if STANDARD_SETTINGS.contains(&key) {
+                lines.push((line, source));
+            }
+            continue;
+        }
+
+        // The limits the podcast sets itself are either dates or turned off with `false`.
+        let relevant = BACKLOG_SETTINGS.contains(&key) || STANDARD_SETTINGS.contains(&key);
+        match layer {
+            0 if relevant => lines.push((line, source)),
+            1 if STANDARD_SETTINGS.contains(&key) => {
+                lines.push((line, format!("{}, ignored in backlog mode", source)))
+            }
+            _ => {}
+        }
+    }
+
+    lines
+}
+
+fn print_lines(lines: Vec<(String, String)>) {
+    let width = lines.iter().map(|(line, _)| line.chars().count()).max();
+    for (line, source) in &lines {
+        println!("{:<width$}  {}", line, source, width = width.unwrap_or(0));
+    }
+}
+
+/// Collects the effective value of every key under `path`, along with the index of the layer
+/// it came from: the podcast, the global config or the defaults.
+fn merge(
+    settings: &mut Vec<Setting>,
+    path: Vec<String>,
+    podcast: Option<&Value>,
+    global: Option<&Value>,
+    defaults: Option<&Value>,
+) {
+    let layers = [podcast, global, defaults];
+
+    if layers.iter().flatten().any(|value| value.is_table()) {
+        let keys: BTreeSet<&String> = layers
+            .iter()
+            .flatten()
+            .filter_map(|value| value.as_table())
+            .flat_map(|table| table.keys())
+            .collect();
+
+        for key in keys {
+            let child = |value: Option<&Value>| value?.get(key.as_str()).cloned();
+            let mut path = path.clone();
+            path.push(key.clone());
+            merge(
+                settings,
+                path,
+                child(podcast).as_ref(),
+                child(global).as_ref(),
+                child(defaults).as_ref(),
+            );
+        }
+        return;
+    }
+
+    if let Some((layer, value)) = layers
+        .iter()
+        .enumerate()
+        .find_map(|(i, value)| Some((i, (*value)?)))
+    {
+        settings.push((path, value.clone(), layer));
+    }
+}
+
+/// The layer with aliased keys under their usual name.
+fn normalize(value: &Value) -> Value {
+    let mut value = value.clone();
+    if let Some(table) = value.as_table_mut() {
+        for (alias, key) in ALIASES {
+            if let Some(aliased) = table.remove(alias) {
+                table.entry(key).or_insert(aliased);
+            }
+        }
+    }
+    value
+}
+
+/// The setting a key is part of, which is itself for keys that aren't in a table.
+fn top_level(path: &[String]) -> &str {
+    path.first().map(String::as_str).unwrap_or_default()
+}
+
+fn is_under(path: &[String], key: &str) -> bool {
+    let joined = path.join(".");
+    joined == key || joined.starts_with(&format!("{}.", key))
+}
+
+fn podcast_path(name: &str, path: &[String]) -> Vec<String> {
+    let mut full = vec![name.to_string()];
+    full.extend(path.iter().cloned());
+    full
+}
+
+fn with_line(label: &str, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{}:{}", label, line),
+        None => label.to_string(),
+    }
+}
+
+/// The line each key of a toml file is set on, counting from 1.
+///
+/// The toml crate doesn't keep track of where values were, so this goes through the table
+/// headers and `key = value` lines itself. Values spanning several lines only count the line
+/// they start on.
+fn key_lines(text: &str) -> HashMap<Vec<String>, usize> {
+    let mut lines = HashMap::new();
+    let mut table: Vec<String> = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            // Arrays of tables, like `[[name]]`, are counted as their first table.
+            let header = header.trim_start_matches('[');
+            if let Some(end) = find_unquoted(header, ']') {
+                table = split_key(&header[..end]);
+                lines.entry(table.clone()).or_insert(i + 1);
+            }
+        } else if let Some(end) = find_unquoted(line, '=') {
+            let mut path = table.clone();
+            path.extend(split_key(&line[..end]));
+            lines.entry(path).or_insert(i + 1);
+        }
+    }
+
+    lines
+}
+
+/// Where a character first appears outside of a quoted key.
+fn find_unquoted(s: &str, target: char) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (c, quote) {
+            (c, None) if c == target => return Some(i),
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The parts of a dotted key like `a."b.c"`.
+fn split_key(key: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut part = String::new();
+    let mut quote = None;
+
+    for c in key.chars() {
+        match (c, quote) {
+            ('"' | '\'', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('.', None) => parts.push(std::mem::take(&mut part).trim().to_string()),
+            (c, _) => part.push(c),
+        }
+    }
+    parts.push(part.trim().to_string());
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(label: &str, text: &str) -> Layer {
+        Layer {
+            label: label.to_string(),
+            table: text.parse().unwrap(),
+            lines: key_lines(text),
+        }
+    }
+
+    fn path(key: &str) -> Vec<String> {
+        key.split('.').map(String::from).collect()
+    }
+
+    const PODCASTS: &str = r#"
+# A comment = with an equals sign
+[show]
+url = "https://example.com/feed.xml"
+path = "/podcasts/show"
+max_days = 30
+id3_tags = { TPE1 = "Host" }
+
+["other.show"]
+url = "https://example.com/other.xml"
+"#;
+
+    const GLOBAL: &str = r#"
+max_days = 7
+max_episodes = 3
+
+[id3_tags]
+TPE1 = "Nobody"
+TALB = "Podcasts"
+"#;
+
+    /// The effective settings of `show`, with where they come from.
+    fn settings() -> Vec<(String, String, String)> {
+        let podcasts = layer("podcasts.toml", PODCASTS);
+        let global = layer("config.toml", GLOBAL);
+        let defaults: Value = "name_pattern = \"{title}\"\nmax_days = 100"
+            .parse()
+            .unwrap();
+
+        let mut settings = vec![];
+        merge(
+            &mut settings,
+            vec![],
+            Some(&normalize(&podcasts.table["show"])),
+            Some(&normalize(&global.table)),
+            Some(&defaults),
+        );
+
+        settings
+            .into_iter()
+            .map(|(key, value, layer)| {
+                let source = match layer {
+                    0 => with_line(
+                        &podcasts.label,
+                        podcasts.line(&podcast_path("show", &key), 1),
+                    ),
+                    1 => with_line(&global.label, global.line(&key, 0)),
+                    _ => "default".to_string(),
+                };
+                (key.join("."), value.to_string(), source)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn settings_come_from_the_first_layer_that_sets_them() {
+        let settings = settings();
+        let setting = |key: &str| {
+            settings
+                .iter()
+                .find(|(path, _, _)| path == key)
+                .map(|(_, value, source)| (value.as_str(), source.as_str()))
+        };
+
+        assert_eq!(setting("max_days"), Some(("30", "podcasts.toml:6")));
+        assert_eq!(setting("max_episodes"), Some(("3", "config.toml:3")));
+        assert_eq!(setting("name_pattern"), Some(("\"{title}\"", "default")));
+        assert_eq!(
+            setting("url").map(|(_, source)| source),
+            Some("podcasts.toml:4")
+        );
+    }
+
+    #[test]
+    fn tables_are_merged_key_by_key() {
+        let settings = settings();
+        let tags: Vec<_> = settings
+            .iter()
+            .filter(|(key, _, _)| is_under(&path(key), "id3_tags"))
+            .collect();
+
+        // An inline table only has the line of the key it's under.
+        assert_eq!(
+            tags,
+            [
+                &(
+                    "id3_tags.TALB".to_string(),
+                    "\"Podcasts\"".to_string(),
+                    "config.toml:7".to_string()
+                ),
+                &(
+                    "id3_tags.TPE1".to_string(),
+                    "\"Host\"".to_string(),
+                    "podcasts.toml:7".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn aliases_are_explained_under_their_usual_name() {
+        let settings = settings();
+        assert!(settings.iter().all(|(path, _, _)| path != "path"));
+        assert!(settings.contains(&(
+            "download_path".to_string(),
+            "\"/podcasts/show\"".to_string(),
+            "podcasts.toml:5".to_string()
+        )));
+    }
+
+    #[test]
+    fn key_lines_of_tables_and_quoted_keys() {
+        let lines = key_lines(PODCASTS);
+        assert_eq!(lines.get(&path("show")), Some(&3));
+        assert_eq!(lines.get(&path("show.id3_tags")), Some(&7));
+        assert_eq!(lines.get(&vec!["other.show".to_string()]), Some(&9));
+        assert_eq!(
+            lines.get(&vec!["other.show".to_string(), "url".to_string()]),
+            Some(&10)
+        );
+        assert!(!lines.contains_key(&path("# A comment")));
+
+        let lines = key_lines("[[hooks]]\ncmd = \"a\"\n[[hooks]]\ncmd = \"b\"\na.\"b.c\" = 1");
+        assert_eq!(lines.get(&path("hooks")), Some(&1));
+        assert_eq!(lines.get(&path("hooks.cmd")), Some(&2));
+        assert_eq!(
+            lines.get(&vec![
+                "hooks".to_string(),
+                "a".to_string(),
+                "b.c".to_string()
+            ]),
+            Some(&5)
+        );
+    }
+
+    #[test]
+    fn keys_under_a_setting() {
+        assert!(is_under(&path("id3_tags.TPE1"), "id3_tags"));
+        assert!(is_under(&path("id3_tags"), "id3_tags"));
+        assert!(!is_under(&path("id3_tags_extra"), "id3_tags"));
+    }
+
+    fn mode(podcast: &str) -> Vec<(String, String)> {
+        let mut settings = vec![];
+        merge(
+            &mut settings,
+            vec![],
+            Some(&podcast.parse().unwrap()),
+            Some(
+                &"max_episodes = 3\nname_pattern = \"{title}\""
+                    .parse()
+                    .unwrap(),
+            ),
+            None,
+        );
+
+        let source = |_: &[String], layer: usize| match layer {
+            0 => "podcasts.toml".to_string(),
+            _ => "config.toml".to_string(),
+        };
+        download_mode(settings, source)
+    }
+
+    #[test]
+    fn standard_download_mode() {
+        assert_eq!(
+            mode("max_days = 30"),
+            [
+                (
+                    "download_mode = \"standard\"".to_string(),
+                    "default".to_string()
+                ),
+                ("max_days = 30".to_string(), "podcasts.toml".to_string()),
+                ("max_episodes = 3".to_string(), "config.toml".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn backlog_download_mode_ignores_global_limits() {
+        assert_eq!(
+            mode("backlog_interval = 7\nbacklog_start = \"2024-01-01\"\nmax_days = false"),
+            [
+                (
+                    "download_mode = \"backlog\"".to_string(),
+                    "podcasts.toml".to_string()
+                ),
+                (
+                    "backlog_interval = 7".to_string(),
+                    "podcasts.toml".to_string()
+                ),
+                (
+                    "backlog_start = \"2024-01-01\"".to_string(),
+                    "podcasts.toml".to_string()
+                ),
+                ("max_days = false".to_string(), "podcasts.toml".to_string()),
+                (
+                    "max_episodes = 3".to_string(),
+                    "config.toml, ignored in backlog mode".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Which items of a feed are new, modified or removed since the last sync, for noticing
+//! episodes that a show quietly takes down or replaces.
+//!
+//! Items are told apart by their episode id, which is the guid unless `id_pattern` says
+//! otherwise. Only a hash of the id and a hash of the title and enclosure url of each item
+//! are kept next to the download tracker, so the state of a feed with thousands of items
+//! stays small. A removed item's title is looked up in the search index and the download
+//! tracker, which still have it.
+//!
+//! The first sync of a podcast only records its items. So does a sync after its settings
+//! changed, since a new `title_pattern` or `id_pattern` would otherwise show up as items
+//! being added and removed.
+
+use crate::download_tracker::DownloadedEpisodes;
+use crate::episode::Episode;
+use crate::index::PodcastIndex;
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hasher;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The items of a feed as of a sync.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SeenItems {
+    /// The digest of the settings the items were read with.
+    settings: Option<u64>,
+    /// Hash of the title and enclosure of each item, by the hash of its id.
+    items: HashMap<u64, u64>,
+}
+
+/// How the items of a feed changed since the last sync, by title.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct FeedDelta {
+    pub new: Vec<String>,
+    /// Items with the same id whose title or enclosure changed.
+    pub modified: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl FeedDelta {
+    pub fn is_empty(&self) -> bool {
+        self.new.is_empty() && self.modified.is_empty() && self.removed.is_empty()
+    }
+
+    /// Like "2 new, 1 modified, 3 removed", leaving out what didn't happen.
+    pub fn describe(&self) -> String {
+        [
+            (self.new.len(), "new"),
+            (self.modified.len(), "modified"),
+            (self.removed.len(), "removed"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, what)| format!("{} {}", count, what))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+
+    pub fn add(&mut self, other: &Self) {
+        self.new.extend(other.new.iter().cloned());
+        self.modified.extend(other.modified.iter().cloned());
+        self.removed.extend(other.removed.iter().cloned());
+    }
+}
+
+fn hash(s: &str) -> u64 {
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write(s.as_bytes());
+    hasher.finish()
+}
+
+fn item_hash(episode: &Episode) -> u64 {
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write(episode.attrs.title().as_bytes());
+    hasher.write_u8(0);
+    hasher.write(episode.attrs.url().as_bytes());
+    hasher.finish()
+}
+
+impl SeenItems {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".seen");
+        tracker_path.with_file_name(name)
+    }
+
+    pub fn new(settings: Option<u64>, episodes: &[Episode]) -> Self {
+        let items = episodes
+            .iter()
+            .map(|episode| (hash(&episode.get_id()), item_hash(episode)))
+            .collect();
+
+        Self { settings, items }
+    }
+
+    fn load(tracker_path: &Path) -> Option<Self> {
+        let s = fs::read_to_string(Self::path(tracker_path)).ok()?;
+        serde_json::from_str(&s).ok()
+    }
+
+    /// Saves the items as compact json, which takes about 40 bytes an item.
+    pub fn save(&self, tracker_path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string(self)
+            .map_err(|_| "failed to serialize the items of the feed".to_string())?;
+        paths::write(Self::path(tracker_path), json)
+            .map_err(|e| format!("failed to save the items of the feed: {}", e))
+    }
+
+    /// How the items changed since the ones recorded by the last sync, if there are any to
+    /// compare with.
+    pub fn delta(
+        &self,
+        name: &str,
+        tracker_path: &Path,
+        episodes: &[Episode],
+    ) -> Option<FeedDelta> {
+        let previous = Self::load(tracker_path)?;
+        let titles: Vec<(u64, String)> = episodes
+            .iter()
+            .map(|episode| (hash(&episode.get_id()), episode.attrs.title().to_string()))
+            .collect();
+
+        self.compare(&previous, &titles, || {
+            Self::known_titles(name, tracker_path)
+        })
+    }
+
+    /// The titles of the items that were seen before, by the hash of their id.
+    fn known_titles(name: &str, tracker_path: &Path) -> HashMap<u64, String> {
+        let mut titles: HashMap<u64, String> = DownloadedEpisodes::entries(tracker_path)
+            .into_iter()
+            .map(|entry| (hash(&entry.id), entry.title))
+            .collect();
+        if let Some(index) = PodcastIndex::load(name) {
+            for (id, title) in index.episode_titles() {
+                titles.insert(hash(id), title.to_string());
+            }
+        }
+
+        titles
+    }
+
+    /// Compares the items with `previous`, given the hash of the id and the title of each
+    /// current item. `known_titles` is only called when items were removed.
+    fn compare(
+        &self,
+        previous: &Self,
+        titles: &[(u64, String)],
+        known_titles: impl FnOnce() -> HashMap<u64, String>,
+    ) -> Option<FeedDelta> {
+        if previous.settings.is_none() || previous.settings != self.settings {
+            return None;
+        }
+
+        let mut delta = FeedDelta::default();
+        for (id, title) in titles {
+            match previous.items.get(id) {
+                None => delta.new.push(title.clone()),
+                Some(item) if *item != self.items[id] => delta.modified.push(title.clone()),
+                Some(_) => {}
+            }
+        }
+
+        let removed: Vec<u64> = previous
+            .items
+            .keys()
+            .filter(|id| !self.items.contains_key(id))
+            .copied()
+            .collect();
+
+        if !removed.is_empty() {
+            let mut titles = known_titles();
+            for id in removed {
+                let title = titles
+                    .remove(&id)
+                    .unwrap_or_else(|| format!("an item whose title is unknown ({:016x})", id));
+                delta.removed.push(title);
+            }
+            delta.removed.sort();
+        }
+
+        Some(delta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(settings: u64, items: &[(&str, u64)]) -> SeenItems {
+        SeenItems {
+            settings: Some(settings),
+            items: items.iter().map(|(id, item)| (hash(id), *item)).collect(),
+        }
+    }
+
+    fn titles(ids: &[&str]) -> Vec<(u64, String)> {
+        ids.iter()
+            .map(|id| (hash(id), format!("title {}", id)))
+            .collect()
+    }
+
+    fn no_titles() -> HashMap<u64, String> {
+        panic!("titles looked up without removed items")
+    }
+
+    #[test]
+    fn new_items() {
+        let previous = items(1, &[("a", 10)]);
+        let current = items(1, &[("a", 10), ("b", 20)]);
+
+        let delta = current
+            .compare(&previous, &titles(&["a", "b"]), no_titles)
+            .unwrap();
+        assert_eq!(delta.new, vec!["title b"]);
+        assert!(delta.modified.is_empty());
+        assert!(delta.removed.is_empty());
+        assert_eq!(delta.describe(), "1 new");
+    }
+
+    #[test]
+    fn modified_items() {
+        let previous = items(1, &[("a", 10), ("b", 20)]);
+        let current = items(1, &[("a", 11), ("b", 20)]);
+
+        let delta = current
+            .compare(&previous, &titles(&["a", "b"]), no_titles)
+            .unwrap();
+        assert!(delta.new.is_empty());
+        assert_eq!(delta.modified, vec!["title a"]);
+        assert!(delta.removed.is_empty());
+    }
+
+    #[test]
+    fn removed_items() {
+        let previous = items(1, &[("a", 10), ("b", 20), ("c", 30)]);
+        let current = items(1, &[("a", 10)]);
+        let known = || HashMap::from([(hash("c"), "old c".to_string())]);
+
+        let delta = current.compare(&previous, &titles(&["a"]), known).unwrap();
+        assert!(delta.new.is_empty());
+        assert!(delta.modified.is_empty());
+        assert_eq!(
+            delta.removed,
+            vec![
+                format!("an item whose title is unknown ({:016x})", hash("b")),
+                "old c".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_items() {
+        let previous = items(1, &[("a", 10)]);
+        let current = items(1, &[("a", 10)]);
+
+        let delta = current
+            .compare(&previous, &titles(&["a"]), no_titles)
+            .unwrap();
+        assert!(delta.is_empty());
+    }
+
+    #[test]
+    fn changed_settings_only_record() {
+        let previous = items(1, &[("a", 10)]);
+        let current = items(2, &[("b", 20)]);
+        assert!(current
+            .compare(&previous, &titles(&["b"]), no_titles)
+            .is_none());
+
+        let unversioned = SeenItems {
+            settings: None,
+            items: HashMap::new(),
+        };
+        assert!(current
+            .compare(&unversioned, &titles(&["b"]), no_titles)
+            .is_none());
+    }
+}
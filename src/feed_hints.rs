@@ -0,0 +1,345 @@
+//! How often a feed says it's worth polling, from `sy:updatePeriod`, `sy:updateFrequency`,
+//! `skipHours` and `skipDays`.
+//!
+//! The hints are read every time the feed is parsed and kept next to the download tracker.
+//! They only apply when syncing with `--respect-schedules`, where they can make a podcast be
+//! polled less often than its schedule, but never more often.
+
+use crate::paths;
+use crate::podcast::RawPodcast;
+use crate::xml;
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::TimeZone;
+use chrono::Timelike;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+const DAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct FeedHints {
+    /// Seconds between updates of the feed, if it declares an update period.
+    update_interval: Option<u64>,
+    /// Hours of the day, in UTC, when the feed shouldn't be polled.
+    skip_hours: Vec<u32>,
+    /// Days of the week, counted from monday, when the feed shouldn't be polled.
+    skip_days: Vec<u32>,
+}
+
+impl FeedHints {
+    pub fn new(raw: &RawPodcast) -> Self {
+        let update_interval = raw.get_str("sy:updatePeriod").and_then(|period| {
+            let frequency = raw.get("sy:updateFrequency").and_then(val_to_u64);
+            update_interval(period, frequency)
+        });
+
+        let mut skip_hours: Vec<u32> = children(raw.get("skipHours"), "hour")
+            .filter_map(val_to_u64)
+            .filter_map(skip_hour)
+            .collect();
+        skip_hours.sort_unstable();
+        skip_hours.dedup();
+
+        let mut skip_days: Vec<u32> = children(raw.get("skipDays"), "day")
+            .filter_map(xml::val_to_str)
+            .filter_map(skip_day)
+            .collect();
+        skip_days.sort_unstable();
+        skip_days.dedup();
+
+        Self {
+            update_interval,
+            skip_hours,
+            skip_days,
+        }
+    }
+
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".hints");
+        tracker_path.with_file_name(name)
+    }
+
+    pub fn load(tracker_path: &Path) -> Option<Self> {
+        let s = fs::read_to_string(Self::path(tracker_path)).ok()?;
+        serde_json::from_str(&s).ok()
+    }
+
+    /// Stores the hints of a feed, or removes them when it has none.
+    pub fn record(&self, tracker_path: &Path) {
+        let path = Self::path(tracker_path);
+        if *self == Self::default() {
+            let _ = fs::remove_file(path);
+            return;
+        }
+
+        let res = serde_json::to_string_pretty(self)
+            .map_err(|e| e.to_string())
+            .and_then(|json| paths::write(path, json).map_err(|e| e.to_string()));
+        if let Err(e) = res {
+            log::warn!("failed to save feed hints: {}", e);
+        }
+    }
+
+    /// When the feed may be polled next, given when it's due otherwise.
+    ///
+    /// The update interval counts from the last sync, and a time the feed asks to be skipped
+    /// moves on to the first hour that isn't.
+    pub fn next_poll<Tz: TimeZone>(
+        &self,
+        last_sync: Option<DateTime<Tz>>,
+        due: DateTime<Tz>,
+        now: DateTime<Tz>,
+    ) -> DateTime<Tz> {
+        let mut next = due;
+        if let (Some(interval), Some(last_sync)) = (self.update_interval, last_sync) {
+            next = next.max(last_sync + chrono::Duration::seconds(interval as i64));
+        }
+
+        let next = next.max(now);
+        self.first_allowed(next.with_timezone(&Utc))
+            .with_timezone(&next.timezone())
+    }
+
+    /// The first moment from `from` that isn't in a skipped hour or day.
+    ///
+    /// Hours are counted in UTC, which keeps them whole in time zones with a half hour offset.
+    fn first_allowed(&self, from: DateTime<Utc>) -> DateTime<Utc> {
+        let mut time = from;
+        // A feed that skips every hour of the week is ignored.
+        for _ in 0..7 * 24 {
+            if !self.is_skipped(time) {
+                return time;
+            }
+            let hour_start = time
+                .with_minute(0)
+                .and_then(|time| time.with_second(0))
+                .and_then(|time| time.with_nanosecond(0))
+                .unwrap_or(time);
+            time = hour_start + chrono::Duration::hours(1);
+        }
+
+        from
+    }
+
+    fn is_skipped(&self, time: DateTime<Utc>) -> bool {
+        self.skip_hours.contains(&time.hour())
+            || self
+                .skip_days
+                .contains(&time.weekday().num_days_from_monday())
+    }
+}
+
+/// Seconds between updates from `sy:updatePeriod` and `sy:updateFrequency`, which is how
+/// many times the feed updates per period.
+fn update_interval(period: &str, frequency: Option<u64>) -> Option<u64> {
+    let period = match period.trim().to_lowercase().as_str() {
+        "hourly" => HOUR,
+        "daily" => DAY,
+        "weekly" => 7 * DAY,
+        "monthly" => 30 * DAY,
+        "yearly" => 365 * DAY,
+        _ => return None,
+    };
+
+    let frequency = frequency.filter(|&frequency| frequency > 0).unwrap_or(1);
+    Some(period / frequency)
+}
+
+/// An hour from `skipHours`. Some feeds write midnight as 24.
+fn skip_hour(hour: u64) -> Option<u32> {
+    match hour {
+        0..=23 => Some(hour as u32),
+        24 => Some(0),
+        _ => None,
+    }
+}
+
+/// A day from `skipDays`, counted from monday.
+fn skip_day(day: &str) -> Option<u32> {
+    let day = day.trim().to_lowercase();
+    DAYS.iter().position(|name| *name == day).map(|i| i as u32)
+}
+
+/// The child elements with the given tag, like the `hour` elements of `skipHours`.
+fn children<'a>(val: Option<&'a Value>, tag: &str) -> impl Iterator<Item = &'a Value> {
+    xml::val_to_vec(val.and_then(|val| val.get(tag))).into_iter()
+}
+
+/// A number, which the xml conversion may have left as a string.
+fn val_to_u64(val: &Value) -> Option<u64> {
+    val.as_u64()
+        .or_else(|| xml::val_to_str(val)?.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn hints(channel: &str) -> FeedHints {
+        let xml = format!(
+            r#"<rss xmlns:sy="http://purl.org/rss/1.0/modules/syndication/"><channel>
+            <title>Test</title>{}</channel></rss>"#,
+            channel
+        );
+        let val = xml::get_inner_channel(&xml).unwrap();
+        FeedHints::new(&RawPodcast::new(xml::restore_namespaces(&val)))
+    }
+
+    /// A time in UTC on monday the 2nd of september 2024.
+    fn monday(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 9, 2, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn update_periods() {
+        assert_eq!(update_interval("hourly", None), Some(HOUR));
+        assert_eq!(update_interval(" Weekly ", None), Some(7 * DAY));
+        assert_eq!(update_interval("daily", Some(4)), Some(6 * HOUR));
+        assert_eq!(update_interval("daily", Some(0)), Some(DAY));
+        assert_eq!(update_interval("fortnightly", Some(2)), None);
+    }
+
+    #[test]
+    fn parses_feed_hints() {
+        let hints = hints(
+            "<sy:updatePeriod>daily</sy:updatePeriod>
+            <sy:updateFrequency>2</sy:updateFrequency>
+            <skipHours><hour>3</hour><hour>24</hour><hour>25</hour><hour>3</hour></skipHours>
+            <skipDays><day>Sunday</day><day>Funday</day><day>monday</day></skipDays>",
+        );
+
+        assert_eq!(
+            hints,
+            FeedHints {
+                update_interval: Some(12 * HOUR),
+                skip_hours: vec![0, 3],
+                skip_days: vec![0, 6],
+            }
+        );
+    }
+
+    #[test]
+    fn single_skipped_hour() {
+        let hints = hints("<skipHours><hour>5</hour></skipHours>");
+        assert_eq!(hints.skip_hours, vec![5]);
+        assert_eq!(hints.update_interval, None);
+    }
+
+    #[test]
+    fn feed_without_hints() {
+        assert_eq!(hints(""), FeedHints::default());
+    }
+
+    #[test]
+    fn update_interval_postpones_schedule() {
+        let hints = FeedHints {
+            update_interval: Some(DAY),
+            ..Default::default()
+        };
+        let last_sync = monday(6, 0);
+
+        // Due by the schedule, but the feed only updates once a day.
+        let next = hints.next_poll(Some(last_sync), monday(12, 0), monday(13, 0));
+        assert_eq!(next, Utc.with_ymd_and_hms(2024, 9, 3, 6, 0, 0).unwrap());
+
+        // Hints never bring a sync forward.
+        let due = Utc.with_ymd_and_hms(2024, 9, 5, 6, 0, 0).unwrap();
+        assert_eq!(hints.next_poll(Some(last_sync), due, monday(13, 0)), due);
+
+        // Without a last sync, the podcast is due now.
+        assert_eq!(
+            hints.next_poll(None, monday(13, 0), monday(13, 0)),
+            monday(13, 0)
+        );
+    }
+
+    #[test]
+    fn skipped_hours_and_days() {
+        let hints = FeedHints {
+            update_interval: None,
+            skip_hours: vec![13, 14],
+            skip_days: vec![],
+        };
+        assert_eq!(
+            hints.next_poll(None, monday(13, 20), monday(13, 20)),
+            monday(15, 0)
+        );
+        assert_eq!(
+            hints.next_poll(None, monday(12, 59), monday(12, 59)),
+            monday(12, 59)
+        );
+
+        let hints = FeedHints {
+            update_interval: None,
+            skip_hours: vec![],
+            skip_days: vec![0],
+        };
+        let tuesday = Utc.with_ymd_and_hms(2024, 9, 3, 0, 0, 0).unwrap();
+        assert_eq!(hints.next_poll(None, monday(9, 30), monday(9, 30)), tuesday);
+    }
+
+    #[test]
+    fn skipped_hours_are_in_utc() {
+        let hints = FeedHints {
+            update_interval: None,
+            skip_hours: vec![3],
+            skip_days: vec![],
+        };
+        let india = FixedOffset::east_opt(5 * 3600 + 1800).unwrap();
+        let now = monday(3, 10).with_timezone(&india);
+
+        let next = hints.next_poll(None, now, now);
+        assert_eq!(next, monday(4, 0));
+        assert_eq!(next.offset(), &india);
+    }
+
+    #[test]
+    fn skipping_every_hour_is_ignored() {
+        let hints = FeedHints {
+            update_interval: None,
+            skip_hours: (0..24).collect(),
+            skip_days: vec![],
+        };
+        assert_eq!(
+            hints.next_poll(None, monday(8, 0), monday(8, 0)),
+            monday(8, 0)
+        );
+    }
+
+    #[test]
+    fn recorded_next_to_tracker() {
+        let dir = crate::paths::test_dir("feed-hints");
+        let tracker = dir.join(".downloaded");
+        assert_eq!(FeedHints::path(&tracker), dir.join(".downloaded.hints"));
+
+        let hints = FeedHints {
+            update_interval: Some(HOUR),
+            skip_hours: vec![1],
+            skip_days: vec![2],
+        };
+        hints.record(&tracker);
+        assert_eq!(FeedHints::load(&tracker), Some(hints));
+
+        FeedHints::default().record(&tracker);
+        assert!(!FeedHints::path(&tracker).exists());
+        assert_eq!(FeedHints::load(&tracker), None);
+    }
+}
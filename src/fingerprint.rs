@@ -0,0 +1,218 @@
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// What the server told us about an enclosure when it was downloaded.
+///
+/// Used for detecting episodes that have been silently replaced at the same url.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Fingerprint {
+    pub length: Option<u64>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Where the episode was downloaded to.
+    #[serde(default)]
+    pub path: PathBuf,
+}
+
+impl Fingerprint {
+    pub fn from_response(response: &reqwest::Response) -> Self {
+        let header = |name: reqwest::header::HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|val| val.to_str().ok())
+                .map(String::from)
+        };
+
+        // For partial responses the content-length is only the remaining bytes.
+        // Note that for HEAD requests `Response::content_length` is always zero,
+        // so the header has to be read directly.
+        let length = header(reqwest::header::CONTENT_RANGE)
+            .and_then(|range| range.rsplit_once('/')?.1.parse().ok())
+            .or_else(|| header(reqwest::header::CONTENT_LENGTH)?.parse().ok());
+
+        Self {
+            length,
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+            path: PathBuf::new(),
+        }
+    }
+
+    /// Whether the remote enclosure differs materially from what we downloaded.
+    ///
+    /// When both lengths are known, only a length difference larger than `tolerance`
+    /// (a fraction of the original length) counts as a change, since some servers hand out
+    /// different validators for the same file. Otherwise the ETag, and then Last-Modified,
+    /// headers are compared.
+    pub fn differs(&self, remote: &Self, tolerance: f64) -> bool {
+        if let (Some(old), Some(new)) = (self.length, remote.length) {
+            let diff = old.abs_diff(new) as f64;
+            return diff > old as f64 * tolerance;
+        }
+
+        if let (Some(old), Some(new)) = (&self.etag, &remote.etag) {
+            return old != new;
+        }
+
+        if let (Some(old), Some(new)) = (&self.last_modified, &remote.last_modified) {
+            return old != new;
+        }
+
+        false
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "length: {:?}, etag: {:?}, last-modified: {:?}",
+            self.length, self.etag, self.last_modified
+        )
+    }
+}
+
+/// The fingerprints of the downloaded episodes of a podcast, keyed by episode ID.
+///
+/// Stored next to the download tracker.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Fingerprints(HashMap<String, Fingerprint>);
+
+impl Fingerprints {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".fingerprints");
+        tracker_path.with_file_name(name)
+    }
+
+    pub fn load(tracker_path: &Path) -> Self {
+        fs::read_to_string(Self::path(tracker_path))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Fingerprint> {
+        self.0.get(id)
+    }
+
+    pub fn insert(tracker_path: &Path, id: &str, fingerprint: Fingerprint) -> Result<(), String> {
+        let mut fingerprints = Self::load(tracker_path);
+        fingerprints.0.insert(id.to_string(), fingerprint);
+
+        let json = serde_json::to_string_pretty(&fingerprints)
+            .map_err(|_| "failed to serialize fingerprints".to_string())?;
+        paths::write(Self::path(tracker_path), json)
+            .map_err(|_| "failed to save fingerprints".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn fingerprint(length: Option<u64>, etag: Option<&str>, modified: Option<&str>) -> Fingerprint {
+        Fingerprint {
+            length,
+            etag: etag.map(String::from),
+            last_modified: modified.map(String::from),
+            path: PathBuf::new(),
+        }
+    }
+
+    /// The response of a local server that answers with `head` and no body.
+    async fn response(head: &'static str) -> reqwest::Response {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await.unwrap();
+            stream.write_all(head.as_bytes()).await.unwrap();
+        });
+
+        reqwest::Client::new()
+            .head(format!("http://{}/ep.mp3", addr))
+            .send()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn from_headers() {
+        let response = response(
+            "HTTP/1.1 200 OK\r\nContent-Length: 1000\r\nETag: \"abc\"\r\n\
+             Last-Modified: Mon, 02 Sep 2024 06:00:00 GMT\r\n\r\n",
+        )
+        .await;
+
+        assert_eq!(
+            Fingerprint::from_response(&response),
+            fingerprint(
+                Some(1000),
+                Some("\"abc\""),
+                Some("Mon, 02 Sep 2024 06:00:00 GMT")
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_response_has_full_length() {
+        let response = response(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 100-999/1000\r\n\
+             Content-Length: 0\r\n\r\n",
+        )
+        .await;
+
+        assert_eq!(
+            Fingerprint::from_response(&response),
+            fingerprint(Some(1000), None, None)
+        );
+    }
+
+    #[test]
+    fn lengths_within_tolerance() {
+        let old = fingerprint(Some(1000), Some("a"), None);
+
+        assert!(!old.differs(&fingerprint(Some(1000), Some("b"), None), 0.0));
+        assert!(!old.differs(&fingerprint(Some(1050), None, None), 0.05));
+        assert!(old.differs(&fingerprint(Some(1051), None, None), 0.05));
+        assert!(old.differs(&fingerprint(Some(949), None, None), 0.05));
+    }
+
+    #[test]
+    fn validators_without_lengths() {
+        let old = fingerprint(None, Some("a"), Some("monday"));
+
+        assert!(!old.differs(&fingerprint(None, Some("a"), Some("tuesday")), 0.0));
+        assert!(old.differs(&fingerprint(None, Some("b"), Some("monday")), 0.0));
+        assert!(old.differs(&fingerprint(Some(1000), None, Some("tuesday")), 0.0));
+        assert!(!old.differs(&fingerprint(None, None, Some("monday")), 0.0));
+        assert!(!old.differs(&Fingerprint::default(), 0.0));
+    }
+
+    #[test]
+    fn stored_next_to_tracker() {
+        let dir = paths::test_dir("fingerprints");
+        let tracker = dir.join(".downloaded");
+        assert!(Fingerprints::load(&tracker).get("ep").is_none());
+
+        let first = fingerprint(Some(1), Some("a"), None);
+        let second = fingerprint(Some(2), None, Some("monday"));
+        Fingerprints::insert(&tracker, "ep", first).unwrap();
+        Fingerprints::insert(&tracker, "other", second.clone()).unwrap();
+        Fingerprints::insert(&tracker, "ep", second.clone()).unwrap();
+
+        let fingerprints = Fingerprints::load(&tracker);
+        assert_eq!(fingerprints.get("ep"), Some(&second));
+        assert_eq!(fingerprints.get("other"), Some(&second));
+        assert_eq!(
+            Fingerprints::path(&tracker),
+            dir.join(".downloaded.fingerprints")
+        );
+    }
+}
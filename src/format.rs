@@ -0,0 +1,214 @@
+//! Human readable sizes, durations and dates, formatted the same wherever they're shown.
+//!
+//! Sizes are in binary units (KiB, MiB) unless `size_units = "decimal"` is set, which is
+//! picked up once at startup so the progress bars, reports and patterns all agree.
+
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static SIZE_UNITS: OnceLock<SizeUnits> = OnceLock::new();
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const MONTH: u64 = 30 * DAY;
+const YEAR: u64 = 365 * DAY;
+
+/// Whether sizes count in powers of 1024 or of 1000.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeUnits {
+    /// KiB, MiB and GiB.
+    #[default]
+    Binary,
+    /// kB, MB and GB.
+    Decimal,
+}
+
+impl SizeUnits {
+    fn base(self) -> f64 {
+        match self {
+            Self::Binary => 1024.0,
+            Self::Decimal => 1000.0,
+        }
+    }
+
+    fn prefixes(self) -> [&'static str; 5] {
+        match self {
+            Self::Binary => ["KiB", "MiB", "GiB", "TiB", "PiB"],
+            Self::Decimal => ["kB", "MB", "GB", "TB", "PB"],
+        }
+    }
+}
+
+/// Sets the units sizes are shown in for the rest of the run.
+pub fn init(units: SizeUnits) {
+    if SIZE_UNITS.set(units).is_err() {
+        log::warn!("size units already set");
+    }
+}
+
+fn size_units() -> SizeUnits {
+    SIZE_UNITS.get().copied().unwrap_or_default()
+}
+
+/// A size like "512 B" or "1.5 MiB", in the configured units.
+pub fn human_bytes(bytes: u64) -> String {
+    bytes_in(bytes, size_units())
+}
+
+fn bytes_in(bytes: u64, units: SizeUnits) -> String {
+    let base = units.base();
+    let mut size = bytes as f64;
+    if size < base {
+        return format!("{} B", bytes);
+    }
+
+    let mut prefix = "";
+    for unit in units.prefixes() {
+        size /= base;
+        prefix = unit;
+        // Rounding could otherwise show e.g. "1024.0 KiB".
+        if size < base - 0.05 {
+            break;
+        }
+    }
+
+    format!("{:.1} {}", size, prefix)
+}
+
+/// A duration like "45s", "3m 5s", "1h 42m" or "2d 3h", leaving out a trailing zero.
+pub fn human_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64().round() as u64;
+
+    let (major, major_unit, minor, minor_unit) = if secs >= DAY {
+        (secs / DAY, "d", secs % DAY / HOUR, "h")
+    } else if secs >= HOUR {
+        (secs / HOUR, "h", secs % HOUR / MINUTE, "m")
+    } else if secs >= MINUTE {
+        (secs / MINUTE, "m", secs % MINUTE, "s")
+    } else {
+        return format!("{}s", secs);
+    };
+
+    match minor {
+        0 => format!("{}{}", major, major_unit),
+        _ => format!("{}{} {}{}", major, major_unit, minor, minor_unit),
+    }
+}
+
+/// How long ago a unix time was, like "3 days ago", or how far off it is if it's later
+/// than `now`, like "in 2 hours".
+pub fn relative_date(unix: u64, now: u64) -> String {
+    let (secs, future) = match unix > now {
+        true => (unix - now, true),
+        false => (now - unix, false),
+    };
+
+    if secs < MINUTE {
+        return "just now".to_string();
+    }
+
+    let (unit_secs, unit) = [
+        (YEAR, "year"),
+        (MONTH, "month"),
+        (7 * DAY, "week"),
+        (DAY, "day"),
+        (HOUR, "hour"),
+    ]
+    .into_iter()
+    .find(|(unit_secs, _)| secs >= *unit_secs)
+    .unwrap_or((MINUTE, "minute"));
+
+    let count = secs / unit_secs;
+    let plural = if count == 1 { "" } else { "s" };
+    match future {
+        true => format!("in {} {}{}", count, unit, plural),
+        false => format!("{} {}{} ago", count, unit, plural),
+    }
+}
+
+/// Parses a duration as written in `itunes:duration`, either seconds or `[HH:]MM:SS`.
+///
+/// Anything else, like more than three parts or a number too large for a duration, is
+/// `None`.
+pub fn parse_clock_duration(s: &str) -> Option<Duration> {
+    let parts: Vec<&str> = s.trim().split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+
+    let mut secs = 0.0;
+    for part in parts {
+        let part: f64 = part.trim().parse().ok()?;
+        if !part.is_finite() || part < 0.0 {
+            return None;
+        }
+        secs = secs * 60.0 + part;
+    }
+
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_sizes() {
+        let units = SizeUnits::Binary;
+        assert_eq!(bytes_in(0, units), "0 B");
+        assert_eq!(bytes_in(1023, units), "1023 B");
+        assert_eq!(bytes_in(1024, units), "1.0 KiB");
+        assert_eq!(bytes_in(1536, units), "1.5 KiB");
+        assert_eq!(bytes_in(1024 * 1024 - 1, units), "1.0 MiB");
+        assert_eq!(bytes_in(5 * 1024 * 1024 * 1024, units), "5.0 GiB");
+    }
+
+    #[test]
+    fn decimal_sizes() {
+        let units = SizeUnits::Decimal;
+        assert_eq!(bytes_in(999, units), "999 B");
+        assert_eq!(bytes_in(1000, units), "1.0 kB");
+        assert_eq!(bytes_in(1_500_000, units), "1.5 MB");
+        assert_eq!(bytes_in(999_999, units), "1.0 MB");
+    }
+
+    #[test]
+    fn durations() {
+        let secs = |secs: u64| human_duration(Duration::from_secs(secs));
+        assert_eq!(secs(0), "0s");
+        assert_eq!(secs(45), "45s");
+        assert_eq!(human_duration(Duration::from_millis(59_600)), "1m");
+        assert_eq!(secs(185), "3m 5s");
+        assert_eq!(secs(HOUR), "1h");
+        assert_eq!(secs(HOUR + 42 * MINUTE + 59), "1h 42m");
+        assert_eq!(secs(2 * DAY + 3 * HOUR), "2d 3h");
+    }
+
+    #[test]
+    fn relative_dates() {
+        let now = 1_700_000_000;
+        assert_eq!(relative_date(now - 30, now), "just now");
+        assert_eq!(relative_date(now + 30, now), "just now");
+        assert_eq!(relative_date(now - MINUTE, now), "1 minute ago");
+        assert_eq!(relative_date(now - 3 * DAY, now), "3 days ago");
+        assert_eq!(relative_date(now - 8 * DAY, now), "1 week ago");
+        assert_eq!(relative_date(now - 400 * DAY, now), "1 year ago");
+        assert_eq!(relative_date(now + 2 * HOUR, now), "in 2 hours");
+    }
+
+    #[test]
+    fn clock_durations() {
+        let parse = |s: &str| parse_clock_duration(s).map(|d| d.as_secs_f64());
+        assert_eq!(parse("90"), Some(90.0));
+        assert_eq!(parse("1.5"), Some(1.5));
+        assert_eq!(parse(" 01:30 "), Some(90.0));
+        assert_eq!(parse("1:00:00"), Some(3600.0));
+        assert_eq!(parse("1:2:3:4"), None);
+        assert_eq!(parse("-5"), None);
+        assert_eq!(parse("abc"), None);
+        assert_eq!(parse("1e400"), None);
+    }
+}
@@ -0,0 +1,780 @@
+//! Finding the program a `download_hook` runs, and what it's told about the episode.
+//!
+//! A hook is either the path to a program, or a program followed by arguments. Programs
+//! without a directory are looked up in `PATH`, and relative paths are relative to the
+//! config directory, so the same config works wherever TaleCast is run from.
+//!
+//! Besides the path of the episode, a hook gets a JSON file with everything known about the
+//! episode and its podcast, whose path is in `TALECAST_CONTEXT_FILE`.
+//!
+//! Hooks run alongside the downloads. A hook is marked as pending next to the download
+//! tracker when it starts, and the mark is only cleared once it succeeds, so a hook that
+//! failed or was cut off by TaleCast stopping is reported by the next sync.
+//!
+//! With `hook_sandbox`, a hook only sees the `TALECAST_*` variables and a few basic ones
+//! like `PATH`, runs in the podcast's download directory, and can be given a lower CPU and
+//! IO priority and a memory limit. The limits that only exist on Linux are skipped elsewhere.
+
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Version of the layout of the context file, raised when a key changes meaning or goes away.
+const CONTEXT_VERSION: u32 = 1;
+
+/// The environment variables a sandboxed hook keeps, besides the ones in `hook_sandbox_env`.
+const SANDBOX_ENV: [&str; 8] = [
+    "PATH", "HOME", "USER", "LOGNAME", "LANG", "LC_ALL", "TZ", "TMPDIR",
+];
+
+#[derive(Debug, Clone)]
+pub struct HookCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl HookCommand {
+    /// Splits a hook into its program and arguments, unless the whole of it names a file.
+    pub fn parse(hook: &Path) -> Self {
+        let whole = Self {
+            program: hook.to_path_buf(),
+            args: vec![],
+        };
+
+        let Some(hook_str) = hook.to_str() else {
+            return whole;
+        };
+
+        let mut words = hook_str.split_whitespace();
+        match words.next() {
+            Some(program) if whole.resolve().is_err() => Self {
+                program: PathBuf::from(program),
+                args: words.map(String::from).collect(),
+            },
+            _ => whole,
+        }
+    }
+
+    /// The path of the program, if it exists and can be executed.
+    pub fn resolve(&self) -> Result<PathBuf, String> {
+        let program = &self.program;
+
+        if program.is_absolute() {
+            return check_executable(program.clone());
+        }
+
+        if program.components().count() > 1 {
+            return check_executable(paths::config_dir().join(program));
+        }
+
+        let path_var = std::env::var_os("PATH").unwrap_or_default();
+        std::env::split_paths(&path_var)
+            .map(|dir| dir.join(program))
+            .find(|candidate| check_executable(candidate.clone()).is_ok())
+            .ok_or_else(|| format!("{:?} not found in PATH", program))
+    }
+
+    /// The hook with its program replaced by the path it resolves to, if it does.
+    pub fn resolved(self) -> Self {
+        match self.resolve() {
+            Ok(program) => Self { program, ..self },
+            Err(_) => self,
+        }
+    }
+}
+
+fn check_executable(path: PathBuf) -> Result<PathBuf, String> {
+    match path.metadata() {
+        Ok(metadata) if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 => {
+            Ok(path)
+        }
+        Ok(metadata) if metadata.is_file() => Err(format!("{:?} is not executable", path)),
+        Ok(_) => Err(format!("{:?} is not a file", path)),
+        Err(_) => Err(format!("{:?} does not exist", path)),
+    }
+}
+
+/// How a sandboxed hook is limited, from the `hook_*` settings.
+#[derive(Debug, Clone, Default)]
+pub struct HookSandbox {
+    /// Variables to keep on top of [`SANDBOX_ENV`].
+    pub env: Vec<String>,
+    /// Niceness of the hook, where higher is a lower CPU priority.
+    pub nice: Option<i32>,
+    pub ionice: Option<IoPriority>,
+    /// How much memory the hook may use, in bytes.
+    pub memory_limit: Option<u64>,
+}
+
+impl HookSandbox {
+    /// Sets up the command to run with the environment and limits of the sandbox.
+    ///
+    /// Clears the environment, so the hook's own variables have to be set afterwards.
+    fn apply(&self, command: &mut tokio::process::Command, dir: &Path) {
+        command.env_clear().current_dir(dir);
+        for key in SANDBOX_ENV
+            .iter()
+            .copied()
+            .chain(self.env.iter().map(String::as_str))
+        {
+            if let Some(value) = std::env::var_os(key) {
+                command.env(key, value);
+            }
+        }
+
+        let (nice, ionice, memory_limit) = (self.nice, self.ionice, self.memory_limit);
+        if nice.is_none() && ionice.is_none() && memory_limit.is_none() {
+            return;
+        }
+
+        // Runs in the forked child, so it only makes system calls. Limits that can't be
+        // set, like a negative niceness without the permission for it, are skipped.
+        let limit = move || {
+            if let Some(nice) = nice {
+                unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+            }
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(ionice) = ionice {
+                    unsafe { libc::syscall(libc::SYS_ioprio_set, 1, 0, ionice.value()) };
+                }
+                if let Some(bytes) = memory_limit {
+                    let limit = libc::rlimit {
+                        rlim_cur: bytes as libc::rlim_t,
+                        rlim_max: bytes as libc::rlim_t,
+                    };
+                    unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) };
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            let _ = (ionice, memory_limit);
+            Ok(())
+        };
+        unsafe { command.pre_exec(limit) };
+    }
+}
+
+/// The IO scheduling class of a sandboxed hook, as `hook_ionice` names it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IoPriority {
+    /// Only gets to use the disk when nothing else does.
+    Idle,
+    /// A level from 0 to 7 in the default class, where 7 is the lowest priority.
+    BestEffort(u8),
+}
+
+impl IoPriority {
+    /// Parses `"idle"`, `"best-effort"` or a level like `"best-effort:7"`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s == "idle" {
+            return Ok(Self::Idle);
+        }
+
+        match s.split_once(':') {
+            None if s == "best-effort" => Ok(Self::BestEffort(4)),
+            Some(("best-effort", level)) => match level.trim().parse() {
+                Ok(level) if level <= 7 => Ok(Self::BestEffort(level)),
+                _ => Err(format!("\"{}\" is not a level from 0 to 7", level)),
+            },
+            _ => Err(format!(
+                "\"{}\" is not \"idle\", \"best-effort\" or \"best-effort:<level>\"",
+                s
+            )),
+        }
+    }
+
+    /// The value `ioprio_set` takes, with the class in the top bits.
+    #[cfg(target_os = "linux")]
+    fn value(self) -> libc::c_int {
+        match self {
+            Self::Idle => 3 << 13,
+            Self::BestEffort(level) => (2 << 13) | level as libc::c_int,
+        }
+    }
+}
+
+/// What a download hook is told about the episode, in the file at `TALECAST_CONTEXT_FILE`.
+#[derive(Serialize, Debug)]
+pub struct HookContext<'a> {
+    pub version: u32,
+    /// Name of the podcast in `podcasts.toml`.
+    pub podcast: &'a str,
+    /// Where the episode was downloaded to.
+    pub path: &'a Path,
+    pub first_download: bool,
+    /// Every tag of the episode's `item` element in the feed.
+    pub episode: &'a Map<String, Value>,
+    /// The tags of the feed's `channel` element, without its items.
+    pub channel: &'a Map<String, Value>,
+    /// The cached image of the episode, falling back to the podcast image.
+    pub artwork_path: Option<PathBuf>,
+    /// The transcript the feed links to with `podcast:transcript`.
+    pub transcript_url: Option<&'a str>,
+}
+
+impl<'a> HookContext<'a> {
+    pub fn new(
+        podcast: &'a str,
+        path: &'a Path,
+        first_download: bool,
+        episode: &'a Map<String, Value>,
+        channel: &'a Map<String, Value>,
+    ) -> Self {
+        Self {
+            version: CONTEXT_VERSION,
+            podcast,
+            path,
+            first_download,
+            episode,
+            channel,
+            artwork_path: None,
+            transcript_url: None,
+        }
+    }
+
+    /// Writes the context to a new file in the directory of this run, returning its path.
+    ///
+    /// Control characters in the feed are escaped by the serializer, so the file is always
+    /// valid JSON.
+    pub fn write(&self) -> Result<PathBuf, String> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = context_dir();
+        fs::create_dir_all(dir).map_err(|e| {
+            paths::io_error_message(dir, &e, "failed to create hook context directory")
+        })?;
+
+        let id = COUNTER.fetch_add(1, Ordering::SeqCst);
+        let path = dir.join(format!("{}.json", id));
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("failed to serialize hook context: {}", e))?;
+        paths::write(&path, json)
+            .map_err(|e| paths::io_error_message(&path, &e, "failed to write hook context"))?;
+
+        Ok(path)
+    }
+}
+
+/// The directory for the context files of this run.
+fn context_dir() -> &'static Path {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| std::env::temp_dir().join(format!("talecast-hooks-{}", std::process::id())))
+}
+
+/// Removes the directory of this run's context files, unless some were kept.
+pub fn clean_up_contexts() {
+    let _ = fs::remove_dir(context_dir());
+}
+
+/// How the last run of a hook on an episode ended, if it didn't succeed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStatus {
+    /// The hook was started, but TaleCast stopped before it finished.
+    Pending,
+    Failed(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HookRecord {
+    pub status: HookStatus,
+    /// The episode the hook was run on.
+    pub path: PathBuf,
+    pub first_download: bool,
+}
+
+/// The hooks of a podcast that haven't succeeded, by episode id.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct HookRecords(BTreeMap<String, HookRecord>);
+
+impl HookRecords {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".hooks");
+        tracker_path.with_file_name(name)
+    }
+
+    pub fn load(tracker_path: &Path) -> Self {
+        fs::read_to_string(Self::path(tracker_path))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn into_inner(self) -> BTreeMap<String, HookRecord> {
+        self.0
+    }
+
+    /// Records how the hook of an episode ended, where `None` means it succeeded.
+    pub fn update(tracker_path: &Path, id: &str, record: Option<HookRecord>) {
+        // Hooks of the same podcast finish at the same time.
+        static LOCK: Mutex<()> = Mutex::new(());
+        let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut records = Self::load(tracker_path);
+        match record {
+            Some(record) => records.0.insert(id.to_string(), record),
+            None => records.0.remove(id),
+        };
+
+        if let Err(e) = records.save(tracker_path) {
+            log::warn!("failed to save hook outcome: {}", e);
+        }
+    }
+
+    fn save(&self, tracker_path: &Path) -> Result<(), String> {
+        let path = Self::path(tracker_path);
+        if self.0.is_empty() {
+            let _ = fs::remove_file(path);
+            return Ok(());
+        }
+
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        paths::write(&path, json).map_err(|e| e.to_string())
+    }
+}
+
+/// A run of the download hook on a downloaded episode.
+pub struct HookRun {
+    pub hook: HookCommand,
+    pub tracker_path: PathBuf,
+    /// Id of the episode in the download tracker.
+    pub id: String,
+    pub path: PathBuf,
+    pub first_download: bool,
+    pub context: Option<PathBuf>,
+    pub keep_context: bool,
+    /// How long the hook may run before it's stopped.
+    pub timeout: Option<Duration>,
+    pub sandbox: Option<HookSandbox>,
+    /// The podcast's download directory, which a sandboxed hook runs in.
+    pub dir: PathBuf,
+}
+
+impl HookRun {
+    /// Starts the hook in the background, marking it as pending until it finishes.
+    pub fn spawn(self) -> JoinHandle<Result<(), String>> {
+        HookRecords::update(
+            &self.tracker_path,
+            &self.id,
+            Some(self.record(HookStatus::Pending)),
+        );
+
+        tokio::spawn(async move {
+            let res = self.run().await;
+
+            let record = res
+                .as_ref()
+                .err()
+                .map(|e| self.record(HookStatus::Failed(e.clone())));
+            HookRecords::update(&self.tracker_path, &self.id, record);
+
+            if let Some(context) = self.context.as_ref().filter(|_| !self.keep_context) {
+                let _ = fs::remove_file(context);
+            }
+
+            res
+        })
+    }
+
+    fn record(&self, status: HookStatus) -> HookRecord {
+        HookRecord {
+            status,
+            path: self.path.clone(),
+            first_download: self.first_download,
+        }
+    }
+
+    async fn run(&self) -> Result<(), String> {
+        let program = &self.hook.program;
+        let mut command = tokio::process::Command::new(program);
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.apply(&mut command, &self.dir);
+        }
+        command
+            .args(&self.hook.args)
+            .arg(&self.path)
+            .env("TALECAST_FIRST_DOWNLOAD", self.first_download.to_string())
+            .kill_on_drop(true);
+        if let Some(context) = &self.context {
+            command.env("TALECAST_CONTEXT_FILE", context);
+        }
+
+        let output = command.output();
+        let output = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, output).await.map_err(|_| {
+                format!(
+                    "download hook {:?} timed out after {}s",
+                    program,
+                    timeout.as_secs()
+                )
+            })?,
+            None => output.await,
+        };
+
+        let output =
+            output.map_err(|e| format!("failed to run download hook {:?}: {}", program, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "download hook {:?} failed: {}",
+                program, output.status
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The hooks started while syncing a podcast.
+pub struct HookSupervisor {
+    /// The running hooks, with the name of their episode.
+    handles: Vec<(String, JoinHandle<Result<(), String>>)>,
+    /// How long a single hook may run.
+    timeout: Option<Duration>,
+}
+
+impl HookSupervisor {
+    /// Extra time given to hooks that timed out to be stopped.
+    const DRAIN_MARGIN: Duration = Duration::from_secs(5);
+
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            handles: vec![],
+            timeout,
+        }
+    }
+
+    pub fn add(&mut self, episode: String, handle: JoinHandle<Result<(), String>>) {
+        self.handles.push((episode, handle));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Waits for every hook to finish, returning the errors of the ones that failed and how
+    /// many were still running when the wait timed out.
+    ///
+    /// The hooks run at the same time and each one is limited to the hook timeout, so
+    /// waiting for them all takes about as long as the slowest one. The ones that are
+    /// still running after that are stopped and stay marked as pending.
+    pub async fn drain(self) -> (Vec<(String, String)>, usize) {
+        let deadline = self
+            .timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout + Self::DRAIN_MARGIN);
+
+        let mut errors = vec![];
+        let mut unfinished = 0;
+        for (episode, mut handle) in self.handles {
+            let res = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, &mut handle).await,
+                None => Ok((&mut handle).await),
+            };
+
+            match res {
+                Ok(Ok(Err(e))) => errors.push((episode, e)),
+                Ok(_) => {}
+                Err(_) => {
+                    handle.abort();
+                    unfinished += 1;
+                }
+            }
+        }
+
+        (errors, unfinished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn context_keys() {
+        let episode = map(serde_json::json!({ "title": "Episode" }));
+        let channel = map(serde_json::json!({ "title": "Podcast" }));
+        let context = HookContext {
+            transcript_url: Some("https://example.com/transcript.vtt"),
+            ..HookContext::new(
+                "podcast",
+                Path::new("/episodes/1.mp3"),
+                true,
+                &episode,
+                &channel,
+            )
+        };
+
+        let path = context.write().unwrap();
+        let json: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let mut expected = vec![
+            "version",
+            "podcast",
+            "path",
+            "first_download",
+            "episode",
+            "channel",
+            "artwork_path",
+            "transcript_url",
+        ];
+        expected.sort();
+        assert_eq!(keys, expected);
+
+        assert_eq!(json["version"], 1);
+        assert_eq!(json["podcast"], "podcast");
+        assert_eq!(json["path"], "/episodes/1.mp3");
+        assert_eq!(json["first_download"], true);
+        assert_eq!(json["episode"]["title"], "Episode");
+        assert_eq!(json["channel"]["title"], "Podcast");
+        assert_eq!(json["artwork_path"], Value::Null);
+        assert_eq!(json["transcript_url"], "https://example.com/transcript.vtt");
+    }
+
+    #[test]
+    fn context_escapes_feed_text() {
+        let title = "tab\there \"quoted\" back\\slash\u{1}";
+        let episode = map(serde_json::json!({ "title": title }));
+        let channel = Map::new();
+        let context = HookContext::new("podcast", Path::new("1.mp3"), false, &episode, &channel);
+
+        let path = context.write().unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert!(text.contains(r#""tab\there \"quoted\" back\\slash\u0001""#));
+        let json: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["episode"]["title"], title);
+    }
+
+    /// Runs a hook that copies its context file next to the episode, returning whether the
+    /// context file was still there afterwards.
+    async fn run_copying_context(name: &str, keep_context: bool) -> (Value, bool) {
+        let dir = paths::test_dir(name);
+        let episode_path = dir.join("episode.mp3");
+        let episode = map(serde_json::json!({ "title": "Episode" }));
+        let channel = Map::new();
+        let context = HookContext::new("podcast", &episode_path, false, &episode, &channel)
+            .write()
+            .unwrap();
+
+        let run = HookRun {
+            hook: HookCommand {
+                program: PathBuf::from("/bin/sh"),
+                args: vec![
+                    "-c".to_string(),
+                    "cp \"$TALECAST_CONTEXT_FILE\" \"$1.json\"".to_string(),
+                    "hook".to_string(),
+                ],
+            },
+            tracker_path: dir.join(".downloaded"),
+            id: "1".to_string(),
+            path: episode_path.clone(),
+            first_download: false,
+            context: Some(context.clone()),
+            keep_context,
+            timeout: None,
+            sandbox: None,
+            dir: dir.clone(),
+        };
+        run.spawn().await.unwrap().unwrap();
+
+        let copy = fs::read_to_string(dir.join("episode.mp3.json")).unwrap();
+        let kept = context.exists();
+        let _ = fs::remove_file(context);
+        (serde_json::from_str(&copy).unwrap(), kept)
+    }
+
+    #[tokio::test]
+    async fn context_removed_after_hook() {
+        let (json, kept) = run_copying_context("hook_context_removed", false).await;
+        assert_eq!(json["episode"]["title"], "Episode");
+        assert!(!kept);
+    }
+
+    #[tokio::test]
+    async fn context_kept_after_hook() {
+        let (json, kept) = run_copying_context("hook_context_kept", true).await;
+        assert_eq!(json["version"], CONTEXT_VERSION);
+        assert!(kept);
+    }
+
+    /// A run of `script` with `sh -c`, on an episode in `dir`.
+    fn shell_hook(dir: &Path, id: &str, script: &str, timeout: Option<Duration>) -> HookRun {
+        HookRun {
+            hook: HookCommand {
+                program: PathBuf::from("/bin/sh"),
+                args: vec!["-c".to_string(), script.to_string(), "hook".to_string()],
+            },
+            tracker_path: dir.join(".downloaded"),
+            id: id.to_string(),
+            path: dir.join(format!("{}.mp3", id)),
+            first_download: false,
+            context: None,
+            keep_context: false,
+            timeout,
+            sandbox: None,
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn statuses(dir: &Path) -> Vec<(String, HookStatus)> {
+        HookRecords::load(&dir.join(".downloaded"))
+            .into_inner()
+            .into_iter()
+            .map(|(id, record)| (id, record.status))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn outcomes_are_recorded_per_episode() {
+        let dir = paths::test_dir("hook-outcomes");
+        let ok = shell_hook(&dir, "ok", "exit 0", None).spawn();
+        let failed = shell_hook(&dir, "failed", "exit 3", None).spawn();
+        let running = shell_hook(&dir, "running", "sleep 10", None).spawn();
+
+        assert_eq!(ok.await.unwrap(), Ok(()));
+        let msg = "download hook \"/bin/sh\" failed: exit status: 3".to_string();
+        assert_eq!(failed.await.unwrap(), Err(msg.clone()));
+
+        // Only the hooks that didn't succeed are left, and the running one is pending.
+        let expected = [
+            ("failed".to_string(), HookStatus::Failed(msg)),
+            ("running".to_string(), HookStatus::Pending),
+        ];
+        assert_eq!(statuses(&dir), expected);
+
+        // Cut off like when TaleCast stops, which leaves it pending for the next sync.
+        running.abort();
+        assert!(running.await.unwrap_err().is_cancelled());
+        assert_eq!(statuses(&dir), expected);
+
+        let record = &HookRecords::load(&dir.join(".downloaded")).into_inner()["failed"];
+        assert_eq!(record.path, dir.join("failed.mp3"));
+        assert!(!record.first_download);
+    }
+
+    #[tokio::test]
+    async fn sandbox_scrubs_the_environment() {
+        let dir = paths::test_dir("hook-sandbox-env");
+        std::env::set_var("TALECAST_TEST_SECRET", "secret");
+        std::env::set_var("TALECAST_TEST_KEPT", "kept");
+        let script = "env > \"$1.env\"; pwd > \"$1.pwd\"";
+        let names = |id: &str| -> Vec<String> {
+            let env = fs::read_to_string(dir.join(format!("{}.mp3.env", id))).unwrap();
+            let mut names: Vec<String> = env
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .map(|(name, _)| name.to_string())
+                // Set by the shell itself.
+                .filter(|name| !["PWD", "OLDPWD", "SHLVL", "_"].contains(&name.as_str()))
+                .collect();
+            names.sort();
+            names
+        };
+
+        shell_hook(&dir, "open", script, None)
+            .spawn()
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(names("open").contains(&"TALECAST_TEST_SECRET".to_string()));
+
+        let run = HookRun {
+            sandbox: Some(HookSandbox {
+                env: vec!["TALECAST_TEST_KEPT".to_string()],
+                ..Default::default()
+            }),
+            dir: dir.join("podcast"),
+            ..shell_hook(&dir, "sandboxed", script, None)
+        };
+        fs::create_dir(&run.dir).unwrap();
+        run.spawn().await.unwrap().unwrap();
+
+        // Only the allowed variables are passed on, along with the hook's own.
+        let mut expected: Vec<String> = SANDBOX_ENV
+            .iter()
+            .filter(|key| std::env::var_os(key).is_some())
+            .chain(&["TALECAST_FIRST_DOWNLOAD", "TALECAST_TEST_KEPT"])
+            .map(|key| key.to_string())
+            .collect();
+        expected.sort();
+        assert_eq!(names("sandboxed"), expected);
+
+        // And it runs in the podcast's directory.
+        let pwd = fs::read_to_string(dir.join("sandboxed.mp3.pwd")).unwrap();
+        assert_eq!(Path::new(pwd.trim()), dir.join("podcast"));
+    }
+
+    #[tokio::test]
+    async fn hooks_are_stopped_at_the_timeout() {
+        let dir = paths::test_dir("hook-timeout");
+        let script = "sleep 2; touch \"$1.late\"";
+        let run = shell_hook(&dir, "slow", script, Some(Duration::from_secs(1)));
+
+        let started = std::time::Instant::now();
+        let res = run.spawn().await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        let msg = "download hook \"/bin/sh\" timed out after 1s".to_string();
+        assert_eq!(res, Err(msg.clone()));
+        assert_eq!(
+            statuses(&dir),
+            [("slow".to_string(), HookStatus::Failed(msg))]
+        );
+
+        // The hook was killed rather than left running.
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!dir.join("slow.mp3.late").exists());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn draining_waits_for_the_timeout_and_margin() {
+        let timeout = Duration::from_secs(60);
+        let mut supervisor = HookSupervisor::new(Some(timeout));
+        assert!(supervisor.is_empty());
+
+        let finishing = |secs, res| {
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(secs)).await;
+                res
+            })
+        };
+        supervisor.add("ok".to_string(), finishing(10, Ok(())));
+        supervisor.add(
+            "failed".to_string(),
+            finishing(20, Err("exit 1".to_string())),
+        );
+        // Still finishes within the margin after the timeout.
+        supervisor.add("late".to_string(), finishing(62, Ok(())));
+        supervisor.add("stuck".to_string(), finishing(3600, Ok(())));
+
+        let started = tokio::time::Instant::now();
+        let (errors, unfinished) = supervisor.drain().await;
+        assert_eq!(errors, [("failed".to_string(), "exit 1".to_string())]);
+        assert_eq!(unfinished, 1);
+        assert_eq!(started.elapsed(), timeout + HookSupervisor::DRAIN_MARGIN);
+    }
+}
@@ -0,0 +1,409 @@
+use crate::cache::Cache;
+use crate::cache::CacheKind;
+use crate::config::GlobalConfig;
+use crate::config::PodcastConfig;
+use crate::config::PodcastConfigs;
+use crate::display::DownloadBar;
+use crate::download_tracker::DownloadedEpisodes;
+use crate::episode::Episode;
+use crate::fingerprint::Fingerprints;
+use crate::naming;
+use crate::naming::NameOptions;
+use crate::podcast::Podcast;
+use crate::podcast::RawPodcast;
+use crate::utils;
+use indicatif::MultiProgress;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// Searchable text of a podcast, cached every time its feed is parsed.
+///
+/// Lets us search through podcasts without fetching their feeds.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PodcastIndex {
+    /// Title of the podcast in its feed.
+    #[serde(default)]
+    title: Option<String>,
+    description: Option<String>,
+    keywords: Option<String>,
+    episodes: Vec<IndexedEpisode>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexedEpisode {
+    id: String,
+    title: String,
+    published: u64,
+    #[serde(default)]
+    number: Option<String>,
+    #[serde(default)]
+    number_inferred: bool,
+    #[serde(default)]
+    video: bool,
+    description: Option<String>,
+    keywords: Option<String>,
+    shownotes: Option<String>,
+    download_path: PathBuf,
+    tracker_path: PathBuf,
+    file_name: String,
+}
+
+impl IndexedEpisode {
+    fn is_match(&self, query: &Query) -> bool {
+        [
+            Some(&self.title),
+            self.description.as_ref(),
+            self.keywords.as_ref(),
+            self.shownotes.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|text| query.is_match(text))
+    }
+
+    /// Whether the episode is in the download tracker, and whether catching up put it there.
+    fn tracker_status(&self) -> (bool, bool) {
+        let tracker = DownloadedEpisodes::load(&self.tracker_path);
+        (
+            tracker.contains_episode(&self.id),
+            tracker.is_acknowledged(&self.id),
+        )
+    }
+
+    /// Finds the downloaded file, from the path recorded when it was downloaded or else by
+    /// its name, whatever its extension turned out to be.
+    fn file_path(&self) -> Option<PathBuf> {
+        let recorded = Fingerprints::load(&self.tracker_path)
+            .get(&self.id)
+            .map(|fingerprint| fingerprint.path.clone())
+            .filter(|path| !path.as_os_str().is_empty() && path.is_file());
+        if recorded.is_some() {
+            return recorded;
+        }
+
+        fs::read_dir(&self.download_path)
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_none_or(|ext| ext != "partial"))
+            .find(|path| {
+                path.is_file()
+                    && path
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .is_some_and(|stem| stem == self.file_name)
+            })
+    }
+}
+
+impl PodcastIndex {
+    pub fn new(podcast: &RawPodcast, episodes: &[Episode]) -> Self {
+        let episodes = episodes
+            .iter()
+            .map(|episode| IndexedEpisode {
+                id: episode.get_id(),
+                title: episode.attrs.title().to_string(),
+                published: episode.attrs.published().as_secs(),
+                number: episode.attrs.itunes_episode().ok().map(String::from),
+                number_inferred: episode.attrs.episode_inferred,
+                video: episode.attrs.is_video(),
+                description: episode.attrs.description().ok().map(String::from),
+                keywords: episode.attrs.keywords().ok().map(String::from),
+                shownotes: episode.attrs.shownotes().ok().map(String::from),
+                download_path: episode.config.download_path.clone(),
+                tracker_path: episode.config.tracker_path.clone(),
+                file_name: naming::sanitize_filename(
+                    NameOptions::default(),
+                    &episode.config.name_pattern,
+                ),
+            })
+            .collect();
+
+        Self {
+            title: podcast.get_str("title").map(String::from),
+            description: podcast.description().map(String::from),
+            keywords: podcast.keywords().map(String::from),
+            episodes,
+        }
+    }
+
+    fn key(name: &str) -> String {
+        format!(
+            "{}.json",
+            naming::sanitize_filename(NameOptions::default(), name)
+        )
+    }
+
+    pub fn load(name: &str) -> Option<Self> {
+        let data = Cache::read(CacheKind::Feeds, &Self::key(name))?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn episode_ids(&self) -> impl Iterator<Item = &str> {
+        self.episodes.iter().map(|episode| episode.id.as_str())
+    }
+
+    /// The id and title of every episode.
+    pub fn episode_titles(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.episodes
+            .iter()
+            .map(|episode| (episode.id.as_str(), episode.title.as_str()))
+    }
+
+    pub fn save(&self, name: &str, ui: &DownloadBar) {
+        ui.log_trace("saving search index");
+        let json = match serde_json::to_string(self) {
+            Ok(json) => json,
+            Err(e) => {
+                ui.log_warn(format!("failed to serialize search index: {:?}", e));
+                return;
+            }
+        };
+
+        if let Err(e) = Cache::write(CacheKind::Feeds, &Self::key(name), json.as_bytes()) {
+            ui.log_warn(format!("failed to save search index: {:?}", e));
+        }
+    }
+
+    /// Prints the episodes that match, with whether they're downloaded, or acknowledged if
+    /// catching up marked them or they were published before `acknowledged_before`.
+    fn print_matches(
+        &self,
+        name: &str,
+        query: &Query,
+        acknowledged_before: Option<u64>,
+        counts: &mut MatchCounts,
+    ) {
+        let podcast_matches = [self.description.as_ref(), self.keywords.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|text| query.is_match(text));
+
+        if podcast_matches {
+            println!("{}: matched podcast description", name);
+        }
+
+        for episode in self.episodes.iter().filter(|ep| ep.is_match(query)) {
+            let date = chrono::DateTime::from_timestamp(episode.published as i64, 0)
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_default();
+
+            let (tracked, caught_up) = episode.tracker_status();
+            let acknowledged = caught_up
+                || (!tracked && acknowledged_before.is_some_and(|date| episode.published < date));
+            let status = match (tracked, acknowledged) {
+                (_, true) => {
+                    counts.acknowledged += 1;
+                    "· acknowledged".to_string()
+                }
+                (true, false) => {
+                    counts.downloaded += 1;
+                    match episode.file_path() {
+                        Some(path) => format!("✓ downloaded: {}", path.display()),
+                        None => "✓ downloaded".to_string(),
+                    }
+                }
+                (false, false) => {
+                    counts.not_downloaded += 1;
+                    "not downloaded".to_string()
+                }
+            };
+
+            let number = match (&episode.number, episode.number_inferred) {
+                (Some(number), false) => format!("#{} ", number),
+                (Some(number), true) => format!("#{} (inferred) ", number),
+                (None, _) => String::new(),
+            };
+
+            let kind = match episode.video {
+                true => "[video] ",
+                false => "",
+            };
+
+            println!(
+                "{}: {} {}{}{} ({})",
+                name, date, kind, number, episode.title, status
+            );
+        }
+    }
+}
+
+/// How many of the matching episodes are in each state.
+#[derive(Default)]
+struct MatchCounts {
+    downloaded: usize,
+    acknowledged: usize,
+    not_downloaded: usize,
+}
+
+impl MatchCounts {
+    fn print(&self) {
+        let total = self.downloaded + self.acknowledged + self.not_downloaded;
+        if total == 0 {
+            return;
+        }
+
+        eprintln!(
+            "{} matching episodes: {} downloaded, {} acknowledged, {} not downloaded",
+            total, self.downloaded, self.acknowledged, self.not_downloaded
+        );
+    }
+}
+
+enum Query {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Query {
+    fn new(query: &str, regex: bool) -> Result<Self, String> {
+        if regex {
+            Regex::new(&format!("(?i){}", query))
+                .map(Self::Regex)
+                .map_err(|e| format!("invalid regex: {}", e))
+        } else {
+            Ok(Self::Substring(query.to_lowercase()))
+        }
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Self::Substring(query) => text.to_lowercase().contains(query),
+            Self::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+/// Searches through the cached feeds of the configured podcasts.
+pub async fn find(query: String, regex: bool, filter: Option<Regex>, global_config: &GlobalConfig) {
+    let query = match Query::new(&query, regex) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let podcasts = PodcastConfigs::load().assert_not_empty().filter(filter);
+    let mut missing = vec![];
+    let mut counts = MatchCounts::default();
+
+    for (name, config) in podcasts {
+        let acknowledged_before = config
+            .acknowledged_before(global_config)
+            .map(|date| date.as_secs());
+        match PodcastIndex::load(&name) {
+            Some(index) => index.print_matches(&name, &query, acknowledged_before, &mut counts),
+            None => missing.push((name, config)),
+        }
+    }
+
+    if missing.is_empty() {
+        counts.print();
+        return;
+    }
+
+    let names: Vec<&str> = missing.iter().map(|(name, _)| name.as_str()).collect();
+    eprintln!("no cached feed for: {}", names.join(", "));
+
+    let fetch = utils::get_input(Some("fetch them now? [y/N]: "))
+        .is_some_and(|input| input.eq_ignore_ascii_case("y"));
+
+    if !fetch {
+        counts.print();
+        return;
+    }
+
+    for (name, config) in missing {
+        let acknowledged_before = config
+            .acknowledged_before(global_config)
+            .map(|date| date.as_secs());
+        fetch_index(&name, config, global_config).await;
+        if let Some(index) = PodcastIndex::load(&name) {
+            index.print_matches(&name, &query, acknowledged_before, &mut counts);
+        }
+    }
+
+    counts.print();
+}
+
+pub async fn fetch_index(name: &str, config: PodcastConfig, global_config: &GlobalConfig) {
+    let mp = MultiProgress::new();
+    let client = crate::config::init_reqwest_client(global_config);
+    let mut ui = DownloadBar::new(
+        name.to_string(),
+        global_config.style(),
+        &mp,
+        name.chars().count(),
+    );
+
+    let url = config.url.clone();
+    match Podcast::new(name.to_string(), config, global_config, client, &ui).await {
+        Ok(_) => ui.complete(),
+        Err(e) => ui.error_with_url(&e, &url),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::Fingerprint;
+    use crate::paths;
+    use std::path::Path;
+
+    fn indexed(dir: &Path, id: &str, file_name: &str) -> IndexedEpisode {
+        IndexedEpisode {
+            id: id.to_string(),
+            title: file_name.to_string(),
+            published: 0,
+            number: None,
+            number_inferred: false,
+            video: false,
+            description: None,
+            keywords: None,
+            shownotes: None,
+            download_path: dir.to_path_buf(),
+            tracker_path: dir.join(".downloaded"),
+            file_name: file_name.to_string(),
+        }
+    }
+
+    #[test]
+    fn file_path_matches_whole_name() {
+        let dir = paths::test_dir("index_whole_name");
+        let first = indexed(&dir, "1", "Episode 1");
+        let tenth = indexed(&dir, "10", "Episode 10");
+
+        fs::write(dir.join("Episode 1.mp3"), "").unwrap();
+        assert_eq!(tenth.file_path(), None);
+
+        fs::write(dir.join("Episode 10.m4a"), "").unwrap();
+        fs::write(dir.join("Episode 10.partial"), "").unwrap();
+        assert_eq!(first.file_path(), Some(dir.join("Episode 1.mp3")));
+        assert_eq!(tenth.file_path(), Some(dir.join("Episode 10.m4a")));
+    }
+
+    #[test]
+    fn file_path_prefers_recorded_path() {
+        let dir = paths::test_dir("index_recorded");
+        let episode = indexed(&dir, "1", "Episode 1");
+        fs::write(dir.join("Episode 1.mp3"), "").unwrap();
+        fs::write(dir.join("renamed.mp3"), "").unwrap();
+
+        let fingerprint = Fingerprint {
+            path: dir.join("renamed.mp3"),
+            ..Default::default()
+        };
+        Fingerprints::insert(&episode.tracker_path, "1", fingerprint).unwrap();
+        assert_eq!(episode.file_path(), Some(dir.join("renamed.mp3")));
+
+        fs::remove_file(dir.join("renamed.mp3")).unwrap();
+        assert_eq!(episode.file_path(), Some(dir.join("Episode 1.mp3")));
+    }
+}
@@ -0,0 +1,319 @@
+//! Feeds in the JSON Feed format (https://www.jsonfeed.org), read into the same values as
+//! rss feeds.
+//!
+//! Only version 1 is understood. Fields are renamed to what they're called in rss, so the rest
+//! of TaleCast doesn't need to know where a feed came from: the attachments of an item
+//! become its enclosures, `date_published` its `pubDate`, and so on.
+
+use crate::episode::RawEpisode;
+use crate::podcast::RawPodcast;
+use crate::text;
+use serde_json::Map;
+use serde_json::Value;
+
+const VERSION_PREFIX: &str = "https://jsonfeed.org/version/1";
+
+/// How long a title made up from the text of an item without one may be.
+const MADE_UP_TITLE_WIDTH: usize = 80;
+
+/// Whether the feed is json rather than xml.
+pub fn is_json(text: &str) -> bool {
+    without_bom(text).trim_start().starts_with(['{', '['])
+}
+
+/// Converts a JSON Feed to serde values of the channel and the episodes, failing if the json
+/// is something else.
+pub fn parse(text: &str) -> Result<(RawPodcast, Vec<RawEpisode>), String> {
+    let not_a_feed = || "not a feed: the server sent json that isn't a JSON Feed".to_string();
+
+    let feed: Value = serde_json::from_str(without_bom(text)).map_err(|_| not_a_feed())?;
+    let is_feed = feed
+        .get("version")
+        .and_then(Value::as_str)
+        .is_some_and(|version| version.starts_with(VERSION_PREFIX));
+    if !is_feed {
+        return Err(not_a_feed());
+    }
+
+    let title = feed
+        .get("title")
+        .and_then(Value::as_str)
+        .ok_or("the JSON Feed has no title")?;
+
+    let mut channel = Map::new();
+    channel.insert("title".to_string(), title.into());
+    copy(&feed, "description", &mut channel, "description");
+    copy(&feed, "home_page_url", &mut channel, "link");
+    copy(&feed, "language", &mut channel, "language");
+    // The favicon is too small to be used as artwork.
+    copy(&feed, "icon", &mut channel, "image");
+    if let Some(author) = author(&feed) {
+        channel.insert("itunes:author".to_string(), author.into());
+    }
+
+    let episodes = feed
+        .get("items")
+        .and_then(Value::as_array)
+        .map(|items| items.iter().filter_map(episode).collect())
+        .unwrap_or_default();
+
+    Ok((RawPodcast::new(channel), episodes))
+}
+
+/// An item as an rss item, with its attachments as enclosures to choose from.
+fn episode(item: &Value) -> Option<RawEpisode> {
+    let attachments = item.get("attachments").and_then(Value::as_array);
+    let enclosures: Vec<Value> = attachments
+        .into_iter()
+        .flatten()
+        .filter_map(|attachment| {
+            let mut enclosure = Map::new();
+            enclosure.insert("@url".to_string(), attachment.get("url")?.clone());
+            copy(attachment, "mime_type", &mut enclosure, "@type");
+            copy(attachment, "size_in_bytes", &mut enclosure, "@length");
+            Some(Value::Object(enclosure))
+        })
+        .collect();
+
+    let mut episode = Map::new();
+    if !enclosures.is_empty() {
+        episode.insert("enclosure".to_string(), Value::Array(enclosures));
+    }
+    episode.insert("guid".to_string(), string(item.get("id")?)?.into());
+
+    let summary = item
+        .get("summary")
+        .or(item.get("content_text"))
+        .and_then(string);
+    let title = item
+        .get("title")
+        .and_then(string)
+        .filter(|title| !title.trim().is_empty())
+        .or_else(|| {
+            let line = summary.as_deref()?.lines().next()?.trim();
+            Some(text::truncate_string(line, MADE_UP_TITLE_WIDTH, true))
+        })
+        .filter(|title| !title.is_empty())
+        .or_else(|| string(item.get("id")?))?;
+    episode.insert("title".to_string(), title.into());
+
+    if let Some(summary) = summary {
+        episode.insert("description".to_string(), summary.into());
+    }
+    copy(item, "date_published", &mut episode, "pubDate");
+    copy(item, "url", &mut episode, "link");
+    copy(item, "content_html", &mut episode, "content:encoded");
+    copy(item, "image", &mut episode, "itunes:image");
+    // Attachments are the same episode in different formats, so they share a duration.
+    let duration = attachments
+        .into_iter()
+        .flatten()
+        .find(|attachment| attachment.get("duration_in_seconds").is_some());
+    if let Some(attachment) = duration {
+        copy(
+            attachment,
+            "duration_in_seconds",
+            &mut episode,
+            "itunes:duration",
+        );
+    }
+    if let Some(author) = author(item) {
+        episode.insert("author".to_string(), author.into());
+    }
+
+    let tags: Vec<String> = item
+        .get("tags")
+        .and_then(Value::as_array)
+        .map(|tags| tags.iter().filter_map(string).collect())
+        .unwrap_or_default();
+    if !tags.is_empty() {
+        episode.insert("itunes:keywords".to_string(), tags.join(", ").into());
+    }
+
+    Some(RawEpisode::new(episode))
+}
+
+/// The names of the authors, from `authors` in version 1.1 or `author` in 1.0.
+fn author(object: &Value) -> Option<String> {
+    let names: Vec<&str> = match (object.get("authors"), object.get("author")) {
+        (Some(Value::Array(authors)), _) => authors
+            .iter()
+            .filter_map(|author| author.get("name")?.as_str())
+            .collect(),
+        (_, Some(author)) => author
+            .get("name")
+            .and_then(Value::as_str)
+            .into_iter()
+            .collect(),
+        _ => vec![],
+    };
+
+    (!names.is_empty()).then(|| names.join(", "))
+}
+
+/// Copies a field under the name rss has for it, as text like the values of an rss feed.
+fn copy(from: &Value, key: &str, to: &mut Map<String, Value>, rss_key: &str) {
+    if let Some(value) = from.get(key).and_then(string) {
+        to.insert(rss_key.to_string(), value.into());
+    }
+}
+
+/// A string or number as text.
+fn string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn without_bom(text: &str) -> &str {
+    text.strip_prefix('\u{feff}').unwrap_or(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::episode::Attributes;
+    use crate::episode::XmlWrapper;
+    use crate::media::MediaPreference;
+
+    const FEED: &str = r#"{
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": "Talking Tests",
+        "home_page_url": "https://example.com",
+        "description": "A podcast about tests",
+        "language": "en",
+        "icon": "https://example.com/art.jpg",
+        "authors": [{ "name": "Ann" }, { "name": "Bob" }],
+        "items": [
+            {
+                "id": 2,
+                "title": "Second",
+                "summary": "The second one",
+                "date_published": "2024-09-09T06:00:00Z",
+                "tags": ["rust", "tests"],
+                "attachments": [
+                    {
+                        "url": "https://example.com/2.mp4",
+                        "mime_type": "video/mp4",
+                        "size_in_bytes": 9000
+                    },
+                    {
+                        "url": "https://example.com/2.mp3",
+                        "mime_type": "audio/mpeg",
+                        "size_in_bytes": 3000,
+                        "duration_in_seconds": 1800
+                    }
+                ]
+            },
+            {
+                "id": "first",
+                "content_text": "  Untitled first episode\nwith more text",
+                "date_published": "2024-09-02T06:00:00Z",
+                "author": { "name": "Guest" },
+                "attachments": [{ "url": "https://example.com/1.mp3" }]
+            },
+            {
+                "id": "post",
+                "title": "A blog post",
+                "date_published": "2024-09-03T06:00:00Z"
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn told_apart_by_content() {
+        assert!(is_json(FEED));
+        assert!(is_json("\u{feff}  {}"));
+        assert!(is_json("[]"));
+        assert!(!is_json("<?xml version=\"1.0\"?><rss></rss>"));
+        assert!(!is_json("\u{feff}<rss></rss>"));
+    }
+
+    #[test]
+    fn json_that_isnt_a_feed() {
+        let not_a_feed = "not a feed: the server sent json that isn't a JSON Feed".to_string();
+        for text in [
+            r#"{"error": "unauthorized"}"#,
+            r#"{"version": "https://example.com/v1", "title": "x"}"#,
+            "{ broken",
+        ] {
+            assert_eq!(parse(text).err(), Some(not_a_feed.clone()), "{}", text);
+        }
+
+        let untitled = r#"{"version": "https://jsonfeed.org/version/1"}"#;
+        assert_eq!(
+            parse(untitled).err().as_deref(),
+            Some("the JSON Feed has no title")
+        );
+    }
+
+    #[test]
+    fn channel_fields() {
+        let (podcast, _) = parse(FEED).unwrap();
+
+        assert_eq!(podcast.title(), Some("Talking Tests"));
+        assert_eq!(podcast.author(), Some("Ann, Bob"));
+        assert_eq!(podcast.language(), Some("en"));
+        assert_eq!(podcast.description(), Some("A podcast about tests"));
+        assert_eq!(podcast.get_str("link"), Some("https://example.com"));
+        assert_eq!(
+            podcast.get_str("image"),
+            Some("https://example.com/art.jpg")
+        );
+    }
+
+    #[test]
+    fn items_as_rss_items() {
+        let (_, episodes) = parse(FEED).unwrap();
+        assert_eq!(episodes.len(), 3);
+
+        let second = &episodes[0];
+        assert_eq!(second.get_str("guid"), Ok("2"));
+        assert_eq!(second.get_str("title"), Ok("Second"));
+        assert_eq!(second.get_str("description"), Ok("The second one"));
+        assert_eq!(second.get_str("pubDate"), Ok("2024-09-09T06:00:00Z"));
+        assert_eq!(second.get_str("itunes:duration"), Ok("1800"));
+        assert_eq!(second.get_str("itunes:keywords"), Ok("rust, tests"));
+
+        let first = &episodes[1];
+        assert_eq!(first.get_str("title"), Ok("Untitled first episode"));
+        assert_eq!(first.get_str("author"), Ok("Guest"));
+        assert!(first.get_str("itunes:duration").is_err());
+    }
+
+    #[test]
+    fn attachments_as_enclosures() {
+        let (_, episodes) = parse(FEED).unwrap();
+        let media = MediaPreference::default();
+        let mut episodes = episodes.into_iter();
+
+        let second = Attributes::new(episodes.next().unwrap(), &media).unwrap();
+        assert_eq!(second.url(), "https://example.com/2.mp3");
+        assert_eq!(second.mime(), Some("audio/mpeg"));
+        assert_eq!(second.length(), Some(3000));
+        assert_eq!(second.guid(), "2");
+
+        let first = Attributes::new(episodes.next().unwrap(), &media).unwrap();
+        assert_eq!(first.url(), "https://example.com/1.mp3");
+        assert_eq!(first.mime(), Some("audio/mpeg"));
+
+        // An item without attachments has nothing to download.
+        let post = Attributes::new(episodes.next().unwrap(), &media);
+        assert_eq!(post.err().as_deref(), Some(crate::episode::NO_ENCLOSURE));
+    }
+
+    #[test]
+    fn item_without_title_or_text() {
+        let feed = r#"{
+            "version": "https://jsonfeed.org/version/1",
+            "title": "x",
+            "items": [{ "id": "only-id", "title": " " }, { "title": "no id" }]
+        }"#;
+        let (_, episodes) = parse(feed).unwrap();
+
+        assert_eq!(episodes.len(), 1);
+        assert_eq!(episodes[0].get_str("title"), Ok("only-id"));
+    }
+}
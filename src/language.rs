@@ -0,0 +1,197 @@
+//! Language codes for the TLAN frame, which players expect to hold ISO 639-2/T codes.
+//!
+//! Feeds are meant to give a language tag like `en-us` in `<language>`, but names like
+//! "English" and oddities like `EN_us` are common too. Only the language is kept, so
+//! `pt-BR` becomes `por`.
+
+/// The two-letter code, ISO 639-2/T code and names of a language.
+///
+/// The names are the English one and, where it's different, the language's own.
+const LANGUAGES: [(&str, &str, &[&str]); 56] = [
+    ("af", "afr", &["afrikaans"]),
+    ("ar", "ara", &["arabic", "العربية"]),
+    ("bg", "bul", &["bulgarian", "български"]),
+    ("bn", "ben", &["bengali", "bangla", "বাংলা"]),
+    ("ca", "cat", &["catalan", "català"]),
+    ("cs", "ces", &["czech", "čeština"]),
+    ("cy", "cym", &["welsh", "cymraeg"]),
+    ("da", "dan", &["danish", "dansk"]),
+    ("de", "deu", &["german", "deutsch"]),
+    ("el", "ell", &["greek", "ελληνικά"]),
+    ("en", "eng", &["english"]),
+    ("eo", "epo", &["esperanto"]),
+    ("es", "spa", &["spanish", "español", "castellano"]),
+    ("et", "est", &["estonian", "eesti"]),
+    ("eu", "eus", &["basque", "euskara"]),
+    ("fa", "fas", &["persian", "farsi", "فارسی"]),
+    ("fi", "fin", &["finnish", "suomi"]),
+    ("fil", "fil", &["filipino"]),
+    ("fr", "fra", &["french", "français"]),
+    ("ga", "gle", &["irish", "gaeilge"]),
+    ("gl", "glg", &["galician", "galego"]),
+    ("he", "heb", &["hebrew", "עברית"]),
+    ("hi", "hin", &["hindi", "हिन्दी"]),
+    ("hr", "hrv", &["croatian", "hrvatski"]),
+    ("hu", "hun", &["hungarian", "magyar"]),
+    ("hy", "hye", &["armenian", "հայերեն"]),
+    ("id", "ind", &["indonesian", "bahasa indonesia"]),
+    ("is", "isl", &["icelandic", "íslenska"]),
+    ("it", "ita", &["italian", "italiano"]),
+    ("ja", "jpn", &["japanese", "日本語"]),
+    ("ka", "kat", &["georgian", "ქართული"]),
+    ("ko", "kor", &["korean", "한국어"]),
+    ("lt", "lit", &["lithuanian", "lietuvių"]),
+    ("lv", "lav", &["latvian", "latviešu"]),
+    ("mk", "mkd", &["macedonian", "македонски"]),
+    ("ms", "msa", &["malay", "bahasa melayu"]),
+    ("nl", "nld", &["dutch", "nederlands", "flemish", "vlaams"]),
+    ("no", "nor", &["norwegian", "norsk"]),
+    ("pl", "pol", &["polish", "polski"]),
+    ("pt", "por", &["portuguese", "português"]),
+    ("ro", "ron", &["romanian", "română"]),
+    ("ru", "rus", &["russian", "русский"]),
+    ("sk", "slk", &["slovak", "slovenčina"]),
+    ("sl", "slv", &["slovenian", "slovene", "slovenščina"]),
+    ("sq", "sqi", &["albanian", "shqip"]),
+    ("sr", "srp", &["serbian", "српски", "srpski"]),
+    ("sv", "swe", &["swedish", "svenska"]),
+    ("sw", "swa", &["swahili", "kiswahili"]),
+    ("ta", "tam", &["tamil", "தமிழ்"]),
+    ("th", "tha", &["thai", "ไทย"]),
+    ("tl", "tgl", &["tagalog"]),
+    ("tr", "tur", &["turkish", "türkçe"]),
+    ("uk", "ukr", &["ukrainian", "українська"]),
+    ("ur", "urd", &["urdu", "اردو"]),
+    ("vi", "vie", &["vietnamese", "tiếng việt"]),
+    ("zh", "zho", &["chinese", "mandarin", "cantonese", "中文"]),
+];
+
+/// Codes for a language in `LANGUAGES` under another code: the ISO 639-2/B codes that
+/// differ from the /T ones, and the two written standards of Norwegian.
+const ALIASES: [(&str, &str); 21] = [
+    ("alb", "sqi"),
+    ("arm", "hye"),
+    ("baq", "eus"),
+    ("chi", "zho"),
+    ("cze", "ces"),
+    ("dut", "nld"),
+    ("fre", "fra"),
+    ("geo", "kat"),
+    ("ger", "deu"),
+    ("gre", "ell"),
+    ("ice", "isl"),
+    ("mac", "mkd"),
+    ("may", "msa"),
+    ("nb", "nor"),
+    ("nn", "nor"),
+    ("nno", "nor"),
+    ("nob", "nor"),
+    ("per", "fas"),
+    ("rum", "ron"),
+    ("slo", "slk"),
+    ("wel", "cym"),
+];
+
+/// The ISO 639-2/T code of a language given as a language tag, code or name, if it's one
+/// that's known.
+pub fn iso_639_2(language: &str) -> Option<&'static str> {
+    let language = language.trim().to_lowercase();
+    if language.is_empty() {
+        return None;
+    }
+
+    if let Some(code) = by_name(&language) {
+        return Some(code);
+    }
+
+    // A tag like `pt-BR` or `EN_us`, or a name with a region like "English (US)".
+    let primary = language
+        .split(|c: char| !c.is_alphabetic())
+        .find(|part| !part.is_empty())?;
+
+    by_code(primary).or_else(|| by_name(primary))
+}
+
+fn by_code(code: &str) -> Option<&'static str> {
+    LANGUAGES
+        .iter()
+        .find(|(two, three, _)| *two == code || *three == code)
+        .map(|(_, three, _)| *three)
+        .or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == code)
+                .map(|(_, three)| *three)
+        })
+}
+
+fn by_name(name: &str) -> Option<&'static str> {
+    LANGUAGES
+        .iter()
+        .find(|(_, _, names)| names.contains(&name))
+        .map(|(_, three, _)| *three)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn codes() {
+        assert_eq!(iso_639_2("en"), Some("eng"));
+        assert_eq!(iso_639_2("deu"), Some("deu"));
+        assert_eq!(iso_639_2("fil"), Some("fil"));
+        // ISO 639-2/B codes become the /T ones.
+        assert_eq!(iso_639_2("ger"), Some("deu"));
+        assert_eq!(iso_639_2("fre"), Some("fra"));
+        assert_eq!(iso_639_2("nb"), Some("nor"));
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(iso_639_2("en-us"), Some("eng"));
+        assert_eq!(iso_639_2("pt-BR"), Some("por"));
+        assert_eq!(iso_639_2(" EN_us "), Some("eng"));
+        assert_eq!(iso_639_2("zh-Hant-TW"), Some("zho"));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(iso_639_2("English"), Some("eng"));
+        assert_eq!(iso_639_2("English (US)"), Some("eng"));
+        assert_eq!(iso_639_2("Español"), Some("spa"));
+        assert_eq!(iso_639_2("日本語"), Some("jpn"));
+        assert_eq!(iso_639_2("bahasa indonesia"), Some("ind"));
+    }
+
+    #[test]
+    fn unknown_languages() {
+        for language in ["", "  ", "klingon", "xx-YY", "123", "-"] {
+            assert_eq!(iso_639_2(language), None, "{:?}", language);
+        }
+    }
+
+    #[test]
+    fn table() {
+        let mut codes = HashSet::new();
+        for (two, three, names) in LANGUAGES {
+            assert!(codes.insert(three), "{} is listed twice", three);
+            assert_eq!(iso_639_2(two), Some(three));
+            assert_eq!(iso_639_2(three), Some(three));
+            for name in names {
+                assert_eq!(name.to_lowercase(), *name);
+                assert_eq!(iso_639_2(name), Some(three), "{}", name);
+            }
+        }
+
+        for (alias, three) in ALIASES {
+            assert!(
+                codes.contains(three),
+                "{} is an alias of an unknown code",
+                alias
+            );
+            assert_eq!(iso_639_2(alias), Some(three));
+        }
+    }
+}
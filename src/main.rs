@@ -1,19 +1,66 @@
+use crate::cache::Cache;
+use crate::cache::CacheKind;
 use crate::config::GlobalConfig;
+use crate::config::Layout;
 use crate::config::PodcastConfigs;
+use crate::feed_delta::FeedDelta;
+use crate::summary::SyncSummary;
 use clap::Parser;
 use regex::Regex;
 use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
 
+mod backlog;
 mod cache;
+mod cadence;
+mod changelog;
+mod chaos;
+mod chapters;
 mod config;
+mod deadline;
 mod display;
 mod download_tracker;
+mod email;
 mod episode;
+mod estimate;
+mod explain;
+mod feed_delta;
+mod feed_hints;
+mod fingerprint;
+mod format;
+mod hook;
+mod index;
+mod json_feed;
+mod language;
+mod maintenance;
+mod media;
+mod mirror;
+mod mp3;
+mod mp4;
+mod naming;
+mod net;
+mod numbering;
+mod ogg;
 mod opml;
+mod orphans;
+mod paths;
 mod patterns;
 mod podcast;
+mod quota;
+mod read_only;
+mod reliability;
+mod schedule;
+mod snapshot;
+mod state;
+mod summary;
 mod tags;
+mod text;
+mod throttle;
+mod transcript;
+mod trash;
 mod utils;
+mod xml;
 
 pub const APPNAME: &'static str = "talecast";
 
@@ -22,7 +69,9 @@ pub const APPNAME: &'static str = "talecast";
     name = "TaleCast",
     version,
     about = "A simple CLI podcast manager.",
-    long_about = None
+    long_about = None,
+    group(clap::ArgGroup::new("move_target").multiple(true)),
+    group(clap::ArgGroup::new("since_target").multiple(true))
 )]
 struct Args {
     #[arg(
@@ -36,17 +85,137 @@ struct Args {
         short,
         long,
         value_name = "FILE",
-        help = "Export your podcasts to an OPML file"
+        num_args = 0..=1,
+        help = "Export your podcasts to an OPML file, or to stdout without one"
     )]
-    export: Option<PathBuf>,
+    export: Option<Option<PathBuf>>,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Restore the config and downloaded episodes from a state file"
+    )]
+    import_state: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "OLD=NEW",
+        num_args = 1..,
+        requires = "import_state",
+        help = "Replace a path of the old machine with one on this machine when importing state"
+    )]
+    map_root: Vec<String>,
+    #[arg(
+        long,
+        requires = "import_state",
+        help = "Replace the existing config and state when importing state"
+    )]
+    force: bool,
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Export the config and downloaded episodes to a state file"
+    )]
+    export_state: Option<PathBuf>,
+    #[arg(
+        long = "move",
+        value_name = "NAME",
+        requires = "move_target",
+        help = "Move a podcast and its episodes to another download root or layout"
+    )]
+    move_podcast: Option<String>,
+    #[arg(
+        long,
+        value_name = "ROOT",
+        requires = "move_podcast",
+        group = "move_target",
+        help = "The download root to move the podcast to"
+    )]
+    root: Option<String>,
+    #[arg(
+        long,
+        value_name = "LAYOUT",
+        requires = "move_podcast",
+        group = "move_target",
+        help = "The layout to move the podcast to"
+    )]
+    layout: Option<Layout>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        requires = "dest",
+        help = "Download every episode of a podcast to another directory, without tracking them"
+    )]
+    mirror: Option<String>,
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "mirror",
+        help = "The directory to mirror the podcast to"
+    )]
+    dest: Option<PathBuf>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        requires = "until",
+        help = "Skip a podcast when syncing until the given date"
+    )]
+    pause: Option<String>,
+    #[arg(
+        long,
+        value_name = "DATE",
+        requires = "pause",
+        help = "The date to pause the podcast until"
+    )]
+    until: Option<String>,
     #[arg(short, long, help = "Print the downloaded paths to stdout")]
     print: bool,
+    #[arg(
+        long,
+        conflicts_with = "print",
+        help = "Print a summary of the sync, or the list of podcasts, to stdout as JSON"
+    )]
+    json: bool,
+    #[arg(long, help = "Print how long each phase of the sync took")]
+    timings: bool,
+    #[arg(long, help = "Only sync the podcasts whose schedule says they're due")]
+    respect_schedules: bool,
+    #[arg(
+        long,
+        help = "Download every feed in full, even if the server says it hasn't changed"
+    )]
+    force_refresh: bool,
+    #[arg(
+        long,
+        help = "Exit before syncing if the download hook of a podcast can't be run"
+    )]
+    strict: bool,
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = deadline::parse_duration,
+        help = "Stop starting new downloads after the given time, e.g. 12m"
+    )]
+    max_runtime: Option<Duration>,
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = deadline::parse_duration,
+        requires = "max_runtime",
+        help = "How long running downloads may continue after the max runtime, e.g. 2m"
+    )]
+    grace: Option<Duration>,
     #[arg(
         short,
         long,
-        help = "Configure to skip episodes published prior to current time. Can be combined with filter, add, and import"
+        help = "Mark episodes published prior to current time as acknowledged, without downloading them. Can be combined with filter, add, import, and search"
     )]
     catch_up: bool,
+    #[arg(
+        long,
+        value_name = "DATE",
+        requires = "catch_up",
+        help = "Only catch up with episodes published before the given date"
+    )]
+    before: Option<String>,
     #[arg(short, long, num_args = 1..=2, value_names = &["URL", "NAME"], help = "Add new podcast")]
     add: Vec<String>,
     #[arg(
@@ -61,14 +230,127 @@ struct Args {
         help = "Override the path to the config file"
     )]
     config: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Don't write to the config, state or cache. Implied by the commands that only print things"
+    )]
+    read_only: bool,
+    #[arg(
+        long,
+        value_name = "SPEC",
+        help = "Randomly inject network failures, e.g. p=0.2. For testing error recovery"
+    )]
+    simulate_failures: Option<String>,
+    #[arg(
+        long,
+        requires = "simulate_failures",
+        help = "Seed for reproducing simulated failures"
+    )]
+    seed: Option<u64>,
     #[arg(long, help = "Edit the config.toml file")]
     edit_config: bool,
     #[arg(long, help = "Edit the podcasts.toml file")]
     edit_podcasts: bool,
     #[arg(short, long, value_name = "QUERY",  num_args = 1.., help = "Search for podcasts to add")]
     search: Option<Vec<String>>,
-    #[arg(long, help = "Print your podcasts to stdout")]
+    #[arg(long, value_name = "QUERY", num_args = 1.., help = "Search through the cached feeds of your podcasts")]
+    find: Option<Vec<String>>,
+    #[arg(
+        long,
+        requires = "find",
+        help = "Treat the find query as a regex pattern"
+    )]
+    regex: bool,
+    #[arg(
+        long,
+        help = "Print your podcasts with how many episodes the next sync would download"
+    )]
     list: bool,
+    #[arg(
+        long,
+        num_args = 1..=2,
+        value_names = &["NAME", "KEY"],
+        help = "Print the settings of a podcast and which file and line set them, or only one setting"
+    )]
+    explain: Vec<String>,
+    #[arg(long, help = "Print the size of the cache")]
+    cache_stats: bool,
+    #[arg(
+        long,
+        value_name = "KIND",
+        num_args = 0..=1,
+        help = "Clear the cache, or only one kind of it"
+    )]
+    cache_clear: Option<Option<CacheKind>>,
+    #[arg(
+        long,
+        help = "Check that the feeds of your podcasts can be downloaded and parsed"
+    )]
+    validate: bool,
+    #[arg(long, help = "Update the urls of podcasts whose feeds have moved")]
+    refresh_urls: bool,
+    #[arg(
+        long,
+        help = "Give renamed podcasts back the downloads tracked under their old name"
+    )]
+    fix_orphans: bool,
+    #[arg(
+        long,
+        group = "since_target",
+        help = "Apply the current tag settings to episodes that are already downloaded"
+    )]
+    retag: bool,
+    #[arg(
+        long,
+        value_name = "NAME",
+        group = "since_target",
+        requires = "since",
+        help = "Download the episodes of a podcast published since a date, that catching up or earliest_date skipped"
+    )]
+    un_acknowledge: Option<String>,
+    #[arg(
+        long,
+        value_name = "DATE",
+        requires = "since_target",
+        help = "Only retag episodes downloaded since the given date, or un-acknowledge episodes published since it"
+    )]
+    since: Option<String>,
+    #[arg(
+        long,
+        help = "Show what a sync would download, or what retag or set would change, without doing it"
+    )]
+    dry_run: bool,
+    #[arg(
+        long,
+        value_name = "KEY=VALUE",
+        num_args = 1..,
+        help = "Change settings of every podcast in podcasts.toml. Can be combined with filter"
+    )]
+    set: Option<Vec<String>>,
+    #[arg(
+        long,
+        help = "Show how far along the podcasts in backlog mode are. Can be combined with filter"
+    )]
+    backlog_status: bool,
+    #[arg(
+        long,
+        help = "Print the archived snapshots of the feeds. Can be combined with filter"
+    )]
+    snapshots: bool,
+    #[arg(
+        long,
+        value_name = "DATE",
+        help = "Read the feeds from the newest snapshot archived by the given time instead of downloading them"
+    )]
+    from_snapshot: Option<String>,
+    #[arg(long, help = "Print the files in the trash")]
+    trash_list: bool,
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Move a file in the trash back to where it was deleted from"
+    )]
+    trash_restore: Option<PathBuf>,
 }
 
 impl From<Args> for Action {
@@ -79,10 +361,84 @@ impl From<Args> for Action {
         });
 
         let print = args.print;
+        let json = args.json;
+        let timings = args.timings;
         let catch_up = args.catch_up;
+        let before = args
+            .before
+            .as_deref()
+            .map(|date| parse_date("--before", date));
 
         if args.list {
-            return Self::List { filter };
+            return Self::List { filter, json };
+        }
+
+        if !args.explain.is_empty() {
+            let mut explain = args.explain.into_iter();
+            return Self::Explain {
+                name: explain.next().unwrap(),
+                key: explain.next(),
+                config: args.config,
+            };
+        }
+
+        if args.cache_stats {
+            return Self::CacheStats;
+        }
+
+        if let Some(kind) = args.cache_clear {
+            return Self::CacheClear { kind };
+        }
+
+        if args.trash_list {
+            return Self::TrashList;
+        }
+
+        if let Some(path) = args.trash_restore {
+            return Self::TrashRestore { path };
+        }
+
+        if args.validate {
+            return Self::Validate { filter };
+        }
+
+        if args.refresh_urls {
+            return Self::RefreshUrls { filter };
+        }
+
+        if args.fix_orphans {
+            return Self::FixOrphans;
+        }
+
+        if args.backlog_status {
+            return Self::BacklogStatus { filter };
+        }
+
+        if args.snapshots {
+            return Self::Snapshots { filter };
+        }
+
+        if let Some(name) = args.un_acknowledge {
+            return Self::UnAcknowledge {
+                name,
+                since: args.since.unwrap_or_default(),
+            };
+        }
+
+        if args.retag {
+            return Self::Retag {
+                filter,
+                since: args.since,
+                dry_run: args.dry_run,
+            };
+        }
+
+        if let Some(settings) = args.set {
+            return Self::Set {
+                settings,
+                filter,
+                dry_run: args.dry_run,
+            };
         }
 
         if args.edit_config {
@@ -97,17 +453,62 @@ impl From<Args> for Action {
 
         if let Some(query) = args.search {
             let query = query.join(" ");
-            return Self::Search { query, catch_up };
+            return Self::Search {
+                query,
+                catch_up,
+                before,
+            };
+        }
+
+        if let Some(query) = args.find {
+            let query = query.join(" ");
+            return Self::Find {
+                query,
+                regex: args.regex,
+                filter,
+            };
         }
 
         if let Some(path) = args.import {
-            return Self::Import { path, catch_up };
+            return Self::Import {
+                path,
+                catch_up,
+                before,
+            };
         }
 
         if let Some(path) = args.export {
             return Self::Export { path, filter };
         }
 
+        if let Some(name) = args.move_podcast {
+            return Self::Move {
+                name,
+                root: args.root,
+                layout: args.layout,
+            };
+        }
+
+        if let (Some(name), Some(dest)) = (args.mirror, args.dest) {
+            return Self::Mirror { name, dest };
+        }
+
+        if let (Some(name), Some(until)) = (args.pause, args.until) {
+            return Self::Pause { name, until };
+        }
+
+        if let Some(path) = args.import_state {
+            return Self::ImportState {
+                path,
+                map_root: args.map_root,
+                force: args.force,
+            };
+        }
+
+        if let Some(path) = args.export_state {
+            return Self::ExportState { path, filter };
+        }
+
         if !args.add.is_empty() {
             let url = args.add[0].to_string();
             let name = args.add.get(1).cloned();
@@ -116,23 +517,125 @@ impl From<Args> for Action {
                 url,
                 name,
                 catch_up,
+                before,
             };
         }
 
         if catch_up {
-            return Self::CatchUp { filter };
+            return Self::CatchUp { filter, before };
         }
 
-        Self::Sync { filter, print }
+        Self::Sync {
+            filter,
+            print,
+            json,
+            timings,
+            respect_schedules: args.respect_schedules,
+            force_refresh: args.force_refresh,
+            strict: args.strict,
+            dry_run: args.dry_run,
+            max_runtime: args.max_runtime,
+            grace: args.grace.unwrap_or_default(),
+        }
+    }
+}
+
+impl Action {
+    /// Whether the action only prints things, which makes the run read-only.
+    fn is_informational(&self) -> bool {
+        matches!(
+            self,
+            Self::List { .. }
+                | Self::Explain { .. }
+                | Self::CacheStats
+                | Self::TrashList
+                | Self::Validate { .. }
+                | Self::BacklogStatus { .. }
+                | Self::Snapshots { .. }
+                | Self::Find { .. }
+        )
+    }
+
+    /// Whether the action uses the download paths of the podcasts, which have to be valid.
+    ///
+    /// Actions that only change podcasts.toml, like `--set`, still run, so that a path can
+    /// be fixed with them.
+    fn uses_podcast_paths(&self) -> bool {
+        !matches!(
+            self,
+            Self::CacheStats
+                | Self::CacheClear { .. }
+                | Self::Import { .. }
+                | Self::Set { .. }
+                | Self::Edit { .. }
+                | Self::Explain { .. }
+                | Self::Search { .. }
+                | Self::Export { .. }
+                | Self::Pause { .. }
+                | Self::Add { .. }
+                | Self::TrashList
+                | Self::TrashRestore { .. }
+        )
+    }
+
+    /// Whether the action can run with `--read-only`.
+    fn can_be_read_only(&self) -> bool {
+        self.is_informational()
+            || matches!(
+                self,
+                Self::Sync { dry_run: true, .. } | Self::Export { path: None, .. }
+            )
     }
 }
 
 enum Action {
     List {
         filter: Option<Regex>,
+        json: bool,
+    },
+    Explain {
+        name: String,
+        key: Option<String>,
+        config: Option<PathBuf>,
     },
     CatchUp {
         filter: Option<Regex>,
+        before: Option<utils::Unix>,
+    },
+    CacheStats,
+    CacheClear {
+        kind: Option<CacheKind>,
+    },
+    TrashList,
+    TrashRestore {
+        path: PathBuf,
+    },
+    Validate {
+        filter: Option<Regex>,
+    },
+    RefreshUrls {
+        filter: Option<Regex>,
+    },
+    FixOrphans,
+    BacklogStatus {
+        filter: Option<Regex>,
+    },
+    Snapshots {
+        filter: Option<Regex>,
+    },
+    Retag {
+        filter: Option<Regex>,
+        since: Option<String>,
+        dry_run: bool,
+    },
+    UnAcknowledge {
+        name: String,
+        since: String,
+    },
+    Set {
+        settings: Vec<String>,
+        filter: Option<Regex>,
+        dry_run: bool,
     },
     Edit {
         path: PathBuf,
@@ -140,8 +643,31 @@ enum Action {
     Import {
         path: PathBuf,
         catch_up: bool,
+        before: Option<utils::Unix>,
     },
     Export {
+        path: Option<PathBuf>,
+        filter: Option<Regex>,
+    },
+    Move {
+        name: String,
+        root: Option<String>,
+        layout: Option<Layout>,
+    },
+    Mirror {
+        name: String,
+        dest: PathBuf,
+    },
+    Pause {
+        name: String,
+        until: String,
+    },
+    ImportState {
+        path: PathBuf,
+        map_root: Vec<String>,
+        force: bool,
+    },
+    ExportState {
         path: PathBuf,
         filter: Option<Regex>,
     },
@@ -149,14 +675,29 @@ enum Action {
         url: String,
         name: Option<String>,
         catch_up: bool,
+        before: Option<utils::Unix>,
     },
     Search {
         query: String,
         catch_up: bool,
+        before: Option<utils::Unix>,
+    },
+    Find {
+        query: String,
+        regex: bool,
+        filter: Option<Regex>,
     },
     Sync {
         filter: Option<Regex>,
         print: bool,
+        json: bool,
+        timings: bool,
+        respect_schedules: bool,
+        force_refresh: bool,
+        strict: bool,
+        dry_run: bool,
+        max_runtime: Option<Duration>,
+        grace: Duration,
     },
 }
 
@@ -176,7 +717,7 @@ fn setup_logging(config: &config::LogConfig) -> Result<PathBuf, fern::InitError>
         .level(config.level());
 
     let log_dir = PathBuf::from("/tmp/talecast");
-    utils::create_dir(&log_dir);
+    paths::create_dir(&log_dir);
     let log_path = log_dir.join(chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string());
 
     let file_config = base_config.chain(fern::log_file(&log_path)?);
@@ -185,40 +726,174 @@ fn setup_logging(config: &config::LogConfig) -> Result<PathBuf, fern::InitError>
     Ok(log_path)
 }
 
+fn parse_date(arg: &str, date: &str) -> utils::Unix {
+    match utils::day_str_to_unix(date) {
+        Ok(unix) => unix,
+        Err(e) => {
+            eprintln!("invalid date for {}: {}", arg, e);
+            std::process::exit(1);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    let config_path = args.config.clone();
+    let simulate_failures = args.simulate_failures.clone();
+    let seed = args.seed;
+    let read_only = args.read_only;
+    let from_snapshot = args.from_snapshot.clone();
+
+    let action = Action::from(args);
+
+    if read_only && !action.can_be_read_only() {
+        eprintln!("--read-only only works with commands that print things, and --dry-run");
+        std::process::exit(1);
+    }
+    if read_only || action.is_informational() {
+        read_only::init();
+    }
+    if let Some(date) = from_snapshot.as_deref() {
+        snapshot::init(parse_date("--from-snapshot", date));
+    }
 
-    let global_config = match args.config.as_ref() {
+    let global_config = match config_path.as_ref() {
         Some(path) => GlobalConfig::load_from_path(path),
         None => GlobalConfig::load(),
     };
 
-    let log_path = setup_logging(&global_config.log()).unwrap();
+    // The log directory may belong to another user, and a read-only run doesn't need a log.
+    let log_path = match read_only::is_enabled() {
+        true => None,
+        false => Some(setup_logging(&global_config.log()).unwrap()),
+    };
+    format::init(global_config.size_units());
 
-    match Action::from(args) {
-        Action::Import { path, catch_up } => opml::import(&path, catch_up),
+    if let Some(spec) = simulate_failures.as_deref() {
+        if let Err(e) = chaos::init(spec, seed) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        eprintln!("simulating network failures, see the log file for details");
+    }
+
+    // Clearing the cache needs an exclusive lock instead, and a read-only run takes none.
+    let cache_lock = match action {
+        Action::CacheClear { .. } => None,
+        _ if read_only::is_enabled() => None,
+        _ => Cache::lock_shared(),
+    };
+
+    if action.uses_podcast_paths() {
+        PodcastConfigs::check_paths(&global_config);
+    }
+
+    match action {
+        Action::CacheStats => Cache::print_stats(),
+
+        Action::CacheClear { kind } => Cache::clear(kind),
+
+        Action::Import {
+            path,
+            catch_up,
+            before,
+        } => {
+            let added = opml::import(&path);
+            if catch_up && !added.is_empty() {
+                let filter = PodcastConfigs::name_filter(&added);
+                maintenance::catch_up(Some(filter), before, global_config).await;
+            }
+        }
+
+        Action::Set {
+            settings,
+            filter,
+            dry_run,
+        } => config::PodcastConfigs::set(&settings, filter, dry_run),
 
         Action::Edit { path } => utils::edit_file(&path),
 
-        Action::CatchUp { filter } => config::PodcastConfigs::catch_up(filter),
+        Action::CatchUp { filter, before } => {
+            maintenance::catch_up(filter, before, global_config).await
+        }
+
+        Action::List { filter, json } => maintenance::list(filter, json, global_config).await,
+
+        Action::Explain { name, key, config } => {
+            explain::explain(&name, key.as_deref(), config.as_deref())
+        }
+
+        Action::TrashList => trash::list(&global_config),
+
+        Action::TrashRestore { path } => trash::restore(&path, &global_config),
+
+        Action::Validate { filter } => maintenance::validate(filter, &global_config).await,
+
+        Action::RefreshUrls { filter } => maintenance::refresh_urls(filter, &global_config).await,
 
-        Action::List { filter } => {
-            for (name, _) in config::PodcastConfigs::load().filter(filter) {
-                println!("{}", name);
+        Action::FixOrphans => orphans::fix(&global_config),
+
+        Action::BacklogStatus { filter } => backlog::status(filter, &global_config).await,
+
+        Action::Snapshots { filter } => snapshot::list(filter, &global_config),
+
+        Action::Retag {
+            filter,
+            since,
+            dry_run,
+        } => {
+            let since = since.as_deref().map(|since| parse_date("--since", since));
+            maintenance::retag(filter, since, dry_run, global_config).await
+        }
+
+        Action::UnAcknowledge { name, since } => config::PodcastConfigs::un_acknowledge(
+            &name,
+            parse_date("--since", &since),
+            &global_config,
+        ),
+
+        Action::Search {
+            query,
+            catch_up,
+            before,
+        } => {
+            let added = utils::search_podcasts(&global_config, query).await;
+            if catch_up && !added.is_empty() {
+                let filter = PodcastConfigs::name_filter(&added);
+                maintenance::catch_up(Some(filter), before, global_config).await;
             }
         }
 
-        Action::Search { query, catch_up } => {
-            utils::search_podcasts(&global_config, query, catch_up).await
+        Action::Find {
+            query,
+            regex,
+            filter,
+        } => index::find(query, regex, filter, &global_config).await,
+
+        Action::Export { path, filter } => opml::export(path.as_deref(), filter).await,
+
+        Action::Move { name, root, layout } => {
+            config::PodcastConfigs::move_podcast(&name, root, layout, &global_config)
         }
 
-        Action::Export { path, filter } => opml::export(&path, filter).await,
+        Action::Mirror { name, dest } => mirror::mirror(&name, &dest, &global_config).await,
+
+        Action::Pause { name, until } => config::PodcastConfigs::pause(&name, until),
+
+        Action::ImportState {
+            path,
+            map_root,
+            force,
+        } => state::import(&path, &map_root, force, &global_config),
+
+        Action::ExportState { path, filter } => state::export(&path, filter, &global_config),
 
         Action::Add {
             name,
             url,
             catch_up,
+            before,
         } => {
             let name = match name {
                 Some(name) => name,
@@ -233,30 +908,139 @@ async fn main() {
             if config::PodcastConfigs::push(name.clone(), podcast) {
                 eprintln!("'{}' added!", name);
                 if catch_up {
-                    // Matches only the added podcast.
-                    let filter = Regex::new(&format!("^{}$", &name)).unwrap();
-                    config::PodcastConfigs::catch_up(Some(filter));
+                    let filter = PodcastConfigs::name_filter(&[name]);
+                    maintenance::catch_up(Some(filter), before, global_config).await;
                 }
             } else {
                 eprintln!("'{}' already exists!", name);
             }
         }
 
-        Action::Sync { filter, print } => {
-            let paths = PodcastConfigs::load()
+        Action::Sync {
+            filter,
+            print,
+            json,
+            timings,
+            respect_schedules,
+            force_refresh,
+            strict,
+            dry_run,
+            max_runtime,
+            grace,
+        } => {
+            let start = Instant::now();
+            let global_config = match force_refresh {
+                true => global_config.bypass_feed_cache(),
+                false => global_config,
+            };
+            let mut podcasts = PodcastConfigs::load()
                 .assert_not_empty()
+                .check_collisions(&global_config)
                 .filter(filter)
-                .sync(global_config, &log_path)
-                .await;
+                .check_orphans(&global_config)
+                .check_hooks(&global_config, strict)
+                .check_download_modes(&global_config)
+                .skip_paused();
+            if respect_schedules {
+                podcasts = podcasts.skip_unscheduled(&global_config);
+            }
+            if dry_run {
+                maintenance::dry_run(podcasts, global_config).await;
+                return;
+            }
+
+            if let Some(max_runtime) = max_runtime {
+                deadline::init(max_runtime, grace);
+            }
+            trash::empty_expired(&global_config);
+            let cache_max_size = global_config.cache_max_size();
+            let changelog_path = global_config.changelog_path();
+            let email = global_config.email().clone();
+            let log_path = log_path.expect("a sync that downloads is never read-only");
+            let results = podcasts.sync(global_config, &log_path).await;
 
+            if let Some(path) = changelog_path {
+                changelog::append(&path, &results);
+            }
+            email::notify(&email, &results).await;
+
+            if let Some(max_size) = cache_max_size {
+                Cache::evict(max_size, cache_lock);
+            }
+
+            let downloaded: usize = results.iter().map(|res| res.paths.len()).sum();
             eprintln!("Syncing complete!");
-            eprintln!("{} episodes downloaded.", paths.len());
+            eprintln!("{} episodes downloaded.", downloaded);
+
+            let skipped_videos: usize = results.iter().map(|res| res.skipped_videos).sum();
+            if skipped_videos > 0 {
+                eprintln!(
+                    "{} video episodes skipped because of video_handling = \"skip\".",
+                    skipped_videos
+                );
+            }
+
+            let deferred: usize = results.iter().map(|res| res.deferred).sum();
+            if deferred > 0 {
+                eprintln!(
+                    "{} episodes deferred by the daily cap of max_per_day.",
+                    deferred
+                );
+            }
+
+            let mut upstream = FeedDelta::default();
+            for res in &results {
+                upstream.add(&res.upstream);
+                for title in &res.upstream.removed {
+                    eprintln!("{}: removed from the feed: {}", res.name, title);
+                }
+            }
+            if !upstream.is_empty() {
+                eprintln!("{} upstream.", upstream.describe());
+            }
+
+            let removed: usize = results.iter().map(|res| res.removed).sum();
+            if removed > 0 {
+                eprintln!("{} old episodes removed because of keep_latest.", removed);
+            }
+
+            let failed: usize = results.iter().map(|res| res.failed).sum();
+            let skipped: usize = results.iter().map(|res| res.skipped).sum();
+            if failed > 0 {
+                eprintln!(
+                    "{} episodes failed to download, {} skipped after a failure.",
+                    failed, skipped
+                );
+            }
+
+            let postponed: usize = results.iter().map(|res| res.postponed).sum();
+            let not_fetched = results.iter().filter(|res| res.not_fetched).count();
+            if postponed > 0 || not_fetched > 0 {
+                eprintln!(
+                    "max runtime reached: {} episodes and {} podcasts left for the next sync.",
+                    postponed, not_fetched
+                );
+            }
+
+            let summary = SyncSummary::new(&results, start.elapsed());
+
+            if timings {
+                summary.print_timings();
+            }
+
+            if json {
+                println!("{}", summary.to_json());
+            }
 
             if print {
-                for path in paths {
+                for path in results.iter().flat_map(|res| &res.paths) {
                     println!("{}", path.to_str().unwrap());
                 }
             }
+
+            if failed > 0 {
+                std::process::exit(1);
+            }
         }
     }
 }
@@ -0,0 +1,831 @@
+use crate::cadence::Cadence;
+use crate::config::ConfigStamp;
+use crate::config::GlobalConfig;
+use crate::config::PodcastConfig;
+use crate::config::PodcastConfigs;
+use crate::display::DownloadBar;
+use crate::download_tracker::DownloadedEpisodes;
+use crate::episode;
+use crate::episode::Episode;
+use crate::format;
+use crate::media::MediaPreference;
+use crate::mp3::DeferredTags;
+use crate::mp3::FileTags;
+use crate::net;
+use crate::paths;
+use crate::podcast;
+use crate::podcast::Podcast;
+use crate::reliability::LengthReliability;
+use crate::text;
+use crate::trash;
+use crate::utils;
+use crate::utils::Unix;
+use futures::future;
+use indicatif::MultiProgress;
+use regex::Regex;
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::future::Future;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Follows at most this many redirects when refreshing urls.
+const MAX_REDIRECTS: usize = 10;
+
+/// How many files are retagged at once, across all podcasts.
+const RETAG_PARALLELISM: usize = 4;
+
+/// How many files of each podcast to list the tag changes of with `--dry-run`.
+const DRY_RUN_SAMPLE: usize = 3;
+
+/// Runs a check on every podcast concurrently, with a progress bar for each like when syncing.
+///
+/// Returns the outcome of every podcast, sorted by name.
+async fn run_concurrently<T, F, Fut>(
+    podcasts: PodcastConfigs,
+    global_config: &GlobalConfig,
+    check: F,
+) -> Vec<(String, Result<T, String>)>
+where
+    T: Send + 'static,
+    F: Fn(String, Arc<reqwest::Client>, PodcastConfig, DownloadBar) -> Fut,
+    Fut: Future<Output = (DownloadBar, Result<T, String>)> + Send + 'static,
+{
+    let mp = MultiProgress::new();
+    let client = crate::config::init_reqwest_client(global_config);
+
+    let Some(longest_name) = podcasts.longest_name() else {
+        return vec![];
+    };
+
+    let futures = podcasts
+        .into_iter()
+        .map(|(name, config)| {
+            let ui = DownloadBar::new(name.clone(), global_config.style(), &mp, longest_name);
+            ui.fetching();
+            let check = check(name.clone(), Arc::clone(&client), config, ui);
+
+            tokio::task::spawn(async move {
+                let (mut ui, res) = check.await;
+                match &res {
+                    Ok(_) => ui.complete(),
+                    Err(e) => ui.error(e),
+                }
+                (name, res)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut results: Vec<(String, Result<T, String>)> = future::join_all(futures)
+        .await
+        .into_iter()
+        .filter_map(Result::ok)
+        .collect();
+
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+/// Marks the episodes of every podcast published before `before`, or now, as acknowledged
+/// without downloading them, so a sync only downloads the ones published after.
+///
+/// Podcasts in backlog mode are skipped, as they're meant to go through their old episodes.
+pub async fn catch_up(filter: Option<Regex>, before: Option<Unix>, global_config: GlobalConfig) {
+    let podcasts = PodcastConfigs::load()
+        .assert_not_empty()
+        .check_collisions(&global_config)
+        .filter(filter)
+        .skip_backlog();
+    let before = before.unwrap_or_else(utils::current_unix);
+    let global_config = Arc::new(global_config);
+
+    let results = run_concurrently(podcasts, &global_config, |name, client, config, ui| {
+        let global_config = Arc::clone(&global_config);
+        async move {
+            let podcast = Podcast::new(name, config, &global_config, client, &ui).await;
+            let res = podcast.and_then(|podcast| {
+                let mut marked = 0;
+                for episode in podcast.episodes() {
+                    if episode.attrs.published() < before && !episode.is_downloaded() {
+                        episode.mark_acknowledged()?;
+                        marked += 1;
+                    }
+                }
+                Ok(marked)
+            });
+            (ui, res)
+        }
+    })
+    .await;
+
+    eprintln!();
+    for (name, res) in &results {
+        match res {
+            Ok(marked) => eprintln!("caught up with {}: {} episodes acknowledged", name, marked),
+            Err(e) => eprintln!("{}: {}", name, e),
+        }
+    }
+}
+
+/// Checks that the feed of every podcast can be downloaded and parsed.
+pub async fn validate(filter: Option<Regex>, global_config: &GlobalConfig) {
+    let podcasts = PodcastConfigs::load().assert_not_empty().filter(filter);
+    eprintln!("validating {} podcasts", podcasts.len());
+
+    let results = run_concurrently(podcasts, global_config, |name, client, config, ui| {
+        let tracker_path = config.tracker_path(&name, global_config);
+        let reliability = LengthReliability::load(&tracker_path);
+        let cadence = Cadence::load(&tracker_path);
+        let headers = config.feed_headers(global_config);
+        let media = config.media_preference(global_config);
+        async move {
+            let res = match headers {
+                Ok(headers) => validate_feed(&client, &config.url, &headers, &media, &ui).await,
+                Err(e) => Err(e),
+            };
+            (ui, res.map(|counts| (counts, reliability, cadence)))
+        }
+    })
+    .await;
+
+    let mut failed = 0;
+    eprintln!();
+    for (name, res) in &results {
+        match res {
+            Ok(((valid, invalid), reliability, cadence)) => {
+                match invalid {
+                    0 => eprintln!("{}: ok, {} episodes", name, valid),
+                    _ => eprintln!(
+                        "{}: ok, {} episodes ({} could not be parsed)",
+                        name, valid, invalid
+                    ),
+                }
+                if let Some(lengths) = reliability.describe() {
+                    eprintln!("{}: {}", name, lengths);
+                }
+                if let Some(stalled) = cadence.as_ref().and_then(Cadence::describe_stalled) {
+                    eprintln!("{}: {}", name, stalled);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {}", name, e);
+            }
+        }
+    }
+
+    eprintln!(
+        "{} of {} feeds are valid",
+        results.len() - failed,
+        results.len()
+    );
+}
+
+/// Returns the number of episodes that could and couldn't be parsed.
+async fn validate_feed(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    media: &MediaPreference,
+    ui: &DownloadBar,
+) -> Result<(usize, usize), String> {
+    let xml = net::download_text(client, url, headers, true, ui)
+        .await
+        .map(net::Feed::into_xml)
+        .map_err(|e| format!("failed to download xml-file: {}", e))?;
+
+    let (_, raw_episodes) = podcast::parse_feed(&xml, ui)?;
+
+    let total = raw_episodes.len();
+    let valid = raw_episodes
+        .into_iter()
+        .filter(|raw| episode::Attributes::new(raw.clone(), media).is_ok())
+        .count();
+
+    Ok((valid, total - valid))
+}
+
+/// A podcast as it's printed by `--list`, or why its feed couldn't be read.
+#[derive(Debug, Serialize)]
+struct ListEntry {
+    name: String,
+    /// The download root the podcast is on, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    root: Option<String>,
+    episodes: Option<usize>,
+    downloaded: Option<usize>,
+    pending: Option<usize>,
+    /// The publication date of the newest episode.
+    newest: Option<String>,
+    error: Option<String>,
+}
+
+/// Prints every podcast with how many episodes its feed has, how many of them are
+/// downloaded and how many the next sync would download, along with the download root it's
+/// on and how much room is left on each root.
+///
+/// Feeds that fail are listed with the error, without failing the command.
+pub async fn list(filter: Option<Regex>, json: bool, global_config: GlobalConfig) {
+    let podcasts = PodcastConfigs::load()
+        .assert_not_empty()
+        .check_collisions(&global_config)
+        .filter(filter);
+    let mut roots: HashMap<String, String> = HashMap::new();
+    for (name, config) in &podcasts {
+        if let Some(root) = config.root(&global_config) {
+            roots.insert(name.clone(), root.to_string());
+        }
+    }
+    let global_config = Arc::new(global_config);
+
+    let results = run_concurrently(podcasts, &global_config, |name, client, config, ui| {
+        let global_config = Arc::clone(&global_config);
+        async move {
+            let res = Podcast::new(name, config, &global_config, client, &ui)
+                .await
+                .map(|podcast| {
+                    let episodes = podcast.episodes();
+                    let newest = episodes
+                        .iter()
+                        .map(|episode| episode.attrs.published())
+                        .max();
+                    (
+                        episodes.len(),
+                        episodes
+                            .iter()
+                            .filter(|episode| episode.is_downloaded())
+                            .count(),
+                        podcast.pending_count(),
+                        newest.map(|newest| format_date(newest.as_secs())),
+                    )
+                });
+            (ui, res)
+        }
+    })
+    .await;
+
+    let entries: Vec<ListEntry> = results
+        .into_iter()
+        .map(|(name, res)| match res {
+            Ok((episodes, downloaded, pending, newest)) => ListEntry {
+                root: roots.get(&name).cloned(),
+                name,
+                episodes: Some(episodes),
+                downloaded: Some(downloaded),
+                pending: Some(pending),
+                newest,
+                error: None,
+            },
+            Err(e) => ListEntry {
+                root: roots.get(&name).cloned(),
+                name,
+                episodes: None,
+                downloaded: None,
+                pending: None,
+                newest: None,
+                error: Some(e),
+            },
+        })
+        .collect();
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&entries).expect("failed to serialize podcast list")
+        );
+        return;
+    }
+
+    let width = entries
+        .iter()
+        .map(|entry| entry.name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max("podcast".len());
+    // The root column is only shown when some podcast is on a root.
+    let root_width = entries
+        .iter()
+        .filter_map(|entry| Some(entry.root.as_ref()?.chars().count()))
+        .max()
+        .map(|width| width.max("root".len()));
+    let root = |root: Option<&str>| match root_width {
+        Some(width) => format!("{:<width$}  ", root.unwrap_or("-")),
+        None => String::new(),
+    };
+
+    eprintln!();
+    println!(
+        "{:<width$}  {}{:>8}  {:>10}  {:>7}  newest",
+        "podcast",
+        root(Some("root")),
+        "episodes",
+        "downloaded",
+        "pending"
+    );
+    for entry in &entries {
+        match (
+            &entry.error,
+            entry.episodes,
+            entry.downloaded,
+            entry.pending,
+        ) {
+            (None, Some(episodes), Some(downloaded), Some(pending)) => println!(
+                "{:<width$}  {}{:>8}  {:>10}  {:>7}  {}",
+                entry.name,
+                root(entry.root.as_deref()),
+                episodes,
+                downloaded,
+                pending,
+                entry.newest.as_deref().unwrap_or("-")
+            ),
+            (error, ..) => println!(
+                "{:<width$}  {}failed: {}",
+                entry.name,
+                root(entry.root.as_deref()),
+                error.as_deref().unwrap_or("unknown error")
+            ),
+        }
+    }
+
+    let roots = global_config.roots();
+    if !roots.is_empty() {
+        println!();
+    }
+    for (name, dir) in roots {
+        let free = match paths::free_space(Path::new(dir)) {
+            Some(free) => format!("{} free", format::human_bytes(free)),
+            None => "free space unknown".to_string(),
+        };
+        // Trashed files take up room until the trash is emptied, so they are shown apart.
+        let trashed = global_config
+            .trash_dir(Some(name))
+            .map(|trash_dir| trash::size(&trash_dir))
+            .filter(|size| *size > 0)
+            .map(|size| format!(", {} in the trash", format::human_bytes(size)))
+            .unwrap_or_default();
+        println!("root {}: {} ({}{})", name, dir, free, trashed);
+    }
+}
+
+fn format_date(unix: u64) -> String {
+    chrono::DateTime::from_timestamp(unix as i64, 0)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Prints the episodes a sync would download and the paths they'd be saved to, without
+/// downloading them or writing anything next to the downloads of the podcasts.
+///
+/// The episodes `keep_latest` would remove after the downloads are listed too.
+pub async fn dry_run(podcasts: PodcastConfigs, global_config: GlobalConfig) {
+    eprintln!("checking {} podcasts", podcasts.len());
+    let global_config = Arc::new(global_config);
+
+    let results = run_concurrently(podcasts, &global_config, |name, client, config, ui| {
+        let global_config = Arc::clone(&global_config);
+        async move {
+            let res = Podcast::new(name, config.dry_run(), &global_config, client, &ui)
+                .await
+                .map(|podcast| {
+                    let downloads = podcast
+                        .planned_downloads()
+                        .into_iter()
+                        .map(|episode| {
+                            (
+                                episode.attrs.title().to_string(),
+                                episode.planned_path(),
+                                episode.attrs.length(),
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    let removals = podcast
+                        .planned_removals()
+                        .into_iter()
+                        .map(|(episode, path)| (episode.attrs.title().to_string(), path))
+                        .collect::<Vec<_>>();
+                    (downloads, removals)
+                });
+            (ui, res)
+        }
+    })
+    .await;
+
+    let mut count = 0;
+    let mut size = 0;
+    let mut removed = 0;
+    eprintln!();
+    for (name, res) in &results {
+        match res {
+            Ok((planned, removals)) => {
+                for (title, path, length) in planned {
+                    match length {
+                        Some(length) => {
+                            println!("{}: {} ({})", name, title, format::human_bytes(*length))
+                        }
+                        None => println!("{}: {}", name, title),
+                    }
+                    println!("  {}", path.display());
+                    count += 1;
+                    size += length.unwrap_or(0);
+                }
+
+                for (title, path) in removals {
+                    println!("{}: would remove {}", name, title);
+                    if let Some(path) = path {
+                        println!("  {}", path.display());
+                    }
+                    removed += 1;
+                }
+            }
+            Err(e) => eprintln!("{}: {}", name, e),
+        }
+    }
+
+    eprintln!(
+        "{} episodes would be downloaded, {} as far as the feeds say",
+        count,
+        format::human_bytes(size)
+    );
+    if removed > 0 {
+        eprintln!(
+            "{} episodes would be removed because of keep_latest",
+            removed
+        );
+    }
+}
+
+/// Updates the urls of podcasts whose feeds have permanently moved.
+///
+/// A feed has moved when its url permanently redirects (301 or 308), or when the feed
+/// announces a new url with `itunes:new-feed-url`.
+pub async fn refresh_urls(filter: Option<Regex>, global_config: &GlobalConfig) {
+    let stamp = ConfigStamp::take();
+    let podcasts = PodcastConfigs::load().assert_not_empty().filter(filter);
+    eprintln!("refreshing urls of {} podcasts", podcasts.len());
+
+    let no_redirect = crate::config::client_builder(global_config)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .map(Arc::new)
+        .expect("error: failed to instantiate reqwest client");
+
+    let results = run_concurrently(podcasts, global_config, |_, client, config, ui| {
+        let no_redirect = Arc::clone(&no_redirect);
+        let headers = config.feed_headers(global_config);
+        async move {
+            let res = match headers {
+                Ok(headers) => moved_url(&client, &no_redirect, &config.url, &headers, &ui).await,
+                Err(e) => Err(e),
+            };
+            (ui, res)
+        }
+    })
+    .await;
+
+    let mut moved = vec![];
+    eprintln!();
+    for (name, res) in &results {
+        match res {
+            Ok(Some(new_url)) => moved.push((name, new_url)),
+            Ok(None) => eprintln!("{}: unchanged", name),
+            Err(e) => eprintln!("{}: {}", name, e),
+        }
+    }
+
+    if !moved.is_empty() {
+        // Only the moved urls are written, onto whatever the file contains by now.
+        let mut changes = vec![];
+        PodcastConfigs::edit_file(&stamp, |podcasts| {
+            changes.clear();
+            for (name, new_url) in &moved {
+                if let Some(old_url) = podcasts.set_url(name, new_url.to_string()) {
+                    changes.push(format!(
+                        "{}: {} -> {}",
+                        name,
+                        net::redact_url(&old_url),
+                        net::redact_url(new_url)
+                    ));
+                }
+            }
+        });
+
+        for change in changes {
+            eprintln!("{}", change);
+        }
+    }
+
+    eprintln!("{} of {} urls updated", moved.len(), results.len());
+}
+
+/// The new url of a feed, if it has moved.
+async fn moved_url(
+    client: &reqwest::Client,
+    no_redirect: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    ui: &DownloadBar,
+) -> Result<Option<String>, String> {
+    let redirected = follow_permanent_redirects(no_redirect, url, headers, ui).await?;
+    let current = redirected.as_deref().unwrap_or(url);
+
+    let xml = net::download_text(client, current, headers, true, ui)
+        .await
+        .map(net::Feed::into_xml)
+        .map_err(|e| format!("failed to download xml-file: {}", e))?;
+
+    let (raw_podcast, _) = podcast::parse_feed(&xml, ui)?;
+
+    let announced = raw_podcast
+        .get_str("itunes:new-feed-url")
+        .map(str::trim)
+        .filter(|new_url| !new_url.is_empty() && *new_url != current)
+        .map(String::from);
+
+    Ok(announced.or(redirected))
+}
+
+/// Follows the redirects of a url for as long as they're permanent.
+///
+/// The client must not follow redirects by itself.
+async fn follow_permanent_redirects(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HeaderMap,
+    ui: &DownloadBar,
+) -> Result<Option<String>, String> {
+    let mut current = reqwest::Url::parse(url).map_err(|_| "Invalid URL".to_string())?;
+    let mut moved = false;
+
+    for _ in 0..MAX_REDIRECTS {
+        let request = client.head(current.clone()).headers(headers.clone());
+        let response = net::short_handle_response(request.send().await)?;
+        let status = response.status();
+
+        let permanent = status == reqwest::StatusCode::MOVED_PERMANENTLY
+            || status == reqwest::StatusCode::PERMANENT_REDIRECT;
+        if !permanent {
+            break;
+        }
+
+        let Some(location) = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|val| val.to_str().ok())
+        else {
+            break;
+        };
+
+        ui.log_debug(format!(
+            "permanently redirected to {}",
+            net::redact_url(location)
+        ));
+        current = current
+            .join(location)
+            .map_err(|_| "invalid redirect location".to_string())?;
+        moved = true;
+    }
+
+    Ok(moved.then(|| current.to_string()))
+}
+
+/// What retagging the episodes of a podcast did.
+#[derive(Default)]
+struct RetagReport {
+    checked: usize,
+    changed: usize,
+    /// Episodes in the tracker whose files are gone.
+    missing: usize,
+    /// Episodes in the tracker that are neither in the feed nor in a snapshot of it.
+    not_in_feed: usize,
+    /// Files that changed, along with what changed in them. Only kept for dry runs.
+    samples: Vec<(PathBuf, Vec<String>)>,
+}
+
+/// Re-applies the tags from the current config to episodes that are already downloaded.
+///
+/// Only episodes downloaded since `since` are retagged, if it's set. With `dry_run`, the
+/// files are left alone and the changes for a few of them are printed instead.
+pub async fn retag(
+    filter: Option<Regex>,
+    since: Option<Unix>,
+    dry_run: bool,
+    global_config: GlobalConfig,
+) {
+    let podcasts = PodcastConfigs::load()
+        .assert_not_empty()
+        .check_collisions(&global_config)
+        .filter(filter);
+    eprintln!("retagging episodes of {} podcasts", podcasts.len());
+
+    // Retagging is how missing artwork is fetched again once it's back.
+    let global_config = Arc::new(global_config.bypass_negative_cache());
+    let permits = Arc::new(Semaphore::new(RETAG_PARALLELISM));
+
+    let results = run_concurrently(podcasts, &global_config, |name, client, config, ui| {
+        let global_config = Arc::clone(&global_config);
+        let permits = Arc::clone(&permits);
+        let image_client = Arc::clone(&client);
+        async move {
+            let archive_config = config.clone();
+            let res = match Podcast::new(name, config, &global_config, client, &ui).await {
+                Ok(podcast) => {
+                    let tracker_path = podcast.tracker_path();
+
+                    // Episodes whose tagging was put off are retagged whenever they were
+                    // downloaded.
+                    let deferred = DeferredTags::load(tracker_path);
+                    let ids: HashSet<String> = DownloadedEpisodes::entries(tracker_path)
+                        .into_iter()
+                        .filter(|entry| entry.acknowledged.is_none())
+                        .filter(|entry| {
+                            since.is_none_or(|since| entry.downloaded >= since.as_secs())
+                                || deferred.contains(&entry.id)
+                        })
+                        .map(|entry| entry.id)
+                        .collect();
+
+                    let mut episodes: Vec<&Episode> = podcast
+                        .episodes()
+                        .iter()
+                        .filter(|episode| ids.contains(&episode.get_id()))
+                        .collect();
+
+                    // Downloaded episodes that dropped out of the feed are tagged from the
+                    // archived snapshots of it.
+                    let gone: HashSet<String> = {
+                        let in_feed: HashSet<String> =
+                            episodes.iter().map(|episode| episode.get_id()).collect();
+                        ids.difference(&in_feed).cloned().collect()
+                    };
+                    let archived = match gone.is_empty() {
+                        true => vec![],
+                        false => {
+                            podcast
+                                .archived_episodes(&archive_config, &global_config, &gone, &ui)
+                                .await
+                        }
+                    };
+                    episodes.extend(&archived);
+
+                    let mut report =
+                        retag_episodes(&episodes, &image_client, &permits, dry_run, &ui).await;
+                    report.not_in_feed = gone.len() - archived.len();
+                    Ok(report)
+                }
+                Err(e) => Err(e),
+            };
+            (ui, res)
+        }
+    })
+    .await;
+
+    let mut changed = 0;
+    eprintln!();
+    for (name, res) in &results {
+        match res {
+            Ok(report) => {
+                changed += report.changed;
+                let verb = if dry_run { "would change" } else { "changed" };
+                eprintln!(
+                    "{}: {} {} of {} files",
+                    name, verb, report.changed, report.checked
+                );
+                if report.missing > 0 {
+                    eprintln!("{}: skipped {} missing files", name, report.missing);
+                }
+                if report.not_in_feed > 0 {
+                    eprintln!(
+                        "{}: skipped {} episodes that are no longer in the feed",
+                        name, report.not_in_feed
+                    );
+                }
+                for (path, changes) in &report.samples {
+                    eprintln!("  {}", path.display());
+                    for change in changes {
+                        eprintln!("    {}", change);
+                    }
+                }
+            }
+            Err(e) => eprintln!("{}: {}", name, e),
+        }
+    }
+
+    let verb = if dry_run {
+        "would be changed"
+    } else {
+        "changed"
+    };
+    eprintln!("{} files {}", changed, verb);
+}
+
+async fn retag_episodes(
+    episodes: &[&Episode],
+    client: &reqwest::Client,
+    permits: &Semaphore,
+    dry_run: bool,
+    ui: &DownloadBar,
+) -> RetagReport {
+    let mut report = RetagReport::default();
+
+    for (index, episode) in episodes.iter().enumerate() {
+        ui.begin_download(episode, index, episodes.len());
+
+        let Some(path) = episode.downloaded_path() else {
+            episode.log_debug(ui, "skipping retag: file is missing");
+            report.missing += 1;
+            continue;
+        };
+
+        if path.extension().is_none_or(|ext| ext != "mp3") {
+            continue;
+        }
+
+        let _permit = permits.acquire().await.expect("semaphore is never closed");
+        report.checked += 1;
+
+        let res = retag_file(episode, client, &path, dry_run, ui).await;
+        if res.is_ok() && !dry_run {
+            DeferredTags::resolve(&episode.config.tracker_path, &episode.get_id());
+        }
+
+        match res {
+            Ok(changes) if changes.is_empty() => {}
+            Ok(changes) => {
+                report.changed += 1;
+                if dry_run && report.samples.len() < DRY_RUN_SAMPLE {
+                    report.samples.push((path, changes));
+                }
+            }
+            Err(e) => episode.log_warn(ui, format!("failed to retag: {}", e)),
+        }
+    }
+
+    report
+}
+
+/// Applies the tags to a file, returning a description of every frame that changed.
+async fn retag_file(
+    episode: &Episode,
+    client: &reqwest::Client,
+    path: &Path,
+    dry_run: bool,
+    ui: &DownloadBar,
+) -> Result<Vec<String>, String> {
+    let read_path = path.to_path_buf();
+    let mut file_tags = tokio::task::spawn_blocking(move || FileTags::read(&read_path))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if let Some(problem) = &file_tags.problem {
+        episode.log_warn(ui, problem.as_str());
+    }
+
+    let old_tags = file_tags.tag.clone();
+    episode.apply_tags(client, &mut file_tags.tag, ui).await;
+    episode.add_chapters(client, &mut file_tags.tag, ui).await;
+
+    let changes = tag_changes(&old_tags, &file_tags.tag);
+    if changes.is_empty() || dry_run {
+        return Ok(changes);
+    }
+
+    let write_path = path.to_path_buf();
+    let written = file_tags.tag.clone();
+    let read_back = tokio::task::spawn_blocking(move || {
+        file_tags.write(&write_path)?;
+        Ok::<_, String>(FileTags::read(&write_path).tag)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    // Otherwise the next retag would change the file again.
+    let unsettled = tag_changes(&read_back, &written);
+    if !unsettled.is_empty() {
+        episode.log_warn(
+            ui,
+            format!("tags changed when read back: {}", unsettled.join(", ")),
+        );
+    }
+
+    Ok(changes)
+}
+
+fn tag_changes(old: &id3::Tag, new: &id3::Tag) -> Vec<String> {
+    use id3::TagLike;
+
+    new.frames()
+        .filter(|frame| !old.frames().any(|old_frame| old_frame == *frame))
+        .map(|frame| {
+            let kind = match old.get(frame.id()) {
+                Some(_) => "changed",
+                None => "added",
+            };
+            let desc = text::truncate_string(&frame.to_string(), 70, true);
+            format!("{} {}: {}", kind, frame.id(), desc)
+        })
+        .collect()
+}
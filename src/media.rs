@@ -0,0 +1,347 @@
+//! Which of the enclosures of an item to download, for feeds that offer an episode in
+//! several formats, like an audio and a video `<enclosure>`, or `media:content` entries at
+//! different bitrates.
+//!
+//! The enclosure of the first type in `preferred_media` that the item has is downloaded. An
+//! item without any of those types falls back to its audio and video enclosures, and only
+//! then to whatever else it has. `media_size` decides between several enclosures of the
+//! chosen type.
+
+use crate::config::MediaSize;
+use crate::episode::RawEpisode;
+use crate::episode::XmlWrapper;
+use crate::text;
+use crate::xml;
+use serde_json::Value;
+
+/// The types preferred when `preferred_media` isn't set.
+pub const DEFAULT_PREFERRED_MEDIA: [&str; 1] = ["audio/*"];
+
+/// A file an item offers to download.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enclosure {
+    pub url: String,
+    /// The MIME type the feed gives, or else the one of the extension in the url.
+    pub mime: Option<String>,
+    pub length: Option<u64>,
+}
+
+impl Enclosure {
+    /// An `<enclosure>` or `media:content` element, unless it has no url.
+    fn from_val(val: &Value, length_key: &str) -> Option<Self> {
+        let url = text::trim_quotes(&val.get("@url")?.to_string());
+        if url.is_empty() {
+            return None;
+        }
+
+        let mime = val
+            .get("@type")
+            .and_then(Value::as_str)
+            .map(|mime| {
+                mime.split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_lowercase()
+            })
+            .filter(|mime| !mime.is_empty())
+            .or_else(|| guess_mime(&url));
+
+        let length = val.get(length_key).and_then(|length| {
+            length
+                .as_u64()
+                .or_else(|| length.as_str()?.trim().parse().ok())
+                .filter(|length| *length > 0)
+        });
+
+        Some(Self { url, mime, length })
+    }
+
+    fn is_media(&self) -> bool {
+        self.mime
+            .as_deref()
+            .is_some_and(|mime| mime.starts_with("audio/") || mime.starts_with("video/"))
+    }
+}
+
+/// The MIME type of the extension in a url, if it has a known one.
+fn guess_mime(url: &str) -> Option<String> {
+    let path = reqwest::Url::parse(url).ok()?.path().to_string();
+    let mime = mime_guess::from_path(path).first()?;
+    Some(mime.essence_str().to_string())
+}
+
+/// The enclosures of an item, in the order the feed lists them.
+///
+/// Reads every `<enclosure>`, along with the `media:content` entries of the item and of
+/// its `media:group`.
+pub fn enclosures(raw: &RawEpisode) -> Vec<Enclosure> {
+    let mut enclosures: Vec<Enclosure> = xml::val_to_vec(raw.get_val("enclosure").ok())
+        .into_iter()
+        .filter_map(|val| Enclosure::from_val(val, "@length"))
+        .collect();
+
+    let mut contents = xml::val_to_vec(raw.get_val("media:content").ok());
+    // The namespaces of tags nested in a group are only restored on request.
+    let groups: Vec<_> = xml::val_to_vec(raw.get_val("media:group").ok())
+        .into_iter()
+        .map(xml::restore_namespaces)
+        .collect();
+    for group in &groups {
+        contents.extend(xml::val_to_vec(group.get("media:content")));
+    }
+
+    for val in contents {
+        let Some(enclosure) = Enclosure::from_val(val, "@fileSize") else {
+            continue;
+        };
+        // Feeds that have both often repeat the enclosure as media content.
+        if !enclosures.iter().any(|other| other.url == enclosure.url) {
+            enclosures.push(enclosure);
+        }
+    }
+
+    enclosures
+}
+
+/// Whether a MIME type matches a pattern like `audio/mpeg` or `audio/*`.
+fn matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(kind) => mime.split_once('/').is_some_and(|(other, _)| other == kind),
+        None => pattern == "*/*" || pattern == mime,
+    }
+}
+
+/// Checks that each preferred type looks like `audio/mpeg` or `audio/*`.
+pub fn check_preferred_media(types: &[String]) -> Result<(), String> {
+    for mime in types {
+        let valid = mime.split_once('/').is_some_and(|(kind, subtype)| {
+            !kind.is_empty() && !subtype.is_empty() && (kind != "*" || subtype == "*")
+        });
+        if !valid {
+            return Err(format!(
+                "invalid MIME type in 'preferred_media': \"{}\"",
+                mime
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// How to choose between the enclosures of an item.
+#[derive(Debug, Clone)]
+pub struct MediaPreference {
+    /// MIME types, most preferred first.
+    types: Vec<String>,
+    size: MediaSize,
+}
+
+impl Default for MediaPreference {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_PREFERRED_MEDIA.map(String::from).to_vec(),
+            MediaSize::default(),
+        )
+    }
+}
+
+impl MediaPreference {
+    pub fn new(types: Vec<String>, size: MediaSize) -> Self {
+        let types = types
+            .iter()
+            .map(|mime| mime.trim().to_lowercase())
+            .collect();
+        Self { types, size }
+    }
+
+    /// The enclosure to download, if the item has any.
+    pub fn choose(&self, enclosures: Vec<Enclosure>) -> Option<Enclosure> {
+        for pattern in &self.types {
+            let matching: Vec<&Enclosure> = enclosures
+                .iter()
+                .filter(|enclosure| {
+                    enclosure
+                        .mime
+                        .as_deref()
+                        .is_some_and(|mime| matches(pattern, mime))
+                })
+                .collect();
+            if !matching.is_empty() {
+                return self.by_size(matching).cloned();
+            }
+        }
+
+        let media: Vec<&Enclosure> = enclosures.iter().filter(|e| e.is_media()).collect();
+        match media.is_empty() {
+            true => self.by_size(enclosures.iter().collect()).cloned(),
+            false => self.by_size(media).cloned(),
+        }
+    }
+
+    /// Enclosures without a length are only chosen by size when none has one. Of equally
+    /// sized ones, the one listed first is chosen.
+    fn by_size<'a>(&self, enclosures: Vec<&'a Enclosure>) -> Option<&'a Enclosure> {
+        let mut enclosures = enclosures.into_iter();
+        match self.size {
+            MediaSize::First => enclosures.next(),
+            MediaSize::Smallest => enclosures.min_by_key(|e| e.length.unwrap_or(u64::MAX)),
+            // `max_by_key` keeps the last of equal ones.
+            MediaSize::Largest => enclosures.rev().max_by_key(|e| e.length),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GlobalConfig;
+    use crate::display::DownloadBar;
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+
+    fn enclosure(name: &str, mime: &str, length: Option<u64>) -> Enclosure {
+        Enclosure {
+            url: format!("https://example.com/{}", name),
+            mime: Some(mime.to_string()),
+            length,
+        }
+    }
+
+    /// The name of the enclosure chosen with `types` and `size`.
+    fn chosen(types: &[&str], size: MediaSize, enclosures: &[Enclosure]) -> Option<String> {
+        let preference = MediaPreference::new(types.iter().map(|t| t.to_string()).collect(), size);
+        let chosen = preference.choose(enclosures.to_vec())?;
+        Some(
+            chosen
+                .url
+                .trim_start_matches("https://example.com/")
+                .to_string(),
+        )
+    }
+
+    #[test]
+    fn preferred_types_in_order() {
+        let enclosures = [
+            enclosure("a.mp3", "audio/mpeg", None),
+            enclosure("a.aac", "audio/aac", None),
+            enclosure("a.mp4", "video/mp4", None),
+        ];
+        let first = MediaSize::First;
+
+        assert_eq!(chosen(&["audio/*"], first, &enclosures).unwrap(), "a.mp3");
+        assert_eq!(
+            chosen(&["video/*", "audio/*"], first, &enclosures).unwrap(),
+            "a.mp4"
+        );
+        // An exact type before a wildcard wins, even if the feed lists it later.
+        assert_eq!(
+            chosen(&["audio/aac", "audio/*"], first, &enclosures).unwrap(),
+            "a.aac"
+        );
+        assert_eq!(
+            chosen(&["audio/*", "audio/aac"], first, &enclosures).unwrap(),
+            "a.mp3"
+        );
+        assert_eq!(chosen(&["*/*"], first, &enclosures).unwrap(), "a.mp3");
+        assert_eq!(
+            chosen(&[" Video/MP4 "], first, &enclosures).unwrap(),
+            "a.mp4"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_audio_and_video() {
+        let enclosures = [
+            enclosure("a.pdf", "application/pdf", None),
+            enclosure("a.mp4", "video/mp4", None),
+            enclosure("a.ogg", "audio/ogg", None),
+        ];
+        let first = MediaSize::First;
+        assert_eq!(
+            chosen(&["audio/mpeg"], first, &enclosures).unwrap(),
+            "a.mp4"
+        );
+
+        let others = &enclosures[..1];
+        assert_eq!(chosen(&["audio/mpeg"], first, others).unwrap(), "a.pdf");
+        assert_eq!(chosen(&["audio/mpeg"], first, &[]), None);
+    }
+
+    #[test]
+    fn sizes_with_missing_lengths() {
+        let enclosures = [
+            enclosure("unknown.mp3", "audio/mpeg", None),
+            enclosure("large.mp3", "audio/mpeg", Some(100)),
+            enclosure("small.mp3", "audio/mpeg", Some(50)),
+            enclosure("also-small.mp3", "audio/mpeg", Some(50)),
+        ];
+        let audio = ["audio/*"];
+        assert_eq!(
+            chosen(&audio, MediaSize::First, &enclosures).unwrap(),
+            "unknown.mp3"
+        );
+        assert_eq!(
+            chosen(&audio, MediaSize::Smallest, &enclosures).unwrap(),
+            "small.mp3"
+        );
+        assert_eq!(
+            chosen(&audio, MediaSize::Largest, &enclosures).unwrap(),
+            "large.mp3"
+        );
+
+        // Without any lengths, the first one is chosen.
+        let unknown = [
+            enclosure("one.mp3", "audio/mpeg", None),
+            enclosure("two.mp3", "audio/mpeg", None),
+        ];
+        assert_eq!(
+            chosen(&audio, MediaSize::Smallest, &unknown).unwrap(),
+            "one.mp3"
+        );
+        assert_eq!(
+            chosen(&audio, MediaSize::Largest, &unknown).unwrap(),
+            "one.mp3"
+        );
+    }
+
+    #[test]
+    fn media_content_repeating_the_enclosure() {
+        let feed = r#"<?xml version="1.0"?>
+            <rss xmlns:media="http://search.yahoo.com/mrss/"><channel><title>Podcast</title>
+            <item>
+                <title>Episode</title>
+                <enclosure url="https://example.com/a.mp3" type="audio/mpeg" length="10"/>
+                <media:content url="https://example.com/a.mp3" type="audio/mpeg" fileSize="99"/>
+                <media:group>
+                    <media:content url="https://example.com/b.mp3" type="audio/mpeg" fileSize="5"/>
+                    <media:content url="https://example.com/c" fileSize="7"/>
+                </media:group>
+            </item>
+            </channel></rss>"#;
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new("x".to_string(), GlobalConfig::default().style(), &mp, 7);
+        let (_, items) = crate::podcast::parse_feed(feed, &ui).unwrap();
+
+        assert_eq!(
+            enclosures(&items[0]),
+            [
+                enclosure("a.mp3", "audio/mpeg", Some(10)),
+                enclosure("b.mp3", "audio/mpeg", Some(5)),
+                Enclosure {
+                    url: "https://example.com/c".to_string(),
+                    mime: None,
+                    length: Some(7),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn preferred_media_syntax() {
+        let types = |types: &[&str]| types.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert!(check_preferred_media(&types(&["audio/mpeg", "video/*", "*/*"])).is_ok());
+        assert!(check_preferred_media(&types(&["audio"])).is_err());
+        assert!(check_preferred_media(&types(&["*/mpeg"])).is_err());
+        assert!(check_preferred_media(&types(&["audio/"])).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Copying every episode of a podcast to another directory, like an external drive.
+//!
+//! Mirroring ignores and leaves alone the download tracker. Files already in the
+//! destination are skipped, so an interrupted mirror can be run again to finish it.
+
+use crate::config::GlobalConfig;
+use crate::config::PodcastConfigs;
+use crate::display::DownloadBar;
+use crate::episode::Episode;
+use crate::paths;
+use crate::podcast::Podcast;
+use indicatif::MultiProgress;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+
+/// Sizes of the mirrored files by file name, kept in the destination.
+///
+/// Tagging changes the size of a file, so it can't be compared to the length in the feed.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Manifest(HashMap<String, u64>);
+
+impl Manifest {
+    fn path(dest: &Path) -> PathBuf {
+        dest.join(".talecast-mirror.json")
+    }
+
+    fn load(dest: &Path) -> Self {
+        fs::read_to_string(Self::path(dest))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, dest: &Path) {
+        let Ok(json) = serde_json::to_string_pretty(self) else {
+            return;
+        };
+
+        if let Err(e) = paths::write(Self::path(dest), json) {
+            log::warn!("failed to save mirror manifest: {:?}", e);
+        }
+    }
+
+    fn insert(&mut self, path: &Path) {
+        let (Some(name), Ok(metadata)) = (path.file_name(), fs::metadata(path)) else {
+            return;
+        };
+        self.0
+            .insert(name.to_string_lossy().into_owned(), metadata.len());
+    }
+
+    /// Whether the episode is already in the destination, complete.
+    fn contains(&self, episode: &Episode) -> bool {
+        let Some(path) = episode.find_by_name() else {
+            return false;
+        };
+        let (Some(name), Ok(metadata)) = (path.file_name(), fs::metadata(&path)) else {
+            return false;
+        };
+
+        match self.0.get(name.to_string_lossy().as_ref()) {
+            Some(size) => *size == metadata.len(),
+            // Not mirrored by us, so it's compared to the untagged size.
+            None => episode.attrs.length() == Some(metadata.len()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct MirrorReport {
+    mirrored: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+/// Downloads every episode of a podcast into `dest`, exiting with an error if any failed.
+pub async fn mirror(name: &str, dest: &Path, global_config: &GlobalConfig) {
+    let Some((name, config)) = PodcastConfigs::load()
+        .into_iter()
+        .find(|(podcast, _)| podcast == name)
+    else {
+        eprintln!("no podcast named '{}'", name);
+        process::exit(1);
+    };
+
+    paths::create_dir(dest);
+    let dest = dest.canonicalize().unwrap_or_else(|_| dest.to_path_buf());
+
+    let mp = MultiProgress::new();
+    let client = crate::config::init_reqwest_client(global_config);
+    let mut ui = DownloadBar::new(
+        name.clone(),
+        global_config.style(),
+        &mp,
+        name.chars().count(),
+    );
+
+    let url = config.url.clone();
+    let config = config.mirror_to(&dest);
+    let podcast = match Podcast::new(name, config, global_config, client.clone(), &ui).await {
+        Ok(podcast) => podcast,
+        Err(e) => {
+            ui.error_with_url(&e, &url);
+            process::exit(1);
+        }
+    };
+
+    ui.init();
+    let mut manifest = Manifest::load(&dest);
+    let mut report = MirrorReport::default();
+    let episodes = podcast.episodes();
+
+    for (index, episode) in episodes.iter().enumerate() {
+        if manifest.contains(episode) {
+            report.skipped += 1;
+            continue;
+        }
+
+        ui.begin_download(episode, index, episodes.len());
+        match episode.mirror(&client, &ui).await {
+            Ok(path) => {
+                manifest.insert(&path);
+                manifest.save(&dest);
+                report.mirrored += 1;
+            }
+            Err(e) => {
+                episode.log_warn(&ui, format!("failed to mirror: {}", e));
+                report.failed += 1;
+            }
+        }
+    }
+
+    match report.failed {
+        0 => ui.complete(),
+        n => ui.error(&format!("{} episodes failed", n)),
+    }
+
+    eprintln!(
+        "{} mirrored, {} skipped, {} failed",
+        report.mirrored, report.skipped, report.failed
+    );
+
+    if report.failed > 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,530 @@
+//! Rewriting the ID3 tags of downloaded mp3 files without damaging them.
+//!
+//! Some feeds serve files whose existing tags are corrupt or too old for the id3 crate to
+//! read. Those tags are read as far as they can be, or stripped if they can't be read at
+//! all. Every rewrite keeps a `.pretag` copy of the file until the audio is confirmed to be
+//! unchanged, and puts the copy back if anything went wrong.
+//!
+//! Since a rewrite needs room for two more copies of the file, it isn't started on a
+//! filesystem that's too full. Episodes that weren't tagged because of that are kept next to
+//! the download tracker until `--retag` tags them.
+
+use crate::format;
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// How much of the audio is compared before and after writing the tags.
+const AUDIO_HEAD: usize = 4096;
+
+/// Free space kept on top of what a rewrite needs, so it doesn't fill the disk.
+const REWRITE_MARGIN: u64 = 64 * 1024 * 1024;
+
+/// The ID3v2 tag of a file, as far as it could be read.
+pub struct FileTags {
+    pub tag: id3::Tag,
+    /// Why the existing tag couldn't be read completely.
+    pub problem: Option<String>,
+    /// Whether the existing tag has to be stripped before a new one can be written.
+    unreadable: bool,
+}
+
+impl FileTags {
+    pub fn read(path: &Path) -> Self {
+        let mut tags = match id3::Tag::read_from_path(path) {
+            Ok(tag) => Self::new(tag),
+            Err(id3::Error {
+                kind: id3::ErrorKind::NoTag,
+                ..
+            }) => Self::new(id3::Tag::new()),
+            Err(id3::Error {
+                partial_tag: Some(tag),
+                description,
+                ..
+            }) => Self {
+                tag,
+                problem: Some(format!(
+                    "existing tag is partly unreadable: {}",
+                    description
+                )),
+                unreadable: false,
+            },
+            Err(e) => Self {
+                tag: id3::Tag::new(),
+                problem: Some(format!("existing tag is unreadable: {}", e)),
+                unreadable: true,
+            },
+        };
+
+        // The frames can be fine while the header is broken, which would make the id3
+        // crate write over the audio.
+        if !tags.unreadable {
+            if let Err(e) = Audio::read(path) {
+                tags.problem = Some(format!("existing tag header is broken: {}", e));
+                tags.unreadable = true;
+            }
+        }
+
+        tags
+    }
+
+    fn new(tag: id3::Tag) -> Self {
+        Self {
+            tag,
+            problem: None,
+            unreadable: false,
+        }
+    }
+
+    /// Writes the tag to the file, leaving the file as it was if that fails.
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        check_room(path)?;
+
+        let backup = backup_path(path);
+
+        // A backup left behind means the last rewrite never finished.
+        if backup.exists() {
+            fs::rename(&backup, path)
+                .map_err(|e| format!("failed to restore unfinished rewrite: {}", e))?;
+        }
+
+        fs::copy(path, &backup).map_err(|e| format!("failed to back up file: {}", e))?;
+
+        match self.rewrite(path) {
+            Ok(()) => {
+                if let Err(e) = fs::remove_file(&backup) {
+                    log::warn!("failed to remove {:?}: {}", backup, e);
+                }
+                Ok(())
+            }
+            Err(e) => match fs::rename(&backup, path) {
+                Ok(()) => Err(format!("{}, the file was left as it was", e)),
+                Err(restore) => Err(format!(
+                    "{}, and restoring it from {:?} failed: {}",
+                    e, backup, restore
+                )),
+            },
+        }
+    }
+
+    fn rewrite(&self, path: &Path) -> Result<(), String> {
+        if self.unreadable {
+            strip_tag(path)?;
+        }
+
+        let before = Audio::read(path)?;
+        self.tag
+            .write_to_path(path, id3::Version::Id3v24)
+            .map_err(|e| format!("failed to write tags to file: {}", e))?;
+        let after = Audio::read(path)?;
+
+        if before != after {
+            return Err("the audio changed while writing tags".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".pretag");
+    path.with_file_name(name)
+}
+
+/// Where the audio starts, after the ID3v2 tag at the start of the file if there is one.
+fn audio_start(header: &[u8]) -> Result<u64, String> {
+    if header.len() < 10 || !header.starts_with(b"ID3") {
+        return Ok(0);
+    }
+
+    let size = &header[6..10];
+    if size.iter().any(|byte| byte & 0x80 != 0) {
+        return Err("the size in the tag header is invalid".to_string());
+    }
+
+    let size = size
+        .iter()
+        .fold(0u64, |size, byte| (size << 7) | u64::from(*byte));
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+
+    Ok(10 + size + footer)
+}
+
+/// Whether the bytes start with the header of an MPEG audio frame.
+fn is_frame_header(bytes: &[u8]) -> bool {
+    let [a, b, c, ..] = *bytes else {
+        return false;
+    };
+
+    let sync = a == 0xFF && b & 0xE0 == 0xE0;
+    let version = (b >> 3) & 0b11;
+    let layer = (b >> 1) & 0b11;
+    let bitrate = c >> 4;
+    let sample_rate = (c >> 2) & 0b11;
+
+    sync && version != 0b01 && layer != 0b00 && bitrate != 0b1111 && sample_rate != 0b11
+}
+
+/// Removes an ID3v2 tag that can't be parsed, by cutting the file where the audio starts.
+fn strip_tag(path: &Path) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!("failed to read file: {}", e))?;
+    if !bytes.starts_with(b"ID3") {
+        return Ok(());
+    }
+
+    let declared = audio_start(&bytes)
+        .ok()
+        .and_then(|start| usize::try_from(start).ok())
+        .filter(|&start| bytes.get(start..).is_some_and(is_frame_header));
+
+    // A truncated or garbled header doesn't say where the tag ends, so look for the first
+    // audio frame instead.
+    let start = declared
+        .or_else(|| (10..bytes.len()).find(|&i| is_frame_header(&bytes[i..])))
+        .ok_or_else(|| "couldn't find the audio after the unreadable tag".to_string())?;
+
+    paths::write(path, &bytes[start..]).map_err(|e| format!("failed to strip the tag: {}", e))
+}
+
+/// The length of the audio in a file and its first bytes, to notice when writing tags
+/// changed more than the tags.
+#[derive(PartialEq)]
+struct Audio {
+    len: u64,
+    head: Vec<u8>,
+}
+
+impl Audio {
+    fn read(path: &Path) -> Result<Self, String> {
+        let err = |e: std::io::Error| format!("failed to read file: {}", e);
+        let mut file = fs::File::open(path).map_err(err)?;
+        let len = file.metadata().map_err(err)?.len();
+
+        let mut header = [0; 10];
+        let read = file.read(&mut header).map_err(err)?;
+        let start = audio_start(&header[..read])?;
+        if start > len {
+            return Err("the tag is longer than the file".to_string());
+        }
+
+        let mut head = Vec::with_capacity(AUDIO_HEAD);
+        file.seek(SeekFrom::Start(start)).map_err(err)?;
+        file.take(AUDIO_HEAD as u64)
+            .read_to_end(&mut head)
+            .map_err(err)?;
+
+        Ok(Self {
+            len: len - start,
+            head,
+        })
+    }
+}
+
+/// Checks that the filesystem has room for the backup of the file and the rewritten copy.
+///
+/// Filesystems whose free space can't be told are assumed to have room.
+pub fn check_room(path: &Path) -> Result<(), String> {
+    let size = fs::metadata(path)
+        .map_err(|e| format!("failed to read file size: {}", e))?
+        .len();
+    let Some(free) = paths::free_space(path) else {
+        return Ok(());
+    };
+
+    let needed = 2 * size + REWRITE_MARGIN;
+    if free < needed {
+        return Err(format!(
+            "low disk space: rewriting the tags needs {} free, {} left",
+            format::human_bytes(needed),
+            format::human_bytes(free)
+        ));
+    }
+
+    Ok(())
+}
+
+/// Episodes whose tagging was put off, by episode id, with the reason.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DeferredTags(BTreeMap<String, String>);
+
+impl DeferredTags {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".untagged");
+        tracker_path.with_file_name(name)
+    }
+
+    pub fn load(tracker_path: &Path) -> Self {
+        fs::read_to_string(Self::path(tracker_path))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.0.contains_key(id)
+    }
+
+    /// Records that the tagging of an episode was put off.
+    pub fn defer(tracker_path: &Path, id: &str, reason: &str) {
+        let mut deferred = Self::load(tracker_path);
+        deferred.0.insert(id.to_string(), reason.to_string());
+        deferred.save(tracker_path);
+    }
+
+    /// Forgets an episode once it has been tagged.
+    pub fn resolve(tracker_path: &Path, id: &str) {
+        let mut deferred = Self::load(tracker_path);
+        if deferred.0.remove(id).is_some() {
+            deferred.save(tracker_path);
+        }
+    }
+
+    fn save(&self, tracker_path: &Path) {
+        let path = Self::path(tracker_path);
+        if self.0.is_empty() {
+            let _ = fs::remove_file(path);
+            return;
+        }
+
+        let res = serde_json::to_string_pretty(self)
+            .map_err(|e| e.to_string())
+            .and_then(|json| paths::write(path, json).map_err(|e| e.to_string()));
+        if let Err(e) = res {
+            log::warn!("failed to save deferred tagging: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::TagLike;
+
+    /// Audio of a few MPEG frames, MPEG-1 layer III at 128 kbps.
+    fn audio() -> Vec<u8> {
+        let mut audio = vec![];
+        for i in 0..20u8 {
+            audio.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+            audio.extend([i; 413]);
+        }
+        audio
+    }
+
+    /// An ID3v2.4 tag header declaring `size` bytes, with the syncsafe size as given.
+    fn header(size: [u8; 4]) -> Vec<u8> {
+        let mut header = b"ID3\x04\x00\x00".to_vec();
+        header.extend_from_slice(&size);
+        header
+    }
+
+    fn file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = paths::test_dir(name).join("episode.mp3");
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// Gives the file a title, and checks that only the tag changed.
+    fn retitle(path: &Path) -> FileTags {
+        let mut tags = FileTags::read(path);
+        tags.tag.set_title("New title");
+        tags.write(path).unwrap();
+
+        assert!(!backup_path(path).exists());
+        let tag = id3::Tag::read_from_path(path).unwrap();
+        assert_eq!(tag.title(), Some("New title"));
+        assert_eq!(Audio::read(path).unwrap().len, audio().len() as u64);
+        assert!(fs::read(path).unwrap().ends_with(&audio()));
+        tags
+    }
+
+    #[test]
+    fn audio_starts_after_the_tag() {
+        assert_eq!(audio_start(&audio()), Ok(0));
+        assert_eq!(audio_start(b"ID3"), Ok(0));
+        assert_eq!(audio_start(&header([0, 0, 1, 0])), Ok(10 + 128));
+
+        let mut footer = header([0, 0, 0, 10]);
+        footer[5] = 0x10;
+        assert_eq!(audio_start(&footer), Ok(10 + 10 + 10));
+
+        assert!(audio_start(&header([0, 0, 0x80, 0])).is_err());
+    }
+
+    #[test]
+    fn frame_headers() {
+        assert!(is_frame_header(&[0xFF, 0xFB, 0x90, 0x64]));
+        assert!(!is_frame_header(&[0xFF, 0xFB]));
+        assert!(!is_frame_header(b"ID3\x04"));
+        // Reserved version, reserved layer, bad bitrate and reserved sample rate.
+        assert!(!is_frame_header(&[0xFF, 0xEB, 0x90, 0x00]));
+        assert!(!is_frame_header(&[0xFF, 0xF9, 0x90, 0x00]));
+        assert!(!is_frame_header(&[0xFF, 0xFB, 0xF0, 0x00]));
+        assert!(!is_frame_header(&[0xFF, 0xFB, 0x9C, 0x00]));
+    }
+
+    #[test]
+    fn file_without_tag() {
+        let path = file("mp3-untagged", &audio());
+        let tags = retitle(&path);
+        assert_eq!(tags.problem, None);
+    }
+
+    #[test]
+    fn readable_tag_is_kept() {
+        let path = file("mp3-tagged", &audio());
+        let mut tag = id3::Tag::new();
+        tag.set_artist("Artist");
+        tag.write_to_path(&path, id3::Version::Id3v23).unwrap();
+
+        let tags = retitle(&path);
+        assert_eq!(tags.problem, None);
+        assert_eq!(tags.tag.artist(), Some("Artist"));
+    }
+
+    #[test]
+    fn invalid_tag_size() {
+        let mut bytes = header([0, 0, 0x80, 0]);
+        bytes.extend_from_slice(b"TIT2 garbage that isn't a frame");
+        bytes.extend(audio());
+        let path = file("mp3-invalid-size", &bytes);
+
+        let tags = retitle(&path);
+        assert!(tags.unreadable);
+        assert!(tags.problem.is_some());
+    }
+
+    #[test]
+    fn tag_longer_than_the_file() {
+        let mut bytes = header([0x7F, 0x7F, 0x7F, 0x7F]);
+        bytes.extend(audio());
+        let path = file("mp3-truncated", &bytes);
+
+        let tags = FileTags::read(&path);
+        assert!(tags.unreadable);
+        assert!(tags.problem.is_some());
+        retitle(&path);
+    }
+
+    #[test]
+    fn partly_readable_tag() {
+        let mut tag = id3::Tag::new();
+        tag.set_artist("Artist");
+        let mut bytes = vec![];
+        tag.write_to(&mut bytes, id3::Version::Id3v24).unwrap();
+
+        // A frame with an encoding that doesn't exist, after the readable one.
+        let frame = b"TALB\x00\x00\x00\x05\x00\x00\x09abcd";
+        let size = bytes.len() - 10 + frame.len();
+        let mut tagged = header([
+            (size >> 21) as u8 & 0x7F,
+            (size >> 14) as u8 & 0x7F,
+            (size >> 7) as u8 & 0x7F,
+            size as u8 & 0x7F,
+        ]);
+        tagged.extend_from_slice(&bytes[10..]);
+        tagged.extend_from_slice(frame);
+        tagged.extend(audio());
+        let path = file("mp3-partial", &tagged);
+
+        let tags = retitle(&path);
+        assert!(!tags.unreadable);
+        let problem = tags.problem.unwrap();
+        assert!(
+            problem.starts_with("existing tag is partly unreadable"),
+            "{}",
+            problem
+        );
+        assert_eq!(tags.tag.artist(), Some("Artist"));
+    }
+
+    #[test]
+    fn unfinished_rewrite_is_restored() {
+        let path = file("mp3-unfinished", b"half written");
+        fs::write(backup_path(&path), audio()).unwrap();
+
+        let mut tags = FileTags {
+            tag: id3::Tag::new(),
+            problem: None,
+            unreadable: false,
+        };
+        tags.tag.set_title("New title");
+        tags.write(&path).unwrap();
+
+        assert!(!backup_path(&path).exists());
+        assert!(fs::read(&path).unwrap().ends_with(&audio()));
+    }
+
+    #[test]
+    fn failed_rewrite_leaves_the_file() {
+        // Nothing that looks like audio follows the broken tag.
+        let mut bytes = header([0, 0, 0x80, 0]);
+        bytes.extend_from_slice(&[0; 64]);
+        let path = file("mp3-failed", &bytes);
+
+        let tags = FileTags::read(&path);
+        let err = tags.write(&path).unwrap_err();
+        assert_eq!(
+            err,
+            "couldn't find the audio after the unreadable tag, the file was left as it was"
+        );
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        assert!(!backup_path(&path).exists());
+    }
+
+    #[tokio::test]
+    async fn room_for_rewriting() {
+        let path = file("tag-room", &audio());
+        let needed = 2 * audio().len() as u64 + REWRITE_MARGIN;
+
+        paths::with_free_space(Some(needed), async {
+            assert_eq!(check_room(&path), Ok(()));
+            retitle(&path);
+        })
+        .await;
+        paths::with_free_space(None, async { assert_eq!(check_room(&path), Ok(())) }).await;
+
+        let size = fs::metadata(&path).unwrap().len();
+        let before = fs::read(&path).unwrap();
+        paths::with_free_space(Some(needed), async {
+            let err = FileTags::read(&path).write(&path).unwrap_err();
+            assert_eq!(
+                err,
+                format!(
+                    "low disk space: rewriting the tags needs {} free, {} left",
+                    format::human_bytes(2 * size + REWRITE_MARGIN),
+                    format::human_bytes(needed)
+                )
+            );
+        })
+        .await;
+        // Nothing was touched, not even a backup made.
+        assert_eq!(fs::read(&path).unwrap(), before);
+        assert!(!backup_path(&path).exists());
+    }
+
+    #[test]
+    fn deferred_tags_until_resolved() {
+        let tracker = paths::test_dir("deferred-tags").join(".downloaded");
+        DeferredTags::defer(&tracker, "1", "low disk space");
+        DeferredTags::defer(&tracker, "2", "low disk space");
+        assert!(DeferredTags::load(&tracker).contains("1"));
+
+        DeferredTags::resolve(&tracker, "1");
+        DeferredTags::resolve(&tracker, "unknown");
+        let deferred = DeferredTags::load(&tracker);
+        assert!(!deferred.contains("1"));
+        assert!(deferred.contains("2"));
+
+        // The file goes away with the last one.
+        DeferredTags::resolve(&tracker, "2");
+        assert!(!DeferredTags::path(&tracker).exists());
+    }
+}
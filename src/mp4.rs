@@ -0,0 +1,824 @@
+//! Writing iTunes-style metadata to MP4 audio files, like `.m4a`.
+//!
+//! The metadata lives in the `moov/udta/meta/ilst` box, with a box per field. The fields
+//! TaleCast knows are read into an [`id3::Tag`], so they're filled in by the same code as the
+//! tags of mp3 files, and written back from it. Other fields are left as they were.
+//!
+//! Rewriting the fields changes the size of the `moov` box. When media data comes after it,
+//! the chunk offsets in the `stco` and `co64` boxes are moved along. The file is written to a
+//! copy that only replaces it once it's complete.
+
+use id3::frame::Picture;
+use id3::frame::PictureType;
+use id3::TagLike;
+use std::fs;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Extensions of the MP4 files that are tagged.
+///
+/// `.aac` files aren't among them, since they're raw streams without a container to hold tags.
+const EXTENSIONS: [&str; 3] = ["m4a", "m4b", "mp4"];
+
+type Kind = [u8; 4];
+
+const TITLE: Kind = *b"\xa9nam";
+const ARTIST: Kind = *b"\xa9ART";
+const ALBUM: Kind = *b"\xa9alb";
+const GENRE: Kind = *b"\xa9gen";
+const YEAR: Kind = *b"\xa9day";
+const TRACK: Kind = *b"trkn";
+const DESCRIPTION: Kind = *b"desc";
+const COPYRIGHT: Kind = *b"cprt";
+const COVER: Kind = *b"covr";
+
+/// The fields that are read into the tag and written back from it.
+const KNOWN: [Kind; 9] = [
+    TITLE,
+    ARTIST,
+    ALBUM,
+    GENRE,
+    YEAR,
+    TRACK,
+    DESCRIPTION,
+    COPYRIGHT,
+    COVER,
+];
+
+/// Fields that hold the text of an id3 frame as it is, so custom `id3_tags` for those frames
+/// are written too.
+const TEXT_FRAMES: [(Kind, &str); 6] = [
+    (*b"aART", "TPE2"),
+    (*b"\xa9wrt", "TCOM"),
+    (*b"\xa9grp", "TIT1"),
+    (*b"catg", "TCAT"),
+    (*b"keyw", "TKWD"),
+    (*b"egid", "TGID"),
+];
+
+/// Boxes that only hold other boxes.
+const CONTAINERS: [Kind; 8] = [
+    *b"moov", *b"trak", *b"mdia", *b"minf", *b"stbl", *b"udta", *b"edts", *b"ilst",
+];
+
+/// Types of the values in the `data` box of a field.
+const TYPE_BINARY: u32 = 0;
+const TYPE_UTF8: u32 = 1;
+const TYPE_JPEG: u32 = 13;
+const TYPE_PNG: u32 = 14;
+
+/// Whether the file is an MP4 file that can be tagged, going by its extension.
+pub fn is_mp4(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// The metadata of an MP4 file, as far as TaleCast knows the fields.
+pub struct Mp4Tags {
+    pub tag: id3::Tag,
+}
+
+impl Mp4Tags {
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let (_, moov) = Moov::read(path)?;
+        let mut tag = id3::Tag::new();
+
+        let items = moov
+            .find(&[*b"udta", *b"meta", *b"ilst"])
+            .map(Atom::children)
+            .unwrap_or_default();
+
+        for item in items {
+            let Content::Data(payload) = &item.content else {
+                continue;
+            };
+            let values = item_values(payload);
+            let text = || {
+                values
+                    .iter()
+                    .find(|(kind, _)| *kind == TYPE_UTF8)
+                    .and_then(|(_, value)| String::from_utf8(value.clone()).ok())
+            };
+
+            match item.kind {
+                TITLE => text().map(|text| tag.set_title(text)),
+                ARTIST => text().map(|text| tag.set_artist(text)),
+                ALBUM => text().map(|text| tag.set_album(text)),
+                GENRE => text().map(|text| tag.set_genre(text)),
+                DESCRIPTION => text().map(|text| tag.set_text("TDES", text)),
+                COPYRIGHT => text().map(|text| tag.set_text("TCOP", text)),
+                YEAR => text()
+                    .and_then(|text| text.get(..4)?.parse().ok())
+                    .map(|year| tag.set_year(year)),
+                TRACK => values.first().and_then(|(_, value)| {
+                    let number =
+                        |i: usize| Some(u16::from_be_bytes([*value.get(i)?, *value.get(i + 1)?]));
+                    let (track, total) = (number(2)?, number(4)?);
+                    if track > 0 {
+                        tag.set_track(u32::from(track));
+                    }
+                    if total > 0 {
+                        tag.set_total_tracks(u32::from(total));
+                    }
+                    Some(())
+                }),
+                COVER => values.iter().find_map(|(kind, value)| {
+                    let mime_type = match *kind {
+                        TYPE_JPEG => "image/jpeg",
+                        TYPE_PNG => "image/png",
+                        _ => return None,
+                    };
+                    tag.add_frame(Picture {
+                        mime_type: mime_type.to_string(),
+                        picture_type: PictureType::CoverFront,
+                        description: String::new(),
+                        data: value.clone(),
+                    });
+                    Some(())
+                }),
+                kind => text_frame(kind).and_then(|id| text().map(|text| tag.set_text(id, text))),
+            };
+        }
+
+        Ok(Self { tag })
+    }
+
+    /// Writes the fields to the file, leaving the file as it was if that fails.
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let (position, mut moov) = Moov::read(path)?;
+
+        let ilst = moov.ilst_mut()?;
+        ilst.retain(|item| !KNOWN.contains(&item.kind) && text_frame(item.kind).is_none());
+        ilst.extend(self.items());
+
+        let mut bytes = moov.atom.to_bytes()?;
+        let delta = bytes.len() as i64 - position.size as i64;
+        if delta != 0 {
+            moov.shift_chunk_offsets(position.end(), delta)?;
+            bytes = moov.atom.to_bytes()?;
+        }
+
+        let tmp = tmp_path(path);
+        let res = replace_range(path, &tmp, position, &bytes);
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        res
+    }
+
+    fn items(&self) -> Vec<Atom> {
+        let tag = &self.tag;
+        let mut items = vec![];
+        let mut text = |kind: Kind, text: Option<&str>| {
+            if let Some(text) = text.filter(|text| !text.is_empty()) {
+                items.push(item(kind, TYPE_UTF8, text.as_bytes()));
+            }
+        };
+
+        text(TITLE, tag.title());
+        text(ARTIST, tag.artist());
+        text(ALBUM, tag.album());
+        text(GENRE, tag.genre());
+        text(DESCRIPTION, frame_text(tag, "TDES"));
+        text(COPYRIGHT, frame_text(tag, "TCOP"));
+        for (kind, id) in TEXT_FRAMES {
+            // Frames like the categories can have several values, which id3 keeps apart with
+            // nulls.
+            let value = frame_text(tag, id).map(|value| value.replace('\0', ", "));
+            text(kind, value.as_deref());
+        }
+
+        let year = tag
+            .year()
+            .or_else(|| tag.date_released().map(|date| date.year));
+        if let Some(year) = year {
+            items.push(item(YEAR, TYPE_UTF8, year.to_string().as_bytes()));
+        }
+
+        if let Some(track) = tag.track() {
+            let track = u16::try_from(track).unwrap_or(u16::MAX);
+            let total = tag
+                .total_tracks()
+                .map_or(0, |total| u16::try_from(total).unwrap_or(u16::MAX));
+            let mut value = vec![0, 0];
+            value.extend(track.to_be_bytes());
+            value.extend(total.to_be_bytes());
+            value.extend([0, 0]);
+            items.push(item(TRACK, TYPE_BINARY, &value));
+        }
+
+        let cover = tag
+            .pictures()
+            .find(|picture| picture.picture_type == PictureType::CoverFront);
+        if let Some(cover) = cover {
+            let kind = match cover.mime_type.as_str() {
+                "image/jpeg" | "image/jpg" => Some(TYPE_JPEG),
+                "image/png" => Some(TYPE_PNG),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                items.push(item(COVER, kind, &cover.data));
+            }
+        }
+
+        items
+    }
+}
+
+/// The id3 frame a field holds the text of, if it's one of [`TEXT_FRAMES`].
+fn text_frame(kind: Kind) -> Option<&'static str> {
+    TEXT_FRAMES
+        .iter()
+        .find(|(frame_kind, _)| *frame_kind == kind)
+        .map(|(_, id)| *id)
+}
+
+fn frame_text<'a>(tag: &'a id3::Tag, id: &str) -> Option<&'a str> {
+    tag.get(id).and_then(|frame| frame.content().text())
+}
+
+/// A field of the `ilst` box with a single value.
+fn item(kind: Kind, value_type: u32, value: &[u8]) -> Atom {
+    let mut data = value_type.to_be_bytes().to_vec();
+    // The locale, which is always zero.
+    data.extend([0; 4]);
+    data.extend(value);
+
+    let data = Atom {
+        kind: *b"data",
+        header: vec![],
+        content: Content::Data(data),
+    };
+
+    Atom {
+        kind,
+        header: vec![],
+        content: Content::Data(data.to_bytes().unwrap_or_default()),
+    }
+}
+
+/// The values in the `data` boxes of a field, with their types.
+fn item_values(payload: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let Ok(atoms) = parse_atoms(payload, false) else {
+        return vec![];
+    };
+
+    atoms
+        .into_iter()
+        .filter(|atom| atom.kind == *b"data")
+        .filter_map(|atom| match atom.content {
+            Content::Data(data) if data.len() >= 8 => {
+                let kind = u32::from_be_bytes([0, data[1], data[2], data[3]]);
+                Some((kind, data[8..].to_vec()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Where a box is in the file.
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    offset: u64,
+    size: u64,
+}
+
+impl Position {
+    fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+}
+
+/// The `moov` box of a file, which holds the metadata and the layout of the media data.
+struct Moov {
+    atom: Atom,
+}
+
+impl Moov {
+    fn read(path: &Path) -> Result<(Position, Self), String> {
+        let err = |e: std::io::Error| format!("failed to read file: {}", e);
+        let mut file = fs::File::open(path).map_err(err)?;
+        let len = file.metadata().map_err(err)?.len();
+
+        let position = find_top_level(&mut file, len, *b"moov")?
+            .ok_or_else(|| "not an MP4 file: no moov box".to_string())?;
+        let size =
+            usize::try_from(position.size).map_err(|_| "the moov box is too big".to_string())?;
+
+        let mut bytes = vec![0; size];
+        file.seek(SeekFrom::Start(position.offset)).map_err(err)?;
+        file.read_exact(&mut bytes).map_err(err)?;
+
+        let atom = parse_atoms(&bytes, true)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| "the moov box is empty".to_string())?;
+
+        Ok((position, Self { atom }))
+    }
+
+    fn find(&self, path: &[Kind]) -> Option<&Atom> {
+        path.iter()
+            .try_fold(&self.atom, |atom, kind| atom.child(*kind))
+    }
+
+    /// The fields of the file, adding the boxes that hold them if they're missing.
+    fn ilst_mut(&mut self) -> Result<&mut Vec<Atom>, String> {
+        let udta = self.atom.child_or_insert(*b"udta", vec![])?;
+        let meta = udta.child_or_insert(*b"meta", vec![0; 4])?;
+        if meta.child(*b"hdlr").is_none() {
+            let mut hdlr = vec![0; 8];
+            hdlr.extend(b"mdirappl");
+            hdlr.extend([0; 9]);
+            meta.children_mut()?.insert(
+                0,
+                Atom {
+                    kind: *b"hdlr",
+                    header: vec![],
+                    content: Content::Data(hdlr),
+                },
+            );
+        }
+
+        meta.child_or_insert(*b"ilst", vec![])?.children_mut()
+    }
+
+    /// Moves the chunk offsets that point past `from` by `delta` bytes.
+    fn shift_chunk_offsets(&mut self, from: u64, delta: i64) -> Result<(), String> {
+        let overflow = || "the chunk offsets don't fit after tagging".to_string();
+
+        for trak in self.atom.children_mut()? {
+            if trak.kind != *b"trak" {
+                continue;
+            }
+
+            let Some(stbl) = [*b"mdia", *b"minf", *b"stbl"]
+                .iter()
+                .try_fold(&mut *trak, |atom, kind| atom.child_mut(*kind))
+            else {
+                continue;
+            };
+
+            for table in stbl.children_mut()? {
+                let width = match &table.kind {
+                    b"stco" => 4,
+                    b"co64" => 8,
+                    _ => continue,
+                };
+                let Content::Data(data) = &mut table.content else {
+                    continue;
+                };
+
+                for entry in data
+                    .get_mut(8..)
+                    .unwrap_or_default()
+                    .chunks_exact_mut(width)
+                {
+                    let offset = match width {
+                        4 => u64::from(u32::from_be_bytes(entry.try_into().unwrap())),
+                        _ => u64::from_be_bytes(entry.try_into().unwrap()),
+                    };
+                    if offset < from {
+                        continue;
+                    }
+
+                    let offset = offset.checked_add_signed(delta).ok_or_else(overflow)?;
+                    match width {
+                        4 => entry.copy_from_slice(
+                            &u32::try_from(offset).map_err(|_| overflow())?.to_be_bytes(),
+                        ),
+                        _ => entry.copy_from_slice(&offset.to_be_bytes()),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds a box at the top level of the file.
+fn find_top_level(file: &mut fs::File, len: u64, kind: Kind) -> Result<Option<Position>, String> {
+    let err = |e: std::io::Error| format!("failed to read file: {}", e);
+    let mut offset = 0;
+
+    while offset + 8 <= len {
+        let mut header = [0; 16];
+        file.seek(SeekFrom::Start(offset)).map_err(err)?;
+        let read = file.read(&mut header).map_err(err)?;
+        if read < 8 {
+            break;
+        }
+
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => len - offset,
+            1 if read >= 16 => u64::from_be_bytes(header[8..16].try_into().unwrap()),
+            size => u64::from(size),
+        };
+        if size < 8 || offset + size > len {
+            return Err("the file has a broken box".to_string());
+        }
+
+        if header[4..8] == kind {
+            return Ok(Some(Position { offset, size }));
+        }
+        offset += size;
+    }
+
+    Ok(None)
+}
+
+/// Copies the file with the bytes at `position` replaced, and puts the copy in its place.
+fn replace_range(path: &Path, tmp: &Path, position: Position, bytes: &[u8]) -> Result<(), String> {
+    let err = |e: std::io::Error| format!("failed to write tags to file: {}", e);
+    let mut file = fs::File::open(path).map_err(err)?;
+    let mut out = fs::File::create(tmp).map_err(err)?;
+
+    std::io::copy(&mut (&mut file).take(position.offset), &mut out).map_err(err)?;
+    out.write_all(bytes).map_err(err)?;
+    file.seek(SeekFrom::Start(position.end())).map_err(err)?;
+    std::io::copy(&mut file, &mut out).map_err(err)?;
+    out.sync_all().map_err(err)?;
+
+    fs::rename(tmp, path).map_err(err)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".tagging");
+    path.with_file_name(name)
+}
+
+#[derive(Debug, Clone)]
+struct Atom {
+    kind: Kind,
+    /// Bytes between the header and the children of a container, like the version of `meta`.
+    header: Vec<u8>,
+    content: Content,
+}
+
+#[derive(Debug, Clone)]
+enum Content {
+    Data(Vec<u8>),
+    Children(Vec<Atom>),
+}
+
+impl Atom {
+    fn parse(kind: Kind, payload: &[u8]) -> Result<Self, String> {
+        // `meta` has a version before its children, except in some QuickTime files.
+        let header_len = match &kind {
+            b"meta" if payload.get(4..8) != Some(b"hdlr") => 4,
+            b"meta" => 0,
+            kind if CONTAINERS.contains(kind) => 0,
+            _ => {
+                return Ok(Self {
+                    kind,
+                    header: vec![],
+                    content: Content::Data(payload.to_vec()),
+                })
+            }
+        };
+
+        let header = payload
+            .get(..header_len)
+            .ok_or_else(|| "the file has a broken box".to_string())?;
+
+        Ok(Self {
+            kind,
+            header: header.to_vec(),
+            content: Content::Children(parse_atoms(&payload[header_len..], true)?),
+        })
+    }
+
+    fn children(&self) -> &[Atom] {
+        match &self.content {
+            Content::Children(children) => children,
+            Content::Data(_) => &[],
+        }
+    }
+
+    fn children_mut(&mut self) -> Result<&mut Vec<Atom>, String> {
+        match &mut self.content {
+            Content::Children(children) => Ok(children),
+            Content::Data(_) => Err("the file has a broken box".to_string()),
+        }
+    }
+
+    fn child(&self, kind: Kind) -> Option<&Atom> {
+        self.children().iter().find(|atom| atom.kind == kind)
+    }
+
+    fn child_mut(&mut self, kind: Kind) -> Option<&mut Atom> {
+        self.children_mut()
+            .ok()?
+            .iter_mut()
+            .find(|atom| atom.kind == kind)
+    }
+
+    fn child_or_insert(&mut self, kind: Kind, header: Vec<u8>) -> Result<&mut Atom, String> {
+        let children = self.children_mut()?;
+        let index = match children.iter().position(|atom| atom.kind == kind) {
+            Some(index) => index,
+            None => {
+                children.push(Atom {
+                    kind,
+                    header,
+                    content: Content::Children(vec![]),
+                });
+                children.len() - 1
+            }
+        };
+
+        Ok(&mut children[index])
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut payload = self.header.clone();
+        match &self.content {
+            Content::Data(data) => payload.extend(data),
+            Content::Children(children) => {
+                for child in children {
+                    payload.extend(child.to_bytes()?);
+                }
+            }
+        }
+
+        let size = u32::try_from(payload.len() + 8)
+            .map_err(|_| format!("the {} box is too big", String::from_utf8_lossy(&self.kind)))?;
+        let mut bytes = size.to_be_bytes().to_vec();
+        bytes.extend(self.kind);
+        bytes.extend(payload);
+        Ok(bytes)
+    }
+}
+
+/// Parses the boxes in `bytes`. Boxes with a size of zero reach to the end.
+///
+/// With `allow_padding`, a few bytes left over at the end that are too short to be a box are
+/// dropped, which some encoders leave at the end of `udta`.
+fn parse_atoms(mut bytes: &[u8], allow_padding: bool) -> Result<Vec<Atom>, String> {
+    let broken = || "the file has a broken box".to_string();
+    let mut atoms = vec![];
+
+    while !bytes.is_empty() {
+        if bytes.len() < 8 && allow_padding && bytes.iter().all(|byte| *byte == 0) {
+            break;
+        }
+        if bytes.len() < 8 {
+            return Err(broken());
+        }
+
+        let kind: Kind = bytes[4..8].try_into().unwrap();
+        let (header_len, size) = match u32::from_be_bytes(bytes[..4].try_into().unwrap()) {
+            0 => (8, bytes.len()),
+            1 => {
+                let size = bytes.get(8..16).ok_or_else(broken)?;
+                let size = u64::from_be_bytes(size.try_into().unwrap());
+                (16, usize::try_from(size).map_err(|_| broken())?)
+            }
+            size => (8, size as usize),
+        };
+        if size < header_len || size > bytes.len() {
+            return Err(broken());
+        }
+
+        atoms.push(Atom::parse(kind, &bytes[header_len..size])?);
+        bytes = &bytes[size..];
+    }
+
+    Ok(atoms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paths;
+
+    const AUDIO: &[u8] = b"the audio data";
+
+    fn atom(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut bytes = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend(kind);
+        bytes.extend(payload);
+        bytes
+    }
+
+    /// The `data` box of a field.
+    fn data(value_type: u32, value: &[u8]) -> Vec<u8> {
+        let mut data = value_type.to_be_bytes().to_vec();
+        data.extend([0; 4]);
+        data.extend(value);
+        atom(b"data", &data)
+    }
+
+    fn nested(path: &[&[u8; 4]], payload: &[u8]) -> Vec<u8> {
+        path.iter()
+            .rev()
+            .fold(payload.to_vec(), |payload, kind| atom(kind, &payload))
+    }
+
+    /// A track whose only chunk starts at `offset`, in an `stco` or a `co64` table.
+    fn trak(offset: u64, wide: bool) -> Vec<u8> {
+        let mut table = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        let kind = match wide {
+            true => {
+                table.extend(offset.to_be_bytes());
+                b"co64"
+            }
+            false => {
+                table.extend((offset as u32).to_be_bytes());
+                b"stco"
+            }
+        };
+        nested(&[b"trak", b"mdia", b"minf", b"stbl"], &atom(kind, &table))
+    }
+
+    /// An m4a file with the audio before or after `moov`, which holds `ilst` if given.
+    fn m4a(ilst: Option<&[u8]>, audio_first: bool) -> Vec<u8> {
+        let ftyp = atom(b"ftyp", b"M4A \0\0\0\0M4A isom");
+        let mdat = atom(b"mdat", AUDIO);
+        let moov = |offset: u64| {
+            let mut moov = atom(b"mvhd", &[0; 100]);
+            moov.extend(trak(offset, false));
+            moov.extend(trak(offset, true));
+            if let Some(ilst) = ilst {
+                let mut meta = vec![0; 4];
+                meta.extend(atom(b"hdlr", b"\0\0\0\0\0\0\0\0mdirappl\0\0\0\0\0\0\0\0\0"));
+                meta.extend(atom(b"ilst", ilst));
+                moov.extend(nested(&[b"udta", b"meta"], &meta));
+            }
+            atom(b"moov", &moov)
+        };
+
+        let mut file = ftyp.clone();
+        if audio_first {
+            file.extend(&mdat);
+            file.extend(moov(ftyp.len() as u64 + 8));
+        } else {
+            let moov_len = moov(0).len();
+            file.extend(moov((ftyp.len() + moov_len) as u64 + 8));
+            file.extend(&mdat);
+        }
+        file
+    }
+
+    fn write_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = paths::test_dir(&format!("mp4-{}", name)).join("episode.m4a");
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// The chunk offsets of every track, read back from the file.
+    fn chunk_offsets(path: &Path) -> Vec<u64> {
+        let (_, moov) = Moov::read(path).unwrap();
+        moov.atom
+            .children()
+            .iter()
+            .filter(|atom| atom.kind == *b"trak")
+            .flat_map(|trak| {
+                let stbl = [*b"mdia", *b"minf", *b"stbl"]
+                    .iter()
+                    .try_fold(trak, |atom, kind| atom.child(*kind))
+                    .unwrap();
+                stbl.children().to_vec()
+            })
+            .map(|table| {
+                let Content::Data(data) = &table.content else {
+                    panic!("chunk offset table without data");
+                };
+                match &table.kind {
+                    b"stco" => u64::from(u32::from_be_bytes(data[8..12].try_into().unwrap())),
+                    _ => u64::from_be_bytes(data[8..16].try_into().unwrap()),
+                }
+            })
+            .collect()
+    }
+
+    fn assert_offsets_point_at_audio(path: &Path) {
+        let file = fs::read(path).unwrap();
+        let offsets = chunk_offsets(path);
+        assert_eq!(offsets.len(), 2);
+        for offset in offsets {
+            let offset = offset as usize;
+            assert_eq!(&file[offset..offset + AUDIO.len()], AUDIO);
+        }
+    }
+
+    fn tag() -> id3::Tag {
+        let mut tag = id3::Tag::new();
+        tag.set_title("Episode");
+        tag.set_artist("Host");
+        tag.set_album("Podcast");
+        tag.set_genre("podcast");
+        tag.set_year(2024);
+        tag.set_track(3);
+        tag.set_total_tracks(10);
+        tag.set_text("TDES", "What it's about");
+        tag.set_text("TCOP", "© Someone");
+        tag.set_text("TPE2", "Network");
+        tag.add_frame(Picture {
+            mime_type: "image/png".to_string(),
+            picture_type: PictureType::CoverFront,
+            description: String::new(),
+            data: b"\x89PNG fake".to_vec(),
+        });
+        tag
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        let path = write_file("round-trip", &m4a(None, false));
+        Mp4Tags { tag: tag() }.write(&path).unwrap();
+
+        let read = Mp4Tags::read(&path).unwrap().tag;
+        assert_eq!(read.title(), Some("Episode"));
+        assert_eq!(read.artist(), Some("Host"));
+        assert_eq!(read.album(), Some("Podcast"));
+        assert_eq!(read.genre(), Some("podcast"));
+        assert_eq!(read.year(), Some(2024));
+        assert_eq!(read.track(), Some(3));
+        assert_eq!(read.total_tracks(), Some(10));
+        assert_eq!(frame_text(&read, "TDES"), Some("What it's about"));
+        assert_eq!(frame_text(&read, "TCOP"), Some("© Someone"));
+        assert_eq!(frame_text(&read, "TPE2"), Some("Network"));
+
+        let cover = read.pictures().next().unwrap();
+        assert_eq!(cover.mime_type, "image/png");
+        assert_eq!(cover.data, b"\x89PNG fake");
+    }
+
+    #[test]
+    fn chunk_offsets_follow_the_audio() {
+        let path = write_file("offsets", &m4a(None, false));
+        assert_offsets_point_at_audio(&path);
+        let before = chunk_offsets(&path);
+
+        Mp4Tags { tag: tag() }.write(&path).unwrap();
+        assert_offsets_point_at_audio(&path);
+        assert!(chunk_offsets(&path)[0] > before[0]);
+
+        // Writing fewer fields shrinks moov again.
+        let mut short = id3::Tag::new();
+        short.set_title("E");
+        Mp4Tags { tag: short }.write(&path).unwrap();
+        assert_offsets_point_at_audio(&path);
+        assert!(chunk_offsets(&path)[0] > before[0]);
+    }
+
+    #[test]
+    fn audio_before_moov_stays_where_it_is() {
+        let path = write_file("audio-first", &m4a(None, true));
+        let before = chunk_offsets(&path);
+
+        Mp4Tags { tag: tag() }.write(&path).unwrap();
+        assert_eq!(chunk_offsets(&path), before);
+        assert_offsets_point_at_audio(&path);
+    }
+
+    #[test]
+    fn unknown_fields_are_kept() {
+        let mut ilst = atom(b"\xa9too", &data(TYPE_UTF8, b"Encoder 1.0"));
+        ilst.extend(atom(b"\xa9nam", &data(TYPE_UTF8, b"Old title")));
+        let path = write_file("unknown", &m4a(Some(&ilst), false));
+
+        let mut tag = id3::Tag::new();
+        tag.set_title("New title");
+        Mp4Tags { tag }.write(&path).unwrap();
+
+        let (_, moov) = Moov::read(&path).unwrap();
+        let ilst = moov.find(&[*b"udta", *b"meta", *b"ilst"]).unwrap();
+        let kinds: Vec<Kind> = ilst.children().iter().map(|item| item.kind).collect();
+        assert_eq!(kinds, [*b"\xa9too", TITLE]);
+        assert_eq!(Mp4Tags::read(&path).unwrap().tag.title(), Some("New title"));
+        assert_offsets_point_at_audio(&path);
+    }
+
+    #[test]
+    fn files_without_moov() {
+        let path = write_file("no-moov", &atom(b"ftyp", b"M4A \0\0\0\0"));
+        assert_eq!(
+            Mp4Tags::read(&path).err(),
+            Some("not an MP4 file: no moov box".to_string())
+        );
+
+        let path = write_file("broken", b"\0\0\0\x40moov");
+        assert_eq!(
+            Mp4Tags::read(&path).err(),
+            Some("the file has a broken box".to_string())
+        );
+    }
+
+    #[test]
+    fn mp4_extensions() {
+        assert!(is_mp4(Path::new("episode.m4a")));
+        assert!(is_mp4(Path::new("episode.M4B")));
+        assert!(is_mp4(Path::new("episode.mp4")));
+        assert!(!is_mp4(Path::new("episode.aac")));
+        assert!(!is_mp4(Path::new("episode.mp3")));
+    }
+}
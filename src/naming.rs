@@ -0,0 +1,193 @@
+//! Turning arbitrary strings, like episode titles and IDs, into file names.
+//!
+//! Every feature that derives a file name from feed data should go through
+//! [`sanitize_filename`], so that all of them agree on which characters are allowed
+//! and how overlong names are shortened.
+
+use percent_encoding::percent_decode_str;
+use unicode_normalization::UnicodeNormalization;
+
+/// Most filesystems don't allow longer file names than this, in bytes.
+pub const MAX_FILE_NAME_LEN: usize = 255;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NameOptions<'a> {
+    /// Shorten the name so that it fits within this many bytes.
+    pub max_len: Option<usize>,
+    /// Extension that will be appended to the name afterwards.
+    ///
+    /// The extension is not added to the returned name, but room is left for it
+    /// (including the dot) when shortening the name.
+    pub extension: Option<&'a str>,
+}
+
+impl<'a> NameOptions<'a> {
+    /// Options for a name that has to fit on the filesystem along with its extension.
+    pub fn file(extension: Option<&'a str>) -> Self {
+        Self {
+            max_len: Some(MAX_FILE_NAME_LEN),
+            extension,
+        }
+    }
+}
+
+/// Removes characters that aren't allowed in file names, and shortens the result
+/// according to `options`.
+///
+/// Shortening removes one character from the end for every byte the name is over the
+/// limit, which keeps names stable between versions.
+pub fn sanitize_filename(options: NameOptions, input: &str) -> String {
+    let mut name = sanitize_filename::sanitize(input);
+
+    if let Some(max_len) = options.max_len {
+        let ext_len = options.extension.map(|ext| ext.len() + 1).unwrap_or(0); // + 1 for the dot.
+        let overflow = (name.len() + ext_len).saturating_sub(max_len);
+        for _ in 0..overflow {
+            name.pop();
+        }
+    }
+
+    name
+}
+
+/// How a name compares on filesystems that ignore case or unicode normalization.
+///
+/// Names that fold to the same string may end up as the same file.
+pub fn fold(name: &str) -> String {
+    name.nfc().collect::<String>().to_lowercase()
+}
+
+/// The file name the server serves a download under.
+///
+/// Taken from the `Content-Disposition` header when present, otherwise from the last
+/// segment of the final url after redirects.
+pub fn original_filename(response: &reqwest::Response) -> Option<String> {
+    let from_header = response
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|val| val.to_str().ok())
+        .and_then(content_disposition_filename);
+
+    let Some(name) = from_header else {
+        return url_filename(response.url());
+    };
+
+    let name = sanitize_filename(NameOptions::file(None), name.trim());
+    (!name.is_empty()).then_some(name)
+}
+
+/// The name of the file at the end of a url.
+pub fn url_filename(url: &reqwest::Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    let name = sanitize_filename(NameOptions::file(None), percent_decode(segment).trim());
+    (!name.is_empty()).then_some(name)
+}
+
+/// Extracts the filename from a `Content-Disposition` header value.
+///
+/// Prefers the RFC 5987 `filename*=UTF-8''...` parameter over the plain `filename`.
+fn content_disposition_filename(header: &str) -> Option<String> {
+    let params: Vec<(&str, &str)> = header
+        .split(';')
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, val)| (key.trim(), val.trim()))
+        .collect();
+
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, val)| *val)
+    };
+
+    if let Some(val) = param("filename*") {
+        // The charset and language come before the encoded name.
+        if let Some((_, encoded)) = val.trim_matches('"').split_once("''") {
+            return Some(percent_decode(encoded));
+        }
+    }
+
+    param("filename").map(|val| percent_decode(val.trim_matches('"')))
+}
+
+fn percent_decode(s: &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold_ignores_case_and_normalization() {
+        assert_eq!(fold("Café"), fold("Cafe\u{301}"));
+        assert_eq!(fold("CAFÉ"), fold("cafe\u{301}"));
+        assert_eq!(fold("ΑΘΗΝΑ"), fold("αθηνα"));
+        assert_eq!(fold("Ünïcode"), "ünïcode");
+    }
+
+    #[test]
+    fn fold_keeps_distinct_names_apart() {
+        assert_ne!(fold("Café"), fold("Cafe"));
+        assert_ne!(fold("Straße"), fold("Strasse"));
+        assert_ne!(fold("日本"), fold("日本語"));
+    }
+
+    #[test]
+    fn sanitizing() {
+        let options = NameOptions::default();
+        assert_eq!(sanitize_filename(options, "a/b: c?"), "ab c");
+        assert_eq!(sanitize_filename(options, "Ünïcode 日本"), "Ünïcode 日本");
+    }
+
+    #[test]
+    fn shortening_leaves_room_for_the_extension() {
+        let options = NameOptions {
+            max_len: Some(10),
+            extension: Some("mp3"),
+        };
+        assert_eq!(sanitize_filename(options, "short"), "short");
+        assert_eq!(sanitize_filename(options, "a long title"), "a long");
+
+        // A character is removed for every byte over, so multibyte names end up shorter.
+        let options = NameOptions {
+            max_len: Some(6),
+            extension: None,
+        };
+        assert_eq!(sanitize_filename(options, "ééééé"), "é");
+        assert_eq!(NameOptions::file(None).max_len, Some(MAX_FILE_NAME_LEN));
+    }
+
+    #[test]
+    fn names_from_urls() {
+        let url = |s| reqwest::Url::parse(s).unwrap();
+        assert_eq!(
+            url_filename(&url("https://example.com/feed/Episode%201.mp3?token=x")),
+            Some("Episode 1.mp3".to_string())
+        );
+        assert_eq!(url_filename(&url("https://example.com/")), None);
+        assert_eq!(
+            url_filename(&url("https://example.com/a%2Fb%3F.mp3")),
+            Some("ab.mp3".to_string())
+        );
+    }
+
+    #[test]
+    fn names_from_content_disposition() {
+        assert_eq!(
+            content_disposition_filename("attachment; filename=\"Episode 1.mp3\""),
+            Some("Episode 1.mp3".to_string())
+        );
+        assert_eq!(
+            content_disposition_filename(
+                "attachment; filename=\"fallback.mp3\"; filename*=UTF-8''%C3%89pisode.mp3"
+            ),
+            Some("Épisode.mp3".to_string())
+        );
+        assert_eq!(
+            content_disposition_filename("attachment; FILENAME=plain.mp3"),
+            Some("plain.mp3".to_string())
+        );
+        assert_eq!(content_disposition_filename("inline"), None);
+    }
+}
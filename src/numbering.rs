@@ -0,0 +1,187 @@
+//! Guessing episode numbers from titles like "Ep. 217: ..." or "#217 – ...".
+//!
+//! A number found in a title is only trusted when it lines up with the numbers found in
+//! the titles of the episodes around it, which weeds out dates. A bare number that looks
+//! like a year, as in "2019: Year in review", is skipped, since the titles of an annual show
+//! would line up too. Years are only taken as numbers after "Episode", "Ep" or "#".
+
+use crate::episode::Attributes;
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// How far a number may stray from the one expected by counting episodes.
+///
+/// Feeds skip numbers for removed episodes and insert unnumbered bonus episodes, so the
+/// numbers rarely line up exactly.
+const TOLERANCE: u32 = 5;
+
+/// Bare numbers in this range are taken to be years.
+const YEARS: std::ops::RangeInclusive<u32> = 1900..=2100;
+
+/// Patterns for a number at the start of a title, most specific first, with whether the
+/// title says the number is an episode number.
+fn patterns() -> &'static [(Regex, bool)] {
+    static PATTERNS: OnceLock<Vec<(Regex, bool)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            // "Episode 217", "Ep. 217", "Ep 217:", "E217"
+            (r"(?i)^\s*(?:episode|ep\.?|e)\s*#?\s*(\d{1,5})\b", true),
+            // "#217 – ...", "No. 217"
+            (r"(?i)^\s*(?:#|no\.\s*)(\d{1,5})\b", true),
+            // "217: ...", "217 - ...", "217. ..."
+            (r"^\s*(\d{1,5})\s*[:.|\-–—]\s", false),
+        ]
+        .iter()
+        .map(|(pattern, explicit)| (Regex::new(pattern).unwrap(), *explicit))
+        .collect()
+    })
+}
+
+/// Finds a leading episode number in a title.
+fn parse_title(title: &str) -> Option<u32> {
+    patterns().iter().find_map(|(pattern, explicit)| {
+        let number = pattern.captures(title)?.get(1)?.as_str().parse().ok()?;
+        (*explicit || !YEARS.contains(&number)).then_some(number)
+    })
+}
+
+/// Whether `later` is a plausible episode number for an episode `distance` episodes
+/// after the one numbered `earlier`.
+fn follows(earlier: u32, later: u32, distance: usize) -> bool {
+    let distance = distance as u32;
+    later > earlier && later - earlier <= distance + TOLERANCE
+}
+
+/// Sets the episode number of every episode that lacks `itunes:episode` but has one in
+/// its title. The episodes must be sorted from oldest to newest.
+///
+/// A number is only used if it follows the last number used before it, or is followed by
+/// the next number found after it.
+pub fn infer(episodes: &mut [Attributes]) {
+    let candidates: Vec<(usize, u32)> = episodes
+        .iter()
+        .enumerate()
+        .filter(|(_, attrs)| attrs.itunes_episode().is_err())
+        .filter_map(|(index, attrs)| Some((index, parse_title(attrs.title())?)))
+        .collect();
+
+    let mut last_used: Option<(usize, u32)> = None;
+
+    for (i, &(index, number)) in candidates.iter().enumerate() {
+        let after_prev =
+            last_used.is_some_and(|(prev_index, prev)| follows(prev, number, index - prev_index));
+
+        let before_next = candidates
+            .get(i + 1)
+            .is_some_and(|&(next_index, next)| follows(number, next, next_index - index));
+
+        if after_prev || before_next {
+            episodes[index].set_inferred_episode(number);
+            last_used = Some((index, number));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::episode::RawEpisode;
+    use crate::media::MediaPreference;
+
+    fn episodes(titles: &[&str]) -> Vec<Attributes> {
+        titles
+            .iter()
+            .map(|title| {
+                let item = serde_json::json!({
+                    "title": title,
+                    "pubDate": "2024-01-01T06:00:00Z",
+                    "enclosure": { "@url": "https://example.com/episode.mp3" },
+                });
+                let raw = RawEpisode::new(item.as_object().unwrap().clone());
+                Attributes::new(raw, &MediaPreference::default()).unwrap()
+            })
+            .collect()
+    }
+
+    /// The numbers inferred for episodes with these titles, oldest first.
+    fn inferred(titles: &[&str]) -> Vec<Option<String>> {
+        let mut episodes = episodes(titles);
+        infer(&mut episodes);
+        episodes
+            .iter()
+            .map(|attrs| attrs.itunes_episode().ok().map(String::from))
+            .collect()
+    }
+
+    fn numbers(numbers: &[u32]) -> Vec<Option<String>> {
+        numbers.iter().map(|n| Some(n.to_string())).collect()
+    }
+
+    #[test]
+    fn episode_prefix() {
+        assert_eq!(
+            inferred(&["Episode 1: Hi", "Ep. 2 - Again", "E3 Third"]),
+            numbers(&[1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn hash_prefix() {
+        assert_eq!(
+            inferred(&["#41 – One", "#42 – Two", "No. 43 Three"]),
+            numbers(&[41, 42, 43])
+        );
+    }
+
+    #[test]
+    fn bare_number() {
+        assert_eq!(
+            inferred(&["216: One", "217 - Two", "218. Three"]),
+            numbers(&[216, 217, 218])
+        );
+    }
+
+    #[test]
+    fn years_are_not_numbers() {
+        assert_eq!(
+            inferred(&["2019: Year in review", "2020: Year in review"]),
+            vec![None, None]
+        );
+        assert_eq!(
+            inferred(&["Episode 2019", "Episode 2020"]),
+            numbers(&[2019, 2020])
+        );
+        assert_eq!(
+            inferred(&["#2019 One", "#2020 Two"]),
+            numbers(&[2019, 2020])
+        );
+    }
+
+    #[test]
+    fn bonus_episodes_are_tolerated() {
+        let titles = [
+            "10: One",
+            "Bonus",
+            "Bonus",
+            "11: Two",
+            "17: Three",
+            "99: Four",
+        ];
+        assert_eq!(
+            inferred(&titles),
+            vec![
+                Some("10".to_string()),
+                None,
+                None,
+                Some("11".to_string()),
+                Some("17".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_numbers_are_not_trusted() {
+        assert_eq!(inferred(&["3: Things", "Interview"]), vec![None, None]);
+    }
+}
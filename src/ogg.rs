@@ -0,0 +1,638 @@
+//! Writing Vorbis comments to Ogg Vorbis and Opus files, like `.ogg` and `.opus`.
+//!
+//! The comments are the second packet of the stream, after the packet identifying the codec.
+//! Like for MP4 files, the fields TaleCast knows are read into an [`id3::Tag`] and written
+//! back from it, and other comments are left as they were. Pictures are stored in
+//! `METADATA_BLOCK_PICTURE` comments, which hold a FLAC picture block in base64.
+//!
+//! The header packets are split into pages again after the comments changed. When that
+//! changes the number of pages, the sequence numbers and checksums of the pages after them
+//! are updated. The file is written to a copy that only replaces it once it's complete.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use id3::frame::Picture;
+use id3::frame::PictureType;
+use id3::TagLike;
+use std::fs;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Extensions of the Ogg files that are tagged.
+const EXTENSIONS: [&str; 3] = ["ogg", "oga", "opus"];
+
+const TITLE: &str = "TITLE";
+const ARTIST: &str = "ARTIST";
+const ALBUM: &str = "ALBUM";
+const GENRE: &str = "GENRE";
+const DATE: &str = "DATE";
+const TRACK: &str = "TRACKNUMBER";
+const TOTAL_TRACKS: &str = "TRACKTOTAL";
+const DESCRIPTION: &str = "DESCRIPTION";
+const COPYRIGHT: &str = "COPYRIGHT";
+const PICTURE: &str = "METADATA_BLOCK_PICTURE";
+
+/// The fields that are read into the tag and written back from it.
+const KNOWN: [&str; 10] = [
+    TITLE,
+    ARTIST,
+    ALBUM,
+    GENRE,
+    DATE,
+    TRACK,
+    TOTAL_TRACKS,
+    DESCRIPTION,
+    COPYRIGHT,
+    PICTURE,
+];
+
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+const FIRST_PAGE: u8 = 0x02;
+const CONTINUED_PACKET: u8 = 0x01;
+const MAX_SEGMENTS: usize = 255;
+
+/// Whether the file is an Ogg file that can be tagged, going by its extension.
+pub fn is_ogg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// The comments of an Ogg file, as far as TaleCast knows the fields.
+pub struct OggTags {
+    pub tag: id3::Tag,
+}
+
+impl OggTags {
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let headers = Headers::read(path)?;
+        let comments = Comments::parse(headers.codec, &headers.packets[1])?;
+        let mut tag = id3::Tag::new();
+
+        for (key, value) in &comments.fields {
+            match key.to_uppercase().as_str() {
+                TITLE => tag.set_title(value.as_str()),
+                ARTIST => tag.set_artist(value.as_str()),
+                ALBUM => tag.set_album(value.as_str()),
+                GENRE => tag.set_genre(value.as_str()),
+                DESCRIPTION => tag.set_text("TDES", value.as_str()),
+                COPYRIGHT => tag.set_text("TCOP", value.as_str()),
+                DATE => {
+                    if let Ok(date) = value.trim().parse() {
+                        tag.set_date_released(date);
+                    }
+                }
+                TRACK => {
+                    // Some taggers write the total along with the number, like "3/12".
+                    let mut numbers = value.split('/').map(|n| n.trim().parse::<u32>());
+                    if let Some(Ok(track)) = numbers.next() {
+                        tag.set_track(track);
+                    }
+                    if let Some(Ok(total)) = numbers.next() {
+                        tag.set_total_tracks(total);
+                    }
+                }
+                TOTAL_TRACKS => {
+                    if let Ok(total) = value.trim().parse() {
+                        tag.set_total_tracks(total);
+                    }
+                }
+                PICTURE => {
+                    if let Some(picture) = parse_picture(value) {
+                        tag.add_frame(picture);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self { tag })
+    }
+
+    /// Writes the fields to the file, leaving the file as it was if that fails.
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let mut headers = Headers::read(path)?;
+
+        let mut comments = Comments::parse(headers.codec, &headers.packets[1])?;
+        comments
+            .fields
+            .retain(|(key, _)| !KNOWN.contains(&key.to_uppercase().as_str()));
+        comments.fields.extend(self.fields());
+        headers.packets[1] = comments.to_packet(headers.codec);
+
+        let tmp = tmp_path(path);
+        let res = rewrite(path, &tmp, &headers);
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        res
+    }
+
+    fn fields(&self) -> Vec<(String, String)> {
+        let tag = &self.tag;
+        let mut fields = vec![];
+        let mut text = |key: &str, text: Option<String>| {
+            if let Some(text) = text.filter(|text| !text.is_empty()) {
+                fields.push((key.to_string(), text));
+            }
+        };
+
+        text(TITLE, tag.title().map(String::from));
+        text(ARTIST, tag.artist().map(String::from));
+        text(ALBUM, tag.album().map(String::from));
+        text(GENRE, tag.genre().map(String::from));
+        text(DESCRIPTION, frame_text(tag, "TDES"));
+        text(COPYRIGHT, frame_text(tag, "TCOP"));
+        text(DATE, date(tag));
+        text(TRACK, tag.track().map(|track| track.to_string()));
+        text(
+            TOTAL_TRACKS,
+            tag.total_tracks().map(|total| total.to_string()),
+        );
+
+        for picture in tag.pictures() {
+            fields.push((PICTURE.to_string(), BASE64.encode(picture_block(picture))));
+        }
+
+        fields
+    }
+}
+
+fn frame_text(tag: &id3::Tag, id: &str) -> Option<String> {
+    tag.get(id)
+        .and_then(|frame| frame.content().text())
+        .map(String::from)
+}
+
+/// The release date as far as it's known, like "2024-03-05", or else the year.
+fn date(tag: &id3::Tag) -> Option<String> {
+    let Some(date) = tag.date_released() else {
+        return tag.year().map(|year| year.to_string());
+    };
+
+    let mut s = format!("{:04}", date.year);
+    if let Some(month) = date.month {
+        s.push_str(&format!("-{:02}", month));
+        if let Some(day) = date.day {
+            s.push_str(&format!("-{:02}", day));
+        }
+    }
+    Some(s)
+}
+
+/// A FLAC picture block, as stored in `METADATA_BLOCK_PICTURE`.
+///
+/// The dimensions are left at zero, which readers take as unknown.
+fn picture_block(picture: &Picture) -> Vec<u8> {
+    let picture_type = u32::from(u8::from(picture.picture_type));
+    let mut block = picture_type.to_be_bytes().to_vec();
+    for field in [picture.mime_type.as_bytes(), picture.description.as_bytes()] {
+        block.extend((field.len() as u32).to_be_bytes());
+        block.extend(field);
+    }
+
+    // Width, height, color depth and number of colors.
+    block.extend([0; 16]);
+    block.extend((picture.data.len() as u32).to_be_bytes());
+    block.extend(&picture.data);
+    block
+}
+
+fn parse_picture(value: &str) -> Option<Picture> {
+    let block = BASE64.decode(value.trim()).ok()?;
+    let mut reader = ByteReader(&block);
+
+    let picture_type = reader.u32_be()?;
+    let mime_type = reader.string_be()?;
+    let description = reader.string_be()?;
+    reader.take(16)?;
+    let len = reader.u32_be()? as usize;
+    let data = reader.take(len)?.to_vec();
+
+    Some(Picture {
+        mime_type,
+        picture_type: picture_type_from_u32(picture_type),
+        description,
+        data,
+    })
+}
+
+fn picture_type_from_u32(n: u32) -> PictureType {
+    use PictureType as Ty;
+    match n {
+        1 => Ty::Icon,
+        2 => Ty::OtherIcon,
+        3 => Ty::CoverFront,
+        4 => Ty::CoverBack,
+        5 => Ty::Leaflet,
+        6 => Ty::Media,
+        7 => Ty::LeadArtist,
+        8 => Ty::Artist,
+        9 => Ty::Conductor,
+        10 => Ty::Band,
+        11 => Ty::Composer,
+        12 => Ty::Lyricist,
+        13 => Ty::RecordingLocation,
+        14 => Ty::DuringRecording,
+        15 => Ty::DuringPerformance,
+        16 => Ty::ScreenCapture,
+        17 => Ty::BrightFish,
+        18 => Ty::Illustration,
+        19 => Ty::BandLogo,
+        20 => Ty::PublisherLogo,
+        _ => Ty::Other,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Codec {
+    Vorbis,
+    Opus,
+}
+
+impl Codec {
+    fn from_id_packet(packet: &[u8]) -> Option<Self> {
+        if packet.starts_with(b"\x01vorbis") {
+            Some(Self::Vorbis)
+        } else if packet.starts_with(b"OpusHead") {
+            Some(Self::Opus)
+        } else {
+            None
+        }
+    }
+
+    /// What the comment packet starts with.
+    fn comment_magic(self) -> &'static [u8] {
+        match self {
+            Self::Vorbis => b"\x03vorbis",
+            Self::Opus => b"OpusTags",
+        }
+    }
+
+    /// How many packets come before the audio.
+    fn header_packets(self) -> usize {
+        match self {
+            Self::Vorbis => 3,
+            Self::Opus => 2,
+        }
+    }
+}
+
+/// The comment packet, split into the vendor string and the comments.
+struct Comments {
+    vendor: Vec<u8>,
+    fields: Vec<(String, String)>,
+    /// What comes after the comments, like the framing bit of Vorbis.
+    trailer: Vec<u8>,
+}
+
+impl Comments {
+    fn parse(codec: Codec, packet: &[u8]) -> Result<Self, String> {
+        let invalid = || "invalid comment header".to_string();
+
+        let mut reader = ByteReader(packet);
+        if reader.take(codec.comment_magic().len()) != Some(codec.comment_magic()) {
+            return Err(invalid());
+        }
+
+        let len = reader.u32_le().ok_or_else(invalid)? as usize;
+        let vendor = reader.take(len).ok_or_else(invalid)?.to_vec();
+
+        let count = reader.u32_le().ok_or_else(invalid)?;
+        let mut fields = vec![];
+        for _ in 0..count {
+            let len = reader.u32_le().ok_or_else(invalid)? as usize;
+            let comment = reader.take(len).ok_or_else(invalid)?;
+            let comment = String::from_utf8_lossy(comment);
+            // Comments without a `=` aren't valid, and are dropped.
+            if let Some((key, value)) = comment.split_once('=') {
+                fields.push((key.to_string(), value.to_string()));
+            }
+        }
+
+        Ok(Self {
+            vendor,
+            fields,
+            trailer: reader.0.to_vec(),
+        })
+    }
+
+    fn to_packet(&self, codec: Codec) -> Vec<u8> {
+        let mut packet = codec.comment_magic().to_vec();
+        packet.extend((self.vendor.len() as u32).to_le_bytes());
+        packet.extend(&self.vendor);
+
+        packet.extend((self.fields.len() as u32).to_le_bytes());
+        for (key, value) in &self.fields {
+            let comment = format!("{}={}", key, value);
+            packet.extend((comment.len() as u32).to_le_bytes());
+            packet.extend(comment.as_bytes());
+        }
+
+        packet.extend(&self.trailer);
+        if matches!(codec, Codec::Vorbis) && self.trailer.is_empty() {
+            packet.push(1);
+        }
+        packet
+    }
+}
+
+/// The packets before the audio, and where the pages holding them end.
+struct Headers {
+    codec: Codec,
+    packets: Vec<Vec<u8>>,
+    serial: u32,
+    /// How many pages the packets took up.
+    pages: u32,
+    end: u64,
+}
+
+impl Headers {
+    fn read(path: &Path) -> Result<Self, String> {
+        let file = fs::File::open(path).map_err(|e| format!("failed to open file: {}", e))?;
+        let mut reader = BufReader::new(file);
+
+        let mut packets = vec![];
+        let mut packet = vec![];
+
+        let first = Page::read(&mut reader)?.ok_or("empty ogg file")?;
+        if first.header_type & FIRST_PAGE == 0 {
+            return Err("not the start of an ogg stream".to_string());
+        }
+        first.split_packets(&mut packets, &mut packet);
+
+        let codec = packets
+            .first()
+            .and_then(|packet| Codec::from_id_packet(packet))
+            .ok_or("not an ogg vorbis or opus stream")?;
+        let serial = first.serial;
+        let mut pages = 1;
+        let mut end = first.len() as u64;
+
+        while packets.len() < codec.header_packets() {
+            let page = Page::read(&mut reader)?.ok_or("ogg file ends before its headers")?;
+            if page.serial != serial {
+                return Err("multiplexed ogg streams aren't supported".to_string());
+            }
+            pages += 1;
+            end += page.len() as u64;
+            page.split_packets(&mut packets, &mut packet);
+        }
+
+        // The audio always starts on a page of its own.
+        if packets.len() > codec.header_packets() || !packet.is_empty() {
+            return Err("audio shares a page with the ogg headers".to_string());
+        }
+
+        Ok(Self {
+            codec,
+            packets,
+            serial,
+            pages,
+            end,
+        })
+    }
+
+    /// The header packets split into pages, the first packet on a page of its own.
+    fn pages(&self) -> Vec<Page> {
+        let mut pages = vec![];
+        let mut page = Page::new(self.serial, 0, FIRST_PAGE);
+
+        for (i, packet) in self.packets.iter().enumerate() {
+            let mut lacing = vec![MAX_SEGMENTS as u8; packet.len() / MAX_SEGMENTS];
+            lacing.push((packet.len() % MAX_SEGMENTS) as u8);
+
+            let mut offset = 0;
+            for segment in lacing {
+                if page.segments.len() == MAX_SEGMENTS {
+                    let sequence = page.sequence + 1;
+                    let header_type = match offset {
+                        0 => 0,
+                        _ => CONTINUED_PACKET,
+                    };
+                    pages.push(std::mem::replace(
+                        &mut page,
+                        Page::new(self.serial, sequence, header_type),
+                    ));
+                }
+
+                let segment_len = segment as usize;
+                page.segments.push(segment);
+                page.data.extend(&packet[offset..offset + segment_len]);
+                offset += segment_len;
+            }
+
+            let last = i + 1 == self.packets.len();
+            if i == 0 || last {
+                let sequence = page.sequence + 1;
+                pages.push(std::mem::replace(
+                    &mut page,
+                    Page::new(self.serial, sequence, 0),
+                ));
+            }
+        }
+
+        pages
+    }
+}
+
+/// Copies the file with new header pages, and puts the copy in its place.
+fn rewrite(path: &Path, tmp: &Path, headers: &Headers) -> Result<(), String> {
+    let err = |e: std::io::Error| format!("failed to write tags to file: {}", e);
+    let mut file = fs::File::open(path).map_err(err)?;
+    let mut out = BufWriter::new(fs::File::create(tmp).map_err(err)?);
+
+    let pages = headers.pages();
+    for page in &pages {
+        out.write_all(&page.to_bytes()).map_err(err)?;
+    }
+
+    file.seek(SeekFrom::Start(headers.end)).map_err(err)?;
+    let shift = pages.len() as i64 - headers.pages as i64;
+    if shift == 0 {
+        std::io::copy(&mut file, &mut out).map_err(err)?;
+    } else {
+        let mut reader = BufReader::new(file);
+        while let Some(mut page) = Page::read(&mut reader)? {
+            if page.serial == headers.serial {
+                page.sequence = (page.sequence as i64 + shift) as u32;
+            }
+            out.write_all(&page.to_bytes()).map_err(err)?;
+        }
+    }
+
+    let out = out.into_inner().map_err(|e| err(e.into_error()))?;
+    out.sync_all().map_err(err)?;
+    fs::rename(tmp, path).map_err(err)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".tagging");
+    path.with_file_name(name)
+}
+
+struct Page {
+    header_type: u8,
+    granule: u64,
+    serial: u32,
+    sequence: u32,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl Page {
+    const HEADER_LEN: usize = 27;
+
+    /// An empty page of the headers, which have no granule position.
+    fn new(serial: u32, sequence: u32, header_type: u8) -> Self {
+        Self {
+            header_type,
+            granule: 0,
+            serial,
+            sequence,
+            segments: vec![],
+            data: vec![],
+        }
+    }
+
+    /// Reads the next page, or nothing at the end of the file.
+    fn read(reader: &mut impl Read) -> Result<Option<Self>, String> {
+        let err = |e: std::io::Error| format!("failed to read ogg page: {}", e);
+
+        let mut header = [0; Self::HEADER_LEN];
+        let read = read_full(reader, &mut header).map_err(err)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if read < header.len() || &header[..4] != CAPTURE_PATTERN || header[4] != 0 {
+            return Err("invalid ogg page".to_string());
+        }
+
+        let mut segments = vec![0; header[26] as usize];
+        reader.read_exact(&mut segments).map_err(err)?;
+        let len = segments.iter().map(|&segment| segment as usize).sum();
+        let mut data = vec![0; len];
+        reader.read_exact(&mut data).map_err(err)?;
+
+        let u32_at = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap());
+        Ok(Some(Self {
+            header_type: header[5],
+            granule: u64::from_le_bytes(header[6..14].try_into().unwrap()),
+            serial: u32_at(14),
+            sequence: u32_at(18),
+            segments,
+            data,
+        }))
+    }
+
+    fn len(&self) -> usize {
+        Self::HEADER_LEN + self.segments.len() + self.data.len()
+    }
+
+    /// Adds the data of the page to the packet being read, and moves each packet that ends
+    /// on this page to `packets`.
+    fn split_packets(&self, packets: &mut Vec<Vec<u8>>, packet: &mut Vec<u8>) {
+        let mut offset = 0;
+        for &segment in &self.segments {
+            let segment = segment as usize;
+            packet.extend(&self.data[offset..offset + segment]);
+            offset += segment;
+
+            if segment < MAX_SEGMENTS {
+                packets.push(std::mem::take(packet));
+            }
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        bytes.extend(CAPTURE_PATTERN);
+        bytes.push(0);
+        bytes.push(self.header_type);
+        bytes.extend(self.granule.to_le_bytes());
+        bytes.extend(self.serial.to_le_bytes());
+        bytes.extend(self.sequence.to_le_bytes());
+        bytes.extend([0; 4]);
+        bytes.push(self.segments.len() as u8);
+        bytes.extend(&self.segments);
+        bytes.extend(&self.data);
+
+        let crc = crc(&bytes);
+        bytes[22..26].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+}
+
+/// Reads until the buffer is full or the file ends, returning how much was read.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// The table of the CRC-32 Ogg uses, which unlike the common one isn't bit-reversed.
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 0x8000_0000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x04c1_1db7,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+fn crc(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+    })
+}
+
+/// Reads the fields of a packet or picture block one after the other.
+struct ByteReader<'a>(&'a [u8]);
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u32_le(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u32_be(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn string_be(&mut self) -> Option<String> {
+        let len = self.u32_be()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+}
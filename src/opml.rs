@@ -1,79 +1,248 @@
 use crate::config;
 use crate::config::PodcastConfig;
+use crate::config::PodcastConfigs;
+use crate::index::PodcastIndex;
+use crate::paths;
+use opml::Outline;
 use opml::OPML;
 use regex::Regex;
-use std::collections::HashMap;
 use std::fs;
-use std::io::Write as IoWrite;
 use std::path::Path;
 
-pub async fn export(p: &Path, filter: Option<Regex>) {
+/// Writes the podcasts as OPML to a file, or to stdout if there's none.
+pub async fn export(p: Option<&Path>, filter: Option<Regex>) {
     let podcasts = config::PodcastConfigs::load()
         .assert_not_empty()
         .filter(filter);
 
-    let opml = OPML::from(podcasts);
-    let xml_string = opml.to_string().unwrap();
+    let xml_string = export_opml(podcasts);
 
-    fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(p)
-        .unwrap()
-        .write_all(xml_string.as_bytes())
-        .unwrap();
+    let Some(p) = p else {
+        print!("{}", xml_string);
+        return;
+    };
+
+    if let Err(e) = paths::write(p, xml_string) {
+        eprintln!("failed to write {}: {}", p.display(), e);
+        std::process::exit(1);
+    }
 }
 
-pub fn import(p: &Path, catch_up: bool) {
-    let opml_string = std::fs::read_to_string(p).unwrap();
-    let opml = opml::OPML::from_str(&opml_string).unwrap();
+/// The podcasts as an OPML document, which [`import_opml`] reads back.
+///
+/// The `text` of a feed is its title from the last sync, if it has been synced, while `title`
+/// keeps its name in `podcasts.toml`, so importing the file gives the same names.
+pub fn export_opml(podcasts: PodcastConfigs) -> String {
+    let mut opml = OPML::from(podcasts);
+    for outline in &mut opml.body.outlines {
+        let fetched_title = outline
+            .title
+            .as_deref()
+            .and_then(PodcastIndex::load)
+            .and_then(|index| index.title().map(String::from));
+        if let Some(title) = fetched_title {
+            outline.text = title;
+        }
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}\n",
+        opml.to_string().unwrap()
+    )
+}
 
-    let mut podcasts = HashMap::default();
+/// Subscribes to the podcasts in an OPML file, skipping the ones already subscribed to.
+///
+/// Returns the names the podcasts were added under.
+pub fn import(p: &Path) -> Vec<String> {
+    let podcasts = match import_opml(p) {
+        Ok(podcasts) => podcasts,
+        Err(e) => {
+            eprintln!("importing failed: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    for podcast in opml.body.outlines.into_iter() {
-        let title = {
-            let title = podcast.title.unwrap_or(podcast.text);
+    if podcasts.is_empty() {
+        eprintln!("no podcasts found.");
+        return vec![];
+    }
 
-            if title.is_empty() {
-                None
-            } else {
-                Some(title)
-            }
+    let (added, skipped) = config::PodcastConfigs::extend(podcasts);
+    for name in &skipped {
+        eprintln!("skipped '{}': already subscribed", name);
+    }
+    for (title, name) in &added {
+        if title != name {
+            eprintln!("added '{}' as '{}': the name is taken", title, name);
+        }
+    }
+    eprintln!("{} podcasts added, {} skipped", added.len(), skipped.len());
+
+    added.into_iter().map(|(_, name)| name).collect()
+}
+
+/// Reads the podcasts of an OPML file, in the order they're listed.
+///
+/// Outlines that group others, like categories, are flattened. Feeds without a title are
+/// skipped, and feeds that are listed more than once are only read once.
+pub fn import_opml(p: &Path) -> Result<Vec<(String, PodcastConfig)>, String> {
+    let opml_string =
+        fs::read_to_string(p).map_err(|e| format!("failed to read {:?}: {}", p, e))?;
+    let opml = OPML::from_str(&opml_string).map_err(|e| format!("invalid OPML: {}", e))?;
+
+    let mut feeds = vec![];
+    collect_feeds(opml.body.outlines, &mut feeds);
+
+    // A feed listed under several categories is only imported once.
+    let mut podcasts: Vec<(String, PodcastConfig)> = vec![];
+    for (name, podcast) in feeds {
+        let duplicate = podcasts
+            .iter()
+            .any(|(_, config)| config.url.trim() == podcast.url.trim());
+        if !duplicate {
+            podcasts.push((name, podcast));
+        }
+    }
+
+    Ok(podcasts)
+}
+
+fn collect_feeds(outlines: Vec<Outline>, podcasts: &mut Vec<(String, PodcastConfig)>) {
+    for outline in outlines {
+        collect_feeds(outline.outlines, podcasts);
+
+        let Some(url) = outline.xml_url.filter(|url| !url.trim().is_empty()) else {
+            continue;
         };
 
-        let (name, mut podcast) = match (title, podcast.xml_url) {
-            (None, None) => {
-                eprintln!("importing failed due to feed with missing title and url");
-                std::process::exit(1);
-            }
-            (Some(title), None) => {
-                eprintln!(
-                    "importing failed due to following podcast missing its' url: {}",
-                    title
-                );
-                std::process::exit(1);
-            }
-            (None, Some(url)) => {
-                eprintln!(
-                    "importing failed due to podcast with following url missing a title: {}",
-                    url
-                );
-                std::process::exit(1);
-            }
-            (Some(title), Some(url)) => (title, PodcastConfig::new(url)),
+        let name = [outline.title.unwrap_or_default(), outline.text]
+            .into_iter()
+            .map(|name| name.trim().to_string())
+            .find(|name| !name.is_empty());
+
+        let Some(name) = name else {
+            eprintln!("skipped feed without a title: {}", url);
+            continue;
         };
 
-        if catch_up {
-            podcast.catch_up();
-        }
+        podcasts.push((name, PodcastConfig::new(url.trim().to_string())));
+    }
+}
 
-        podcasts.insert(name, podcast);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GlobalConfig;
+    use crate::display::DownloadBar;
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+
+    fn podcasts(feeds: &[(&str, &str)]) -> PodcastConfigs {
+        feeds
+            .iter()
+            .map(|(name, url)| (name.to_string(), PodcastConfig::new(url.to_string())))
+            .collect()
     }
 
-    if podcasts.is_empty() {
-        eprintln!("no podcasts found.");
-    } else {
-        config::PodcastConfigs::extend(podcasts);
+    /// Writes the OPML to a file and imports it again.
+    fn round_trip(opml: &str, name: &str) -> Vec<(String, String)> {
+        let path = paths::test_dir(name).join("feeds.opml");
+        fs::write(&path, opml).unwrap();
+        import_opml(&path)
+            .unwrap()
+            .into_iter()
+            .map(|(name, config)| (name, config.url))
+            .collect()
+    }
+
+    #[test]
+    fn export_then_import() {
+        let feeds = [
+            ("Zebra Talk", "https://example.com/zebra.xml"),
+            ("Café & Co", "https://example.com/feed?id=1&format=rss"),
+            ("日本語ラジオ", "https://example.com/jp.xml"),
+        ];
+        let opml = export_opml(podcasts(&feeds));
+        assert!(opml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+
+        let mut expected: Vec<(String, String)> = feeds
+            .iter()
+            .map(|(name, url)| (name.to_string(), url.to_string()))
+            .collect();
+        expected.sort();
+        assert_eq!(round_trip(&opml, "opml-round-trip"), expected);
+    }
+
+    #[test]
+    fn synced_feeds_are_named_after_their_title() {
+        let name = "opml test podcast that was synced";
+        let index: PodcastIndex = serde_json::from_value(
+            serde_json::json!({ "title": "The Real Title", "episodes": [] }),
+        )
+        .unwrap();
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new(name.to_string(), GlobalConfig::default().style(), &mp, 7);
+        index.save(name, &ui);
+
+        let opml = export_opml(podcasts(&[(name, "https://example.com/synced.xml")]));
+        let outline = OPML::from_str(&opml).unwrap().body.outlines.remove(0);
+        assert_eq!(outline.text, "The Real Title");
+        assert_eq!(outline.title.as_deref(), Some(name));
+
+        // The name from podcasts.toml wins when importing.
+        assert_eq!(
+            round_trip(&opml, "opml-synced"),
+            vec![(
+                name.to_string(),
+                "https://example.com/synced.xml".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn sorted_by_name() {
+        let opml = export_opml(podcasts(&[
+            ("b", "https://b"),
+            ("a", "https://a"),
+            ("c", "https://c"),
+        ]));
+        let texts: Vec<String> = OPML::from_str(&opml)
+            .unwrap()
+            .body
+            .outlines
+            .into_iter()
+            .map(|outline| outline.text)
+            .collect();
+        assert_eq!(texts, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn imports_from_other_apps() {
+        let opml = r#"<?xml version="1.0"?>
+            <opml version="2.0"><head><title>Other app</title></head><body>
+                <outline text="News">
+                    <outline text="Daily" type="rss" xmlUrl=" https://example.com/daily.xml "/>
+                    <outline text="No title" title="" type="rss" xmlUrl="https://example.com/x.xml"/>
+                </outline>
+                <outline text="Tech">
+                    <outline text="Daily again" type="rss" xmlUrl="https://example.com/daily.xml"/>
+                </outline>
+                <outline text="   " type="rss" xmlUrl="https://example.com/untitled.xml"/>
+                <outline text="Not a feed"/>
+            </body></opml>"#;
+
+        assert_eq!(
+            round_trip(opml, "opml-other-apps"),
+            vec![
+                (
+                    "Daily".to_string(),
+                    "https://example.com/daily.xml".to_string()
+                ),
+                (
+                    "No title".to_string(),
+                    "https://example.com/x.xml".to_string()
+                ),
+            ]
+        );
     }
 }
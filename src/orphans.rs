@@ -0,0 +1,414 @@
+//! Download trackers left behind by podcasts that were renamed in `podcasts.toml`.
+//!
+//! A podcast is identified by its name alone: the download directory and tracker follow
+//! from it, while the feed url can change freely. Renaming a podcast therefore leaves its
+//! tracker behind, and the next sync would download every episode again. To find those
+//! trackers, each sync records the feed url next to the tracker. A tracker that no podcast
+//! uses anymore belongs to the podcast whose url is the same as the recorded one, up to
+//! differences like a trailing slash or a query string.
+//!
+//! Only trackers in a directory of their own, next to the download directory of a podcast,
+//! are found. That covers the default `download_path` and any other that ends with the name.
+
+use crate::config::GlobalConfig;
+use crate::config::Layout;
+use crate::config::PodcastConfigs;
+use crate::download_tracker::DownloadedEpisodes;
+use crate::naming;
+use crate::paths;
+use crate::state;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+
+/// The feed url a podcast was last synced from, kept next to its download tracker.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FeedRecord {
+    url: String,
+}
+
+impl FeedRecord {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".feed");
+        tracker_path.with_file_name(name)
+    }
+
+    fn load(tracker_path: &Path) -> Option<String> {
+        let s = fs::read_to_string(Self::path(tracker_path)).ok()?;
+        let record: Self = serde_json::from_str(&s).ok()?;
+        Some(record.url)
+    }
+
+    /// Records the url, unless it's already the recorded one.
+    pub fn record(tracker_path: &Path, url: &str) {
+        if Self::load(tracker_path).as_deref() == Some(url) {
+            return;
+        }
+
+        let record = Self {
+            url: url.to_string(),
+        };
+        let Ok(json) = serde_json::to_string_pretty(&record) else {
+            return;
+        };
+
+        if let Err(e) = paths::write(Self::path(tracker_path), json) {
+            log::warn!("failed to save feed url: {:?}", e);
+        }
+    }
+}
+
+/// The url without the parts that don't tell feeds apart: the scheme, a `www.` prefix,
+/// the query, the fragment and trailing slashes.
+///
+/// Urls that can't be parsed are only trimmed.
+fn normalize(url: &str) -> String {
+    let url = url.trim();
+    let Ok(parsed) = reqwest::Url::parse(url) else {
+        return url.trim_end_matches('/').to_lowercase();
+    };
+
+    let host = parsed.host_str().unwrap_or_default();
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let port = parsed.port().map(|port| format!(":{}", port));
+    format!(
+        "{}{}{}",
+        host,
+        port.unwrap_or_default(),
+        parsed.path().trim_end_matches('/')
+    )
+}
+
+/// A tracker that no podcast uses, with the podcast whose feed it was recorded for.
+#[derive(Debug)]
+pub struct Orphan {
+    pub podcast: String,
+    pub tracker: PathBuf,
+}
+
+impl Orphan {
+    fn dir(&self) -> &Path {
+        self.tracker.parent().unwrap_or(Path::new(""))
+    }
+}
+
+/// The orphaned trackers whose feed url matches exactly one of the podcasts.
+pub fn find(podcasts: &PodcastConfigs, global_config: &GlobalConfig) -> Vec<Orphan> {
+    let mut used = HashSet::new();
+    let mut by_url: HashMap<String, Vec<&String>> = HashMap::new();
+    // The directories to look for trackers in, with the file name trackers have there.
+    let mut places = HashSet::new();
+
+    for (name, config) in podcasts {
+        let tracker = config.tracker_path(name, global_config);
+        used.insert(naming::fold(&tracker.to_string_lossy()));
+        by_url.entry(normalize(&config.url)).or_default().push(name);
+
+        let dir = config.download_dir(name, global_config);
+        if config.layout(global_config) != Layout::PerPodcast || tracker.parent() != Some(&dir) {
+            continue;
+        }
+        if let (Some(parent), Some(file_name)) = (dir.parent(), tracker.file_name()) {
+            places.insert((parent.to_path_buf(), file_name.to_owned()));
+        }
+    }
+
+    let mut orphans = vec![];
+    for (parent, file_name) in places {
+        let Ok(entries) = fs::read_dir(&parent) else {
+            continue;
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let tracker = entry.path().join(&file_name);
+            if used.contains(&naming::fold(&tracker.to_string_lossy())) || !tracker.is_file() {
+                continue;
+            }
+
+            let Some(url) = FeedRecord::load(&tracker) else {
+                continue;
+            };
+
+            if let Some([podcast]) = by_url.get(&normalize(&url)).map(Vec::as_slice) {
+                orphans.push(Orphan {
+                    podcast: (*podcast).clone(),
+                    tracker,
+                });
+            }
+        }
+    }
+
+    orphans.sort_by(|a, b| a.tracker.cmp(&b.tracker));
+    orphans
+}
+
+/// Moves the orphaned trackers, along with their state and episodes, to the podcasts they
+/// belong to, for `--fix-orphans`.
+///
+/// A podcast only adopts a tracker when it has nothing downloaded yet, and when exactly one
+/// tracker was left behind for it. The state of its own tracker is replaced.
+pub fn fix(global_config: &GlobalConfig) {
+    let podcasts = PodcastConfigs::load();
+    let orphans = find(&podcasts, global_config);
+    if orphans.is_empty() {
+        eprintln!("no orphaned download trackers found");
+        return;
+    }
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for orphan in &orphans {
+        *counts.entry(orphan.podcast.as_str()).or_default() += 1;
+    }
+
+    let mut failed = false;
+    for orphan in &orphans {
+        let name = &orphan.podcast;
+        if counts[name.as_str()] > 1 {
+            eprintln!(
+                "'{}': skipped {:?}, several trackers were left behind for it",
+                name,
+                orphan.dir()
+            );
+            failed = true;
+            continue;
+        }
+
+        match adopt(&podcasts, orphan, global_config) {
+            Ok(dir) => eprintln!("'{}': adopted {:?}, now at {:?}", name, orphan.dir(), dir),
+            Err(e) => {
+                eprintln!("'{}': skipped {:?}, {}", name, orphan.dir(), e);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn adopt(
+    podcasts: &PodcastConfigs,
+    orphan: &Orphan,
+    global_config: &GlobalConfig,
+) -> Result<PathBuf, String> {
+    let name = &orphan.podcast;
+    let (_, config) = podcasts
+        .into_iter()
+        .find(|(podcast, _)| *podcast == name)
+        .ok_or_else(|| format!("no podcast named '{}'", name))?;
+    let tracker = config.tracker_path(name, global_config);
+    let dir = config.download_dir(name, global_config);
+
+    if !DownloadedEpisodes::load(&tracker).is_empty() {
+        return Err("the podcast already has downloaded episodes".to_string());
+    }
+
+    let own_state: Vec<PathBuf> = state::tracker_state_paths(&tracker)
+        .into_iter()
+        .map(|(_, path)| path)
+        .chain([tracker.clone()])
+        .collect();
+
+    let moves = PodcastConfigs::planned_moves(
+        name,
+        (orphan.dir(), Layout::PerPodcast, &orphan.tracker),
+        (&dir, config.layout(global_config), &tracker),
+    );
+    if let Some((_, to)) = moves
+        .iter()
+        .find(|(_, to)| to.exists() && !own_state.contains(to))
+    {
+        return Err(format!("file already exists: {:?}", to));
+    }
+
+    for path in own_state.iter().filter(|path| path.exists()) {
+        fs::remove_file(path).map_err(|e| format!("failed to remove {:?}: {}", path, e))?;
+    }
+
+    paths::create_dir(&dir);
+    for (from, to) in moves {
+        paths::move_path(&from, &to)
+            .map_err(|e| format!("failed to move {:?} to {:?}: {}", from, to, e))?;
+    }
+
+    // Only succeeds if nothing else is left in it.
+    let _ = fs::remove_dir(orphan.dir());
+
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PodcastConfig;
+    use crate::display::DownloadBar;
+    use crate::net;
+    use crate::podcast::Podcast;
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn normalizing_urls() {
+        let url = "example.com/feed.xml";
+        for variant in [
+            "https://example.com/feed.xml",
+            "http://www.example.com/feed.xml/",
+            "https://example.com/feed.xml?format=rss#top",
+            " https://Example.com/feed.xml ",
+        ] {
+            assert_eq!(normalize(variant), url, "{}", variant);
+        }
+        assert_eq!(
+            normalize("https://example.com:8080/feed"),
+            "example.com:8080/feed"
+        );
+        assert_ne!(normalize("https://example.com/other.xml"), url);
+        assert_eq!(normalize("not a url/"), "not a url");
+    }
+
+    /// A server with a feed of two episodes, at `/feed.xml` with or without a query.
+    /// Returns its url and the paths it has been asked for.
+    async fn feed_server() -> (String, Arc<Mutex<Vec<String>>>) {
+        net::test_server(|url| {
+            let items: String = (1..=2)
+                .map(|n| {
+                    format!(
+                        "<item><title>Episode {n}</title><guid>episode-{n}</guid>\
+                        <pubDate>2024-01-0{n}T06:00:00Z</pubDate><enclosure url=\"{url}/{n}.mp3\" \
+                        type=\"audio/mpeg\" length=\"5\"/></item>"
+                    )
+                })
+                .collect();
+            let feed = format!(
+                "<?xml version=\"1.0\"?><rss><channel><title>Podcast</title>{}</channel></rss>",
+                items
+            );
+            vec![
+                (
+                    "/feed.xml",
+                    "application/rss+xml",
+                    feed.clone().into_bytes(),
+                ),
+                (
+                    "/feed.xml?format=rss",
+                    "application/rss+xml",
+                    feed.into_bytes(),
+                ),
+                ("/1.mp3", "audio/mpeg", b"one..".to_vec()),
+                ("/2.mp3", "audio/mpeg", b"two..".to_vec()),
+            ]
+        })
+        .await
+    }
+
+    /// Every podcast in `dir`, by the name it has there.
+    fn global_config(dir: &Path) -> GlobalConfig {
+        toml::from_str(&format!(
+            "download_path = \"{}/{{podname}}\"\npipeline = [\"rename\"]",
+            dir.display()
+        ))
+        .unwrap()
+    }
+
+    fn podcast_config(url: &str) -> PodcastConfig {
+        toml::from_str(&format!("url = \"{}\"", url)).unwrap()
+    }
+
+    /// Syncs the podcast, returning how many episodes were downloaded.
+    async fn sync(name: &str, url: &str, global_config: &GlobalConfig) -> usize {
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let mut ui = DownloadBar::new(name.to_string(), global_config.style(), &mp, 7);
+        let client = Arc::new(reqwest::Client::new());
+        let podcast = Podcast::new(
+            name.to_string(),
+            podcast_config(url),
+            global_config,
+            client,
+            &ui,
+        )
+        .await
+        .unwrap();
+        podcast.sync(&mut ui).await.new
+    }
+
+    fn enclosure_requests(requests: &Mutex<Vec<String>>) -> usize {
+        let requests = requests.lock().unwrap();
+        requests
+            .iter()
+            .filter(|path| path.ends_with(".mp3"))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn editing_the_url_keeps_the_podcast() {
+        let dir = paths::test_dir("orphans-url-edit");
+        let global_config = global_config(&dir);
+        let (url, requests) = feed_server().await;
+        let feed = format!("{}/feed.xml", url);
+
+        assert_eq!(sync("podcast", &feed, &global_config).await, 2);
+        assert_eq!(enclosure_requests(&requests), 2);
+
+        // The state stays with the name, so nothing is downloaded again.
+        let edited = format!("{}?format=rss", feed);
+        assert_eq!(sync("podcast", &edited, &global_config).await, 0);
+        assert_eq!(enclosure_requests(&requests), 2);
+
+        let podcasts =
+            PodcastConfigs::from_iter([("podcast".to_string(), podcast_config(&edited))]);
+        let tracker = podcast_config(&edited).tracker_path("podcast", &global_config);
+        assert_eq!(FeedRecord::load(&tracker), Some(edited));
+        assert!(find(&podcasts, &global_config).is_empty());
+    }
+
+    #[tokio::test]
+    async fn renamed_podcasts_adopt_their_tracker() {
+        let dir = paths::test_dir("orphans-renamed");
+        let global_config = global_config(&dir);
+        let (url, requests) = feed_server().await;
+        let feed = format!("{}/feed.xml", url);
+        assert_eq!(sync("old", &feed, &global_config).await, 2);
+
+        // Renamed, with the url edited along the way.
+        let edited = format!("{}?format=rss", feed);
+        let podcasts = PodcastConfigs::from_iter([("new".to_string(), podcast_config(&edited))]);
+        let orphans = find(&podcasts, &global_config);
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].podcast, "new");
+        assert_eq!(orphans[0].dir(), dir.join("old"));
+
+        let adopted = adopt(&podcasts, &orphans[0], &global_config).unwrap();
+        assert_eq!(adopted, dir.join("new"));
+        assert!(!dir.join("old").exists());
+        assert!(find(&podcasts, &global_config).is_empty());
+
+        // The episodes came along, so nothing is downloaded again.
+        assert_eq!(sync("new", &edited, &global_config).await, 0);
+        assert_eq!(enclosure_requests(&requests), 2);
+        let tracker = podcast_config(&edited).tracker_path("new", &global_config);
+        assert_eq!(DownloadedEpisodes::entries(&tracker).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn adopting_needs_an_empty_podcast() {
+        let dir = paths::test_dir("orphans-taken");
+        let global_config = global_config(&dir);
+        let (url, _) = feed_server().await;
+        let feed = format!("{}/feed.xml", url);
+        assert_eq!(sync("old", &feed, &global_config).await, 2);
+        assert_eq!(sync("new", &feed, &global_config).await, 2);
+
+        let podcasts = PodcastConfigs::from_iter([("new".to_string(), podcast_config(&feed))]);
+        let orphans = find(&podcasts, &global_config);
+        assert_eq!(orphans.len(), 1);
+        let e = adopt(&podcasts, &orphans[0], &global_config).unwrap_err();
+        assert_eq!(e, "the podcast already has downloaded episodes");
+        assert!(dir.join("old").exists());
+    }
+}
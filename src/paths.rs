@@ -0,0 +1,493 @@
+//! Where TaleCast keeps its files, and helpers for managing them.
+
+use crate::read_only;
+use crate::trash;
+use crate::trash::Trash;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+pub fn config_dir() -> PathBuf {
+    let path = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => dirs::home_dir()
+            .expect("unable to locate home directory. Try setting 'XDG_CONFIG_HOME' manually")
+            .join(".config"),
+    }
+    .join(crate::APPNAME);
+
+    prepare_dir(&path);
+
+    path
+}
+
+pub fn cache_dir() -> PathBuf {
+    // Tests keep what they cache out of the user's cache.
+    #[cfg(test)]
+    let path = std::env::temp_dir().join(format!("talecast-{}-cache", process::id()));
+    #[cfg(not(test))]
+    let path = match std::env::var("XDG_CACHE_HOME") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => dirs::cache_dir()
+            .expect("unable to locate cache direcotry. Try setting 'XDG_CACHE_HOME' manually"),
+    }
+    .join(crate::APPNAME);
+
+    prepare_dir(&path);
+
+    path
+}
+
+pub fn default_download_path() -> PathBuf {
+    let path = dirs::home_dir()
+        .expect("unable to load home directory. Try hardcoding the download path in settings.")
+        .join(crate::APPNAME);
+    prepare_dir(&path);
+    path
+}
+
+/// Creates a directory TaleCast keeps its files in. A read-only run leaves a missing one
+/// missing, for reading from it to fail like it would when it's empty.
+fn prepare_dir(path: &Path) {
+    if !read_only::is_enabled() {
+        create_dir(path);
+    }
+}
+
+/// Writes a file like `fs::write`, unless the run is read-only.
+pub fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    read_only::check(path.as_ref());
+    fs::write(path, contents)
+}
+
+/// Moves a file or directory, falling back to copying when crossing filesystems.
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    read_only::check(to);
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    if from.is_dir() {
+        move_dir_contents(from, to)
+    } else {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    }
+}
+
+/// Moves everything inside `from` into `to`, removing `from` afterwards.
+pub fn move_dir_contents(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        move_path(&entry.path(), &to.join(entry.file_name()))?;
+    }
+
+    fs::remove_dir(from)
+}
+
+/// Removes all but the `keep` most recent `.old` backups of the given file.
+pub fn prune_backups(path: &Path, keep: usize, trash: Option<&Trash>) {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return;
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut backups: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|p| {
+            p.file_name()
+                .map(|n| n.to_string_lossy())
+                .is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".old"))
+        })
+        .collect();
+
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    for backup in backups.into_iter().take(excess) {
+        if let Err(e) = trash::remove_file(trash, &backup, None) {
+            log::warn!("failed to remove backup {:?}: {}", &backup, e);
+        }
+    }
+}
+
+pub fn create_dir(path: &Path) {
+    if path.is_dir() {
+        return;
+    }
+
+    read_only::check(path);
+    if let Err(e) = fs::create_dir_all(path) {
+        eprintln!("failed to create following directory: {:?}", path);
+        match name_too_long(path, &e) {
+            Some(msg) => eprintln!("error: {}", msg),
+            None => eprintln!("error: {:?}", e),
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+tokio::task_local! {
+    /// The free space a single test pretends every filesystem has.
+    static FREE_SPACE: Option<u64>;
+}
+
+/// Bytes that can still be written to the filesystem that `path` is on, if it can be told.
+pub fn free_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    #[cfg(test)]
+    if let Ok(free) = FREE_SPACE.try_with(|free| *free) {
+        return free;
+    }
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    // The field types differ between platforms.
+    #[allow(clippy::useless_conversion)]
+    Some(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+}
+
+/// Runs `future` as if every filesystem had `free` bytes left, or an unknown amount.
+#[cfg(test)]
+pub async fn with_free_space<F: std::future::Future>(free: Option<u64>, future: F) -> F::Output {
+    FREE_SPACE.scope(free, future).await
+}
+
+/// How long names and paths may be on a filesystem, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathLimits {
+    /// Longest allowed name of a single file or directory.
+    pub component: usize,
+    /// Longest allowed path.
+    pub total: usize,
+}
+
+impl PathLimits {
+    /// What's assumed when the filesystem can't be probed.
+    #[cfg(windows)]
+    const DEFAULT: Self = Self {
+        component: 255,
+        total: 260,
+    };
+    #[cfg(target_os = "macos")]
+    const DEFAULT: Self = Self {
+        component: 255,
+        total: 1024,
+    };
+    #[cfg(not(any(windows, target_os = "macos")))]
+    const DEFAULT: Self = Self {
+        component: 255,
+        total: 4096,
+    };
+
+    /// The limits of the filesystem that `dir` is on.
+    ///
+    /// The longest allowed file name is probed by creating files in the nearest existing
+    /// directory, once per directory and run. Filesystems like eCryptfs allow much less
+    /// than the usual 255 bytes. A read-only run can't probe, and assumes the usual limits.
+    pub fn of(dir: &Path) -> Self {
+        if read_only::is_enabled() {
+            return Self::DEFAULT;
+        }
+
+        let Some(dir) = dir.ancestors().find(|dir| dir.is_dir()) else {
+            return Self::DEFAULT;
+        };
+
+        let mut probed = Self::probed().lock().unwrap();
+        *probed.entry(dir.to_path_buf()).or_insert_with(|| {
+            let component = probe_component_len(dir).unwrap_or(Self::DEFAULT.component);
+            if component != Self::DEFAULT.component {
+                log::info!("file names in {:?} are limited to {} bytes", dir, component);
+            }
+            Self {
+                component,
+                ..Self::DEFAULT
+            }
+        })
+    }
+
+    /// The limits found for each directory so far.
+    fn probed() -> &'static Mutex<HashMap<PathBuf, PathLimits>> {
+        static PROBED: OnceLock<Mutex<HashMap<PathBuf, PathLimits>>> = OnceLock::new();
+        PROBED.get_or_init(Default::default)
+    }
+
+    /// Makes [`Self::of`] return `limits` for the existing directory `dir`, instead of
+    /// probing it.
+    #[cfg(test)]
+    pub fn assume(dir: &Path, limits: PathLimits) {
+        Self::probed()
+            .lock()
+            .unwrap()
+            .insert(dir.to_path_buf(), limits);
+    }
+
+    /// How long the name of a file in `dir` may be.
+    pub fn file_name_len(&self, dir: &Path) -> usize {
+        let dir_len = dir.as_os_str().len() + 1; // + 1 for the separator.
+        self.component.min(self.total.saturating_sub(dir_len))
+    }
+}
+
+/// Finds the longest file name that can be created in `dir`, if it can be found out.
+fn probe_component_len(dir: &Path) -> Option<usize> {
+    const PREFIX: &str = ".talecast-probe-";
+
+    // Whether a file with a name of `len` bytes can be created. `None` if something
+    // other than the length of the name went wrong.
+    let fits = |len: usize| -> Option<bool> {
+        let name = format!("{}{}", PREFIX, "x".repeat(len.saturating_sub(PREFIX.len())));
+        let path = dir.join(name);
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => {
+                let _ = fs::remove_file(&path);
+                Some(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::InvalidFilename => Some(false),
+            Err(e) => {
+                log::debug!("failed to probe file name length in {:?}: {}", dir, e);
+                None
+            }
+        }
+    };
+
+    longest_fitting(PREFIX.len(), PathLimits::DEFAULT.component, fits)
+}
+
+/// The longest length from `min` up to `max` that `fits`, assuming `min` does. `None` if
+/// `fits` can't tell.
+fn longest_fitting(
+    min: usize,
+    max: usize,
+    mut fits: impl FnMut(usize) -> Option<bool>,
+) -> Option<usize> {
+    if fits(max)? {
+        return Some(max);
+    }
+
+    // The longest length that fits is in `low..high`.
+    let (mut low, mut high) = (min, max);
+    while high - low > 1 {
+        let mid = (low + high) / 2;
+        match fits(mid)? {
+            true => low = mid,
+            false => high = mid,
+        }
+    }
+
+    Some(low)
+}
+
+/// Explains an error caused by `path` being too long, naming the part that's over the limit.
+///
+/// Returns `None` for other errors.
+pub fn name_too_long(path: &Path, error: &io::Error) -> Option<String> {
+    if error.kind() != io::ErrorKind::InvalidFilename {
+        return None;
+    }
+
+    let limits = PathLimits::of(path.parent().unwrap_or(path));
+
+    let long_component = path
+        .components()
+        .map(|component| component.as_os_str())
+        .find(|component| component.len() > limits.component);
+
+    let msg = match long_component {
+        Some(component) => format!(
+            "the name {:?} is {} bytes long, but the filesystem allows at most {} bytes",
+            component,
+            component.len(),
+            limits.component
+        ),
+        None if path.as_os_str().len() > limits.total => format!(
+            "the path {:?} is {} bytes long, but at most {} bytes are allowed",
+            path,
+            path.as_os_str().len(),
+            limits.total
+        ),
+        None => format!("the path {:?} is too long", path),
+    };
+
+    Some(msg)
+}
+
+/// The message for an io error on `path`, explaining what's too long if that's the problem.
+pub fn io_error_message(path: &Path, error: &io::Error, msg: &str) -> String {
+    match name_too_long(path, error) {
+        Some(reason) => format!("{}: {}", msg, reason),
+        None => msg.to_string(),
+    }
+}
+
+/// An empty directory of its own for a test.
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("talecast-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probing_finds_the_longest_name() {
+        let mut probes = 0;
+        let fits = |len| {
+            probes += 1;
+            Some(len <= 143)
+        };
+        assert_eq!(longest_fitting(16, 255, fits), Some(143));
+        assert!(probes <= 9, "{} probes", probes);
+
+        assert_eq!(longest_fitting(16, 255, |_| Some(true)), Some(255));
+        // Probing gives up when a probe fails for another reason.
+        let flaky = |len| (len < 200).then_some(len <= 143);
+        assert_eq!(longest_fitting(16, 255, flaky), None);
+    }
+
+    #[test]
+    fn assumed_limits() {
+        let dir = test_dir("assumed-limits");
+        let limits = PathLimits {
+            component: 143,
+            total: 4096,
+        };
+        PathLimits::assume(&dir, limits);
+
+        assert_eq!(PathLimits::of(&dir), limits);
+        // Directories that don't exist yet have the limits of the nearest one that does.
+        assert_eq!(PathLimits::of(&dir.join("new")), limits);
+        assert_eq!(limits.file_name_len(&dir), 143);
+
+        let short_paths = PathLimits {
+            component: 255,
+            total: dir.as_os_str().len() + 1 + 100,
+        };
+        assert_eq!(short_paths.file_name_len(&dir), 100);
+    }
+
+    #[test]
+    fn moving_directory_contents() {
+        let dir = test_dir("move-dir-contents");
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::create_dir_all(from.join("nested")).unwrap();
+        fs::write(from.join("a.mp3"), "a").unwrap();
+        fs::write(from.join("nested").join("b.mp3"), "b").unwrap();
+        fs::create_dir_all(&to).unwrap();
+        fs::write(to.join("c.mp3"), "c").unwrap();
+
+        move_dir_contents(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(to.join("a.mp3")).unwrap(), "a");
+        assert_eq!(
+            fs::read_to_string(to.join("nested").join("b.mp3")).unwrap(),
+            "b"
+        );
+        assert_eq!(fs::read_to_string(to.join("c.mp3")).unwrap(), "c");
+    }
+
+    #[test]
+    fn pruning_keeps_the_newest_backups() {
+        let dir = test_dir("prune-backups");
+        let path = dir.join("podcasts.toml");
+        for name in [
+            "podcasts.toml",
+            "podcasts.toml.1.old",
+            "podcasts.toml.2.old",
+            "podcasts.toml.3.old",
+            "podcasts.toml.tmp",
+            "config.toml.1.old",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        prune_backups(&path, 2, None);
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "config.toml.1.old",
+                "podcasts.toml",
+                "podcasts.toml.2.old",
+                "podcasts.toml.3.old",
+                "podcasts.toml.tmp",
+            ]
+        );
+    }
+
+    #[test]
+    fn explaining_long_names() {
+        let dir = test_dir("name-too-long");
+        // Long enough for the directories of the test, but nothing longer.
+        let component = dir
+            .components()
+            .map(|component| component.as_os_str().len())
+            .max()
+            .unwrap();
+        let total = dir.as_os_str().len() + 2 * component;
+        PathLimits::assume(&dir, PathLimits { component, total });
+        let too_long = io::Error::from(io::ErrorKind::InvalidFilename);
+
+        let name = "a".repeat(component + 1);
+        assert_eq!(
+            name_too_long(&dir.join(&name), &too_long).unwrap(),
+            format!(
+                "the name {:?} is {} bytes long, but the filesystem allows at most {} bytes",
+                name,
+                component + 1,
+                component
+            )
+        );
+
+        let name = "a".repeat(component);
+        let path = dir.join(&name).join(&name).join("c.mp3");
+        assert_eq!(
+            name_too_long(&path, &too_long).unwrap(),
+            format!(
+                "the path {:?} is {} bytes long, but at most {} bytes are allowed",
+                path,
+                path.as_os_str().len(),
+                total
+            )
+        );
+
+        let other = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(name_too_long(&path, &other), None);
+        assert_eq!(
+            io_error_message(&path, &other, "failed to save episode"),
+            "failed to save episode"
+        );
+        assert!(io_error_message(&path, &too_long, "failed to save episode")
+            .starts_with("failed to save episode: the path"));
+    }
+}
@@ -1,6 +1,10 @@
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
+use crate::format;
+use crate::naming;
+use crate::naming::NameOptions;
+use crate::paths;
 use crate::utils;
 
 use regex::Regex;
@@ -20,8 +24,10 @@ impl FullPattern {
             if c == '}' {
                 assert!(is_inside);
                 let text_pattern = std::mem::take(&mut pattern);
-                let pattern = Pattern::from_str(&text_pattern);
-                let segment = Segment::Pattern(pattern);
+                let mut parts = text_pattern.split('|');
+                let pattern = Pattern::from_str(parts.next().unwrap_or_default());
+                let filters = parts.map(Filter::from_str).collect();
+                let segment = Segment::Pattern(pattern, filters);
                 segments.push(segment);
                 is_inside = false;
             } else if c == '{' {
@@ -46,6 +52,74 @@ impl FullPattern {
         Self(segments)
     }
 
+    /// Checks that a pattern would parse, without exiting if it doesn't.
+    pub fn check(s: &str) -> Result<(), String> {
+        let mut pattern: Option<String> = None;
+
+        for c in s.chars() {
+            match (c, &mut pattern) {
+                ('{', None) => pattern = Some(String::new()),
+                ('{', Some(_)) => return Err(format!("unexpected '{{' in \"{}\"", s)),
+                ('}', None) => return Err(format!("unexpected '}}' in \"{}\"", s)),
+                ('}', Some(inner)) => {
+                    let mut parts = inner.split('|');
+                    let name = parts.next().unwrap_or_default();
+                    if UnitPattern::from_str(name).is_none() && DataPattern::parse(name)?.is_none()
+                    {
+                        return Err(format!("invalid pattern: \"{}\"", name));
+                    }
+                    if let Some(filter) = parts.find(|f| Filter::parse(f).is_none()) {
+                        return Err(format!("invalid filter: \"{}\"", filter));
+                    }
+                    pattern = None;
+                }
+                (c, Some(inner)) => inner.push(c),
+                (_, None) => {}
+            }
+        }
+
+        match pattern {
+            Some(_) => Err(format!("unclosed '{{' in \"{}\"", s)),
+            None => Ok(()),
+        }
+    }
+
+    /// The placeholders of a pattern whose values come from the feed, like `{title}`, with
+    /// where they start.
+    ///
+    /// Paths that are found without fetching the feed, like the download tracker, can't
+    /// depend on these. Assumes the pattern was checked.
+    pub fn feed_placeholders(s: &str) -> Vec<(usize, String)> {
+        let mut placeholders = vec![];
+        let mut start = None;
+
+        for (i, c) in s.char_indices() {
+            match (c, start) {
+                ('{', _) => start = Some(i),
+                ('}', Some(at)) => {
+                    let inner = &s[at + 1..i];
+                    let name = inner.split('|').next().unwrap_or_default();
+                    let from_feed = match UnitPattern::from_str(name) {
+                        Some(unit) => !matches!(
+                            unit,
+                            UnitPattern::PodName | UnitPattern::AppName | UnitPattern::Home
+                        ),
+                        None => DataPattern::parse(name).is_ok_and(|data| {
+                            data.is_some_and(|data| !matches!(data.ty, DataPatternType::CurrDate))
+                        }),
+                    };
+                    if from_feed {
+                        placeholders.push((at, format!("{{{}}}", inner)));
+                    }
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        placeholders
+    }
+
     pub fn direct_eval(s: &str, data: EvalData<'_>) -> String {
         Self::from_str(s).evaluate(data)
     }
@@ -53,14 +127,14 @@ impl FullPattern {
     pub fn direct_eval_file(s: &str, data: EvalData<'_>) -> PathBuf {
         let p = PathBuf::from(Self::direct_eval(s, data));
         if let Some(path) = p.parent() {
-            utils::create_dir(&path);
+            paths::create_dir(&path);
         }
         p
     }
 
     pub fn direct_eval_dir(s: &str, data: EvalData<'_>) -> PathBuf {
         let p = PathBuf::from(Self::direct_eval(s, data));
-        utils::create_dir(&p);
+        paths::create_dir(&p);
         p
     }
 }
@@ -68,7 +142,7 @@ impl FullPattern {
 #[derive(Clone, Debug)]
 enum Segment {
     Text(String),
-    Pattern(Pattern),
+    Pattern(Pattern, Vec<Filter>),
 }
 
 #[derive(Debug, Clone)]
@@ -80,43 +154,147 @@ enum Pattern {
 impl Pattern {
     fn from_str(s: &str) -> Self {
         if let Some(unit) = UnitPattern::from_str(s) {
-            Self::Unit(unit)
-        } else if let Some(data) = DataPattern::from_str(s) {
-            Self::Data(data)
-        } else {
-            eprintln!("invalid pattern: \"{}\"", s);
-            std::process::exit(1);
+            return Self::Unit(unit);
+        }
+
+        match DataPattern::parse(s) {
+            Ok(Some(data)) => Self::Data(data),
+            Ok(None) => {
+                eprintln!("invalid pattern: \"{}\"", s);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
         }
     }
 }
 
+/// Reformats the value of a pattern, like `{enclosure_length|human_bytes}`.
+///
+/// Values that don't parse as what the filter expects are left as they are.
+#[derive(Clone, Debug)]
+enum Filter {
+    /// A size in bytes.
+    HumanBytes,
+    /// A duration in seconds or `[HH:]MM:SS`.
+    HumanDuration,
+    /// A unix time or a date.
+    RelativeDate,
+    /// Any text, with the characters that aren't allowed in file names removed.
+    Sanitize,
+}
+
+impl Filter {
+    fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "human_bytes" => Self::HumanBytes,
+            "human_duration" => Self::HumanDuration,
+            "relative_date" => Self::RelativeDate,
+            "sanitize" => Self::Sanitize,
+            _ => return None,
+        }
+        .into()
+    }
+
+    fn from_str(s: &str) -> Self {
+        match Self::parse(s) {
+            Some(filter) => filter,
+            None => {
+                eprintln!("invalid filter: \"{}\"", s);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn apply(&self, value: String) -> String {
+        let formatted = match self {
+            Self::HumanBytes => value.trim().parse().ok().map(format::human_bytes),
+            Self::HumanDuration => format::parse_clock_duration(&value).map(format::human_duration),
+            Self::RelativeDate => value
+                .trim()
+                .parse()
+                .ok()
+                .or_else(|| Some(utils::date_str_to_unix(&value).ok()?.as_secs()))
+                .map(|unix| format::relative_date(unix, utils::current_unix().as_secs())),
+            Self::Sanitize => Some(naming::sanitize_filename(NameOptions::default(), &value)),
+        };
+
+        formatted.unwrap_or(value)
+    }
+}
+
 #[derive(Clone, Debug)]
 struct DataPattern {
     ty: DataPatternType,
     data: String,
+    /// The number of digits to pad numbers to with zeros, like `{rss::episode::itunes:episode::3}`.
+    width: Option<usize>,
 }
 
 impl DataPattern {
-    fn from_str(s: &str) -> Option<Self> {
+    /// Parses a data pattern, or returns `None` if it isn't one.
+    ///
+    /// Fails if the date format or width that the pattern takes in is invalid, so that it's
+    /// noticed when the config is loaded rather than halfway through a sync.
+    fn parse(s: &str) -> Result<Option<Self>, String> {
         for ty in DataPatternType::iter() {
-            if let Some(caps) = ty.regex().captures(s) {
-                if let Some(match_str) = caps.get(1) {
-                    return Some(Self {
-                        ty,
-                        data: match_str.as_str().to_owned(),
-                    });
+            let Some(match_str) = ty.regex().captures(s).and_then(|caps| caps.get(1)) else {
+                continue;
+            };
+
+            let mut data = match_str.as_str().to_owned();
+            let mut width = None;
+            match ty {
+                DataPatternType::PubDate | DataPatternType::CurrDate => {
+                    let is_valid = data == "unix"
+                        || !chrono::format::StrftimeItems::new(&data)
+                            .any(|item| item == chrono::format::Item::Error);
+                    if !is_valid {
+                        return Err(format!("invalid date format: \"{}\"", data));
+                    }
+                }
+                DataPatternType::RssEpisode | DataPatternType::RssChannel => {
+                    if let Some((key, digits)) = data.rsplit_once("::") {
+                        let parsed = match digits.parse() {
+                            Ok(width)
+                                if digits.chars().all(|c| c.is_ascii_digit())
+                                    && width <= MAX_WIDTH =>
+                            {
+                                width
+                            }
+                            _ => return Err(format!("invalid width: \"{}\" in \"{}\"", digits, s)),
+                        };
+                        width = Some(parsed);
+                        data = key.to_owned();
+                    }
                 }
             }
+
+            return Ok(Some(Self { ty, data, width }));
         }
-        None
+
+        Ok(None)
     }
 }
 
+/// The widest a number can be padded to, which is already more than any file name needs.
+const MAX_WIDTH: usize = 32;
+
+/// Pads a number with zeros to `width` digits. Anything else is left as it is.
+fn pad_number(value: String, width: usize) -> String {
+    if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit()) {
+        return value;
+    }
+
+    format!("{:0>width$}", value, width = width)
+}
+
 impl Evaluate for DataPattern {
     fn evaluate(&self, data: EvalData) -> String {
         use chrono::TimeZone;
         use DataPatternType as Ty;
-        let null = "<value not found>";
 
         match self.ty {
             Ty::CurrDate => {
@@ -143,15 +321,24 @@ impl Evaluate for DataPattern {
                     datetime.format(formatting).to_string()
                 }
             }
+            // Tags missing from the feed leave their part of the pattern empty.
             Ty::RssEpisode => {
                 let key = &self.data;
 
-                data.episode.get_str(&key).unwrap_or(null).to_string()
+                let value = data.episode.get_text(key).unwrap_or_default();
+                match self.width {
+                    Some(width) => pad_number(value, width),
+                    None => value,
+                }
             }
             Ty::RssChannel => {
                 let key = &self.data;
 
-                data.podcast.get_str(&key).unwrap_or(null).to_string()
+                let value = data.podcast.get_text(key).unwrap_or_default();
+                match self.width {
+                    Some(width) => pad_number(value, width),
+                    None => value,
+                }
             }
         }
     }
@@ -187,6 +374,10 @@ enum UnitPattern {
     PodName,
     AppName,
     Home,
+    EnclosureLength,
+    Title,
+    Author,
+    PodcastTitle,
 }
 
 impl UnitPattern {
@@ -197,6 +388,10 @@ impl UnitPattern {
             "podname" => Self::PodName,
             "appname" => Self::AppName,
             "home" => Self::Home,
+            "enclosure_length" => Self::EnclosureLength,
+            "title" => Self::Title,
+            "author" => Self::Author,
+            "podcast_title" => Self::PodcastTitle,
             _ => return None,
         }
         .into()
@@ -211,6 +406,17 @@ impl Evaluate for UnitPattern {
             Self::PodName => data.pod_name.to_string(),
             Self::AppName => crate::APPNAME.to_string(),
             Self::Home => home().unwrap_or("<missing home>".to_string()),
+            Self::EnclosureLength => match data.episode.length() {
+                Some(length) => length.to_string(),
+                None => String::new(),
+            },
+            Self::Title => data.episode.title().to_string(),
+            // Like the artist and album tags.
+            Self::Author => match data.episode.author() {
+                Ok(author) => author.to_string(),
+                Err(_) => data.podcast.author().unwrap_or_default().to_string(),
+            },
+            Self::PodcastTitle => data.podcast.title().unwrap_or(data.pod_name).to_string(),
         }
     }
 }
@@ -239,8 +445,15 @@ impl Evaluate for FullPattern {
         for segment in &self.0 {
             let text = match segment {
                 Segment::Text(text) => text.clone(),
-                Segment::Pattern(Pattern::Unit(pattern)) => pattern.evaluate(data),
-                Segment::Pattern(Pattern::Data(pattern)) => pattern.evaluate(data),
+                Segment::Pattern(pattern, filters) => {
+                    let value = match pattern {
+                        Pattern::Unit(pattern) => pattern.evaluate(data),
+                        Pattern::Data(pattern) => pattern.evaluate(data),
+                    };
+                    filters
+                        .iter()
+                        .fold(value, |value, filter| filter.apply(value))
+                }
             };
             output.push_str(&text);
         }
@@ -248,3 +461,146 @@ impl Evaluate for FullPattern {
         output
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::episode::Attributes;
+    use crate::episode::RawEpisode;
+    use crate::media::MediaPreference;
+    use crate::podcast::RawPodcast;
+    use crate::xml;
+
+    /// A podcast and its episode from the tags of an rss channel and its item.
+    fn feed(channel: &str, item: &str) -> (RawPodcast, Attributes) {
+        let xml = format!(
+            r#"<rss xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd"><channel>
+            {}
+            <item>
+                <pubDate>Mon, 02 Sep 2024 06:00:00 +0000</pubDate>
+                <enclosure url="https://example.com/ep.mp3" type="audio/mpeg" length="1000"/>
+                {}
+            </item>
+            </channel></rss>"#,
+            channel, item
+        );
+        let mut channel = xml::get_inner_channel(&xml).unwrap();
+        let item = channel.as_object_mut().unwrap().remove("item").unwrap();
+
+        let podcast = RawPodcast::new(xml::restore_namespaces(&channel));
+        let raw = RawEpisode::new(xml::restore_namespaces(&item));
+        let episode = Attributes::new(raw, &MediaPreference::default()).unwrap();
+        (podcast, episode)
+    }
+
+    fn eval(pattern: &str, channel: &str, item: &str) -> String {
+        let (podcast, episode) = feed(channel, item);
+        FullPattern::direct_eval(pattern, EvalData::new("podname", &podcast, &episode))
+    }
+
+    #[test]
+    fn valid_patterns() {
+        for pattern in [
+            "{pubdate::%Y-%m-%d} {rss::episode::title}",
+            "{pubdate::unix}-{guid}",
+            "{home}/{appname}/{podname}",
+            "{rss::episode::itunes:episode::3} {title|sanitize}",
+            "{enclosure_length|human_bytes}",
+            "no patterns at all",
+        ] {
+            assert_eq!(FullPattern::check(pattern), Ok(()), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let cases = [
+            ("{pubdate::%Q}", "invalid date format: \"%Q\""),
+            (
+                "{rss::episode::itunes:episode::x}",
+                "invalid width: \"x\" in \"rss::episode::itunes:episode::x\"",
+            ),
+            (
+                "{rss::episode::itunes:episode::33}",
+                "invalid width: \"33\" in \"rss::episode::itunes:episode::33\"",
+            ),
+            ("{nonsense}", "invalid pattern: \"nonsense\""),
+            ("{title|shout}", "invalid filter: \"shout\""),
+            ("{title", "unclosed '{' in \"{title\""),
+            ("{ti{tle}", "unexpected '{' in \"{ti{tle}\""),
+            ("title}", "unexpected '}' in \"title}\""),
+        ];
+
+        for (pattern, error) in cases {
+            assert_eq!(FullPattern::check(pattern), Err(error.to_string()));
+        }
+    }
+
+    #[test]
+    fn padded_numbers() {
+        assert_eq!(pad_number("7".to_string(), 3), "007");
+        assert_eq!(pad_number("1234".to_string(), 3), "1234");
+        assert_eq!(pad_number("7b".to_string(), 3), "7b");
+        assert_eq!(pad_number(String::new(), 3), "");
+    }
+
+    #[test]
+    fn numeric_tags_are_padded() {
+        let pattern = "{rss::episode::itunes:episode::3} {rss::episode::itunes:season::2}";
+        let item = "<title>Hi</title>
+            <itunes:episode>7</itunes:episode>
+            <itunes:season>bonus</itunes:season>";
+        assert_eq!(eval(pattern, "", item), "007 bonus");
+    }
+
+    #[test]
+    fn missing_tags_are_empty() {
+        let pattern = "[{rss::episode::itunes:episode::3}][{rss::channel::itunes:author}]";
+        assert_eq!(eval(pattern, "", "<title>Hi</title>"), "[][]");
+    }
+
+    #[test]
+    fn sanitized_titles() {
+        let pattern = "{pubdate::%Y-%m-%d} {rss::episode::title|sanitize}";
+        let item = "<title>AC/DC: live?</title>";
+        assert_eq!(eval(pattern, "", item), "2024-09-02 ACDC live");
+
+        let pattern = "{rss::channel::title|sanitize}/{title}";
+        let channel = "<title>ラジオ/第1回</title>";
+        assert_eq!(
+            eval(pattern, channel, "<title>a/b</title>"),
+            "ラジオ第1回/a/b"
+        );
+    }
+
+    #[test]
+    fn feed_placeholders() {
+        let names = |s: &str| -> Vec<String> {
+            FullPattern::feed_placeholders(s)
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect()
+        };
+        assert!(names("{home}/{appname}/{podname}/{currdate::%Y}").is_empty());
+        assert_eq!(
+            names("{home}/{podcast_title}/{pubdate::%Y}/{title|sanitize}"),
+            vec!["{podcast_title}", "{pubdate::%Y}", "{title|sanitize}"]
+        );
+        assert_eq!(
+            FullPattern::feed_placeholders("/pods/{rss::channel::title}"),
+            vec![(6, "{rss::channel::title}".to_string())]
+        );
+    }
+
+    #[test]
+    fn formatting_filters() {
+        let pattern =
+            "{enclosure_length|human_bytes} {rss::episode::itunes:duration|human_duration}";
+        let item = "<title>Hi</title><itunes:duration>01:30:00</itunes:duration>";
+        assert_eq!(eval(pattern, "", item), "1000 B 1h 30m");
+
+        // Values that aren't what the filter expects are left as they are.
+        assert_eq!(eval("{title|human_bytes}", "", "<title>Hi</title>"), "Hi");
+        assert_eq!(eval("{podcast_title}", "", "<title>Hi</title>"), "podname");
+    }
+}
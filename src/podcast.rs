@@ -1,78 +1,124 @@
+use crate::cache::CachedFeed;
+use crate::cadence::Cadence;
+use crate::changelog::ChangelogEntry;
 use crate::config::DownloadMode;
 use crate::config::EvalData;
 use crate::config::PodcastConfig;
+use crate::config::RecheckSettings;
+use crate::config::RetrySettings;
+use crate::config::TitleFilter;
 use crate::config::{Config, GlobalConfig};
+use crate::deadline;
 use crate::display::DownloadBar;
+use crate::download_tracker::DownloadedEpisodes;
 use crate::episode;
+use crate::episode::DownloadedEpisode;
 use crate::episode::Episode;
 use crate::episode::RawEpisode;
+use crate::episode::XmlWrapper;
+use crate::feed_delta::FeedDelta;
+use crate::feed_delta::SeenItems;
+use crate::feed_hints::FeedHints;
+use crate::hook::HookRecords;
+use crate::hook::HookStatus;
+use crate::hook::HookSupervisor;
+use crate::index::PodcastIndex;
+use crate::json_feed;
+use crate::net;
+use crate::net::DownloadError;
+use crate::numbering;
+use crate::orphans::FeedRecord;
+use crate::paths;
+use crate::quota;
+use crate::quota::DailyCap;
+use crate::reliability::LengthReliability;
+use crate::snapshot;
+use crate::summary::Phase;
+use crate::summary::SyncResult;
 use crate::tags;
-use crate::utils;
-use quickxml_to_serde::{xml_string_to_json, Config as XmlConfig};
+use crate::throttle;
+use crate::trash;
+use crate::trash::Trash;
+use crate::utils::Unix;
+use crate::xml;
+use futures::stream;
+use futures::StreamExt;
 use serde_json::Map;
 use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::Hasher;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
-fn get_inner_channel(xml: String) -> Option<serde_json::Value> {
-    let conf = XmlConfig::new_with_defaults();
-    std::mem::take(
-        xml_string_to_json(xml, &conf)
-            .ok()?
-            .get_mut("rss")?
-            .get_mut("channel")?,
-    )
-    .into()
+/// A digest of the settings that decide what a sync of the podcast downloads.
+fn settings_digest(
+    name: &str,
+    config: &PodcastConfig,
+    global_config: &GlobalConfig,
+) -> Option<u64> {
+    // Going through a json value sorts the keys of the maps in the settings.
+    let settings = serde_json::to_value((name, config, global_config)).ok()?;
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write(settings.to_string().as_bytes());
+    Some(hasher.finish())
 }
 
-/// Converts the podcast's xml string to serde values of the channel and the episodes.
-///
-/// The library will merge different namespaces together, which is why we manually change
-/// the itunes namespace, and then after converting it, we change it back. Preserving itunes:XXX as
-/// separate keys.
-fn xml_to_value(xml: &str, ui: &DownloadBar) -> Option<(RawPodcast, Vec<RawEpisode>)> {
-    ui.log_info("converting xml to serde values");
-    let placeholder = "__placeholder__";
-    let replacement = format!("itunes{}", placeholder);
-    let xml = xml.replace("itunes:", &replacement);
-    let mut val = match get_inner_channel(xml) {
-        Some(val) => val,
-        None => {
-            ui.log_error("failed to find rss/channel xml tags");
-            return None;
-        }
+/// The settings digest combined with the download tracker, which is what a settled sync
+/// is checked against.
+fn sync_state(settings: u64, tracker_path: &Path) -> Option<u64> {
+    let tracker = match fs::read(tracker_path) {
+        Ok(tracker) => tracker,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(_) => return None,
     };
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write_u64(settings);
+    hasher.write(&tracker);
+    Some(hasher.finish())
+}
 
-    // Create a new map to store the transformed keys at the top level
-    let mut new_map: Map<String, Value> = Map::new();
-
-    if let Some(obj) = val.as_object() {
-        for (key, value) in obj {
-            let new_key = key.replace(&replacement, "itunes:");
-            new_map.insert(new_key, value.clone());
-        }
+/// Converts the podcast's feed to serde values of the channel and the episodes.
+///
+/// Feeds are read as rss, unless they're json, which is read as a JSON Feed.
+pub fn parse_feed(text: &str, ui: &DownloadBar) -> Result<(RawPodcast, Vec<RawEpisode>), String> {
+    if json_feed::is_json(text) {
+        ui.log_info("converting JSON Feed to serde values");
+        return json_feed::parse(text);
     }
 
-    let podcast = RawPodcast::new(new_map);
+    ui.log_info("converting xml to serde values");
+    let Some(mut val) = xml::get_inner_channel(text) else {
+        ui.log_error("failed to find rss/channel xml tags");
+        return Err("failed to parse xml".into());
+    };
+
+    let podcast = RawPodcast::new(xml::restore_namespaces(&val));
 
-    let items = std::mem::take(val.as_object_mut()?.get_mut("item")?.as_array_mut()?);
+    // A feed with a single episode has it as an object rather than in an array.
+    let items = val
+        .as_object_mut()
+        .and_then(|channel| channel.remove("item"));
+    let items = match items {
+        Some(Value::Array(items)) => items,
+        Some(item) => vec![item],
+        None => vec![],
+    };
 
     let episodes = items
         .iter()
-        .map(|item| {
-            let mut new_item_map: Map<String, Value> = Map::new();
-            for (key, val) in item.as_object().expect("unexpected serde type").iter() {
-                let new_key = key.replace(&replacement, "itunes:");
-                new_item_map.insert(new_key, val.clone());
-            }
-            RawEpisode::new(new_item_map)
-        })
+        .map(|item| RawEpisode::new(xml::restore_namespaces(item)))
         .collect::<Vec<RawEpisode>>();
 
-    Some((podcast, episodes))
+    Ok((podcast, episodes))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct RawPodcast(Map<String, serde_json::Value>);
 
 impl RawPodcast {
@@ -80,12 +126,24 @@ impl RawPodcast {
         Self(raw)
     }
 
+    pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+        self.0.get(key)
+    }
+
     pub fn get_str(&self, key: &str) -> Option<&str> {
-        utils::val_to_str(self.0.get(key)?)
+        xml::val_to_str(self.0.get(key)?)
+    }
+
+    /// The text of a tag, with numbers written out too.
+    pub fn get_text(&self, key: &str) -> Option<String> {
+        xml::val_to_string(self.0.get(key)?)
     }
 
-    pub fn title(&self) -> &str {
-        self.get_str("title").unwrap()
+    /// The title of the podcast, unless the feed has none that can be read.
+    pub fn title(&self) -> Option<&str> {
+        self.get_str("title")
+            .map(str::trim)
+            .filter(|title| !title.is_empty())
     }
 
     pub fn author(&self) -> Option<&str> {
@@ -96,14 +154,14 @@ impl RawPodcast {
     pub fn categories(&self) -> Vec<&str> {
         let key = "itunes:category";
         match self.0.get(key).and_then(|x| x.as_array()) {
-            Some(v) => v.iter().filter_map(utils::val_to_str).collect(),
+            Some(v) => v.iter().filter_map(xml::val_to_str).collect(),
             None => vec![],
         }
     }
 
     pub fn copyright(&self) -> Option<&str> {
         let inner = self.0.get("copyright")?;
-        utils::val_to_str(&inner)
+        xml::val_to_str(&inner)
     }
 
     pub fn language(&self) -> Option<&str> {
@@ -112,15 +170,132 @@ impl RawPodcast {
 
     pub fn image(&self) -> Option<&str> {
         let inner = self.0.get("image")?;
-        utils::val_to_url(inner)
+        xml::val_to_url(inner)
     }
+
+    pub fn description(&self) -> Option<&str> {
+        self.get_str("description")
+    }
+
+    pub fn keywords(&self) -> Option<&str> {
+        self.get_str("itunes:keywords")
+    }
+
+    pub fn funding(&self) -> Vec<Funding> {
+        xml::val_to_vec(self.0.get("podcast:funding"))
+            .into_iter()
+            .filter_map(|val| {
+                let url = val.get("@url")?.as_str()?.trim().to_string();
+                let text = val
+                    .get("#text")
+                    .and_then(|text| text.as_str())
+                    .map(str::trim)
+                    .filter(|text| !text.is_empty())
+                    .map(String::from);
+                Some(Funding { url, text })
+            })
+            .collect()
+    }
+}
+
+/// The parts of a feed that are the same for all of its episodes.
+#[derive(Debug, Default)]
+pub struct Channel {
+    /// Name of the podcast in `podcasts.toml`.
+    pub name: String,
+    /// The tags of the `channel` element, without its items.
+    pub tags: Map<String, serde_json::Value>,
+}
+
+impl Channel {
+    fn new(name: &str, raw: &RawPodcast) -> Self {
+        let mut tags = raw.0.clone();
+        tags.remove("item");
+        Self {
+            name: name.to_string(),
+            tags,
+        }
+    }
+}
+
+/// A link for supporting the podcast, from the `podcast:funding` tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Funding {
+    pub url: String,
+    pub text: Option<String>,
+}
+
+impl Funding {
+    /// Writes the funding links to a file, unless it already contains them.
+    fn save(funding: &[Self], path: &Path, ui: &DownloadBar) {
+        if funding.is_empty() {
+            return;
+        }
+
+        let contents: String = funding
+            .iter()
+            .map(|link| match &link.text {
+                Some(text) => format!("{}: {}\n", text, link.url),
+                None => format!("{}\n", link.url),
+            })
+            .collect();
+
+        if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+            return;
+        }
+
+        ui.log_debug(format!("saving funding links to {:?}", path));
+        if let Err(e) = paths::write(path, contents) {
+            ui.log_warn(format!("failed to save funding links: {:?}", e));
+        }
+    }
+}
+
+/// What became of an episode that a sync set out to download.
+enum Outcome<'a> {
+    Downloaded(DownloadedEpisode<'a>),
+    Failed(&'a Episode, DownloadError),
+    /// Left for the next sync because the max runtime was reached.
+    Postponed,
+    /// Not started because an earlier episode failed.
+    Skipped,
 }
 
 #[derive(Debug)]
 pub struct Podcast {
+    name: String,
+    url: String,
+    /// A digest of the settings, unless they couldn't be serialized.
+    settings: Option<u64>,
     episodes: Vec<Episode>,
     client: Arc<reqwest::Client>,
     mode: DownloadMode,
+    user_agent: String,
+    stop_at_known: bool,
+    recheck: RecheckSettings,
+    retry: RetrySettings,
+    /// How many episodes are downloaded at once.
+    episode_concurrency: usize,
+    trash: Option<Trash>,
+    /// How far off the lengths advertised by the feed tend to be.
+    length_reliability: LengthReliability,
+    funding: Vec<Funding>,
+    /// Where to save the funding links, if enabled.
+    funding_path: Option<PathBuf>,
+    /// Whether nothing has been downloaded from the podcast before this run.
+    first_download: bool,
+    /// The limit on new episodes per day, if there is one.
+    daily_cap: Option<DailyCap>,
+    tracker_path: PathBuf,
+    /// How many downloaded episodes are kept on disk, if older ones are removed.
+    keep_latest: Option<usize>,
+    /// The items of the feed, to be recorded by the sync, unless it doesn't record state.
+    seen: Option<SeenItems>,
+    /// How the items of the feed changed since the last sync, if that's known.
+    delta: Option<FeedDelta>,
+    /// How long a download hook may run, if it's limited.
+    hook_timeout: Option<Duration>,
+    rerun_failed_hooks: bool,
 }
 
 impl Podcast {
@@ -131,100 +306,785 @@ impl Podcast {
         client: Arc<reqwest::Client>,
         ui: &DownloadBar,
     ) -> Result<Podcast, String> {
+        let feed = Self::fetch(&name, &config, global_config, &client, ui).await?;
+        Self::from_xml(name, config, global_config, client, feed.into_xml(), ui).await
+    }
+
+    /// The episodes with `ids` in the archived snapshots of the feed, for episodes that are no
+    /// longer in it. Each one is read from the newest snapshot that has it.
+    pub async fn archived_episodes(
+        &self,
+        config: &PodcastConfig,
+        global_config: &GlobalConfig,
+        ids: &HashSet<String>,
+        ui: &DownloadBar,
+    ) -> Vec<Episode> {
+        let media = config.media_preference(global_config);
+        let mut episodes: Vec<Episode> = vec![];
+
+        for path in snapshot::paths(&self.tracker_path).into_iter().rev() {
+            if episodes.len() == ids.len() {
+                break;
+            }
+
+            let Ok(feed) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let Ok((raw_podcast, raw_episodes)) = parse_feed(&feed, ui) else {
+                ui.log_warn(format!("failed to parse feed snapshot {:?}", path));
+                continue;
+            };
+
+            let attrs = raw_episodes
+                .into_iter()
+                .filter_map(|episode| episode::Attributes::new(episode, &media).ok())
+                .collect();
+            for episode in
+                Self::episodes_from(&self.name, config, global_config, &raw_podcast, attrs, ui)
+                    .await
+            {
+                let id = episode.get_id();
+                if ids.contains(&id) && !episodes.iter().any(|found| found.get_id() == id) {
+                    ui.log_debug(format!("found {:?} in {:?}", episode.attrs.title(), path));
+                    episodes.push(episode);
+                }
+            }
+        }
+
+        episodes
+    }
+
+    /// Like [`Self::new`], but `None` if the feed hasn't changed since a sync that left
+    /// nothing to do, and neither have the settings or download tracker of the podcast.
+    pub async fn unless_settled(
+        name: String,
+        config: PodcastConfig,
+        global_config: &GlobalConfig,
+        client: Arc<reqwest::Client>,
+        ui: &DownloadBar,
+    ) -> Result<Option<Podcast>, String> {
+        let feed = Self::fetch(&name, &config, global_config, &client, ui).await?;
+
+        if let net::Feed::Unchanged(cached) = &feed {
+            let tracker_path = config.tracker_path(&name, global_config);
+            let settings = settings_digest(&name, &config, global_config);
+            let state = settings.and_then(|settings| sync_state(settings, &tracker_path));
+            if state.is_some_and(|state| cached.is_settled(state)) {
+                ui.log_info("feed unchanged since a sync that left nothing to do, skipping it");
+                return Ok(None);
+            }
+        }
+
+        Self::from_xml(name, config, global_config, client, feed.into_xml(), ui)
+            .await
+            .map(Some)
+    }
+
+    async fn fetch(
+        name: &str,
+        config: &PodcastConfig,
+        global_config: &GlobalConfig,
+        client: &reqwest::Client,
+        ui: &DownloadBar,
+    ) -> Result<net::Feed, String> {
+        config.check_layout(name, global_config)?;
+
+        let tracker_path = config.tracker_path(name, global_config);
+        if let Some(at) = snapshot::from() {
+            let (xml, path) = snapshot::load(&tracker_path, at)?;
+            ui.log_info(format!("reading the feed from {:?}", path));
+            return Ok(net::Feed::Downloaded(xml));
+        }
+
         ui.fetching();
         ui.log_info("downloading podcast info...");
-        let Some(xml_string) = utils::download_text(&client, &config.url, ui).await else {
-            return Err("failed to download xml-file".into());
-        };
+        let start = Instant::now();
+        let retry = RetrySettings::new(global_config, config);
+        let use_cache = global_config.use_feed_cache();
+        let headers = config.feed_headers(global_config)?;
+        let feed = net::with_retries(&retry, "feed", ui, |retry| {
+            if retry > 0 {
+                ui.fetching();
+            }
+            net::download_text(client, &config.url, &headers, use_cache, ui)
+        })
+        .await
+        .map_err(|e| format!("failed to download xml-file: {}", e))?;
+        ui.record(Phase::FeedFetching, start.elapsed());
 
-        let Some((raw_podcast, raw_episodes)) = xml_to_value(&xml_string, ui) else {
-            return Err("failed to parse xml".into());
-        };
+        if let Some(max) = config.feed_snapshots(global_config) {
+            if config.records_state() {
+                match snapshot::archive(&tracker_path, feed.xml(), max) {
+                    Ok(Some(path)) => ui.log_debug(format!("archived the feed to {:?}", path)),
+                    Ok(None) => ui.log_trace("feed already archived"),
+                    Err(e) => ui.log_warn(e),
+                }
+            }
+        }
 
+        Ok(feed)
+    }
+
+    async fn episodes_from(
+        name: &str,
+        config: &PodcastConfig,
+        global_config: &GlobalConfig,
+        raw_podcast: &RawPodcast,
+        episode_attrs: Vec<episode::Attributes>,
+        ui: &DownloadBar,
+    ) -> Vec<Episode> {
+        let channel = Arc::new(Channel::new(name, raw_podcast));
+        let keep_description_html = config.keep_description_html(global_config);
+        let mut episodes = vec![];
+        for (index, attr) in episode_attrs.into_iter().enumerate() {
+            let tags =
+                tags::extract_tags_from_raw(raw_podcast, name, &attr, keep_description_html, ui)
+                    .await;
+            let config = {
+                let data = EvalData::new(name, raw_podcast, &attr);
+                Config::new(global_config, config, data)
+            };
+
+            let url = attr
+                .image()
+                .ok()
+                .or(raw_podcast.image())
+                .map(ToString::to_string);
+
+            let podcast_url = raw_podcast.image().map(ToString::to_string);
+
+            let episode = Episode::new(
+                attr,
+                index,
+                config,
+                tags,
+                url,
+                podcast_url,
+                Arc::clone(&channel),
+            );
+            episodes.push(episode);
+        }
+
+        episodes
+    }
+
+    async fn from_xml(
+        name: String,
+        config: PodcastConfig,
+        global_config: &GlobalConfig,
+        client: Arc<reqwest::Client>,
+        xml_string: String,
+        ui: &DownloadBar,
+    ) -> Result<Podcast, String> {
+        let retry = RetrySettings::new(global_config, &config);
+        let settings = settings_digest(&name, &config, global_config);
+        let start = Instant::now();
+
+        let title_filter = TitleFilter::new(&config)?;
+        let (raw_podcast, raw_episodes) = parse_feed(&xml_string, ui)?;
+
+        // Episodes whose titles don't match are left out before they're numbered and counted,
+        // so limits like `max_episodes` only count the ones that match. So are the episodes
+        // outside the dates of a backlog, so that its schedule only paces the ones inside.
+        let (earliest_date, latest_date) = config.backlog_dates();
+        let in_backlog_dates = |published: Unix| {
+            earliest_date.is_none_or(|date| published >= date)
+                && latest_date.is_none_or(|date| published < date)
+        };
+        let media = config.media_preference(global_config);
         let episode_attrs = {
             let mut attrs = vec![];
 
             for episode in raw_episodes {
                 ui.log_trace("parsing attributes from raw episode");
-                match episode::Attributes::new(episode) {
+                let title = episode.get_string("title").unwrap_or_default();
+                match episode::Attributes::new(episode, &media) {
+                    Ok(attr) if !title_filter.matches(attr.title()) => {
+                        ui.log_debug(format!("title doesn't match: {}", attr.title()));
+                    }
+                    Ok(attr) if !in_backlog_dates(attr.published()) => {
+                        ui.log_debug(format!("published outside the backlog: {}", attr.title()));
+                    }
                     Ok(attr) => attrs.push(attr),
+                    Err(e) if e == episode::NO_ENCLOSURE => {
+                        ui.log_warn(format!("skipping {:?}: {}", title, e));
+                    }
                     Err(e) => {
                         ui.log_debug(e);
                     }
                 }
             }
 
+            attrs
+        };
+
+        // The fast path relies on the feed listing its newest episodes first.
+        let feed_is_ordered = episode_attrs
+            .windows(2)
+            .all(|pair| pair[0].published() >= pair[1].published());
+
+        let stop_at_known = config.stop_at_known() && {
+            if !feed_is_ordered {
+                ui.log_warn("feed is not in reverse-chronological order, doing a full scan");
+            }
+            feed_is_ordered
+        };
+
+        let episode_attrs = {
+            let mut attrs = episode_attrs;
             attrs.sort_by_key(|attr| attr.published());
+            if config.infer_episode_number(global_config) {
+                numbering::infer(&mut attrs);
+            }
             attrs
         };
 
-        let mut episodes = vec![];
-        for (index, attr) in episode_attrs.into_iter().enumerate() {
-            let tags = tags::extract_tags_from_raw(&raw_podcast, &attr, ui).await;
-            let config = {
-                let data = EvalData::new(&name, &raw_podcast, &attr);
-                Config::new(global_config, &config, data)
-            };
+        let episodes = Self::episodes_from(
+            &name,
+            &config,
+            global_config,
+            &raw_podcast,
+            episode_attrs,
+            ui,
+        )
+        .await;
 
-            let url = attr
-                .image()
-                .ok()
-                .or(raw_podcast.image())
-                .map(ToString::to_string);
+        ui.record(Phase::FeedParsing, start.elapsed());
 
-            let episode = Episode::new(attr, index, config, tags, url);
-            episodes.push(episode);
+        // Compared before the search index is replaced, as it has the titles of removed items.
+        let tracker_path = config.tracker_path(&name, global_config);
+        let (seen, delta) = match config.records_state() {
+            true => {
+                let seen = SeenItems::new(settings, &episodes);
+                let delta = seen.delta(&name, &tracker_path, &episodes);
+                (Some(seen), delta)
+            }
+            false => (None, None),
+        };
+
+        // The index of a mirror would point at the mirrored files.
+        if !config.is_mirror() {
+            let start = Instant::now();
+            PodcastIndex::new(&raw_podcast, &episodes).save(&name, ui);
+            ui.record(Phase::StateWrites, start.elapsed());
         }
 
-        let mode = DownloadMode::new(global_config, &config);
+        let funding_path = config
+            .save_funding_links(global_config)
+            .then(|| config.funding_path(&name, global_config));
+
+        if config.records_state() {
+            let published = episodes.iter().map(|e| e.attrs.published().as_secs());
+            Cadence::record(&tracker_path, published);
+            FeedHints::new(&raw_podcast).record(&tracker_path);
+            FeedRecord::record(&tracker_path, &config.url);
+        }
+
+        let first_download = DownloadedEpisodes::load(&tracker_path).is_empty();
+        let length_reliability = LengthReliability::load(&tracker_path);
+
+        let mode = DownloadMode::new(global_config, &config, &tracker_path);
+        let recheck = RecheckSettings::new(global_config, &config);
+        let trash = Trash::new(global_config, &name, config.root(global_config));
+        let daily_cap = match mode {
+            DownloadMode::Standard { .. } => config
+                .max_per_day(global_config)
+                .map(|max| DailyCap::load(&tracker_path, max)),
+            DownloadMode::Backlog { .. } => None,
+        };
+        let keep_latest = match mode {
+            DownloadMode::Standard { keep_latest, .. } => keep_latest,
+            DownloadMode::Backlog { .. } => None,
+        };
 
         Ok(Podcast {
+            name,
+            url: config.url.clone(),
+            settings,
             episodes,
             client,
             mode,
+            user_agent: global_config.user_agent(),
+            stop_at_known,
+            recheck,
+            retry,
+            episode_concurrency: config.episode_concurrency(global_config),
+            trash,
+            length_reliability,
+            funding: raw_podcast.funding(),
+            funding_path,
+            first_download,
+            daily_cap,
+            hook_timeout: global_config.hook_timeout(),
+            rerun_failed_hooks: config.rerun_failed_hooks(global_config),
+            tracker_path,
+            keep_latest,
+            seen,
+            delta,
         })
     }
 
-    pub async fn sync(self, ui: &mut DownloadBar) -> Vec<PathBuf> {
+    pub fn episodes(&self) -> &[Episode] {
+        &self.episodes
+    }
+
+    pub fn tracker_path(&self) -> &Path {
+        &self.tracker_path
+    }
+
+    /// How many episodes a sync would download now.
+    pub fn pending_count(&self) -> usize {
+        self.pending_episodes().len()
+    }
+
+    /// The episodes a sync would download now, in the order it would start them.
+    ///
+    /// Unlike [`Self::pending_count`], skipped videos and episodes over the daily cap are
+    /// left out.
+    pub fn planned_downloads(&self) -> Vec<&Episode> {
+        let mut episodes: Vec<&Episode> = self
+            .pending_episodes()
+            .into_iter()
+            .filter(|episode| !episode.is_skipped_video())
+            .collect();
+
+        if let Some(cap) = &self.daily_cap {
+            episodes.truncate(cap.remaining(quota::today()));
+        }
+
+        episodes
+    }
+
+    /// The downloaded episodes a sync would remove because of `keep_latest`, along with
+    /// where they are on disk, counting the episodes it would download.
+    pub fn planned_removals(&self) -> Vec<(&Episode, Option<PathBuf>)> {
+        let planned = self.planned_downloads();
+        self.surplus(&planned)
+            .into_iter()
+            .map(|episode| (episode, episode.downloaded_path()))
+            .collect()
+    }
+
+    pub async fn sync(mut self, ui: &mut DownloadBar) -> SyncResult {
         ui.init();
         ui.log_info("syncing...");
 
-        let episodes = self.pending_episodes();
-        let mut downloaded = vec![];
+        let mut result = SyncResult::new(self.name.clone());
+        result.first_download = self.first_download;
+
+        if let Some(delta) = self.delta.take() {
+            for title in &delta.new {
+                ui.log_info(format!("new in the feed: {}", title));
+            }
+            for title in &delta.modified {
+                ui.log_info(format!("modified in the feed: {}", title));
+            }
+            for title in &delta.removed {
+                ui.log_warn(format!("removed from the feed: {}", title));
+            }
+            result.upstream = delta;
+        }
+        if let Some(seen) = &self.seen {
+            if let Err(e) = seen.save(&self.tracker_path) {
+                ui.log_warn(e);
+            }
+        }
+
+        if let Some(path) = &self.funding_path {
+            Funding::save(&self.funding, path, ui);
+        }
+
+        let mut hooks = HookSupervisor::new(self.hook_timeout);
+        self.earlier_hooks(&mut hooks, ui);
+
+        let today = quota::today();
+        let mut daily_cap = self.daily_cap.take();
+
+        let mut downloaded = self.recheck(ui).await;
+        let (videos, mut episodes): (Vec<&Episode>, Vec<&Episode>) = self
+            .pending_episodes()
+            .into_iter()
+            .partition(|episode| episode.is_skipped_video());
+        for video in &videos {
+            video.log_debug(ui, "skipping video episode");
+        }
+        result.skipped_videos = videos.len();
+
+        // Pending episodes are newest first, so the newest ones are kept.
+        if let Some(cap) = &daily_cap {
+            let remaining = cap.remaining(today).min(episodes.len());
+            let deferred = episodes.split_off(remaining);
+            for episode in &deferred {
+                episode.log_debug(ui, "deferred by daily cap");
+            }
+            result.deferred = deferred.len();
+        }
+
+        let mut total_length = 0;
+        for episode in &episodes {
+            let length = episode.attrs.length();
+            let length = length.map(|length| self.length_reliability.adjust(length));
+            ui.add_pending(episode.attrs.url(), length);
+            total_length += length.unwrap_or(0);
+        }
+
+        if self.episode_concurrency > 1 && episodes.len() > 1 {
+            ui.begin_concurrent(total_length);
+        }
 
-        for (index, episode) in episodes.iter().enumerate() {
-            ui.begin_download(&episode, index, episodes.len());
+        let mut failure = None;
+        {
+            let bar: &DownloadBar = ui;
+            let qty = episodes.len();
+            let stopped = AtomicBool::new(false);
 
-            match episode.download(&self.client, ui).await {
-                Ok(downloaded_episode) => downloaded.push(downloaded_episode),
-                Err(e) => {
-                    ui.error(&e);
-                    break;
+            // Episodes only start once there's room for them, so the checks for the max
+            // runtime and earlier failures apply to each one as it's about to start. Those
+            // that are already downloading when one fails are let finish.
+            let attempts: Vec<_> = episodes
+                .iter()
+                .enumerate()
+                .map(|(index, episode)| self.attempt(episode, index, qty, &stopped, bar))
+                .collect();
+            let mut outcomes = stream::iter(attempts).buffer_unordered(self.episode_concurrency);
+
+            while let Some(outcome) = outcomes.next().await {
+                match outcome {
+                    Outcome::Downloaded(downloaded_episode) => {
+                        // Counted for the day it finished, in case the sync runs past
+                        // midnight.
+                        if let Some(cap) = &mut daily_cap {
+                            cap.record(quota::today());
+                        }
+                        downloaded.push(downloaded_episode);
+                    }
+                    Outcome::Postponed => result.postponed += 1,
+                    Outcome::Skipped => result.skipped += 1,
+                    Outcome::Failed(episode, e) => {
+                        let url = episode.attrs.url();
+                        bar.log_debug(format!(
+                            "reproduce with: {}",
+                            net::curl_command(url, &self.user_agent, "test.mp3")
+                        ));
+                        result.failed += 1;
+                        result.failed_urls.push(net::redact_url(url));
+                        match failure {
+                            None if !self.retry.continue_on_error => {
+                                failure = Some((e.to_string(), url))
+                            }
+                            _ => episode.log_error(bar, format!("{}: {}", e, net::redact_url(url))),
+                        }
+                    }
                 }
-            };
+            }
+        }
+
+        if result.postponed > 0 {
+            ui.log_info("max runtime reached, leaving the rest for the next sync");
+        }
+        if let Some((e, url)) = failure {
+            ui.error_with_url(&e, url);
         }
 
-        let mut paths = vec![];
+        ui.finish_pending();
 
-        ui.hook_status();
         for mut episode in downloaded {
-            episode.await_handle(ui).await;
-            paths.push(episode.into_path());
+            if let Some(handle) = episode.take_hook() {
+                hooks.add(episode.inner().attrs.title().to_string(), handle);
+            }
+            result
+                .changelog
+                .push(ChangelogEntry::new(&episode.inner().attrs));
+            result.paths.push(episode.into_path());
+        }
+        result.new = result.paths.len();
+
+        if !hooks.is_empty() {
+            ui.hook_status();
+            let start = Instant::now();
+            let (errors, unfinished) = hooks.drain().await;
+            for (episode, e) in errors {
+                ui.log_warn(format!("{}: {}", episode, e));
+            }
+            if unfinished > 0 {
+                ui.log_warn(format!(
+                    "stopped waiting for {} download hooks, they'll be reported by the next sync",
+                    unfinished
+                ));
+            }
+            ui.record(Phase::Hooks, start.elapsed());
+        }
+
+        if result.failed > 0 && self.retry.continue_on_error {
+            ui.error(&format!("{} episodes failed to download", result.failed));
+        }
+
+        // An old snapshot of the feed doesn't know which episodes are the newest.
+        if result.failed == 0 && snapshot::from().is_none() {
+            result.removed = self.remove_surplus(ui);
+        }
+
+        if self.is_settled(&result) {
+            let state = self
+                .settings
+                .and_then(|settings| sync_state(settings, &self.tracker_path));
+            if let Some(state) = state {
+                CachedFeed::settle(&self.url, state);
+            }
         }
 
         ui.complete();
-        paths
+        result
     }
 
-    fn pending_episodes(&self) -> Vec<&Episode> {
-        let qty = self.episodes.len();
+    /// The downloaded episodes beyond the newest `keep_latest`, oldest first, with `planned`
+    /// counted as downloaded.
+    ///
+    /// Only episodes in the download tracker that are still in the feed are counted, as the
+    /// feed is what tells how new they are. Acknowledged episodes have no file to remove.
+    fn surplus<'a>(&'a self, planned: &[&Episode]) -> Vec<&'a Episode> {
+        let Some(keep) = self.keep_latest else {
+            return vec![];
+        };
 
-        let mut pending: Vec<&Episode> = self
+        let tracker = DownloadedEpisodes::load(&self.tracker_path);
+        let mut downloaded: Vec<&Episode> = self
             .episodes
             .iter()
-            .filter(|episode| episode.should_download(&self.mode, qty))
+            .filter(|episode| {
+                let id = episode.get_id();
+                (tracker.contains_episode(&id) && !tracker.is_acknowledged(&id))
+                    || planned.iter().any(|planned| planned.index == episode.index)
+            })
             .collect();
 
+        downloaded.sort_by_key(|episode| episode.attrs.published());
+        downloaded.truncate(downloaded.len().saturating_sub(keep));
+        downloaded
+    }
+
+    /// Removes the downloaded episodes beyond the newest `keep_latest` along with their
+    /// tracker entries, and returns how many were removed.
+    ///
+    /// With a trash, the files are moved there instead, and restoring one puts its tracker
+    /// entry back. Episodes whose file is already gone only lose their tracker entry.
+    fn remove_surplus(&self, ui: &DownloadBar) -> usize {
+        let surplus = self.surplus(&[]);
+        if surplus.is_empty() {
+            return 0;
+        }
+
+        let entries = DownloadedEpisodes::entries(&self.tracker_path);
+        let mut removed = HashSet::new();
+        for episode in surplus {
+            let id = episode.get_id();
+            if let Some(path) = episode.downloaded_path() {
+                let entry = entries.iter().find(|entry| entry.id == id).cloned();
+                let tracker = entry.map(|entry| (self.tracker_path.as_path(), entry));
+                if let Err(e) = trash::remove_file(self.trash.as_ref(), &path, tracker) {
+                    episode.log_warn(ui, format!("failed to remove {:?}: {}", path, e));
+                    continue;
+                }
+            }
+
+            episode.log_debug(ui, "removed, older than the episodes to keep");
+            removed.insert(id);
+        }
+
+        if let Err(e) = DownloadedEpisodes::remove(&self.tracker_path, &removed) {
+            ui.log_warn(e);
+        }
+
+        removed.len()
+    }
+
+    /// Whether the sync left nothing for the next one to do, unless the feed or the settings
+    /// change.
+    ///
+    /// Backlog mode and rechecking the latest episodes have something to do on every sync.
+    fn is_settled(&self, result: &SyncResult) -> bool {
+        result.failed == 0
+            && result.skipped == 0
+            && result.deferred == 0
+            && result.postponed == 0
+            && matches!(self.mode, DownloadMode::Standard { .. })
+            && self.recheck.last_n == 0
+            && HookRecords::load(&self.tracker_path)
+                .into_inner()
+                .is_empty()
+    }
+
+    /// Downloads an episode, unless the max runtime was reached or an earlier episode failed
+    /// by the time it's its turn.
+    ///
+    /// `stopped` is set when the download fails and the sync shouldn't continue past it.
+    async fn attempt<'a>(
+        &'a self,
+        episode: &'a Episode,
+        index: usize,
+        episode_qty: usize,
+        stopped: &AtomicBool,
+        ui: &DownloadBar,
+    ) -> Outcome<'a> {
+        if deadline::passed() {
+            return Outcome::Postponed;
+        }
+        if stopped.load(Ordering::SeqCst) {
+            return Outcome::Skipped;
+        }
+
+        match self
+            .download_with_retries(episode, index, episode_qty, ui)
+            .await
+        {
+            Ok(downloaded_episode) => Outcome::Downloaded(downloaded_episode),
+            Err(e) if deadline::grace_expired() => {
+                episode.log_debug(ui, format!("download stopped: {}", e));
+                Outcome::Postponed
+            }
+            Err(e) => {
+                if !self.retry.continue_on_error {
+                    stopped.store(true, Ordering::SeqCst);
+                }
+                Outcome::Failed(episode, e)
+            }
+        }
+    }
+
+    /// Downloads an episode, trying again with a growing delay if it fails.
+    ///
+    /// Every attempt waits for room under `max_concurrent_downloads` first.
+    async fn download_with_retries<'a>(
+        &'a self,
+        episode: &'a Episode,
+        index: usize,
+        episode_qty: usize,
+        ui: &DownloadBar,
+    ) -> Result<DownloadedEpisode<'a>, DownloadError> {
+        net::with_retries(&self.retry, episode.attrs.title(), ui, |_| async move {
+            let _slot = throttle::acquire().await;
+            ui.begin_download(episode, index, episode_qty);
+            let outcome = episode
+                .download(&self.client, self.first_download, ui)
+                .await;
+            throttle::record(&outcome);
+            outcome
+        })
+        .await
+    }
+
+    /// Reports the download hooks that failed or didn't finish in an earlier sync, and runs
+    /// them again if configured to.
+    ///
+    /// Only hooks of episodes that are still in the feed and on disk can be run again, the
+    /// others are forgotten once reported.
+    fn earlier_hooks(&self, hooks: &mut HookSupervisor, ui: &DownloadBar) {
+        for (id, record) in HookRecords::load(&self.tracker_path).into_inner() {
+            match &record.status {
+                HookStatus::Pending => ui.log_warn(format!(
+                    "download hook of {:?} didn't finish in an earlier sync",
+                    record.path
+                )),
+                HookStatus::Failed(e) => ui.log_warn(format!(
+                    "download hook of {:?} failed in an earlier sync: {}",
+                    record.path, e
+                )),
+            }
+
+            let rerun = self
+                .episodes
+                .iter()
+                .find(|episode| episode.get_id() == id)
+                .filter(|_| self.rerun_failed_hooks && record.path.exists())
+                .and_then(|episode| Some((episode, episode.config.download_hook.clone()?)));
+
+            // Hooks that aren't run again are only reported once.
+            let Some((episode, hook)) = rerun else {
+                HookRecords::update(&self.tracker_path, &id, None);
+                continue;
+            };
+
+            episode.log_debug(ui, "running download hook again");
+            let run = episode.hook_run(hook, &record.path, record.first_download, ui);
+            hooks.add(episode.attrs.title().to_string(), run.spawn());
+        }
+    }
+
+    /// Checks if the most recently downloaded episodes were replaced on the server,
+    /// and re-downloads them if configured to.
+    async fn recheck(&self, ui: &mut DownloadBar) -> Vec<DownloadedEpisode<'_>> {
+        let mut redownloaded = vec![];
+
+        if self.recheck.last_n == 0 {
+            return redownloaded;
+        }
+
+        let recent: Vec<&Episode> = self
+            .episodes
+            .iter()
+            .rev()
+            .filter(|episode| episode.is_downloaded())
+            .take(self.recheck.last_n)
+            .collect();
+
+        for episode in recent {
+            if deadline::passed() {
+                break;
+            }
+
+            // Rechecks take a slot under `max_concurrent_downloads` like downloads do, so
+            // they don't add to the requests to the server.
+            let _slot = throttle::acquire().await;
+            let Some(local) = episode
+                .remote_changed(&self.client, self.recheck.tolerance, ui)
+                .await
+            else {
+                continue;
+            };
+
+            if !self.recheck.redownload {
+                continue;
+            }
+
+            ui.begin_download(episode, 0, 1);
+            match episode
+                .redownload(
+                    &self.client,
+                    &local,
+                    self.recheck.backups,
+                    self.trash.as_ref(),
+                    ui,
+                )
+                .await
+            {
+                Ok(downloaded) => redownloaded.push(downloaded),
+                Err(e) => episode.log_warn(ui, format!("failed to re-download: {}", e)),
+            }
+        }
+
+        redownloaded
+    }
+
+    fn pending_episodes(&self) -> Vec<&Episode> {
+        let qty = self.episodes.len();
+
+        let mut pending: Vec<&Episode> = match self.mode {
+            // Walk from the newest episode and stop at the first one we already have,
+            // instead of evaluating the entire feed.
+            DownloadMode::Standard { .. } if self.stop_at_known => self
+                .episodes
+                .iter()
+                .rev()
+                .take_while(|episode| !episode.is_downloaded())
+                .filter(|episode| episode.should_download(&self.mode, qty))
+                .collect(),
+            _ => self
+                .episodes
+                .iter()
+                .filter(|episode| episode.should_download(&self.mode, qty))
+                .collect(),
+        };
+
         // In backlog mode it makes more sense to download earliest episode first.
         // in standard mode, the most recent episodes are more relevant.
         match self.mode {
@@ -240,3 +1100,542 @@ impl Podcast {
         pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chaos;
+    use crate::config;
+    use crate::download_tracker::TrackerEntry;
+    use crate::episode::XmlWrapper;
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+
+    fn parse(text: &str) -> Result<(RawPodcast, Vec<RawEpisode>), String> {
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new(
+            "Podcast".to_string(),
+            GlobalConfig::default().style(),
+            &mp,
+            7,
+        );
+        parse_feed(text, &ui)
+    }
+
+    fn rss(items: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\"?><rss><channel><title>Podcast</title>{}</channel></rss>",
+            items
+        )
+    }
+
+    #[test]
+    fn single_item_feed() {
+        let (podcast, episodes) = parse(&rss("<item><title>Only</title></item>")).unwrap();
+        assert_eq!(podcast.title(), Some("Podcast"));
+        assert_eq!(episodes.len(), 1);
+        assert_eq!(episodes[0].get_str("title"), Ok("Only"));
+
+        let two = "<item><title>A</title></item><item><title>B</title></item>";
+        assert_eq!(parse(&rss(two)).unwrap().1.len(), 2);
+    }
+
+    #[test]
+    fn feed_without_items() {
+        let (podcast, episodes) = parse(&rss("")).unwrap();
+        assert_eq!(podcast.title(), Some("Podcast"));
+        assert!(episodes.is_empty());
+    }
+
+    #[test]
+    fn json_feeds_and_other_json() {
+        let feed = r#"{"version": "https://jsonfeed.org/version/1", "title": "Json",
+            "items": [{"id": "1", "title": "A"}]}"#;
+        let (podcast, episodes) = parse(feed).unwrap();
+        assert_eq!(podcast.title(), Some("Json"));
+        assert_eq!(episodes.len(), 1);
+
+        assert_eq!(
+            parse(r#"{"episodes": []}"#).err().as_deref(),
+            Some("not a feed: the server sent json that isn't a JSON Feed")
+        );
+        assert_eq!(
+            parse("<html></html>").err().as_deref(),
+            Some("failed to parse xml")
+        );
+    }
+
+    fn funding(links: &str) -> Vec<Funding> {
+        let (podcast, _) = parse(&format!(
+            r#"<rss xmlns:podcast="https://podcastindex.org/namespace/1.0"><channel>
+            <title>Podcast</title>{}</channel></rss>"#,
+            links
+        ))
+        .unwrap();
+        podcast.funding()
+    }
+
+    fn link(url: &str, text: Option<&str>) -> Funding {
+        Funding {
+            url: url.to_string(),
+            text: text.map(String::from),
+        }
+    }
+
+    #[test]
+    fn funding_links() {
+        let links = funding(
+            r#"<podcast:funding url="https://example.com/donate">Support the show!</podcast:funding>
+            <podcast:funding url=" https://example.com/members ">  </podcast:funding>
+            <podcast:funding>No url</podcast:funding>"#,
+        );
+        assert_eq!(
+            links,
+            vec![
+                link("https://example.com/donate", Some("Support the show!")),
+                link("https://example.com/members", None),
+            ]
+        );
+
+        let single = funding(r#"<podcast:funding url="https://example.com/tip"/>"#);
+        assert_eq!(single, vec![link("https://example.com/tip", None)]);
+        assert!(funding("").is_empty());
+    }
+
+    #[test]
+    fn funding_saved_to_file() {
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new(
+            "Podcast".to_string(),
+            GlobalConfig::default().style(),
+            &mp,
+            7,
+        );
+        let path = paths::test_dir("funding").join("FUNDING.txt");
+
+        Funding::save(&[], &path, &ui);
+        assert!(!path.exists());
+
+        let links = [
+            link("https://example.com/donate", Some("Support the show!")),
+            link("https://example.com/members", None),
+        ];
+        Funding::save(&links, &path, &ui);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "Support the show!: https://example.com/donate\nhttps://example.com/members\n"
+        );
+
+        Funding::save(&links[1..], &path, &ui);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "https://example.com/members\n"
+        );
+    }
+
+    fn item(guid: &str, title: &str) -> String {
+        format!(
+            "<item><title>{}</title><guid>{}</guid><pubDate>2024-01-01T06:00:00Z</pubDate>\
+            <enclosure url=\"https://example.com/{}.mp3\" type=\"audio/mpeg\" length=\"1\"/></item>",
+            title, guid, guid
+        )
+    }
+
+    #[tokio::test]
+    async fn episodes_from_snapshots() {
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new(
+            "Podcast".to_string(),
+            GlobalConfig::default().style(),
+            &mp,
+            7,
+        );
+        let dir = paths::test_dir("archived-episodes");
+        let config: PodcastConfig = toml::from_str(&format!(
+            "url = \"https://example.com/feed.xml\"\ndownload_path = \"{}\"",
+            dir.display()
+        ))
+        .unwrap();
+        let global_config = GlobalConfig::default();
+
+        let tracker_path = config.tracker_path("podcast", &global_config);
+        let snapshots = snapshot::dir(&tracker_path);
+        fs::create_dir_all(&snapshots).unwrap();
+        let old = rss(&(item("a", "Old A") + &item("b", "Old B")));
+        fs::write(snapshots.join("100-0000000000000001.xml"), old).unwrap();
+        let new = rss(&(item("a", "New A") + &item("c", "C")));
+        fs::write(snapshots.join("200-0000000000000002.xml"), new).unwrap();
+
+        let podcast = Podcast::from_xml(
+            "podcast".to_string(),
+            config.clone(),
+            &global_config,
+            Arc::new(reqwest::Client::new()),
+            rss(&item("c", "C")),
+            &ui,
+        )
+        .await
+        .unwrap();
+        assert_eq!(podcast.tracker_path(), tracker_path);
+
+        let ids = HashSet::from(["a".to_string(), "b".to_string(), "d".to_string()]);
+        let episodes = podcast
+            .archived_episodes(&config, &global_config, &ids, &ui)
+            .await;
+        let mut titles: Vec<&str> = episodes.iter().map(|e| e.attrs.title()).collect();
+        titles.sort();
+        assert_eq!(titles, ["New A", "Old B"]);
+    }
+
+    /// The body of the enclosure of the mock episode `n`, long enough for simulated dropped
+    /// connections to cut it short.
+    fn enclosure(n: u8) -> Vec<u8> {
+        (0..512 * 1024).map(|i| (i % 251) as u8 ^ n).collect()
+    }
+
+    /// The mock episode `n`, published on the `n`th of January 2024 with an enclosure at
+    /// `<url>/<n>.mp3`.
+    fn numbered_item(n: u8, url: &str, length: usize) -> String {
+        format!(
+            "<item><title>Episode {n}</title><guid>episode-{n}</guid>\
+            <pubDate>2024-01-0{n}T06:00:00Z</pubDate><enclosure url=\"{url}/{n}.mp3\" \
+            type=\"audio/mpeg\" length=\"{length}\"/></item>",
+        )
+    }
+
+    /// The file of the mock episode `n`.
+    fn episode_file(n: u8) -> String {
+        format!("2024-01-0{} Episode {}.mp3", n, n)
+    }
+
+    /// The mock episodes in the tracker at `dir`, along with whether their files are whole.
+    /// Fails if there's a file that isn't a tracked episode or a partial download.
+    fn synced_files(dir: &Path) -> Vec<(u8, bool)> {
+        let mut tracked: Vec<(u8, bool)> = DownloadedEpisodes::entries(&dir.join(".downloaded"))
+            .into_iter()
+            .map(|entry| {
+                let n = entry.id.trim_start_matches("episode-").parse().unwrap();
+                let data = fs::read(dir.join(episode_file(n)));
+                (n, data.is_ok_and(|data| data == enclosure(n)))
+            })
+            .collect();
+        tracked.sort();
+
+        for entry in fs::read_dir(dir).unwrap() {
+            let name = entry.unwrap().file_name().to_string_lossy().to_string();
+            assert!(
+                name.starts_with('.')
+                    || name.ends_with(".partial")
+                    || tracked.iter().any(|(n, _)| name == episode_file(*n)),
+                "unexpected file: {}",
+                name
+            );
+        }
+
+        tracked
+    }
+
+    #[tokio::test]
+    async fn sync_recovers_from_simulated_failures() {
+        let (url, _) = net::test_server(|url| {
+            let items: String = (1..=3)
+                .map(|n| numbered_item(n, url, enclosure(n).len()))
+                .collect();
+            vec![
+                ("/feed.xml", "application/rss+xml", rss(&items).into_bytes()),
+                ("/1.mp3", "audio/mpeg", enclosure(1)),
+                ("/2.mp3", "audio/mpeg", enclosure(2)),
+                ("/3.mp3", "audio/mpeg", enclosure(3)),
+            ]
+        })
+        .await;
+
+        let dir = paths::test_dir("chaos-sync");
+        let config: PodcastConfig = toml::from_str(&format!(
+            "url = \"{}/feed.xml\"\ndownload_path = \"{}\"\npipeline = [\"rename\"]\n\
+            download_retries = 20\nretry_backoff_secs = 0\ncontinue_on_error = true",
+            url,
+            dir.display()
+        ))
+        .unwrap();
+        let global_config = GlobalConfig::default();
+        let client = Arc::new(reqwest::Client::new());
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let mut ui = DownloadBar::new("Podcast".to_string(), global_config.style(), &mp, 7);
+
+        let sync = async {
+            let podcast = Podcast::new(
+                "podcast".to_string(),
+                config.clone(),
+                &global_config,
+                Arc::clone(&client),
+                &ui,
+            )
+            .await?;
+            Ok::<_, String>(podcast.sync(&mut ui).await)
+        };
+        let (result, sent) = chaos::simulate(0.8, 7, sync).await;
+
+        // Whatever failed, every tracked episode is whole and nothing else was kept.
+        let tracked = synced_files(&dir);
+        assert!(tracked.iter().all(|(_, whole)| *whole), "{:?}", tracked);
+        if let Ok(result) = &result {
+            assert_eq!(result.new, tracked.len());
+        }
+        assert!(sent > 4, "no failures were injected");
+
+        // Without failures, the next sync downloads the rest.
+        let podcast = Podcast::new("podcast".to_string(), config, &global_config, client, &ui)
+            .await
+            .unwrap();
+        let result = podcast.sync(&mut ui).await;
+        assert_eq!(result.failed, 0);
+        assert_eq!(synced_files(&dir), [(1, true), (2, true), (3, true)]);
+        assert!(fs::read_dir(&dir).unwrap().all(|entry| !entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .ends_with(".partial")));
+    }
+
+    #[tokio::test]
+    async fn surplus_beyond_keep_latest() {
+        let dir = paths::test_dir("keep-latest");
+        let global_config: GlobalConfig =
+            toml::from_str(&format!("trash_dir = \"{}\"", dir.join("trash").display())).unwrap();
+        let config: PodcastConfig = toml::from_str(&format!(
+            "url = \"https://example.com/feed.xml\"\ndownload_path = \"{}\"\nkeep_latest = 2",
+            dir.display()
+        ))
+        .unwrap();
+
+        // The oldest episode was acknowledged by catching up, the others are downloaded.
+        let tracker_path = config.tracker_path("podcast", &global_config);
+        let tracker_entry = |n: u8, acknowledged| TrackerEntry {
+            id: format!("episode-{}", n),
+            downloaded: 1,
+            title: format!("Episode {}", n),
+            acknowledged,
+        };
+        DownloadedEpisodes::append_entry(&tracker_path, &tracker_entry(1, Some(1))).unwrap();
+        for n in 2..=5 {
+            DownloadedEpisodes::append_entry(&tracker_path, &tracker_entry(n, None)).unwrap();
+            fs::write(dir.join(episode_file(n)), enclosure(n)).unwrap();
+        }
+        // Files that aren't in the tracker are never removed.
+        fs::write(dir.join(episode_file(1)), enclosure(1)).unwrap();
+        fs::write(dir.join("stray.mp3"), "").unwrap();
+
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new("x".to_string(), global_config.style(), &mp, 7);
+        let items: String = (1..=5)
+            .map(|n| numbered_item(n, "https://example.com", 1))
+            .collect();
+        let podcast = Podcast::from_xml(
+            "podcast".to_string(),
+            config,
+            &global_config,
+            Arc::new(reqwest::Client::new()),
+            rss(&items),
+            &ui,
+        )
+        .await
+        .unwrap();
+
+        let surplus: Vec<&str> = podcast
+            .surplus(&[])
+            .iter()
+            .map(|episode| episode.attrs.title())
+            .collect();
+        assert_eq!(surplus, ["Episode 2", "Episode 3"]);
+        // An episode about to be downloaded counts as one of the newest.
+        let planned = [&podcast.episodes()[0]];
+        assert_eq!(podcast.surplus(&planned).len(), 3);
+
+        assert_eq!(podcast.remove_surplus(&ui), 2);
+
+        let kept: Vec<(String, Option<u64>)> = DownloadedEpisodes::entries(&tracker_path)
+            .into_iter()
+            .map(|entry| (entry.id, entry.acknowledged))
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                ("episode-1".to_string(), Some(1)),
+                ("episode-4".to_string(), None),
+                ("episode-5".to_string(), None),
+            ]
+        );
+        for n in [1, 4, 5] {
+            assert!(dir.join(episode_file(n)).exists());
+        }
+        assert!(dir.join("stray.mp3").exists());
+
+        // The removed episodes are in the trash, along with their tracker entries.
+        let trashed = fs::read_dir(dir.join("trash").join("podcast")).unwrap();
+        let mut infos: Vec<serde_json::Value> = trashed
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "trashinfo"))
+            .map(|path| serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap())
+            .collect();
+        infos.sort_by_key(|info| info["tracker"]["entry"]["id"].to_string());
+        let trashed: Vec<(&str, &str)> = infos
+            .iter()
+            .map(|info| {
+                let original = info["original"].as_str().unwrap();
+                let id = info["tracker"]["entry"]["id"].as_str().unwrap();
+                (original.rsplit('/').next().unwrap(), id)
+            })
+            .collect();
+        assert_eq!(
+            trashed,
+            [
+                ("2024-01-02 Episode 2.mp3", "episode-2"),
+                ("2024-01-03 Episode 3.mp3", "episode-3"),
+            ]
+        );
+        assert_eq!(infos[0]["tracker"]["path"].as_str(), tracker_path.to_str());
+    }
+
+    #[tokio::test]
+    async fn daily_cap_around_midnight() {
+        let (url, _) = net::test_server(|url| {
+            let items: String = (1..=5)
+                .map(|n| numbered_item(n, url, enclosure(n).len()))
+                .collect();
+            let mut files = vec![("/feed.xml", "application/rss+xml", rss(&items).into_bytes())];
+            for (n, path) in ["/1.mp3", "/2.mp3", "/3.mp3", "/4.mp3", "/5.mp3"]
+                .into_iter()
+                .enumerate()
+            {
+                files.push((path, "audio/mpeg", enclosure(n as u8 + 1)));
+            }
+            files
+        })
+        .await;
+
+        let dir = paths::test_dir("daily-cap-sync");
+        let config: PodcastConfig = toml::from_str(&format!(
+            "url = \"{}/feed.xml\"\ndownload_path = \"{}\"\npipeline = [\"rename\"]\nmax_per_day = 2",
+            url,
+            dir.display()
+        ))
+        .unwrap();
+        let global_config = GlobalConfig::default();
+        let client = Arc::new(reqwest::Client::new());
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let mut ui = DownloadBar::new("Podcast".to_string(), global_config.style(), &mp, 7);
+
+        let day = |d| chrono::NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let today = Arc::new(std::sync::Mutex::new(day(1)));
+        // The newest episodes go first, and the cap only allows more after midnight.
+        let syncs = [(day(1), 2, 3), (day(1), 0, 3), (day(2), 2, 1)];
+        for (d, new, deferred) in syncs {
+            *today.lock().unwrap() = d;
+            let result = quota::on_day(Arc::clone(&today), async {
+                let podcast = Podcast::new(
+                    "podcast".to_string(),
+                    config.clone(),
+                    &global_config,
+                    Arc::clone(&client),
+                    &ui,
+                )
+                .await
+                .unwrap();
+                assert_eq!(podcast.planned_downloads().len(), new);
+                podcast.sync(&mut ui).await
+            })
+            .await;
+            assert_eq!((result.new, result.deferred), (new, deferred), "{}", d);
+        }
+        assert_eq!(
+            synced_files(&dir),
+            [(2, true), (3, true), (4, true), (5, true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn max_runtime_suspends_slow_downloads() {
+        // The enclosures never arrive in full.
+        let head = "HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nContent-Length: 10000\r\n\r\n";
+        let slow_url = net::stalling_server([head.as_bytes(), &[1; 1000]].concat()).await;
+        let (url, _) = net::test_server(|_| {
+            let items: String = (1..=2)
+                .map(|n| numbered_item(n, &slow_url, 10000))
+                .collect();
+            vec![("/feed.xml", "application/rss+xml", rss(&items).into_bytes())]
+        })
+        .await;
+
+        let dir = paths::test_dir("max-runtime");
+        let config: PodcastConfig = toml::from_str(&format!(
+            "url = \"{}/feed.xml\"\ndownload_path = \"{}\"\npipeline = [\"rename\"]",
+            url,
+            dir.display()
+        ))
+        .unwrap();
+        let global_config = GlobalConfig::default();
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let mut ui = DownloadBar::new("Podcast".to_string(), global_config.style(), &mp, 7);
+
+        let budget = Duration::from_millis(300);
+        let started = std::time::Instant::now();
+        let result = deadline::limit(budget, budget, async {
+            let client = Arc::new(reqwest::Client::new());
+            let podcast = Podcast::new("podcast".to_string(), config, &global_config, client, &ui)
+                .await
+                .unwrap();
+            podcast.sync(&mut ui).await
+        })
+        .await;
+
+        // The running download got the grace period, and the next one never started.
+        assert!(started.elapsed() >= 2 * budget);
+        assert!(started.elapsed() < 10 * budget);
+        assert_eq!((result.new, result.failed, result.postponed), (0, 0, 2));
+
+        // The part that arrived is kept, to resume from on the next sync.
+        let partial: Vec<u64> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".partial"))
+            .map(|entry| entry.metadata().unwrap().len())
+            .collect();
+        assert_eq!(partial, [1000]);
+    }
+
+    #[tokio::test]
+    async fn stalled_feeds_time_out() {
+        // The server accepts the connection and never answers.
+        let stalled = net::stalling_server(vec![]).await;
+        let (url, _) =
+            net::test_server(|_| vec![("/feed.xml", "application/rss+xml", rss("").into_bytes())])
+                .await;
+
+        let dir = paths::test_dir("stalled-feed");
+        let global_config: GlobalConfig =
+            toml::from_str("timeout = 1\ndownload_retries = 0").unwrap();
+        let client = Arc::new(config::client_builder(&global_config).build().unwrap());
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let new = |name: &'static str, url: String| {
+            let config: PodcastConfig = toml::from_str(&format!(
+                "url = \"{}/feed.xml\"\ndownload_path = \"{}\"",
+                url,
+                dir.join(name).display()
+            ))
+            .unwrap();
+            let ui = DownloadBar::new(name.to_string(), global_config.style(), &mp, 7);
+            let client = Arc::clone(&client);
+            let global_config = &global_config;
+            async move { Podcast::new(name.to_string(), config, global_config, client, &ui).await }
+        };
+
+        let started = std::time::Instant::now();
+        let (stalled, fine) = tokio::join!(new("stalled", stalled), new("fine", url));
+
+        // Only the podcast on the stalled server fails, and it doesn't hold up the others.
+        let e = stalled.err().unwrap();
+        assert!(e.contains("timed out"), "{}", e);
+        assert!(fine.is_ok());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,132 @@
+//! The `max_per_day` cap on how many new episodes of a podcast are downloaded per day.
+//!
+//! Days are calendar days in local time. The episodes downloaded so far today are counted
+//! in a file next to the download tracker, so the cap holds across several syncs.
+
+use crate::paths;
+use chrono::Local;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DailyCount {
+    /// The day as `%Y-%m-%d`.
+    day: Option<String>,
+    downloaded: usize,
+}
+
+#[derive(Debug)]
+pub struct DailyCap {
+    max: usize,
+    path: PathBuf,
+    count: DailyCount,
+}
+
+impl DailyCap {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".daily");
+        tracker_path.with_file_name(name)
+    }
+
+    pub fn load(tracker_path: &Path, max: usize) -> Self {
+        let path = Self::path(tracker_path);
+        let count = fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+
+        Self { max, path, count }
+    }
+
+    /// How many more episodes may be downloaded on `today`.
+    pub fn remaining(&self, today: NaiveDate) -> usize {
+        match self.count.day == Some(day_key(today)) {
+            true => self.max.saturating_sub(self.count.downloaded),
+            false => self.max,
+        }
+    }
+
+    /// Counts an episode downloaded on `today`.
+    pub fn record(&mut self, today: NaiveDate) {
+        if self.count.day != Some(day_key(today)) {
+            self.count = DailyCount {
+                day: Some(day_key(today)),
+                downloaded: 0,
+            };
+        }
+        self.count.downloaded += 1;
+
+        let Ok(json) = serde_json::to_string_pretty(&self.count) else {
+            return;
+        };
+
+        if let Err(e) = paths::write(&self.path, json) {
+            log::warn!("failed to save daily download count: {:?}", e);
+        }
+    }
+}
+
+fn day_key(day: NaiveDate) -> String {
+    day.format("%Y-%m-%d").to_string()
+}
+
+#[cfg(test)]
+tokio::task_local! {
+    /// The day a single test pretends it is.
+    static TODAY: std::sync::Arc<std::sync::Mutex<NaiveDate>>;
+}
+
+/// The current day in local time.
+pub fn today() -> NaiveDate {
+    #[cfg(test)]
+    if let Ok(today) = TODAY.try_with(|today| *today.lock().unwrap()) {
+        return today;
+    }
+
+    Local::now().date_naive()
+}
+
+/// Runs `future` on the day in `today`, which the test can change while it runs.
+#[cfg(test)]
+pub async fn on_day<F: std::future::Future>(
+    today: std::sync::Arc<std::sync::Mutex<NaiveDate>>,
+    future: F,
+) -> F::Output {
+    TODAY.scope(today, future).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, d).unwrap()
+    }
+
+    #[test]
+    fn cap_resets_at_midnight() {
+        let tracker = paths::test_dir("daily-cap").join(".downloaded");
+        let mut cap = DailyCap::load(&tracker, 2);
+        assert_eq!(cap.remaining(day(1)), 2);
+
+        cap.record(day(1));
+        assert_eq!(cap.remaining(day(1)), 1);
+        cap.record(day(1));
+        assert_eq!(cap.remaining(day(1)), 0);
+        assert_eq!(cap.remaining(day(2)), 2);
+
+        // The count is kept between syncs.
+        let mut cap = DailyCap::load(&tracker, 2);
+        assert_eq!(cap.remaining(day(1)), 0);
+
+        // A download finishing after midnight counts for the new day.
+        cap.record(day(2));
+        assert_eq!(cap.remaining(day(2)), 1);
+        assert_eq!(DailyCap::load(&tracker, 2).remaining(day(2)), 1);
+        assert_eq!(DailyCap::load(&tracker, 3).remaining(day(1)), 3);
+    }
+}
@@ -0,0 +1,34 @@
+//! Running without writing anything, for looking at the config and state of TaleCast as a
+//! user that can only read them.
+//!
+//! Enabled with `--read-only`, and implied by the commands that only print things. Feeds and
+//! search indexes are still cached, but in memory only. No lock is taken on the cache and no
+//! log file is written. Code that writes config or state calls [`check`] first, so a write
+//! that was forgotten about fails with a clear error instead of a permission error, or worse,
+//! succeeding.
+
+use std::path::Path;
+use std::process;
+use std::sync::OnceLock;
+
+static READ_ONLY: OnceLock<()> = OnceLock::new();
+
+pub fn init() {
+    let _ = READ_ONLY.set(());
+}
+
+pub fn is_enabled() -> bool {
+    READ_ONLY.get().is_some()
+}
+
+/// Exits with an internal error if the run is read-only, for code that's about to write to
+/// `path`.
+pub fn check(path: &Path) {
+    if is_enabled() {
+        eprintln!(
+            "internal error: tried to write {:?} in read-only mode, please report this",
+            path
+        );
+        process::exit(1);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// How far the downloaded size may be off from the advertised length, as a factor either way,
+/// before the advertised length counts as wrong.
+const MAX_LENGTH_RATIO: f64 = 2.0;
+
+/// Below this share of correct lengths, the advertised lengths of a feed aren't trusted.
+const MIN_RELIABILITY: f64 = 0.5;
+
+/// How much a new download moves the typical ratio between downloaded and advertised size.
+///
+/// The ratios are averaged on a log scale, so that being off by 10x in either direction
+/// weighs the same.
+const RATIO_WEIGHT: f64 = 0.3;
+
+/// How well the enclosure lengths advertised by a feed match what was actually downloaded.
+///
+/// Stored next to the download tracker.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct LengthReliability {
+    /// Downloads whose size was compared to the advertised length.
+    compared: u32,
+    /// Of those, the ones within [`MAX_LENGTH_RATIO`].
+    correct: u32,
+    /// Natural log of the typical ratio of downloaded size to advertised length.
+    log_ratio: Option<f64>,
+}
+
+impl LengthReliability {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".lengths");
+        tracker_path.with_file_name(name)
+    }
+
+    pub fn load(tracker_path: &Path) -> Self {
+        fs::read_to_string(Self::path(tracker_path))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, tracker_path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|_| "failed to serialize length reliability".to_string())?;
+        paths::write(Self::path(tracker_path), json)
+            .map_err(|_| "failed to save length reliability".to_string())
+    }
+
+    /// Compares the downloaded size of an episode to its advertised length.
+    ///
+    /// Returns an error describing the mismatch if the advertised length was far off.
+    pub fn record(tracker_path: &Path, advertised: u64, actual: u64) -> Result<(), String> {
+        let mut reliability = Self::load(tracker_path);
+
+        let ratio = actual as f64 / advertised as f64;
+        let correct = (1.0 / MAX_LENGTH_RATIO..=MAX_LENGTH_RATIO).contains(&ratio);
+
+        reliability.compared += 1;
+        if correct {
+            reliability.correct += 1;
+        }
+        let log_ratio = ratio.ln();
+        reliability.log_ratio = Some(match reliability.log_ratio {
+            Some(old) => old + (log_ratio - old) * RATIO_WEIGHT,
+            None => log_ratio,
+        });
+
+        if let Err(e) = reliability.save(tracker_path) {
+            log::warn!("{}", e);
+        }
+
+        match correct {
+            true => Ok(()),
+            false => Err(format!(
+                "advertised length is {} bytes but {} bytes were downloaded",
+                advertised, actual
+            )),
+        }
+    }
+
+    /// Share of downloads whose advertised length was about right, if any were compared.
+    pub fn score(&self) -> Option<f64> {
+        (self.compared > 0).then(|| self.correct as f64 / self.compared as f64)
+    }
+
+    /// Corrects an advertised length by how far off the feed usually is, if it's unreliable.
+    pub fn adjust(&self, length: u64) -> u64 {
+        match (self.score(), self.log_ratio) {
+            (Some(score), Some(log_ratio)) if score < MIN_RELIABILITY => {
+                (length as f64 * log_ratio.exp()) as u64
+            }
+            _ => length,
+        }
+    }
+
+    pub fn describe(&self) -> Option<String> {
+        let score = self.score()?;
+        Some(format!(
+            "advertised lengths correct for {} of {} downloads ({:.0}%)",
+            self.correct,
+            self.compared,
+            score * 100.0
+        ))
+    }
+}
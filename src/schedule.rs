@@ -0,0 +1,282 @@
+//! Cron schedules that limit when a podcast is synced, like `schedule = "0 6 * * 1,3,5"`.
+//!
+//! Schedules are evaluated in local time. A podcast is due when a scheduled time has
+//! passed since its last sync.
+
+use crate::paths;
+use chrono::DateTime;
+use chrono::Local;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use croner::Cron;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+const FIELDS: [&str; 5] = ["minute", "hour", "day of month", "month", "day of week"];
+
+/// A cron expression with the standard five fields.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct SyncSchedule(String);
+
+impl TryFrom<String> for SyncSchedule {
+    type Error = String;
+
+    fn try_from(expr: String) -> Result<Self, Self::Error> {
+        parse(&expr)?;
+        Ok(Self(expr))
+    }
+}
+
+impl From<SyncSchedule> for String {
+    fn from(schedule: SyncSchedule) -> Self {
+        schedule.0
+    }
+}
+
+fn parse(expr: &str) -> Result<Cron, String> {
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    if fields.len() != FIELDS.len() {
+        return Err(format!(
+            "invalid schedule {:?}: expected 5 fields ({}), found {}",
+            expr,
+            FIELDS.join(", "),
+            fields.len()
+        ));
+    }
+
+    Cron::new(expr).parse().map_err(|e| {
+        // Parse each field on its own to find the one that's wrong.
+        let invalid = (0..fields.len()).find(|&i| {
+            let only_field: Vec<&str> = (0..fields.len())
+                .map(|j| if i == j { fields[i] } else { "*" })
+                .collect();
+            Cron::new(&only_field.join(" ")).parse().is_err()
+        });
+
+        match invalid {
+            Some(i) => {
+                let position = fields[i].as_ptr() as usize - expr.as_ptr() as usize;
+                format!(
+                    "invalid schedule {:?}: {} field {:?} at position {}: {}",
+                    expr, FIELDS[i], fields[i], position, e
+                )
+            }
+            None => format!("invalid schedule {:?}: {}", expr, e),
+        }
+    })
+}
+
+impl SyncSchedule {
+    fn cron(&self) -> Cron {
+        parse(&self.0).expect("schedule was validated when loaded")
+    }
+
+    /// The first scheduled time after `after`.
+    ///
+    /// The schedule is matched against the wall clock, so that every scheduled time
+    /// happens once when the clocks change. A time skipped by the clocks going forward
+    /// happens right after the jump, and a time repeated by the clocks going back only
+    /// happens the first time.
+    pub fn next_after<Tz: TimeZone>(&self, after: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let wall_clock = after.naive_local().and_utc();
+        let next = self
+            .cron()
+            .find_next_occurrence(&wall_clock, false)
+            .ok()?
+            .naive_utc();
+        local_time(&after.timezone(), next)
+    }
+
+    /// Whether a scheduled time has passed since the last sync.
+    pub fn is_due(&self, last_sync: Option<DateTime<Local>>, now: DateTime<Local>) -> bool {
+        let Some(last_sync) = last_sync else {
+            return true;
+        };
+
+        self.next_after(last_sync).is_some_and(|next| next <= now)
+    }
+}
+
+/// The moment a wall clock time happens, or the first moment after it if the clocks
+/// skipped it.
+fn local_time<Tz: TimeZone>(tz: &Tz, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+    (0..=24 * 60)
+        .map(|minutes| time + chrono::Duration::minutes(minutes))
+        .find_map(|time| tz.from_local_datetime(&time).earliest())
+}
+
+/// When a podcast was last synced, stored next to its download tracker.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LastSync {
+    /// Unix time of when the last sync started.
+    started: i64,
+}
+
+impl LastSync {
+    pub fn path(tracker_path: &Path) -> PathBuf {
+        let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+        name.push(".synced");
+        tracker_path.with_file_name(name)
+    }
+
+    pub fn load(tracker_path: &Path) -> Option<DateTime<Local>> {
+        let s = fs::read_to_string(Self::path(tracker_path)).ok()?;
+        let last_sync: Self = serde_json::from_str(&s).ok()?;
+        Local.timestamp_opt(last_sync.started, 0).single()
+    }
+
+    pub fn save(tracker_path: &Path, started: DateTime<Local>) {
+        let last_sync = Self {
+            started: started.timestamp(),
+        };
+
+        let Ok(json) = serde_json::to_string_pretty(&last_sync) else {
+            return;
+        };
+
+        if let Err(e) = paths::write(Self::path(tracker_path), json) {
+            log::warn!("failed to save time of last sync: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, LocalResult, NaiveDate};
+
+    /// Central European time in 2024: summer time from 31 March 01:00 UTC, when 02:00 is
+    /// skipped, to 27 October 01:00 UTC, when 02:00 to 03:00 happens twice.
+    #[derive(Debug, Clone, Copy)]
+    struct Cet;
+
+    fn winter() -> FixedOffset {
+        FixedOffset::east_opt(3600).unwrap()
+    }
+
+    fn summer() -> FixedOffset {
+        FixedOffset::east_opt(2 * 3600).unwrap()
+    }
+
+    fn utc(day: (u32, u32), hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, day.0, day.1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    impl TimeZone for Cet {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Cet
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            // The earlier moment of a repeated time is the one in summer time.
+            let offsets: Vec<FixedOffset> = [summer(), winter()]
+                .into_iter()
+                .filter(|offset| self.offset_from_utc_datetime(&(*local - *offset)) == *offset)
+                .collect();
+
+            match offsets[..] {
+                [offset] => LocalResult::Single(offset),
+                [earliest, latest] => LocalResult::Ambiguous(earliest, latest),
+                _ => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, time: &NaiveDateTime) -> FixedOffset {
+            match (utc((3, 31), 1)..utc((10, 27), 1)).contains(time) {
+                true => summer(),
+                false => winter(),
+            }
+        }
+    }
+
+    fn at(month: u32, day: u32, hour: u32, minute: u32, offset: FixedOffset) -> DateTime<Cet> {
+        let local = NaiveDate::from_ymd_opt(2024, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap();
+        let time = Cet.from_utc_datetime(&(local - offset));
+        assert_eq!(time.offset(), &offset, "{} isn't at {}", local, offset);
+        time
+    }
+
+    fn schedule(expr: &str) -> SyncSchedule {
+        SyncSchedule::try_from(expr.to_string()).unwrap()
+    }
+
+    #[test]
+    fn skipped_times_happen_after_the_jump() {
+        let skipped = NaiveDate::from_ymd_opt(2024, 3, 31)
+            .unwrap()
+            .and_hms_opt(2, 30, 0)
+            .unwrap();
+        assert_eq!(local_time(&Cet, skipped), Some(at(3, 31, 3, 0, summer())));
+
+        let schedule = schedule("30 2 * * *");
+        let first = schedule.next_after(at(3, 31, 1, 0, winter())).unwrap();
+        assert_eq!(first, at(3, 31, 3, 0, summer()));
+
+        // The jump doesn't make it happen again the same day.
+        let second = schedule.next_after(first).unwrap();
+        assert_eq!(second, at(4, 1, 2, 30, summer()));
+    }
+
+    #[test]
+    fn repeated_times_happen_once() {
+        let repeated = NaiveDate::from_ymd_opt(2024, 10, 27)
+            .unwrap()
+            .and_hms_opt(2, 30, 0)
+            .unwrap();
+        assert_eq!(
+            local_time(&Cet, repeated),
+            Some(at(10, 27, 2, 30, summer()))
+        );
+
+        let schedule = schedule("30 2 * * *");
+        let first = schedule.next_after(at(10, 27, 1, 0, summer())).unwrap();
+        assert_eq!(first, at(10, 27, 2, 30, summer()));
+
+        let second = schedule.next_after(first).unwrap();
+        assert_eq!(second, at(10, 28, 2, 30, winter()));
+
+        // Nor is it due again when the clock shows it the second time.
+        let again = at(10, 27, 2, 45, winter());
+        assert!(schedule.next_after(first).is_some_and(|next| next > again));
+    }
+
+    #[test]
+    fn hourly_schedules_across_the_clock_change() {
+        let schedule = schedule("0 * * * *");
+
+        let mut time = at(10, 27, 1, 30, summer());
+        let mut hours = vec![];
+        for _ in 0..3 {
+            time = schedule.next_after(time).unwrap();
+            hours.push(time);
+        }
+
+        assert_eq!(
+            hours,
+            [
+                at(10, 27, 2, 0, summer()),
+                at(10, 27, 3, 0, winter()),
+                at(10, 27, 4, 0, winter()),
+            ]
+        );
+    }
+}
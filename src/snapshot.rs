@@ -0,0 +1,302 @@
+//! Copies of the feeds of podcasts with `archive_feed`, for when a feed drops its back
+//! catalog or the show disappears altogether.
+//!
+//! Every feed that differs from the archived ones is saved in a `.snapshots` directory next
+//! to the download tracker, named after when it was fetched and a hash of its contents.
+//! Elements that change without the episodes changing, like `lastBuildDate`, are left out of
+//! the hash. Past `max_feed_snapshots`, the oldest ones are removed.
+//!
+//! With `--from-snapshot`, feeds are read from the newest snapshot taken by the given time
+//! instead of being downloaded.
+
+use crate::config::GlobalConfig;
+use crate::config::PodcastConfigs;
+use crate::format;
+use crate::json_feed;
+use crate::paths;
+use crate::read_only;
+use crate::utils;
+use crate::utils::Unix;
+use regex::Regex;
+use std::fs;
+use std::hash::Hasher;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// How many snapshots are kept when `max_feed_snapshots` isn't set.
+pub const DEFAULT_MAX_SNAPSHOTS: usize = 20;
+
+/// Parts of a feed that change on every fetch of some feeds, even though nothing else did.
+const VOLATILE: &str = r"(?s)<lastBuildDate>.*?</lastBuildDate>|<!--.*?-->";
+
+static FROM: OnceLock<Unix> = OnceLock::new();
+
+/// Reads feeds from the newest snapshots taken by `at` for the rest of the run.
+pub fn init(at: Unix) {
+    let _ = FROM.set(at);
+}
+
+/// The time to read snapshots from, if feeds are read from snapshots.
+pub fn from() -> Option<Unix> {
+    FROM.get().copied()
+}
+
+pub fn dir(tracker_path: &Path) -> PathBuf {
+    let mut name = tracker_path.file_name().unwrap_or_default().to_owned();
+    name.push(".snapshots");
+    tracker_path.with_file_name(name)
+}
+
+struct Snapshot {
+    /// When the feed was fetched.
+    taken: Unix,
+    hash: u64,
+    path: PathBuf,
+}
+
+/// The snapshots of a podcast, oldest first.
+fn snapshots(tracker_path: &Path) -> Vec<Snapshot> {
+    let Ok(entries) = fs::read_dir(dir(tracker_path)) else {
+        return vec![];
+    };
+
+    let mut snapshots: Vec<Snapshot> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path();
+            let extension = path.extension()?;
+            if extension != "xml" && extension != "json" {
+                return None;
+            }
+
+            let stem = path.file_stem()?.to_str()?;
+            let (taken, hash) = stem.split_once('-')?;
+            Some(Snapshot {
+                taken: Unix::from_secs(taken.parse().ok()?),
+                hash: u64::from_str_radix(hash, 16).ok()?,
+                path,
+            })
+        })
+        .collect();
+
+    snapshots.sort_by_key(|snapshot| snapshot.taken);
+    snapshots
+}
+
+/// The paths of the snapshots of a podcast, oldest first.
+pub fn paths(tracker_path: &Path) -> Vec<PathBuf> {
+    snapshots(tracker_path)
+        .into_iter()
+        .map(|snapshot| snapshot.path)
+        .collect()
+}
+
+/// A hash of the feed without its volatile parts, which is the same for the same episodes.
+fn digest(feed: &str) -> u64 {
+    let volatile = Regex::new(VOLATILE).unwrap();
+    let mut hasher = fnv::FnvHasher::default();
+    hasher.write(volatile.replace_all(feed, "").as_bytes());
+    hasher.finish()
+}
+
+/// Saves the feed, unless an identical one is already archived. Returns where it was saved.
+///
+/// `max` is how many snapshots to keep, `0` for all of them.
+pub fn archive(tracker_path: &Path, feed: &str, max: usize) -> Result<Option<PathBuf>, String> {
+    if read_only::is_enabled() {
+        return Ok(None);
+    }
+
+    archive_at(tracker_path, feed, max, utils::current_unix())
+}
+
+fn archive_at(
+    tracker_path: &Path,
+    feed: &str,
+    max: usize,
+    taken: Unix,
+) -> Result<Option<PathBuf>, String> {
+    let hash = digest(feed);
+    let mut snapshots = snapshots(tracker_path);
+    if snapshots.iter().any(|snapshot| snapshot.hash == hash) {
+        return Ok(None);
+    }
+
+    let dir = dir(tracker_path);
+    fs::create_dir_all(&dir).map_err(|e| format!("failed to create {:?}: {}", &dir, e))?;
+
+    // Written to a temporary file first, so a snapshot is never half written.
+    let extension = if json_feed::is_json(feed) {
+        "json"
+    } else {
+        "xml"
+    };
+    let path = dir.join(format!("{}-{:016x}.{}", taken.as_secs(), hash, extension));
+    let temp_path = path.with_extension("tmp");
+    paths::write(&temp_path, feed)
+        .and_then(|_| fs::rename(&temp_path, &path))
+        .map_err(|e| format!("failed to save feed snapshot: {}", e))?;
+
+    snapshots.push(Snapshot {
+        taken,
+        hash,
+        path: path.clone(),
+    });
+
+    if max > 0 && snapshots.len() > max {
+        for snapshot in &snapshots[..snapshots.len() - max] {
+            if let Err(e) = fs::remove_file(&snapshot.path) {
+                log::warn!("failed to remove {:?}: {}", &snapshot.path, e);
+            }
+        }
+    }
+
+    Ok(Some(path))
+}
+
+/// The feed of the newest snapshot taken at or before `at`, along with where it's from.
+pub fn load(tracker_path: &Path, at: Unix) -> Result<(String, PathBuf), String> {
+    let snapshot = snapshots(tracker_path)
+        .into_iter()
+        .rev()
+        .find(|snapshot| snapshot.taken <= at)
+        .ok_or_else(|| format!("no feed snapshot from before {}", format_time(at)))?;
+
+    match fs::read_to_string(&snapshot.path) {
+        Ok(feed) => Ok((feed, snapshot.path)),
+        Err(e) => Err(format!("failed to read feed snapshot: {}", e)),
+    }
+}
+
+fn format_time(unix: Unix) -> String {
+    chrono::DateTime::from_timestamp(unix.as_secs() as i64, 0)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+/// Prints the snapshots of every podcast that has them, in the format `--from-snapshot`
+/// takes the time in.
+pub fn list(filter: Option<Regex>, global_config: &GlobalConfig) {
+    let mut podcasts: Vec<_> = PodcastConfigs::load()
+        .assert_not_empty()
+        .filter(filter)
+        .into_iter()
+        .collect();
+    podcasts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut found = false;
+    for (name, config) in podcasts {
+        let tracker_path = config.tracker_path(&name, global_config);
+        for snapshot in snapshots(&tracker_path) {
+            let size = fs::metadata(&snapshot.path).map(|meta| meta.len());
+            println!(
+                "{}: {} ({})",
+                name,
+                format_time(snapshot.taken),
+                size.map(format::human_bytes).unwrap_or_default()
+            );
+            found = true;
+        }
+    }
+
+    if !found {
+        eprintln!("no feed snapshots, set 'archive_feed = true' to archive feeds");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = "<rss><channel><title>Podcast</title>\
+        <lastBuildDate>Mon, 02 Sep 2024 06:00:00 +0000</lastBuildDate>\
+        <item><title>First</title></item></channel></rss>";
+
+    fn at(secs: u64) -> Unix {
+        Unix::from_secs(secs)
+    }
+
+    fn names(tracker: &Path) -> Vec<String> {
+        snapshots(tracker)
+            .iter()
+            .map(|snapshot| snapshot.path.file_name().unwrap().to_string_lossy().into())
+            .collect()
+    }
+
+    #[test]
+    fn volatile_parts_are_not_hashed() {
+        let rebuilt = FEED.replace("02 Sep", "09 Sep");
+        assert_eq!(digest(FEED), digest(&rebuilt));
+
+        let commented = FEED.replace("<item>", "<!-- generated\nat 6:00 --><item>");
+        assert_eq!(digest(FEED), digest(&commented));
+
+        let changed = FEED.replace("First", "Second");
+        assert_ne!(digest(FEED), digest(&changed));
+    }
+
+    #[test]
+    fn identical_feeds_are_archived_once() {
+        let dir = paths::test_dir("snapshots-identical");
+        let tracker = dir.join(".downloaded");
+
+        let path = archive_at(&tracker, FEED, 0, at(100)).unwrap().unwrap();
+        assert_eq!(
+            path,
+            dir.join(".downloaded.snapshots")
+                .join(format!("100-{:016x}.xml", digest(FEED)))
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), FEED);
+
+        let rebuilt = FEED.replace("02 Sep", "09 Sep");
+        assert_eq!(archive_at(&tracker, &rebuilt, 0, at(200)), Ok(None));
+        assert_eq!(names(&tracker).len(), 1);
+
+        let json = r#"{"version": "https://jsonfeed.org/version/1", "title": "x"}"#;
+        let path = archive_at(&tracker, json, 0, at(300)).unwrap().unwrap();
+        assert_eq!(path.extension().unwrap(), "json");
+    }
+
+    #[test]
+    fn oldest_snapshots_are_removed() {
+        let dir = paths::test_dir("snapshots-max");
+        let tracker = dir.join(".downloaded");
+
+        for i in 1..=4 {
+            let feed = FEED.replace("First", &format!("Episode {}", i));
+            archive_at(&tracker, &feed, 2, at(i * 100)).unwrap();
+        }
+
+        let taken: Vec<u64> = snapshots(&tracker)
+            .iter()
+            .map(|snapshot| snapshot.taken.as_secs())
+            .collect();
+        assert_eq!(taken, vec![300, 400]);
+
+        // Temporary files and anything else in the directory are ignored.
+        fs::write(super::dir(&tracker).join("500-zz.xml"), "").unwrap();
+        fs::write(super::dir(&tracker).join("600-1.tmp"), "").unwrap();
+        assert_eq!(names(&tracker).len(), 2);
+    }
+
+    #[test]
+    fn newest_snapshot_by_time() {
+        let dir = paths::test_dir("snapshots-load");
+        let tracker = dir.join(".downloaded");
+        let second = FEED.replace("First", "Second");
+        archive_at(&tracker, FEED, 0, at(100)).unwrap();
+        archive_at(&tracker, &second, 0, at(200)).unwrap();
+
+        assert_eq!(load(&tracker, at(100)).unwrap().0, FEED);
+        assert_eq!(load(&tracker, at(199)).unwrap().0, FEED);
+        assert_eq!(load(&tracker, at(1000)).unwrap().0, second);
+
+        let err = load(&tracker, at(99)).unwrap_err();
+        assert!(err.starts_with("no feed snapshot from before "), "{}", err);
+    }
+}
@@ -0,0 +1,561 @@
+use crate::backlog::Schedule;
+use crate::cadence::Cadence;
+use crate::config::GlobalConfig;
+use crate::config::PodcastConfig;
+use crate::config::PodcastConfigs;
+use crate::download_tracker::DownloadedEpisodes;
+use crate::download_tracker::TrackerEntry;
+use crate::feed_delta::SeenItems;
+use crate::feed_hints::FeedHints;
+use crate::fingerprint::Fingerprints;
+use crate::hook::HookRecords;
+use crate::mp3::DeferredTags;
+use crate::orphans::FeedRecord;
+use crate::paths;
+use crate::quota::DailyCap;
+use crate::reliability::LengthReliability;
+use crate::schedule::LastSync;
+use crate::utils;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+
+/// Version 2 added the config files and the state kept next to the trackers.
+const STATE_VERSION: u32 = 2;
+
+/// Portable snapshot of which episodes have been downloaded.
+///
+/// Used for setting up TaleCast on another machine without re-downloading
+/// every episode that was already downloaded elsewhere.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct State {
+    version: u32,
+    /// Unix time of when the state was exported.
+    exported: u64,
+    /// Version of TaleCast that exported the state.
+    #[serde(default)]
+    talecast_version: Option<String>,
+    podcasts: HashMap<String, Vec<TrackerEntry>>,
+    #[serde(default)]
+    config: Option<ConfigFiles>,
+    /// Contents of the files kept next to each tracker, by podcast and kind of file.
+    #[serde(default)]
+    tracker_state: HashMap<String, HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ConfigFiles {
+    podcasts: toml::Value,
+    global: toml::Value,
+}
+
+impl State {
+    /// The trackers, the state kept next to them and the config of `podcasts`.
+    fn collect(podcasts: PodcastConfigs, global_config: &GlobalConfig) -> Result<Self, String> {
+        let config = match (
+            toml::Value::try_from(&podcasts),
+            toml::Value::try_from(global_config),
+        ) {
+            (Ok(podcasts), Ok(global)) => ConfigFiles { podcasts, global },
+            _ => return Err("failed to serialize config".to_string()),
+        };
+
+        let mut tracker_state = HashMap::new();
+        let podcasts = podcasts
+            .into_iter()
+            .map(|(name, config)| {
+                let tracker = config.tracker_path(&name, global_config);
+
+                let files: HashMap<String, serde_json::Value> = tracker_state_paths(&tracker)
+                    .into_iter()
+                    .filter_map(|(kind, path)| {
+                        let json = fs::read_to_string(path).ok()?;
+                        Some((kind.to_string(), serde_json::from_str(&json).ok()?))
+                    })
+                    .collect();
+                if !files.is_empty() {
+                    tracker_state.insert(name.clone(), files);
+                }
+
+                let entries = DownloadedEpisodes::entries(&tracker);
+                (name, entries)
+            })
+            .collect();
+
+        Ok(Self {
+            version: STATE_VERSION,
+            exported: utils::current_unix().as_secs(),
+            talecast_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            podcasts,
+            config: Some(config),
+            tracker_state,
+        })
+    }
+}
+
+/// The files kept next to a download tracker, by the name they're exported under.
+pub fn tracker_state_paths(tracker: &Path) -> [(&'static str, PathBuf); 11] {
+    [
+        ("fingerprints", Fingerprints::path(tracker)),
+        ("lengths", LengthReliability::path(tracker)),
+        ("backlog", Schedule::path(tracker)),
+        ("synced", LastSync::path(tracker)),
+        ("cadence", Cadence::path(tracker)),
+        ("daily", DailyCap::path(tracker)),
+        ("hints", FeedHints::path(tracker)),
+        ("hooks", HookRecords::path(tracker)),
+        ("untagged", DeferredTags::path(tracker)),
+        ("feed", FeedRecord::path(tracker)),
+        ("seen", SeenItems::path(tracker)),
+    ]
+}
+
+/// Brings a state exported by an older version up to the current format.
+fn migrate(mut state: State) -> State {
+    // Version 1 only lacks the fields that default to empty, so there's nothing to convert.
+    state.version = STATE_VERSION;
+    state
+}
+
+pub fn export(p: &Path, filter: Option<Regex>, global_config: &GlobalConfig) {
+    let podcasts = PodcastConfigs::load().assert_not_empty().filter(filter);
+    let state = match State::collect(podcasts, global_config) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let json = serde_json::to_string_pretty(&state).expect("failed to serialize state");
+
+    if let Err(e) = paths::write(p, json) {
+        eprintln!("failed to write state to {:?}: {:?}", p, e);
+        process::exit(1);
+    }
+
+    let episodes: usize = state.podcasts.values().map(Vec::len).sum();
+    eprintln!(
+        "exported {} episodes from {} podcasts",
+        episodes,
+        state.podcasts.len()
+    );
+}
+
+/// Replaces the download roots of the old machine in paths with the ones on this machine.
+struct RootMap(Vec<(String, String)>);
+
+impl RootMap {
+    fn parse(mappings: &[String]) -> Result<Self, String> {
+        mappings
+            .iter()
+            .map(|mapping| match mapping.split_once('=') {
+                Some((old, new)) if !old.is_empty() && !new.is_empty() => Ok((
+                    old.trim_end_matches('/').to_string(),
+                    new.trim_end_matches('/').to_string(),
+                )),
+                _ => Err(format!(
+                    "expected --map-root OLD=NEW, found \"{}\"",
+                    mapping
+                )),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    fn map(&self, s: &str) -> Option<String> {
+        self.0.iter().find_map(|(old, new)| {
+            let rest = s.strip_prefix(old.as_str())?;
+            (rest.is_empty() || rest.starts_with('/')).then(|| format!("{}{}", new, rest))
+        })
+    }
+
+    fn apply_toml(&self, value: &mut toml::Value) {
+        match value {
+            toml::Value::String(s) => {
+                if let Some(mapped) = self.map(s) {
+                    *s = mapped;
+                }
+            }
+            toml::Value::Array(values) => values.iter_mut().for_each(|v| self.apply_toml(v)),
+            toml::Value::Table(table) => table.iter_mut().for_each(|(_, v)| self.apply_toml(v)),
+            _ => {}
+        }
+    }
+
+    fn apply_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(s) => {
+                if let Some(mapped) = self.map(s) {
+                    *s = mapped;
+                }
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(|v| self.apply_json(v)),
+            serde_json::Value::Object(map) => map.values_mut().for_each(|v| self.apply_json(v)),
+            _ => {}
+        }
+    }
+}
+
+/// Restores an exported state onto this machine.
+///
+/// Episodes are matched by their ID. When an episode is already in the local tracker,
+/// the local entry is kept untouched and the imported one is skipped, so that importing
+/// never changes what this machine considers downloaded. Podcasts that aren't configured
+/// are skipped.
+///
+/// The config files and the state kept next to the trackers are only restored if there's
+/// none on this machine yet, unless `force` is set. Nothing is written if anything would be
+/// replaced without it.
+pub fn import(p: &Path, map_root: &[String], force: bool, global_config: &GlobalConfig) {
+    let json = match fs::read_to_string(p) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("failed to read state file {:?}: {:?}", p, e);
+            process::exit(1);
+        }
+    };
+
+    let state: State = match serde_json::from_str(&json) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("failed to parse state file {:?}: {:?}", p, e);
+            process::exit(1);
+        }
+    };
+
+    if state.version > STATE_VERSION {
+        eprintln!(
+            "state file version {} is newer than supported version {}",
+            state.version, STATE_VERSION
+        );
+        process::exit(1);
+    }
+    let state = migrate(state);
+
+    let root_map = match RootMap::parse(map_root) {
+        Ok(root_map) => root_map,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    let mut plan = match Import::plan(
+        state,
+        root_map,
+        PodcastConfigs::load(),
+        force,
+        global_config,
+    ) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Some((podcasts, global)) = plan.config.take() {
+        podcasts.save_to_file();
+        global.save();
+        eprintln!("restored podcasts.toml and config.toml");
+    }
+
+    if let Err(e) = plan.restore_trackers() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// An import that was checked not to replace anything it shouldn't.
+struct Import {
+    state: State,
+    root_map: RootMap,
+    /// The imported config files, if they're restored.
+    config: Option<(PodcastConfigs, GlobalConfig)>,
+    /// Where the trackers of the configured podcasts are, by podcast.
+    trackers: HashMap<String, PathBuf>,
+}
+
+impl Import {
+    /// Checks that importing `state` onto a machine with `local_podcasts` configured only
+    /// replaces existing config and state if `force` is set.
+    fn plan(
+        mut state: State,
+        root_map: RootMap,
+        local_podcasts: PodcastConfigs,
+        force: bool,
+        global_config: &GlobalConfig,
+    ) -> Result<Self, String> {
+        let config = match state.config.take() {
+            Some(_) if !local_podcasts.is_empty() && !force => {
+                return Err(format!(
+                    "podcasts.toml already has {} podcasts, use --force to replace it and config.toml with the imported ones",
+                    local_podcasts.len()
+                ));
+            }
+            Some(mut config) => {
+                root_map.apply_toml(&mut config.podcasts);
+                root_map.apply_toml(&mut config.global);
+                // The toml crate can't read the map back into the `PodcastConfigs` newtype.
+                match (
+                    config.podcasts.try_into::<HashMap<String, PodcastConfig>>(),
+                    config.global.try_into::<GlobalConfig>(),
+                ) {
+                    (Ok(podcasts), Ok(global)) => {
+                        Some((PodcastConfigs::from_iter(podcasts), global))
+                    }
+                    (Err(e), _) | (_, Err(e)) => {
+                        return Err(format!(
+                            "failed to read the config in the state file: {}",
+                            e
+                        ));
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let (configs, global_config) = match &config {
+            Some((podcasts, global)) => (podcasts.clone(), global),
+            None => (local_podcasts, global_config),
+        };
+        let trackers: HashMap<String, PathBuf> = configs
+            .check_collisions(global_config)
+            .into_iter()
+            .map(|(name, config)| {
+                let tracker = config.tracker_path(&name, global_config);
+                (name, tracker)
+            })
+            .collect();
+
+        if !force {
+            let existing: Vec<PathBuf> = state
+                .tracker_state
+                .iter()
+                .filter_map(|(name, files)| Some((trackers.get(name)?, files)))
+                .flat_map(|(tracker, files)| {
+                    tracker_state_paths(tracker)
+                        .into_iter()
+                        .filter(|(kind, _)| files.contains_key(*kind))
+                        .map(|(_, path)| path)
+                })
+                .filter(|path| fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0))
+                .collect();
+
+            if !existing.is_empty() {
+                let mut msg = String::new();
+                for path in &existing {
+                    msg.push_str(&format!("would replace {:?}\n", path));
+                }
+                msg.push_str("use --force to replace the existing state");
+                return Err(msg);
+            }
+        }
+
+        Ok(Self {
+            state,
+            root_map,
+            config,
+            trackers,
+        })
+    }
+
+    /// Adds the imported entries to the trackers and writes the state kept next to them.
+    fn restore_trackers(mut self) -> Result<(), String> {
+        for (name, entries) in self.state.podcasts {
+            let Some(tracker) = self.trackers.get(&name) else {
+                eprintln!("skipping '{}': podcast not configured", name);
+                continue;
+            };
+
+            let downloaded = DownloadedEpisodes::load(tracker);
+
+            let mut imported = 0;
+            let mut existing = 0;
+            for entry in entries {
+                if downloaded.contains_episode(&entry.id) {
+                    existing += 1;
+                    continue;
+                }
+
+                DownloadedEpisodes::append_entry(tracker, &entry)
+                    .map_err(|e| format!("failed to import state for '{}': {}", name, e))?;
+                imported += 1;
+            }
+
+            let mut files = self.state.tracker_state.remove(&name).unwrap_or_default();
+            if let Some(parent) = tracker.parent().filter(|_| !files.is_empty()) {
+                paths::create_dir(parent);
+            }
+            for (kind, path) in tracker_state_paths(tracker) {
+                let Some(mut value) = files.remove(kind) else {
+                    continue;
+                };
+                self.root_map.apply_json(&mut value);
+
+                let res = serde_json::to_string_pretty(&value)
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| paths::write(&path, json).map_err(|e| e.to_string()));
+                if let Err(e) = res {
+                    eprintln!("failed to import {} of '{}': {}", kind, name, e);
+                }
+            }
+
+            eprintln!(
+                "{}: {} episodes imported, {} already present",
+                name, imported, existing
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn podcasts(download_path: &Path) -> PodcastConfigs {
+        let config = toml::from_str(&format!(
+            "url = \"https://example.com/feed.xml\"\ndownload_path = \"{}\"",
+            download_path.display()
+        ))
+        .unwrap();
+        [("show".to_string(), config)].into_iter().collect()
+    }
+
+    fn no_podcasts() -> PodcastConfigs {
+        std::iter::empty().collect()
+    }
+
+    /// Sets up a tracker with two episodes, along with their fingerprints.
+    fn downloaded(download_path: &Path) -> PathBuf {
+        let tracker = download_path.join(".downloaded");
+        for (n, acknowledged) in [(1, None), (2, Some(5))] {
+            let entry = TrackerEntry {
+                id: format!("episode-{}", n),
+                downloaded: n,
+                title: format!("Episode {}", n),
+                acknowledged,
+            };
+            DownloadedEpisodes::append_entry(&tracker, &entry).unwrap();
+        }
+
+        let fingerprints = serde_json::json!({
+            "episode-1": {
+                "length": 1,
+                "etag": null,
+                "last_modified": null,
+                "path": download_path.join("Episode 1.mp3"),
+            }
+        });
+        paths::write(Fingerprints::path(&tracker), fingerprints.to_string()).unwrap();
+        tracker
+    }
+
+    /// The state as it's read back from the exported file.
+    fn exported(podcasts: PodcastConfigs, global_config: &GlobalConfig) -> State {
+        let state = State::collect(podcasts, global_config).unwrap();
+        let json = serde_json::to_string_pretty(&state).unwrap();
+        migrate(serde_json::from_str(&json).unwrap())
+    }
+
+    #[test]
+    fn round_trip_to_another_root() {
+        let dir = paths::test_dir("state-round-trip");
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        let global_config = GlobalConfig::default();
+        downloaded(&old.join("show"));
+        let state = exported(podcasts(&old.join("show")), &global_config);
+        assert_eq!(state.version, STATE_VERSION);
+
+        let root_map = RootMap::parse(&[format!("{}={}", old.display(), new.display())]).unwrap();
+        let mut plan = Import::plan(state, root_map, no_podcasts(), false, &global_config).unwrap();
+
+        let (podcasts, _) = plan.config.take().unwrap();
+        let tracker = new.join("show").join(".downloaded");
+        assert_eq!(
+            podcasts
+                .into_iter()
+                .next()
+                .unwrap()
+                .1
+                .tracker_path("show", &global_config),
+            tracker
+        );
+        plan.restore_trackers().unwrap();
+
+        let entries: Vec<(String, u64, Option<u64>)> = DownloadedEpisodes::entries(&tracker)
+            .into_iter()
+            .map(|entry| (entry.id, entry.downloaded, entry.acknowledged))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("episode-1".to_string(), 1, None),
+                ("episode-2".to_string(), 2, Some(5)),
+            ]
+        );
+
+        let fingerprints: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(Fingerprints::path(&tracker)).unwrap())
+                .unwrap();
+        assert_eq!(
+            fingerprints["episode-1"]["path"].as_str(),
+            new.join("show").join("Episode 1.mp3").to_str()
+        );
+    }
+
+    #[test]
+    fn existing_state_needs_force() {
+        let dir = paths::test_dir("state-force");
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        let global_config = GlobalConfig::default();
+        downloaded(&old);
+        let local = downloaded(&new);
+        let before = fs::read_to_string(Fingerprints::path(&local)).unwrap();
+
+        let import = |with_config: bool, force| {
+            let mut state = exported(podcasts(&old), &global_config);
+            if !with_config {
+                state.config = None;
+            }
+            Import::plan(
+                state,
+                RootMap::parse(&[]).unwrap(),
+                podcasts(&new),
+                force,
+                &global_config,
+            )
+        };
+
+        // The config would replace the configured podcasts.
+        let err = import(true, false).err().unwrap();
+        assert!(err.starts_with("podcasts.toml already has 1 podcasts"));
+
+        // A state exported without the config would still replace the local fingerprints.
+        let err = import(false, false).err().unwrap();
+        assert_eq!(
+            err,
+            format!(
+                "would replace {:?}\nuse --force to replace the existing state",
+                Fingerprints::path(&local)
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(Fingerprints::path(&local)).unwrap(),
+            before
+        );
+
+        import(false, true).unwrap().restore_trackers().unwrap();
+        assert_ne!(
+            fs::read_to_string(Fingerprints::path(&local)).unwrap(),
+            before
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::changelog::ChangelogEntry;
+use crate::feed_delta::FeedDelta;
+use serde::Serialize;
+use serde::Serializer;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The parts of a sync that time is spent on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    FeedFetching,
+    FeedParsing,
+    Downloading,
+    Tagging,
+    Artwork,
+    Hooks,
+    StateWrites,
+}
+
+impl Phase {
+    pub const ALL: [Self; 7] = [
+        Self::FeedFetching,
+        Self::FeedParsing,
+        Self::Downloading,
+        Self::Tagging,
+        Self::Artwork,
+        Self::Hooks,
+        Self::StateWrites,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::FeedFetching => "feed_fetching",
+            Self::FeedParsing => "feed_parsing",
+            Self::Downloading => "downloading",
+            Self::Tagging => "tagging",
+            Self::Artwork => "artwork",
+            Self::Hooks => "hooks",
+            Self::StateWrites => "state_writes",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Time spent in each [`Phase`] while syncing a podcast.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PhaseTimings([Duration; Phase::ALL.len()]);
+
+impl PhaseTimings {
+    pub fn add(&mut self, phase: Phase, elapsed: Duration) {
+        self.0[phase.index()] += elapsed;
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        self.0[phase.index()]
+    }
+}
+
+impl Serialize for PhaseTimings {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            Phase::ALL
+                .iter()
+                .map(|phase| (phase.name(), self.get(*phase).as_secs_f64())),
+        )
+    }
+}
+
+/// The outcome of syncing a single podcast.
+#[derive(Serialize, Debug, Default)]
+pub struct SyncResult {
+    #[serde(skip)]
+    pub name: String,
+    /// Paths of the episodes that were downloaded.
+    #[serde(skip)]
+    pub paths: Vec<PathBuf>,
+    /// The episodes that were downloaded, as they're listed in the changelog.
+    #[serde(skip)]
+    pub changelog: Vec<ChangelogEntry>,
+    /// Episodes that were downloaded.
+    pub new: usize,
+    /// Whether nothing had been downloaded from the podcast before this run.
+    pub first_download: bool,
+    /// Pending episodes that weren't attempted because an earlier download failed.
+    pub skipped: usize,
+    /// Episodes that failed to download.
+    pub failed: usize,
+    /// Enclosure urls of the episodes that failed to download, with credentials redacted.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_urls: Vec<String>,
+    /// Video episodes that weren't downloaded because of `video_handling = "skip"`.
+    pub skipped_videos: usize,
+    /// Episodes left for another day because of `max_per_day`.
+    pub deferred: usize,
+    /// Pending episodes left for the next sync because `--max-runtime` was reached.
+    pub postponed: usize,
+    /// Downloaded episodes removed from disk because of `keep_latest`.
+    pub removed: usize,
+    /// How the items of the feed changed since the last sync.
+    #[serde(skip_serializing_if = "FeedDelta::is_empty")]
+    pub upstream: FeedDelta,
+    /// Whether the feed wasn't fetched at all because `--max-runtime` was reached.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub not_fetched: bool,
+    /// Whether the feed was skipped because it hadn't changed since a sync that left nothing
+    /// to do.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unchanged: bool,
+    /// The url of the feed, with credentials redacted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feed_url: Option<String>,
+    /// Why the podcast couldn't be synced at all, e.g. when its feed failed to download.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Seconds spent in each phase.
+    pub timings: PhaseTimings,
+}
+
+impl SyncResult {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    /// A podcast whose feed wasn't fetched because `--max-runtime` was reached.
+    pub fn not_fetched(name: String) -> Self {
+        Self {
+            name,
+            not_fetched: true,
+            ..Default::default()
+        }
+    }
+
+    /// A podcast whose feed hadn't changed since a sync that left nothing to do.
+    pub fn unchanged(name: String) -> Self {
+        Self {
+            name,
+            unchanged: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn failed(name: String, error: String) -> Self {
+        Self {
+            name,
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Slowest<'a> {
+    podcast: &'a str,
+    secs: f64,
+}
+
+/// Time spent in a phase across all podcasts.
+#[derive(Serialize, Debug)]
+struct PhaseTotal<'a> {
+    total_secs: f64,
+    slowest: Option<Slowest<'a>>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Totals {
+    new: usize,
+    skipped: usize,
+    failed: usize,
+    postponed: usize,
+    removed: usize,
+    /// Podcasts that couldn't be synced at all.
+    errors: usize,
+    /// Podcasts left for the next sync because `--max-runtime` was reached.
+    not_fetched: usize,
+    /// Podcasts skipped because their feed hadn't changed.
+    unchanged: usize,
+}
+
+/// Machine-readable summary of a sync, printed with `--json`.
+#[derive(Serialize, Debug)]
+pub struct SyncSummary<'a> {
+    podcasts: BTreeMap<&'a str, &'a SyncResult>,
+    totals: Totals,
+    phases: BTreeMap<&'static str, PhaseTotal<'a>>,
+    duration_secs: f64,
+}
+
+impl<'a> SyncSummary<'a> {
+    pub fn new(results: &'a [SyncResult], duration: Duration) -> Self {
+        let mut totals = Totals::default();
+        for res in results {
+            totals.new += res.new;
+            totals.skipped += res.skipped;
+            totals.failed += res.failed;
+            totals.postponed += res.postponed;
+            totals.removed += res.removed;
+            totals.errors += res.error.is_some() as usize;
+            totals.not_fetched += res.not_fetched as usize;
+            totals.unchanged += res.unchanged as usize;
+        }
+
+        let phases = Phase::ALL
+            .iter()
+            .map(|phase| {
+                let total: Duration = results.iter().map(|res| res.timings.get(*phase)).sum();
+                let slowest = results
+                    .iter()
+                    .max_by_key(|res| res.timings.get(*phase))
+                    .filter(|res| !res.timings.get(*phase).is_zero())
+                    .map(|res| Slowest {
+                        podcast: &res.name,
+                        secs: res.timings.get(*phase).as_secs_f64(),
+                    });
+
+                let total = PhaseTotal {
+                    total_secs: total.as_secs_f64(),
+                    slowest,
+                };
+                (phase.name(), total)
+            })
+            .collect();
+
+        Self {
+            podcasts: results.iter().map(|res| (res.name.as_str(), res)).collect(),
+            totals,
+            phases,
+            duration_secs: duration.as_secs_f64(),
+        }
+    }
+
+    /// Prints how long each phase took, to find out where time is spent.
+    pub fn print_timings(&self) {
+        eprintln!("time spent per phase:");
+        for phase in Phase::ALL {
+            let total = &self.phases[phase.name()];
+            match &total.slowest {
+                Some(slowest) => eprintln!(
+                    "  {:<14}{:>8.2}s (slowest: {} {:.2}s)",
+                    phase.name(),
+                    total.total_secs,
+                    slowest.podcast,
+                    slowest.secs
+                ),
+                None => eprintln!("  {:<14}{:>8.2}s", phase.name(), total.total_secs),
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize sync summary")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_download_in_json() {
+        let mut first = SyncResult::new("first".to_string());
+        first.first_download = true;
+        let results = [first, SyncResult::new("second".to_string())];
+
+        let json: serde_json::Value =
+            serde_json::from_str(&SyncSummary::new(&results, Duration::ZERO).to_json()).unwrap();
+        assert_eq!(json["podcasts"]["first"]["first_download"], true);
+        assert_eq!(json["podcasts"]["second"]["first_download"], false);
+    }
+}
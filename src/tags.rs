@@ -1,12 +1,16 @@
 use crate::display::DownloadBar;
 use crate::episode;
+use crate::language;
 use crate::podcast::RawPodcast;
+use crate::text;
 use chrono::Datelike;
 use id3::TagLike;
 
 pub async fn extract_tags_from_raw(
     podcast: &RawPodcast,
+    name: &str,
     episode: &episode::Attributes,
+    keep_description_html: bool,
     ui: &DownloadBar,
 ) -> Option<id3::Tag> {
     let mut tags = id3::Tag::new();
@@ -18,7 +22,7 @@ pub async fn extract_tags_from_raw(
         tags.set_artist(author);
     }
 
-    tags.set_album(podcast.title());
+    tags.set_album(podcast.title().unwrap_or(name));
 
     tags.set_genre("podcast");
 
@@ -41,7 +45,11 @@ pub async fn extract_tags_from_raw(
 
     if let Ok(desc) = episode.description() {
         ui.log_trace("extracting description tag");
-        tags.set_text(Id3Tag::DESCRIPTION, desc);
+        if keep_description_html {
+            tags.set_text(Id3Tag::DESCRIPTION, desc);
+        } else {
+            tags.set_text(Id3Tag::DESCRIPTION, text::html_to_text(desc));
+        }
     }
 
     let mut strs = vec![];
@@ -71,17 +79,23 @@ pub async fn extract_tags_from_raw(
 
     tags.set_date_released(ts);
 
+    // The feed's own value is left as it is for patterns.
     if let Some(language) = podcast.language() {
-        ui.log_trace("extracting language tag");
-        tags.set_text(Id3Tag::LANGUAGE, language);
+        match language::iso_639_2(language) {
+            Some(code) => {
+                ui.log_trace("extracting language tag");
+                tags.set_text(Id3Tag::LANGUAGE, code);
+            }
+            None => ui.log_debug(format!(
+                "skipping language tag: unknown language {:?}",
+                language
+            )),
+        }
     }
 
-    if let Ok(dur) = episode.itunes_duration() {
-        if let Ok(secs) = dur.parse::<u32>() {
-            ui.log_trace("extracting itunes duration tag");
-            let millis = secs * 1000;
-            tags.set_text(Id3Tag::DURATION, millis.to_string());
-        }
+    if let Some(duration) = episode.itunes_duration().filter(|dur| !dur.is_zero()) {
+        ui.log_trace("extracting itunes duration tag");
+        tags.set_text(Id3Tag::DURATION, duration.as_millis().to_string());
     }
 
     if let Some(author) = podcast.author() {
@@ -91,9 +105,42 @@ pub async fn extract_tags_from_raw(
 
     tags.set_text(Id3Tag::PODCAST_ID, episode.guid());
 
+    canonicalize(&mut tags);
     Some(tags)
 }
 
+/// Puts multi-value text frames in a canonical form, so that applying the same tags to a
+/// file again leaves them as they are.
+pub fn canonicalize(tags: &mut id3::Tag) {
+    let canonical: Vec<id3::Frame> = tags
+        .frames()
+        .filter_map(|frame| {
+            let text = frame.content().text()?;
+            let canonical = canonical_text(text);
+            (canonical != text).then(|| id3::Frame::text(frame.id(), canonical))
+        })
+        .collect();
+
+    for frame in canonical {
+        tags.add_frame(frame);
+    }
+}
+
+/// The canonical form of the text of a frame.
+///
+/// Multiple values are separated by null characters. Empty and repeated values are
+/// dropped, keeping the order in which the values first appear.
+pub fn canonical_text(text: &str) -> String {
+    let mut values: Vec<&str> = vec![];
+    for value in text.split('\0') {
+        if !value.is_empty() && !values.contains(&value) {
+            values.push(value);
+        }
+    }
+
+    values.join("\0")
+}
+
 struct Id3Tag;
 
 impl Id3Tag {
@@ -105,3 +152,72 @@ impl Id3Tag {
     const PUBLISHER: &'static str = "TPUB";
     const PODCAST_ID: &'static str = "TGID";
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GlobalConfig;
+    use crate::media::MediaPreference;
+    use indicatif::{MultiProgress, ProgressDrawTarget};
+
+    async fn language_tag(language: &str) -> Option<String> {
+        let podcast = serde_json::json!({ "title": "Podcast", "language": language });
+        let podcast = RawPodcast::new(podcast.as_object().unwrap().clone());
+        let item = serde_json::json!({
+            "title": "Episode",
+            "pubDate": "Mon, 01 Jan 2024 00:00:00 +0000",
+            "enclosure": { "@url": "https://example.com/episode.mp3", "@type": "audio/mpeg" },
+        });
+        let raw = episode::RawEpisode::new(item.as_object().unwrap().clone());
+        let attrs = episode::Attributes::new(raw, &MediaPreference::default()).unwrap();
+
+        let mp = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let ui = DownloadBar::new(
+            "Podcast".to_string(),
+            GlobalConfig::default().style(),
+            &mp,
+            7,
+        );
+        let tags = extract_tags_from_raw(&podcast, "Podcast", &attrs, false, &ui)
+            .await
+            .unwrap();
+        tags.get(Id3Tag::LANGUAGE)
+            .and_then(|frame| frame.content().text())
+            .map(String::from)
+    }
+
+    #[tokio::test]
+    async fn known_languages_are_tagged() {
+        assert_eq!(language_tag("pt-BR").await.as_deref(), Some("por"));
+    }
+
+    #[tokio::test]
+    async fn unknown_languages_are_skipped() {
+        assert_eq!(language_tag("Klingon").await, None);
+        assert_eq!(language_tag("").await, None);
+    }
+
+    #[test]
+    fn canonical_texts() {
+        assert_eq!(canonical_text("News"), "News");
+        assert_eq!(canonical_text("News\0Tech"), "News\0Tech");
+        assert_eq!(canonical_text("News\0\0Tech\0News\0"), "News\0Tech");
+        assert_eq!(canonical_text("\0"), "");
+        assert_eq!(canonical_text(&canonical_text("b\0a\0b")), "b\0a");
+    }
+
+    #[test]
+    fn canonical_frames() {
+        let mut tag = id3::Tag::new();
+        tag.set_text("TCON", "News\0News\0Tech");
+        tag.set_title("Title");
+        canonicalize(&mut tag);
+
+        assert_eq!(
+            tag.get("TCON").and_then(|f| f.content().text()),
+            Some("News\0Tech")
+        );
+        assert_eq!(tag.title(), Some("Title"));
+        assert_eq!(tag.frames().count(), 2);
+    }
+}
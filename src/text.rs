@@ -0,0 +1,319 @@
+use serde_json::Value;
+
+pub fn truncate_string(s: &str, max_width: usize, append_dots: bool) -> String {
+    let mut width = 0;
+    let mut truncated = String::new();
+    let mut reached_max = false;
+
+    for c in s.chars() {
+        let mut buf = [0; 4];
+        let encoded_char = c.encode_utf8(&mut buf);
+        let char_width = unicode_width::UnicodeWidthStr::width(encoded_char);
+        if width + char_width > max_width {
+            reached_max = true;
+            break;
+        }
+        truncated.push(c);
+        width += char_width;
+    }
+
+    if reached_max && append_dots {
+        truncated.pop();
+        truncated.pop();
+        truncated.pop();
+
+        truncated.push_str("...");
+    }
+
+    truncated
+}
+
+pub fn replacer(val: Value, input: &str) -> String {
+    let mut inside = false;
+    let mut output = String::new();
+    let mut pattern = String::new();
+    for c in input.chars() {
+        if c == '{' {
+            if inside {
+                panic!();
+            } else {
+                inside = true;
+            }
+        } else if c == '}' {
+            if !inside {
+                panic!();
+            } else {
+                let p = std::mem::take(&mut pattern);
+                let mut replacement = val
+                    .get(&p)
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| format!("<<{}>>", p))
+                    .replace("\\", "");
+                replacement.pop();
+                replacement.remove(0);
+                output.push_str(&replacement);
+                inside = false;
+            }
+        } else {
+            if inside {
+                pattern.push(c);
+            } else {
+                output.push(c);
+            }
+        }
+    }
+
+    output
+}
+
+pub fn trim_quotes(s: &str) -> String {
+    let s = s.trim_end_matches("\"");
+    let s = s.trim_start_matches("\"");
+    s.to_string()
+}
+
+pub fn parse_quoted_words(line: &str) -> Option<(String, String)> {
+    let (key, val) = line.split_once(" ")?;
+    let key = trim_quotes(key);
+    let val = trim_quotes(val);
+
+    Some((key, val))
+}
+
+/// Tags that start a new paragraph.
+const BLOCK_TAGS: [&str; 13] = [
+    "p",
+    "div",
+    "ul",
+    "ol",
+    "blockquote",
+    "pre",
+    "table",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+];
+
+/// Tags whose contents aren't text.
+const HIDDEN_TAGS: [&str; 2] = ["script", "style"];
+
+/// Plain text from html, like the descriptions in feeds, with the entities decoded.
+///
+/// Paragraphs are separated by a blank line, and `<br>` and list items start a new line.
+/// Other whitespace is collapsed the way a browser would. Text without any tags is only
+/// decoded, so the line breaks of plain text descriptions are kept.
+pub fn html_to_text(html: &str) -> String {
+    let is_html = html
+        .match_indices('<')
+        .any(|(i, _)| html[i + 1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/'));
+    if !is_html {
+        return decode_entities(html).trim().to_string();
+    }
+
+    let mut text = String::new();
+    let mut hidden: Option<String> = None;
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        if hidden.is_none() {
+            push_collapsed(&mut text, &decode_entities(&rest[..start]));
+        }
+
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        if let Some(hidden_name) = &hidden {
+            if closing && name == *hidden_name {
+                hidden = None;
+            }
+            continue;
+        }
+
+        match name.as_str() {
+            name if HIDDEN_TAGS.contains(&name) && !closing => hidden = Some(name.to_string()),
+            "br" => push_break(&mut text, 1),
+            "li" if !closing => {
+                push_break(&mut text, 1);
+                text.push_str("- ");
+            }
+            name if BLOCK_TAGS.contains(&name) => push_break(&mut text, 2),
+            _ => {}
+        }
+    }
+
+    if hidden.is_none() {
+        push_collapsed(&mut text, &decode_entities(rest));
+    }
+
+    text.lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Adds text with its whitespace collapsed to single spaces.
+fn push_collapsed(text: &mut String, s: &str) {
+    for (i, word) in s
+        .split(|c: char| c.is_whitespace() && c != '\u{a0}')
+        .enumerate()
+    {
+        if i > 0 && !text.is_empty() && !text.ends_with([' ', '\n']) {
+            text.push(' ');
+        }
+        text.push_str(word);
+    }
+}
+
+/// Ends the text with at least `lines` line breaks, unless it's empty.
+fn push_break(text: &mut String, lines: usize) {
+    if text.is_empty() {
+        return;
+    }
+
+    while text.ends_with(' ') {
+        text.pop();
+    }
+    let existing = text.len() - text.trim_end_matches('\n').len();
+    for _ in existing..lines {
+        text.push('\n');
+    }
+}
+
+/// Decodes html entities, like `&amp;`, `&#39;` and `&#x27;`. Unknown ones are kept as is.
+pub fn decode_entities(s: &str) -> String {
+    let mut decoded = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let c = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            "ndash" => Some('–'),
+            "mdash" => Some('—'),
+            "hellip" => Some('…'),
+            "lsquo" => Some('‘'),
+            "rsquo" => Some('’'),
+            "ldquo" => Some('“'),
+            "rdquo" => Some('”'),
+            _ => {
+                let code = entity.strip_prefix('#')?;
+                let code = match code.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => code.parse().ok()?,
+                };
+                char::from_u32(code)
+            }
+        });
+
+        match (entity, c) {
+            (Some(entity), Some(c)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncating_by_width() {
+        assert_eq!(truncate_string("short", 10, true), "short");
+        assert_eq!(truncate_string("a long title", 8, false), "a long t");
+        assert_eq!(truncate_string("a long title", 8, true), "a lon...");
+        // Wide characters take two columns.
+        assert_eq!(truncate_string("日本語のタイトル", 7, false), "日本語");
+        assert_eq!(truncate_string("日本語のタイトル", 7, true), "...");
+    }
+
+    #[test]
+    fn replacing_placeholders() {
+        let val = serde_json::json!({ "title": "Episode \"1\"", "n": "2" });
+        assert_eq!(replacer(val.clone(), "{title} ({n})"), "Episode \"1\" (2)");
+        assert_eq!(replacer(val, "no {missing}"), "no <missing>");
+    }
+
+    #[test]
+    fn quoted_words() {
+        assert_eq!(
+            parse_quoted_words("\"id\" \"a title\""),
+            Some(("id".to_string(), "a title".to_string()))
+        );
+        assert_eq!(
+            parse_quoted_words("id 1 \"title\""),
+            Some(("id".to_string(), "1 \"title".to_string()))
+        );
+        assert_eq!(parse_quoted_words("id"), None);
+    }
+
+    #[test]
+    fn html_descriptions() {
+        assert_eq!(
+            html_to_text("<p>First   paragraph</p><p>Second<br>line</p>"),
+            "First paragraph\n\nSecond\nline"
+        );
+        assert_eq!(
+            html_to_text("Links:<ul><li><a href=\"x\">One</a></li><li>Two</li></ul>"),
+            "Links:\n\n- One\n- Two"
+        );
+        assert_eq!(
+            html_to_text("<div>Tom &amp; Jerry<script>alert(1)</script></div>"),
+            "Tom & Jerry"
+        );
+        // Without tags, the line breaks are kept.
+        assert_eq!(
+            html_to_text("  line one\nline two &lt;3  "),
+            "line one\nline two <3"
+        );
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(
+            decode_entities("&amp;&lt;&gt;&quot;&apos;&hellip;"),
+            "&<>\"'…"
+        );
+        assert_eq!(decode_entities("&#39;&#x27;&#X41;"), "''A");
+        assert_eq!(
+            decode_entities("AT&T &unknown; &#xzz; & &;"),
+            "AT&T &unknown; &#xzz; & &;"
+        );
+    }
+}
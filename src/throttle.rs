@@ -0,0 +1,289 @@
+//! A limit on how many episodes are downloaded at once across all podcasts, for
+//! `max_concurrent_downloads`.
+//!
+//! With `adaptive_concurrency`, the limit reacts to how the downloads go, the way TCP
+//! reacts to congestion: it's halved when too many of the recent attempts failed, and
+//! raised by one again after as many successes in a row as the limit. It never exceeds
+//! `max_concurrent_downloads` nor drops below 1. Every attempt, retries included, waits for
+//! a slot, so downloads that time out together on a bad connection don't all retry at once.
+
+use crate::net::DownloadError;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use tokio::sync::Notify;
+
+/// The maximum `adaptive_concurrency` starts from without `max_concurrent_downloads`.
+pub const DEFAULT_ADAPTIVE_MAX: usize = 8;
+
+/// How many of the latest attempts the failure rate is taken over.
+const WINDOW: usize = 20;
+
+/// How many attempts are needed before the failure rate is trusted.
+const MIN_SAMPLES: usize = 4;
+
+/// The share of failed attempts above which the limit is halved.
+const FAILURE_THRESHOLD: f64 = 0.25;
+
+static THROTTLE: OnceLock<Throttle> = OnceLock::new();
+
+struct Throttle {
+    state: Mutex<State>,
+    /// Woken whenever a slot frees up or the limit is raised.
+    freed: Notify,
+}
+
+struct State {
+    limit: usize,
+    active: usize,
+    /// Adjusts the limit, if it's adaptive.
+    controller: Option<Controller>,
+}
+
+/// Decides the limit from the outcomes of the download attempts, in the order they're
+/// recorded. It keeps no time, so the same outcomes always lead to the same limits.
+#[derive(Debug)]
+pub struct Controller {
+    max: usize,
+    limit: usize,
+    /// Whether each of the latest attempts failed, oldest first.
+    recent: VecDeque<bool>,
+    /// Successful attempts since the last failure or change of the limit.
+    successes: usize,
+}
+
+impl Controller {
+    pub fn new(max: usize) -> Self {
+        let max = max.max(1);
+        Self {
+            max,
+            limit: max,
+            recent: VecDeque::with_capacity(WINDOW),
+            successes: 0,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Records the outcome of an attempt, and returns the new limit if it changed.
+    pub fn record(&mut self, failed: bool) -> Option<usize> {
+        if self.recent.len() == WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(failed);
+
+        if failed {
+            self.successes = 0;
+            let failures = self.recent.iter().filter(|failed| **failed).count();
+            let rate = failures as f64 / self.recent.len() as f64;
+            if self.recent.len() < MIN_SAMPLES || rate <= FAILURE_THRESHOLD || self.limit == 1 {
+                return None;
+            }
+
+            // The attempts so far ran at the old limit, so they say nothing about the new one.
+            self.recent.clear();
+            self.limit = (self.limit / 2).max(1);
+            return Some(self.limit);
+        }
+
+        self.successes += 1;
+        if self.successes < self.limit || self.limit == self.max {
+            return None;
+        }
+
+        self.successes = 0;
+        self.limit += 1;
+        Some(self.limit)
+    }
+}
+
+/// Starts limiting the downloads, unless there's no limit.
+pub fn init(max: Option<usize>, adaptive: bool) {
+    let max = match (max.filter(|max| *max > 0), adaptive) {
+        (Some(max), _) => max,
+        (None, true) => DEFAULT_ADAPTIVE_MAX,
+        (None, false) => return,
+    };
+
+    log::info!(
+        "downloading at most {} episodes at once{}",
+        max,
+        if adaptive {
+            ", fewer while failing"
+        } else {
+            ""
+        }
+    );
+
+    let throttle = Throttle {
+        state: Mutex::new(State {
+            limit: max,
+            active: 0,
+            controller: adaptive.then(|| Controller::new(max)),
+        }),
+        freed: Notify::new(),
+    };
+
+    if THROTTLE.set(throttle).is_err() {
+        log::warn!("download limit already set");
+    }
+}
+
+/// The current limit, if it's adaptive.
+pub fn adaptive_limit() -> Option<usize> {
+    let state = THROTTLE.get()?.state.lock().unwrap();
+    state.controller.as_ref().map(Controller::limit)
+}
+
+/// A slot for a download, given back when dropped.
+pub struct Slot(&'static Throttle);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().active -= 1;
+        self.0.freed.notify_waiters();
+    }
+}
+
+/// Waits until there's room for another download, if they're limited.
+pub async fn acquire() -> Option<Slot> {
+    let throttle = THROTTLE.get()?;
+    loop {
+        // Registered before checking, so a slot freed in between isn't missed.
+        let freed = throttle.freed.notified();
+        tokio::pin!(freed);
+        freed.as_mut().enable();
+
+        {
+            let mut state = throttle.state.lock().unwrap();
+            if state.active < state.limit {
+                state.active += 1;
+                return Some(Slot(throttle));
+            }
+        }
+
+        freed.await;
+    }
+}
+
+/// Records how a download attempt went, for the adaptive limit.
+///
+/// Only errors that trying again could fix, like timeouts, count as failures. A missing
+/// episode says nothing about the connection.
+pub fn record<T>(outcome: &Result<T, DownloadError>) {
+    let failed = match outcome {
+        Ok(_) => false,
+        Err(e) if e.is_transient() => true,
+        Err(_) => return,
+    };
+
+    let Some(throttle) = THROTTLE.get() else {
+        return;
+    };
+
+    let mut state = throttle.state.lock().unwrap();
+    let Some(controller) = &mut state.controller else {
+        return;
+    };
+
+    let old = controller.limit();
+    if let Some(limit) = controller.record(failed) {
+        state.limit = limit;
+        match limit < old {
+            true => log::debug!("too many downloads failed, lowering the limit to {}", limit),
+            false => log::debug!("downloads are succeeding, raising the limit to {}", limit),
+        }
+        drop(state);
+        throttle.freed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halves_above_the_threshold() {
+        let mut controller = Controller::new(8);
+        for _ in 0..3 {
+            assert_eq!(controller.record(false), None);
+        }
+
+        // One failure in four is exactly the threshold.
+        assert_eq!(controller.record(true), None);
+        assert_eq!(controller.record(true), Some(4));
+        assert_eq!(controller.limit(), 4);
+    }
+
+    #[test]
+    fn waits_for_enough_samples() {
+        let mut controller = Controller::new(8);
+        for _ in 1..MIN_SAMPLES {
+            assert_eq!(controller.record(true), None);
+        }
+        assert_eq!(controller.record(true), Some(4));
+
+        // The window starts over at the new limit.
+        for _ in 1..MIN_SAMPLES {
+            assert_eq!(controller.record(true), None);
+        }
+        assert_eq!(controller.record(true), Some(2));
+    }
+
+    #[test]
+    fn raises_by_one_after_as_many_successes_as_the_limit() {
+        let mut controller = Controller::new(8);
+        for _ in 0..MIN_SAMPLES {
+            controller.record(true);
+        }
+        assert_eq!(controller.limit(), 4);
+
+        for _ in 1..4 {
+            assert_eq!(controller.record(false), None);
+        }
+        assert_eq!(controller.record(false), Some(5));
+
+        for _ in 1..5 {
+            assert_eq!(controller.record(false), None);
+        }
+        assert_eq!(controller.record(false), Some(6));
+    }
+
+    #[test]
+    fn a_failure_resets_the_successes() {
+        let mut controller = Controller::new(8);
+        for _ in 0..MIN_SAMPLES {
+            controller.record(true);
+        }
+
+        for _ in 0..3 {
+            controller.record(false);
+        }
+        controller.record(true);
+        assert_eq!(controller.limit(), 4);
+        for _ in 1..4 {
+            assert_eq!(controller.record(false), None);
+        }
+        assert_eq!(controller.record(false), Some(5));
+    }
+
+    #[test]
+    fn stays_between_one_and_the_max() {
+        assert_eq!(Controller::new(0).limit(), 1);
+
+        let mut controller = Controller::new(3);
+        for _ in 0..100 {
+            controller.record(true);
+            assert!(controller.limit() >= 1);
+        }
+        assert_eq!(controller.limit(), 1);
+
+        for _ in 0..100 {
+            controller.record(false);
+            assert!(controller.limit() <= 3);
+        }
+        assert_eq!(controller.limit(), 3);
+    }
+}
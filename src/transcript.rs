@@ -0,0 +1,107 @@
+//! Transcripts linked from episodes with `podcast:transcript`, for `download_transcripts`.
+//!
+//! A feed can offer the same transcript in several formats. The first format in
+//! `transcript_formats` that the episode has is downloaded, falling back to the next one if
+//! it can't be, and saved next to the episode under the same name with the extension of the
+//! format. A transcript that can't be downloaded is logged, but never fails the episode.
+
+use crate::display::DownloadBar;
+use crate::net;
+use crate::net::Credentials;
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The formats transcripts are preferred in by default.
+pub const DEFAULT_FORMATS: [TranscriptFormat; 5] = [
+    TranscriptFormat::Vtt,
+    TranscriptFormat::Srt,
+    TranscriptFormat::Json,
+    TranscriptFormat::Html,
+    TranscriptFormat::Txt,
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptFormat {
+    Vtt,
+    Srt,
+    /// The JSON format of the Podcasting 2.0 namespace.
+    Json,
+    Html,
+    Txt,
+}
+
+impl TranscriptFormat {
+    /// The format of a transcript with the MIME type from its `type` attribute.
+    fn from_mime(mime: &str) -> Option<Self> {
+        let mime = mime.split(';').next().unwrap_or_default().trim();
+        let format = match mime.to_lowercase().as_str() {
+            "text/vtt" => Self::Vtt,
+            "application/x-subrip" | "application/srt" | "text/srt" => Self::Srt,
+            "application/json" => Self::Json,
+            "text/html" => Self::Html,
+            "text/plain" => Self::Txt,
+            _ => return None,
+        };
+
+        Some(format)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Vtt => "vtt",
+            Self::Srt => "srt",
+            Self::Json => "json",
+            Self::Html => "html",
+            Self::Txt => "txt",
+        }
+    }
+}
+
+/// The urls of the transcripts in the wanted formats, most preferred first, given the
+/// `(url, type)` pairs of the episode.
+pub fn ranked<'a>(
+    transcripts: &[(&'a str, &'a str)],
+    formats: &[TranscriptFormat],
+) -> Vec<(&'a str, TranscriptFormat)> {
+    formats
+        .iter()
+        .filter_map(|format| {
+            transcripts
+                .iter()
+                .find(|(_, mime)| TranscriptFormat::from_mime(mime) == Some(*format))
+                .map(|(url, _)| (*url, *format))
+        })
+        .collect()
+}
+
+/// Downloads the transcript at the url to `path`.
+pub async fn download(
+    client: &reqwest::Client,
+    credentials: &Credentials,
+    url: &str,
+    path: &Path,
+    ui: &DownloadBar,
+) -> Result<(), String> {
+    ui.log_debug(format!("downloading transcript: {}", net::redact_url(url)));
+    let response = credentials
+        .apply(client.get(url), url)
+        .send()
+        .await
+        .map_err(|e| format!("failed to download transcript: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!(
+            "failed to download transcript: server responded with {}",
+            status
+        ));
+    }
+
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| format!("failed to download transcript: {}", e))?;
+    paths::write(path, bytes).map_err(|e| format!("failed to save transcript: {}", e))
+}
@@ -0,0 +1,347 @@
+//! Where files go instead of being deleted when `trash_dir` is set, so they can be restored.
+//!
+//! Files are kept under `<trash_dir>/<podcast>/<original name>.<unix time>`, next to a
+//! `.trashinfo` file recording where they came from.
+
+use crate::config::GlobalConfig;
+use crate::download_tracker::DownloadedEpisodes;
+use crate::download_tracker::TrackerEntry;
+use crate::format;
+use crate::naming;
+use crate::naming::NameOptions;
+use crate::paths;
+use crate::utils;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+
+const INFO_EXTENSION: &str = "trashinfo";
+
+/// Where a trashed file came from.
+#[derive(Serialize, Deserialize, Debug)]
+struct TrashInfo {
+    original: PathBuf,
+    /// Unix time of when the file was trashed.
+    trashed: u64,
+    /// The tracker entry that was removed along with the file, if any.
+    #[serde(default)]
+    tracker: Option<TrackerRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrackerRecord {
+    path: PathBuf,
+    entry: TrackerEntry,
+}
+
+impl TrashInfo {
+    fn path(trashed: &Path) -> PathBuf {
+        let mut name = trashed.file_name().unwrap_or_default().to_owned();
+        name.push(".");
+        name.push(INFO_EXTENSION);
+        trashed.with_file_name(name)
+    }
+
+    fn load(trashed: &Path) -> Option<Self> {
+        let s = fs::read_to_string(Self::path(trashed)).ok()?;
+        serde_json::from_str(&s).ok()
+    }
+}
+
+/// The trash of a single podcast.
+#[derive(Debug, Clone)]
+pub struct Trash {
+    dir: PathBuf,
+}
+
+impl Trash {
+    /// The trash of a podcast on the download root `root`.
+    pub fn new(global_config: &GlobalConfig, podcast: &str, root: Option<&str>) -> Option<Self> {
+        let name = naming::sanitize_filename(NameOptions::default(), podcast);
+        let dir = global_config.trash_dir(root)?.join(name);
+        Some(Self { dir })
+    }
+
+    /// Moves a file into the trash.
+    ///
+    /// `tracker` is the tracker entry that was removed along with the file, so that
+    /// restoring the file can put it back.
+    pub fn discard(&self, path: &Path, tracker: Option<(&Path, TrackerEntry)>) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+
+        let now = utils::current_unix().as_secs();
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(format!(".{}", now));
+        let trashed = self.dir.join(name);
+
+        let info = TrashInfo {
+            original: path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+            trashed: now,
+            tracker: tracker.map(|(path, entry)| TrackerRecord {
+                path: path.to_path_buf(),
+                entry,
+            }),
+        };
+        let json = serde_json::to_string_pretty(&info).map_err(io::Error::other)?;
+
+        paths::move_path(path, &trashed)?;
+        paths::write(TrashInfo::path(&trashed), json)
+    }
+}
+
+/// Deletes a file, or moves it into the trash if there is one.
+///
+/// `tracker` is the tracker entry removed along with the file, for files of downloaded
+/// episodes.
+pub fn remove_file(
+    trash: Option<&Trash>,
+    path: &Path,
+    tracker: Option<(&Path, TrackerEntry)>,
+) -> io::Result<()> {
+    match trash {
+        Some(trash) => trash.discard(path, tracker),
+        None => fs::remove_file(path),
+    }
+}
+
+/// How many bytes the trashed files in `trash_dir` take up.
+pub fn size(trash_dir: &Path) -> u64 {
+    trashed_files(&[trash_dir.to_path_buf()])
+        .into_iter()
+        .filter_map(|(path, _)| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Every trashed file along with where it came from, oldest first.
+fn trashed_files(trash_dirs: &[PathBuf]) -> Vec<(PathBuf, TrashInfo)> {
+    let mut files: Vec<(PathBuf, TrashInfo)> = trash_dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|podcast| fs::read_dir(podcast.path()).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_none_or(|ext| ext != INFO_EXTENSION))
+        .filter_map(|path| {
+            let info = TrashInfo::load(&path)?;
+            Some((path, info))
+        })
+        .collect();
+
+    files.sort_by_key(|(_, info)| info.trashed);
+    files
+}
+
+/// The trash directories, one for each download root if `trash_dir` uses `{root}`.
+fn trash_dirs_or_exit(global_config: &GlobalConfig) -> Vec<PathBuf> {
+    let dirs = global_config.trash_dirs();
+    if dirs.is_empty() {
+        eprintln!("no trash configured, set 'trash_dir' in the config to enable it");
+        process::exit(1);
+    }
+    dirs
+}
+
+/// Deletes the trashed files that are older than `trash_retention_days`, from the trash
+/// of every download root.
+pub fn empty_expired(global_config: &GlobalConfig) {
+    let Some(retention) = global_config.trash_retention() else {
+        return;
+    };
+
+    let now = utils::current_unix().as_secs();
+    for (path, info) in trashed_files(&global_config.trash_dirs()) {
+        if now.saturating_sub(info.trashed) < retention.as_secs() {
+            continue;
+        }
+
+        log::info!("emptying from trash: {:?}", &path);
+        if let Err(e) = fs::remove_file(&path) {
+            log::warn!("failed to empty {:?} from trash: {}", &path, e);
+            continue;
+        }
+        let _ = fs::remove_file(TrashInfo::path(&path));
+    }
+}
+
+pub fn list(global_config: &GlobalConfig) {
+    let files = trashed_files(&trash_dirs_or_exit(global_config));
+
+    if files.is_empty() {
+        eprintln!("the trash is empty");
+        return;
+    }
+
+    let now = utils::current_unix().as_secs();
+    for (path, info) in files {
+        println!(
+            "{:<14} {} (from {})",
+            format::relative_date(info.trashed, now),
+            path.display(),
+            info.original.display()
+        );
+    }
+}
+
+/// Moves a trashed file back to where it came from, along with its tracker entry.
+pub fn restore(path: &Path, global_config: &GlobalConfig) {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    let in_trash = trash_dirs_or_exit(global_config)
+        .into_iter()
+        .map(|dir| dir.canonicalize().unwrap_or(dir))
+        .any(|dir| path.starts_with(dir));
+
+    let info = match in_trash.then(|| TrashInfo::load(&path)) {
+        Some(Some(info)) => info,
+        _ => {
+            eprintln!("not a file in the trash: {:?}", &path);
+            eprintln!("run with --trash-list to see what's in the trash");
+            process::exit(1);
+        }
+    };
+
+    if info.original.exists() {
+        eprintln!("can't restore, file already exists: {:?}", &info.original);
+        process::exit(1);
+    }
+
+    if let Some(parent) = info.original.parent() {
+        paths::create_dir(parent);
+    }
+
+    if let Err(e) = paths::move_path(&path, &info.original) {
+        eprintln!("failed to restore {:?}: {}", &path, e);
+        process::exit(1);
+    }
+    let _ = fs::remove_file(TrashInfo::path(&path));
+
+    if let Some(TrackerRecord { path, entry }) = info.tracker {
+        if !DownloadedEpisodes::load(&path).contains_episode(&entry.id) {
+            if let Err(e) = DownloadedEpisodes::append_entry(&path, &entry) {
+                eprintln!("failed to restore tracker entry: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    eprintln!("restored {:?}", &info.original);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn global_config(dir: &Path, extra: &str) -> GlobalConfig {
+        let trash_dir = dir.join("trash");
+        toml::from_str(&format!(
+            "trash_dir = \"{}\"\n{}",
+            trash_dir.display(),
+            extra
+        ))
+        .unwrap()
+    }
+
+    fn entry(id: &str) -> TrackerEntry {
+        TrackerEntry {
+            id: id.to_string(),
+            downloaded: 1,
+            title: "Episode".to_string(),
+            acknowledged: None,
+        }
+    }
+
+    /// Creates a file named `name` in `dir` and discards it, returning where it was.
+    fn discard(trash: &Trash, dir: &Path, name: &str, tracker: Option<&Path>) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, name).unwrap();
+        let path = path.canonicalize().unwrap();
+        trash
+            .discard(&path, tracker.map(|tracker| (tracker, entry(name))))
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn discard_records_where_files_came_from() {
+        let dir = paths::test_dir("trash-discard");
+        let global = global_config(&dir, "");
+        let trash = Trash::new(&global, "Podcast", None).unwrap();
+        let tracker = dir.join(".downloaded");
+        let original = discard(&trash, &dir, "episode.mp3", Some(&tracker));
+
+        assert!(!original.exists());
+        let files = trashed_files(&global.trash_dirs());
+        let [(path, info)] = files.as_slice() else {
+            panic!("expected one trashed file, got {:?}", files);
+        };
+        assert!(path.starts_with(dir.join("trash").join("Podcast")));
+        assert_eq!(fs::read_to_string(path).unwrap(), "episode.mp3");
+        assert_eq!(info.original, original);
+
+        let record = info.tracker.as_ref().unwrap();
+        assert_eq!(record.path, tracker);
+        assert_eq!(record.entry.id, "episode.mp3");
+        assert_eq!(size(&dir.join("trash")), "episode.mp3".len() as u64);
+
+        // Files that weren't downloaded episodes have no tracker entry to put back.
+        let backup = dir.join("episode.mp3.1.old");
+        fs::write(&backup, "old").unwrap();
+        remove_file(Some(&trash), &backup, None).unwrap();
+        assert_eq!(trashed_files(&global.trash_dirs()).len(), 2);
+    }
+
+    #[test]
+    fn restore_puts_back_file_and_tracker_entry() {
+        let dir = paths::test_dir("trash-restore");
+        let global = global_config(&dir, "");
+        let trash = Trash::new(&global, "Podcast", None).unwrap();
+        let tracker = dir.join(".downloaded");
+        let original = discard(&trash, &dir, "episode.mp3", Some(&tracker));
+        assert!(!DownloadedEpisodes::load(&tracker).contains_episode("episode.mp3"));
+
+        let (path, _) = trashed_files(&global.trash_dirs()).remove(0);
+        restore(&path, &global);
+
+        assert_eq!(fs::read_to_string(&original).unwrap(), "episode.mp3");
+        assert!(!path.exists());
+        assert!(!TrashInfo::path(&path).exists());
+        assert!(DownloadedEpisodes::load(&tracker).contains_episode("episode.mp3"));
+        assert!(trashed_files(&global.trash_dirs()).is_empty());
+    }
+
+    #[test]
+    fn empty_expired_removes_old_files() {
+        let dir = paths::test_dir("trash-expired");
+        let global = global_config(&dir, "trash_retention_days = 1");
+        let trash = Trash::new(&global, "Podcast", None).unwrap();
+        discard(&trash, &dir, "old.mp3", None);
+        discard(&trash, &dir, "new.mp3", None);
+
+        // Backdates the first file by two days.
+        let (old, mut info) = trashed_files(&global.trash_dirs())
+            .into_iter()
+            .find(|(_, info)| info.original.ends_with("old.mp3"))
+            .unwrap();
+        info.trashed -= 2 * 24 * 60 * 60;
+        paths::write(TrashInfo::path(&old), serde_json::to_string(&info).unwrap()).unwrap();
+
+        // Nothing is emptied without a retention.
+        empty_expired(&global_config(&dir, ""));
+        assert_eq!(trashed_files(&global.trash_dirs()).len(), 2);
+
+        empty_expired(&global);
+        assert!(!old.exists());
+        assert!(!TrashInfo::path(&old).exists());
+        let left: Vec<PathBuf> = trashed_files(&global.trash_dirs())
+            .into_iter()
+            .map(|(_, info)| info.original)
+            .collect();
+        assert_eq!(left, [dir.canonicalize().unwrap().join("new.mp3")]);
+    }
+}
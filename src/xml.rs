@@ -0,0 +1,157 @@
+//! Helpers for reading feed xml after it has been converted to serde values.
+
+use quickxml_to_serde::{xml_string_to_json, Config as XmlConfig};
+use regex::Regex;
+use serde_json::Map;
+use serde_json::Value;
+
+/// Namespaces whose tags are kept apart from the tags without a namespace.
+///
+/// The xml library merges different namespaces together, which is why we manually rename
+/// the tags of these namespaces before converting, and change them back afterwards.
+/// Preserving e.g. itunes:XXX as separate keys.
+const PRESERVED_NAMESPACES: [&str; 4] = ["itunes", "podcast", "sy", "media"];
+
+/// Stands in for the colon of a preserved namespace while converting xml.
+const NAMESPACE_ALTER: &str = "__placeholder__";
+
+/// Converts the feed to serde values, returning the `rss/channel` element.
+pub fn get_inner_channel(xml: &str) -> Option<Value> {
+    // Only tag names are renamed, so that text mentioning a namespace is left untouched.
+    let tags = Regex::new(&format!("<(/?)({}):", PRESERVED_NAMESPACES.join("|"))).unwrap();
+    let xml = tags
+        .replace_all(xml, format!("<${{1}}${{2}}{}", NAMESPACE_ALTER))
+        .into_owned();
+
+    let conf = XmlConfig::new_with_defaults();
+    std::mem::take(
+        xml_string_to_json(xml, &conf)
+            .ok()?
+            .get_mut("rss")?
+            .get_mut("channel")?,
+    )
+    .into()
+}
+
+/// Restores the namespace of the top-level keys of a value from [`get_inner_channel`].
+pub fn restore_namespaces(val: &Value) -> Map<String, Value> {
+    let mut new_map = Map::new();
+
+    if let Some(obj) = val.as_object() {
+        for (key, value) in obj {
+            let mut new_key = key.clone();
+            for namespace in PRESERVED_NAMESPACES {
+                let alias = format!("{}{}", namespace, NAMESPACE_ALTER);
+                if let Some(tag) = key.strip_prefix(&alias) {
+                    new_key = format!("{}:{}", namespace, tag);
+                }
+            }
+            new_map.insert(new_key, value.clone());
+        }
+    }
+
+    new_map
+}
+
+/// Values of a tag that may appear any number of times.
+pub fn val_to_vec(val: Option<&Value>) -> Vec<&Value> {
+    match val {
+        Some(Value::Array(vals)) => vals.iter().collect(),
+        Some(val) => vec![val],
+        None => vec![],
+    }
+}
+
+pub fn val_to_str<'a>(val: &'a serde_json::Value) -> Option<&'a str> {
+    if let Some(val) = val.as_str() {
+        return Some(val);
+    }
+
+    // A tag that appears more than once, like a title repeated in a CDATA section.
+    if let Some(vals) = val.as_array() {
+        return vals.iter().find_map(val_to_str);
+    }
+
+    let obj = val.as_object()?;
+
+    if let Some(text) = obj.get("@text") {
+        return text.as_str();
+    }
+    obj.get("#text")?.as_str()
+}
+
+/// Like [`val_to_str`], but also reads tags that the xml library turned into numbers, like
+/// `<itunes:episode>7</itunes:episode>`.
+pub fn val_to_string(val: &serde_json::Value) -> Option<String> {
+    if let Some(val) = val_to_str(val) {
+        return Some(val.to_owned());
+    }
+
+    match val {
+        serde_json::Value::Number(_) | serde_json::Value::Bool(_) => Some(val.to_string()),
+        serde_json::Value::Array(vals) => vals.iter().find_map(val_to_string),
+        serde_json::Value::Object(obj) => val_to_string(obj.get("#text")?),
+        _ => None,
+    }
+}
+
+pub fn val_to_url<'a>(val: &'a serde_json::Value) -> Option<&'a str> {
+    if let Some(val) = val.as_str() {
+        return Some(val);
+    }
+
+    let obj = val.as_object()?;
+
+    if let Some(url) = obj.get("url") {
+        return url.as_str();
+    }
+
+    if let Some(url) = obj.get("@href") {
+        return url.as_str();
+    }
+
+    if let Some(url) = obj.get("src") {
+        return url.as_str();
+    }
+
+    obj.get("uri")?.as_str()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaced_tags_are_kept_apart() {
+        let channel = get_inner_channel(
+            r#"<rss><channel>
+            <title>Plain</title>
+            <itunes:title>Itunes</itunes:title>
+            <podcast:funding url="https://example.com">Tip</podcast:funding>
+            <description>About podcast: and itunes:author tags</description>
+            </channel></rss>"#,
+        )
+        .unwrap();
+        let channel = restore_namespaces(&channel);
+
+        assert_eq!(channel.get("title").and_then(val_to_str), Some("Plain"));
+        assert_eq!(
+            channel.get("itunes:title").and_then(val_to_str),
+            Some("Itunes")
+        );
+        assert!(channel.contains_key("podcast:funding"));
+        assert_eq!(
+            channel.get("description").and_then(val_to_str),
+            Some("About podcast: and itunes:author tags")
+        );
+    }
+
+    #[test]
+    fn values_of_repeated_tags() {
+        let one = Value::from("a");
+        let many = Value::from(vec!["a", "b"]);
+        assert_eq!(val_to_vec(Some(&one)), vec![&one]);
+        assert_eq!(val_to_vec(Some(&many)).len(), 2);
+        assert!(val_to_vec(None).is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! Runs the commands that only print things against a config and state that can't be
+//! written to, like a monitoring user would.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::SystemTime;
+
+const FEED: &str = "<?xml version=\"1.0\"?><rss><channel><title>Podcast</title>\
+    <item><title>Episode</title><guid>episode-1</guid>\
+    <pubDate>Mon, 01 Jan 2024 06:00:00 GMT</pubDate>\
+    <enclosure url=\"{url}/1.mp3\" type=\"audio/mpeg\" length=\"5\"/></item>\
+    </channel></rss>";
+
+/// Serves the feed and its episode, returning the url of the server.
+fn serve() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let feed = FEED.replace("{url}", &url);
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().filter_map(Result::ok) {
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            }
+
+            let request = String::from_utf8_lossy(&request);
+            let (mime, body) = match request.split_whitespace().nth(1) {
+                Some("/feed.xml") => ("application/rss+xml", feed.as_bytes()),
+                Some("/1.mp3") => ("audio/mpeg", &b"audio"[..]),
+                _ => ("text/plain", &b""[..]),
+            };
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                mime,
+                body.len()
+            );
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(body);
+        }
+    });
+    url
+}
+
+/// Runs TaleCast with its config, cache and data all under `root`.
+fn talecast(root: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_talecast"))
+        .args(args)
+        .env("HOME", root.join("home"))
+        .env("XDG_CONFIG_HOME", root.join("config"))
+        .env("XDG_CACHE_HOME", root.join("cache"))
+        .env("XDG_DATA_HOME", root.join("data"))
+        .env("XDG_STATE_HOME", root.join("state"))
+        .output()
+        .unwrap()
+}
+
+/// Every file and directory under `dir`, with its size and when it was last changed.
+fn snapshot(dir: &Path, files: &mut BTreeMap<PathBuf, (u64, SystemTime)>) {
+    for entry in fs::read_dir(dir).unwrap().map(Result::unwrap) {
+        let metadata = entry.metadata().unwrap();
+        files.insert(entry.path(), (metadata.len(), metadata.modified().unwrap()));
+        if metadata.is_dir() {
+            snapshot(&entry.path(), files);
+        }
+    }
+}
+
+fn set_mode(dir: &Path, mode: u32) {
+    fs::set_permissions(dir, fs::Permissions::from_mode(mode)).unwrap();
+    for entry in fs::read_dir(dir).unwrap().map(Result::unwrap) {
+        if entry.file_type().unwrap().is_dir() {
+            set_mode(&entry.path(), mode);
+        } else {
+            fs::set_permissions(entry.path(), fs::Permissions::from_mode(mode)).unwrap();
+        }
+    }
+}
+
+#[test]
+fn informational_commands_write_nothing() {
+    let root = std::env::temp_dir().join(format!("talecast-read-only-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let config = root.join("config").join("talecast");
+    fs::create_dir_all(&config).unwrap();
+    fs::create_dir_all(root.join("home")).unwrap();
+
+    let url = serve();
+    fs::write(
+        config.join("config.toml"),
+        format!(
+            "download_path = \"{0}/podcasts/{{podname}}\"\ntrash_dir = \"{0}/trash\"\narchive_feed = true\n",
+            root.display()
+        ),
+    )
+    .unwrap();
+    fs::write(
+        config.join("podcasts.toml"),
+        format!("[podcast]\nurl = \"{}/feed.xml\"\n", url),
+    )
+    .unwrap();
+
+    // A regular sync leaves the state behind, and then nothing may be written anymore.
+    let sync = talecast(&root, &[]);
+    assert!(sync.status.success(), "{:?}", sync);
+    assert!(root.join("podcasts/podcast/.downloaded").is_file());
+    set_mode(&root, 0o555);
+    let mut before = BTreeMap::new();
+    snapshot(&root, &mut before);
+
+    let commands: [&[&str]; 10] = [
+        &["--list"],
+        &["--list", "--json"],
+        &["--explain", "podcast"],
+        &["--cache-stats"],
+        &["--trash-list"],
+        &["--validate"],
+        &["--backlog-status"],
+        &["--snapshots"],
+        &["--find", "episode"],
+        &["--read-only", "--dry-run"],
+    ];
+    let failures: Vec<String> = commands
+        .iter()
+        .map(|args| (args, talecast(&root, args)))
+        .filter(|(_, output)| {
+            !output.status.success()
+                || String::from_utf8_lossy(&output.stderr).contains("internal error")
+        })
+        .map(|(args, output)| {
+            format!(
+                "{:?}: {}\n{}",
+                args,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            )
+        })
+        .collect();
+
+    // Checked even when the files could be written, like when the tests run as root.
+    let mut after = BTreeMap::new();
+    snapshot(&root, &mut after);
+    set_mode(&root, 0o755);
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+    let changed: BTreeSet<&PathBuf> = before
+        .keys()
+        .chain(after.keys())
+        .filter(|path| before.get(*path) != after.get(*path))
+        .collect();
+    assert!(changed.is_empty(), "written to: {:?}", changed);
+}